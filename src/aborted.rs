@@ -0,0 +1,86 @@
+use pgrx::prelude::*;
+
+use crate::heap::xmin_aborted;
+use crate::output::{format_tuple, OutputFormat};
+use crate::xid8::Xid8;
+use crate::TupleStream;
+
+/*
+ * Return the tuples whose inserting transaction has aborted. These tuples are
+ * invisible to every snapshot and wait for their removal by pruning or vacuum.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_aborted_inserts(
+    table: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(data, String),
+    ),
+> {
+    let mut tuples = TupleStream::open(
+        table,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        None,
+        None,
+        None,
+    );
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    TableIterator::new(std::iter::from_fn(move || loop {
+        let (htup, tupdesc) = tuples.next_matching()?;
+
+        if !xmin_aborted((*htup).t_data) {
+            continue;
+        }
+
+        let tuple = tuples.decode(htup, tupdesc);
+
+        return Some((
+            tuple.ctid,
+            Xid8::from_xid(tuple.xmin, next_xid),
+            Xid8::from_xid(tuple.xmax, next_xid),
+            format_tuple(&tuple.attributes, output_format),
+        ));
+    }))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_aborted_inserts() {
+        pgrx::Spi::run("CREATE TABLE aborted_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO aborted_test VALUES (1);").unwrap();
+
+        /* Insert a tuple in an aborted subtransaction */
+        pgrx::Spi::run(
+            "DO $$ BEGIN
+                INSERT INTO aborted_test VALUES (2);
+                RAISE EXCEPTION 'abort';
+            EXCEPTION WHEN OTHERS THEN NULL;
+            END $$;",
+        )
+        .unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_aborted_inserts('aborted_test');",
+        )
+        .unwrap();
+        assert_eq!(count, Some(1));
+
+        let data = pgrx::Spi::get_one::<String>(
+            "SELECT data FROM pg_debug_aborted_inserts('aborted_test');",
+        )
+        .unwrap();
+        assert_eq!(data, Some("{\"value\":\"2\"}".to_string()));
+    }
+}
@@ -0,0 +1,134 @@
+use std::ffi::CStr;
+
+use pgrx::{pg_sys::GetTransactionSnapshot, prelude::*};
+
+use crate::output::{format_tuple, OutputFormat};
+use crate::snapshot::get_snapshot_from_str;
+use crate::TupleStream;
+
+/* The number of records for which the predicate is evaluated at once */
+const PREDICATE_BATCH_SIZE: usize = 1000;
+
+/*
+ * Check that the number of tuples visible under the given snapshot that match the
+ * predicate is the expected one and raise an error otherwise. The predicate is a
+ * SQL expression on the columns of the table (e.g., value > 10), which is evaluated
+ * for batches of tuples while the table is scanned. Without a predicate, all visible
+ * tuples are counted.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_assert_visible(
+    table: &str,
+    snapshot: Option<&str>,
+    expected_count: i64,
+    predicate: default!(Option<&str>, "NULL"),
+) -> bool {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let mut tuples = TupleStream::open(table, snapshot_data, None, None, None);
+
+    /* Without a predicate, the tuples do not have to be decoded */
+    let Some(predicate) = predicate else {
+        let mut count = 0;
+        while tuples.next_matching().is_some() {
+            count += 1;
+        }
+
+        return check_count(table, expected_count, count);
+    };
+
+    /* Convert the records back into the row type to evaluate the predicate */
+    let row_type = pg_sys::get_rel_type_id((*tuples.table_rel).rd_id);
+    let row_type_name = CStr::from_ptr(pg_sys::format_type_be(row_type))
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let query = format!(
+        "SELECT count(*) FROM (SELECT (record::{row_type_name}).* FROM unnest($1) record) tuples WHERE {predicate}"
+    );
+
+    /* The predicate is evaluated for batches of records while the table is scanned */
+    let mut count = 0;
+    let mut records = Vec::with_capacity(PREDICATE_BATCH_SIZE);
+    let mut exhausted = false;
+
+    while !exhausted {
+        match tuples.next() {
+            Some(tuple) => records.push(format_tuple(
+                &tuple.attributes,
+                OutputFormat::RecordCompatible,
+            )),
+            None => exhausted = true,
+        }
+
+        if records.len() == PREDICATE_BATCH_SIZE || (exhausted && !records.is_empty()) {
+            count += Spi::get_one_with_args::<i64>(
+                &query,
+                vec![(
+                    PgBuiltInOids::TEXTARRAYOID.oid(),
+                    std::mem::take(&mut records).into_datum(),
+                )],
+            )
+            .unwrap_or_else(|err| error!("Unable to evaluate predicate: {err}"))
+            .unwrap_or(0);
+        }
+    }
+
+    check_count(table, expected_count, count)
+}
+
+/*
+ * Raise an error if the number of matching tuples is not the expected one
+ */
+fn check_count(table: &str, expected_count: i64, count: i64) -> bool {
+    if count != expected_count {
+        error!("Expected {expected_count} visible tuples in table {table}, but found {count}");
+    }
+
+    true
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_assert_visible() {
+        pgrx::Spi::run("CREATE TABLE assert_test (id int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO assert_test VALUES (1, 'a'), (2, 'b'), (3, NULL);").unwrap();
+
+        let result = pgrx::Spi::get_one::<bool>(
+            "SELECT pg_debug_assert_visible('assert_test', NULL, 2, 'value IS NOT NULL');",
+        )
+        .unwrap();
+        assert_eq!(result, Some(true));
+    }
+
+    #[pgrx::pg_test]
+    fn test_assert_visible_batches() {
+        pgrx::Spi::run("CREATE TABLE assert_test (id int);").unwrap();
+        pgrx::Spi::run("INSERT INTO assert_test SELECT generate_series(1, 2500);").unwrap();
+
+        /* The predicate is evaluated for three batches */
+        let result = pgrx::Spi::get_one::<bool>(
+            "SELECT pg_debug_assert_visible('assert_test', NULL, 1250, 'id % 2 = 0');",
+        )
+        .unwrap();
+        assert_eq!(result, Some(true));
+    }
+
+    #[pgrx::pg_test(error = "Expected 5 visible tuples in table assert_test, but found 3")]
+    fn test_assert_visible_mismatch() {
+        pgrx::Spi::run("CREATE TABLE assert_test (id int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO assert_test VALUES (1, 'a'), (2, 'b'), (3, NULL);").unwrap();
+
+        pgrx::Spi::run("SELECT pg_debug_assert_visible('assert_test', NULL, 5);").unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+use pgrx::{
+    pg_sys::{AccessShareLock, TransactionId},
+    prelude::*,
+};
+
+use crate::heap::{page_get_heap_tuples, with_locked_page};
+use crate::prune::get_prune_horizon;
+use crate::visibilitymap::{visibilitymap_get_status, VISIBILITYMAP_ALL_VISIBLE};
+
+/* All user tables the current user is allowed to read */
+const USER_TABLES_QUERY: &str = "SELECT c.oid, c.oid::regclass::text FROM pg_class c
+    JOIN pg_namespace n ON c.relnamespace = n.oid
+    WHERE c.relkind IN ('r', 'm')
+    AND n.nspname NOT IN ('pg_catalog', 'information_schema')
+    AND n.nspname NOT LIKE 'pg_toast%'
+    AND NOT pg_is_other_temp_schema(n.oid)
+    AND has_table_privilege(c.oid, 'SELECT')";
+
+/* The estimated dead tuple versions of a table */
+struct TableBloat {
+    relation: String,
+    blocks: i64,
+    sampled_blocks: i64,
+    dead_tuples: i64,
+    dead_bytes: i64,
+}
+
+/*
+ * Count the dead and recently dead tuple versions of the given block. Only the
+ * tuple headers are inspected.
+ */
+unsafe fn block_dead_versions(
+    table_rel: pg_sys::Relation,
+    blkno: pg_sys::BlockNumber,
+    horizon: TransactionId,
+) -> (i64, i64) {
+    let relid = (*table_rel).rd_id;
+
+    with_locked_page(table_rel, blkno, |buffer, page| {
+        let mut dead_tuples = 0;
+        let mut dead_bytes = 0;
+
+        for mut htup in page_get_heap_tuples(relid, page, blkno) {
+            match pg_sys::HeapTupleSatisfiesVacuum(&mut htup, horizon, buffer) {
+                pg_sys::HTSV_Result_HEAPTUPLE_DEAD
+                | pg_sys::HTSV_Result_HEAPTUPLE_RECENTLY_DEAD => {
+                    dead_tuples += 1;
+                    dead_bytes += htup.t_len as i64;
+                }
+                _ => {}
+            }
+        }
+
+        (dead_tuples, dead_bytes)
+    })
+}
+
+/*
+ * Estimate the dead tuple versions of a table by inspecting up to sample_blocks
+ * evenly distributed blocks. Blocks that are all-visible according to the
+ * visibility map contain no dead versions and are not read.
+ */
+unsafe fn estimate_table_bloat(
+    relid: pg_sys::Oid,
+    relation: String,
+    sample_blocks: i64,
+) -> Option<TableBloat> {
+    /* The table might have been dropped in the meantime */
+    let table_rel = pg_sys::try_table_open(relid, AccessShareLock as i32);
+    if table_rel.is_null() {
+        return None;
+    }
+
+    let horizon = get_prune_horizon(table_rel);
+    let nblocks =
+        pg_sys::RelationGetNumberOfBlocksInFork(table_rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+    let step = (nblocks as i64 / sample_blocks.max(1)).max(1) as usize;
+
+    let mut vmbuffer: pg_sys::Buffer = pg_sys::InvalidBuffer as pg_sys::Buffer;
+    let mut sampled_blocks = 0;
+    let mut dead_tuples = 0;
+    let mut dead_bytes = 0;
+
+    for blkno in (0..nblocks).step_by(step) {
+        sampled_blocks += 1;
+
+        let status = visibilitymap_get_status(table_rel, blkno, &mut vmbuffer);
+        if status & VISIBILITYMAP_ALL_VISIBLE != 0 {
+            continue;
+        }
+
+        let (block_dead_tuples, block_dead_bytes) = block_dead_versions(table_rel, blkno, horizon);
+        dead_tuples += block_dead_tuples;
+        dead_bytes += block_dead_bytes;
+    }
+
+    if vmbuffer != pg_sys::InvalidBuffer as pg_sys::Buffer {
+        pg_sys::ReleaseBuffer(vmbuffer);
+    }
+
+    /*
+     * Release the lock right after the table is processed instead of at the end of
+     * the transaction, so sampling all tables of a database does not exhaust
+     * max_locks_per_transaction
+     */
+    pg_sys::table_close(table_rel, pg_sys::NoLock as i32);
+    pg_sys::UnlockRelationOid(relid, AccessShareLock as i32);
+
+    /* Extrapolate the sampled values to the whole table */
+    let factor = if sampled_blocks > 0 {
+        nblocks as f64 / sampled_blocks as f64
+    } else {
+        0.0
+    };
+
+    Some(TableBloat {
+        relation,
+        blocks: nblocks.into(),
+        sampled_blocks,
+        dead_tuples: (dead_tuples as f64 * factor).round() as i64,
+        dead_bytes: (dead_bytes as f64 * factor).round() as i64,
+    })
+}
+
+/*
+ * Rank the user tables of the current database by the estimated volume of their
+ * dead tuple versions and return the top n tables. Each table is sampled, see
+ * estimate_table_bloat.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_top_bloat(
+    n: default!(i64, 10),
+    sample_blocks: default!(i64, 1000),
+) -> TableIterator<
+    'static,
+    (
+        name!(relation, String),
+        name!(blocks, i64),
+        name!(sampled_blocks, i64),
+        name!(dead_tuples, i64),
+        name!(dead_bytes, i64),
+    ),
+> {
+    let tables: Vec<(pg_sys::Oid, String)> = Spi::connect(|client| {
+        client
+            .select(USER_TABLES_QUERY, None, None)?
+            .map(|row| Ok((row.get::<pg_sys::Oid>(1)?, row.get::<String>(2)?)))
+            .filter_map(|table| match table {
+                Ok((Some(relid), Some(relation))) => Some(Ok((relid, relation))),
+                Ok(_) => None,
+                Err(err) => Some(Err(err)),
+            })
+            .collect::<Result<Vec<_>, pgrx::spi::Error>>()
+    })
+    .unwrap_or_else(|err| error!("Unable to get the user tables: {err}"));
+
+    let mut results: Vec<TableBloat> = tables
+        .into_iter()
+        .filter_map(|(relid, relation)| estimate_table_bloat(relid, relation, sample_blocks))
+        .collect();
+
+    results.sort_by(|a, b| b.dead_bytes.cmp(&a.dead_bytes));
+    results.truncate(n.max(0) as usize);
+
+    TableIterator::new(results.into_iter().map(|table| {
+        (
+            table.relation,
+            table.blocks,
+            table.sampled_blocks,
+            table.dead_tuples,
+            table.dead_bytes,
+        )
+    }))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_top_bloat() {
+        pgrx::Spi::run("CREATE TABLE bloat_test (value int);").unwrap();
+
+        /* Insert tuples in an aborted subtransaction */
+        pgrx::Spi::run(
+            "DO $$ BEGIN
+                INSERT INTO bloat_test SELECT generate_series(1, 10);
+                RAISE EXCEPTION 'abort';
+            EXCEPTION WHEN OTHERS THEN NULL;
+            END $$;",
+        )
+        .unwrap();
+
+        let dead_tuples = pgrx::Spi::get_one::<i64>(
+            "SELECT dead_tuples FROM pg_debug_top_bloat(1000) WHERE relation = 'bloat_test';",
+        )
+        .unwrap();
+        assert_eq!(dead_tuples, Some(10));
+
+        /* The lock of the sampled table is released */
+        let locks = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_locks WHERE pid = pg_backend_pid() \
+             AND relation = 'bloat_test'::regclass AND mode = 'AccessShareLock';",
+        )
+        .unwrap();
+        assert_eq!(locks, Some(0));
+    }
+}
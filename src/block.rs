@@ -0,0 +1,214 @@
+use pgrx::{
+    pg_sys::{AccessShareLock, TransactionId},
+    prelude::*,
+};
+
+use crate::get_relid_from_name;
+use crate::heap::{
+    open_heap_relation, page_get_heap_tuples, prefetch_ahead, with_locked_page,
+    HeapTupleHeaderGetXmax,
+};
+use crate::xid8::Xid8;
+
+/* The oldest and newest normal transaction id of a set of transaction ids */
+#[derive(Default)]
+struct XidRange {
+    min: Option<TransactionId>,
+    max: Option<TransactionId>,
+}
+
+impl XidRange {
+    /*
+     * Add a transaction id to the range. Special transaction ids (e.g., the
+     * frozen xid) are ignored.
+     */
+    unsafe fn add(&mut self, xid: TransactionId) {
+        if xid < pg_sys::FirstNormalTransactionId {
+            return;
+        }
+
+        if self
+            .min
+            .map_or(true, |min| pg_sys::TransactionIdPrecedes(xid, min))
+        {
+            self.min = Some(xid);
+        }
+
+        if self
+            .max
+            .map_or(true, |max| pg_sys::TransactionIdFollows(xid, max))
+        {
+            self.max = Some(xid);
+        }
+    }
+}
+
+/*
+ * Get the age of the given xid, like the age() function of PostgreSQL. The age is
+ * only returned for normal xids.
+ */
+pub(crate) unsafe fn xid_age(xid: TransactionId) -> Option<i32> {
+    if xid < pg_sys::FirstNormalTransactionId {
+        return None;
+    }
+
+    Some(pg_sys::GetStableLatestTransactionId().wrapping_sub(xid) as i32)
+}
+
+/*
+ * Report per block the newest xmin of its tuples and the age of this xmin. Blocks
+ * with a small age were modified recently, blocks with a large age were not
+ * touched for a long time. Frozen tuples are counted, but have no xmin age.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_block_heatmap(
+    table: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(block_number, i64),
+        name!(tuples, i32),
+        name!(newest_xmin, Option<Xid8>),
+        name!(xmin_age, Option<i32>),
+    ),
+> {
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+
+    let nblocks =
+        pg_sys::RelationGetNumberOfBlocksInFork(table_rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut results = Vec::new();
+
+    for blkno in 0..nblocks {
+        prefetch_ahead(table_rel, blkno, nblocks);
+
+        let (tuples, newest_xmin) = with_locked_page(table_rel, blkno, |_buffer, page| {
+            let tuples = page_get_heap_tuples(relid, page, blkno);
+            let mut xmin_range = XidRange::default();
+
+            for htup in &tuples {
+                xmin_range.add(pg_sys::HeapTupleHeaderGetXmin(htup.t_data));
+            }
+
+            (tuples.len() as i32, xmin_range.max)
+        });
+
+        results.push((
+            blkno.into(),
+            tuples,
+            newest_xmin.map(|xmin| Xid8::from_xid(xmin, next_xid)),
+            newest_xmin.and_then(|xmin| xid_age(xmin)),
+        ));
+    }
+
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+
+    TableIterator::new(results)
+}
+
+/*
+ * Report per block the range of the xmin and xmax values of its tuples. This allows
+ * to find the blocks that were touched by a certain transaction before running a
+ * targeted scan.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_block_xids(
+    table: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(block_number, i64),
+        name!(min_xmin, Option<Xid8>),
+        name!(max_xmin, Option<Xid8>),
+        name!(min_xmax, Option<Xid8>),
+        name!(max_xmax, Option<Xid8>),
+    ),
+> {
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+
+    let nblocks =
+        pg_sys::RelationGetNumberOfBlocksInFork(table_rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut results = Vec::new();
+
+    for blkno in 0..nblocks {
+        prefetch_ahead(table_rel, blkno, nblocks);
+
+        let (xmin_range, xmax_range) = with_locked_page(table_rel, blkno, |_buffer, page| {
+            let mut xmin_range = XidRange::default();
+            let mut xmax_range = XidRange::default();
+
+            for htup in page_get_heap_tuples(relid, page, blkno) {
+                xmin_range.add(pg_sys::HeapTupleHeaderGetXmin(htup.t_data));
+                xmax_range.add(HeapTupleHeaderGetXmax(htup.t_data));
+            }
+
+            (xmin_range, xmax_range)
+        });
+
+        results.push((
+            blkno.into(),
+            xmin_range.min.map(|xid| Xid8::from_xid(xid, next_xid)),
+            xmin_range.max.map(|xid| Xid8::from_xid(xid, next_xid)),
+            xmax_range.min.map(|xid| Xid8::from_xid(xid, next_xid)),
+            xmax_range.max.map(|xid| Xid8::from_xid(xid, next_xid)),
+        ));
+    }
+
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+
+    TableIterator::new(results)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_block_heatmap() {
+        pgrx::Spi::run("CREATE TABLE heatmap_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO heatmap_test SELECT generate_series(1, 10);").unwrap();
+
+        let tuples = pgrx::Spi::get_one::<i32>(
+            "SELECT tuples FROM pg_debug_block_heatmap('heatmap_test') WHERE block_number = 0;",
+        )
+        .unwrap();
+        assert_eq!(tuples, Some(10));
+
+        /* The tuples were inserted by the current transaction */
+        let xmin_age = pgrx::Spi::get_one::<i32>(
+            "SELECT xmin_age FROM pg_debug_block_heatmap('heatmap_test') WHERE block_number = 0;",
+        )
+        .unwrap();
+        assert_eq!(xmin_age, Some(0));
+    }
+
+    #[pgrx::pg_test]
+    fn test_block_xids() {
+        pgrx::Spi::run("CREATE TABLE block_xids_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO block_xids_test SELECT generate_series(1, 10);").unwrap();
+
+        let xid = pgrx::Spi::get_one::<String>("SELECT txid_current()::text;").unwrap();
+
+        let min_xmin = pgrx::Spi::get_one::<String>(
+            "SELECT min_xmin::text FROM pg_debug_block_xids('block_xids_test') WHERE block_number = 0;",
+        )
+        .unwrap();
+        assert_eq!(min_xmin, xid);
+
+        /* No tuple is deleted */
+        let max_xmax = pgrx::Spi::get_one::<String>(
+            "SELECT max_xmax::text FROM pg_debug_block_xids('block_xids_test') WHERE block_number = 0;",
+        )
+        .unwrap();
+        assert_eq!(max_xmax, None);
+    }
+}
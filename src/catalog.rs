@@ -0,0 +1,101 @@
+use pgrx::prelude::*;
+
+use crate::snapshot::parse_snapshot_data;
+
+#[cfg(not(feature = "embedded"))]
+extension_sql!(
+    r#"
+CREATE TABLE pg_debug_snapshots (
+    name text PRIMARY KEY,
+    spec text NOT NULL,
+    created timestamptz NOT NULL DEFAULT now()
+);
+
+SELECT pg_catalog.pg_extension_config_dump('pg_debug_snapshots', '');
+"#,
+    name = "snapshot_catalog",
+);
+
+/*
+ * Get the qualified name of the table that contains the stored snapshots
+ */
+fn snapshot_catalog() -> String {
+    Spi::get_one::<String>(
+        "SELECT quote_ident(n.nspname) || '.pg_debug_snapshots' FROM pg_extension e
+        JOIN pg_namespace n ON e.extnamespace = n.oid WHERE e.extname = 'pg_debug_scan'",
+    )
+    .unwrap_or_else(|err| error!("Unable to find the snapshot catalog: {err}"))
+    .unwrap_or_else(|| error!("Unable to find the snapshot catalog"))
+}
+
+/*
+ * Store the snapshot under the given name, so it can be used by later scans and
+ * from other sessions. An existing snapshot with the same name is replaced.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+fn pg_debug_snapshot_store(name: &str, spec: &str) {
+    /* Validate the snapshot before it is stored */
+    parse_snapshot_data(spec, true);
+
+    let query = format!(
+        "INSERT INTO {} (name, spec) VALUES ($1, $2)
+        ON CONFLICT (name) DO UPDATE SET spec = excluded.spec, created = now()",
+        snapshot_catalog()
+    );
+
+    Spi::run_with_args(
+        &query,
+        Some(vec![
+            (PgBuiltInOids::TEXTOID.oid(), name.into_datum()),
+            (PgBuiltInOids::TEXTOID.oid(), spec.into_datum()),
+        ]),
+    )
+    .unwrap_or_else(|err| error!("Unable to store snapshot {name}: {err}"));
+}
+
+/*
+ * Get the specification of a stored snapshot
+ */
+pub(crate) fn get_stored_snapshot(name: &str) -> String {
+    let query = format!("SELECT spec FROM {} WHERE name = $1", snapshot_catalog());
+
+    Spi::get_one_with_args::<String>(
+        &query,
+        vec![(PgBuiltInOids::TEXTOID.oid(), name.into_datum())],
+    )
+    .unwrap_or_else(|err| error!("Unable to read stored snapshot {name}: {err}"))
+    .unwrap_or_else(|| error!("Stored snapshot {name} does not exist"))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_stored_snapshot() {
+        pgrx::Spi::run("CREATE TABLE stored_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO stored_test VALUES (1);").unwrap();
+        pgrx::Spi::run("SELECT pg_debug_snapshot_store('incident', '10:20:12,15');").unwrap();
+
+        let spec = pgrx::Spi::get_one::<String>(
+            "SELECT spec FROM pg_debug_snapshots WHERE name = 'incident';",
+        )
+        .unwrap();
+        assert_eq!(spec, Some("10:20:12,15".to_string()));
+
+        /* Replace the snapshot */
+        pgrx::Spi::run(
+            "SELECT pg_debug_snapshot_store('incident', txid_current() || ':' || txid_current() || ':');",
+        )
+        .unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('stored_test', stored => 'incident');",
+        )
+        .unwrap();
+        assert_eq!(count, Some(1));
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet};
+
+use pgrx::{
+    itemptr::{item_pointer_get_both, item_pointer_set_all},
+    pg_sys::GetTransactionSnapshot,
+    prelude::*,
+};
+
+use crate::header::infomask_flags;
+use crate::heap::{
+    page_hot_chains, with_locked_page, HeapTupleHeaderGetUpdateXid, HeapTupleHeaderGetXmax,
+    OpenRelation, PageGetMaxOffsetNumber, HEAP_XMAX_IS_LOCKED_ONLY,
+};
+use crate::output::{format_tuple, OutputFormat};
+use crate::page::{read_line_pointer, LinePointer};
+use crate::snapshot::get_snapshot_from_str;
+use crate::xid8::Xid8;
+use crate::{decode_tuple, get_relid_from_name, TupleStream};
+
+/* The position of a tuple in its HOT chain */
+struct ChainPosition {
+    root: pg_sys::OffsetNumber,
+    position: i32,
+    length: i32,
+}
+
+/*
+ * Determine the position of each member of the HOT chains of the page
+ */
+unsafe fn chain_positions(
+    relid: pg_sys::Oid,
+    page: pg_sys::Page,
+    blkno: pg_sys::BlockNumber,
+) -> HashMap<pg_sys::OffsetNumber, ChainPosition> {
+    let mut positions = HashMap::new();
+
+    for (root, members) in page_hot_chains(relid, page, blkno) {
+        for (index, offnum) in members.iter().enumerate() {
+            positions.insert(
+                *offnum,
+                ChainPosition {
+                    root,
+                    position: index as i32 + 1,
+                    length: members.len() as i32,
+                },
+            );
+        }
+    }
+
+    positions
+}
+
+/*
+ * Perform a table scan and return for each visible tuple the root ctid of its HOT
+ * chain, the position of the tuple in the chain and the number of chain members.
+ * The chains of a page are determined when the scan reaches the page. This allows
+ * to group and order the tuples by HOT chain in SQL.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_chains(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(root_ctid, pg_sys::ItemPointerData),
+        name!(chain_position, i32),
+        name!(chain_length, i32),
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(data, String),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let mut tuples = TupleStream::open(table, snapshot_data, None, None, None);
+    let relid = (*tuples.table_rel).rd_id;
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    let mut current_block = None;
+    let mut positions = HashMap::new();
+
+    TableIterator::new(std::iter::from_fn(move || {
+        let (htup, tupdesc) = tuples.next_matching()?;
+        let (blkno, offnum) = item_pointer_get_both((*htup).t_self);
+
+        if current_block != Some(blkno) {
+            positions =
+                tuples.with_current_page(|_buffer, page| chain_positions(relid, page, blkno));
+            current_block = Some(blkno);
+        }
+
+        /* Tuples that are not reachable from a chain root form their own chain */
+        let own_chain = ChainPosition {
+            root: offnum,
+            position: 1,
+            length: 1,
+        };
+        let chain_position = positions.get(&offnum).unwrap_or(&own_chain);
+
+        let mut root_ctid = pg_sys::ItemPointerData::default();
+        item_pointer_set_all(&mut root_ctid, blkno, chain_position.root);
+
+        let tuple = tuples.decode(htup, tupdesc);
+
+        Some((
+            root_ctid,
+            chain_position.position,
+            chain_position.length,
+            tuple.ctid,
+            Xid8::from_xid(tuple.xmin, next_xid),
+            Xid8::from_xid(tuple.xmax, next_xid),
+            format_tuple(&tuple.attributes, output_format),
+        ))
+    }))
+}
+
+/*
+ * The versions of a row, starting at a ctid. The t_ctid links of updated tuples and
+ * the redirects of HOT chains are followed one hop at a time. Each line pointer is
+ * read while the page is locked, so the visibility of a version is checked against
+ * the current state of the page.
+ */
+pub(crate) struct VersionChain {
+    relid: pg_sys::Oid,
+    table: OpenRelation,
+    nblocks: pg_sys::BlockNumber,
+    snapshot_data: *mut pg_sys::SnapshotData,
+    next_tid: Option<(pg_sys::BlockNumber, pg_sys::OffsetNumber)>,
+    /* The updating transaction of the previous version */
+    prior_xmax: Option<pg_sys::TransactionId>,
+    visited: HashSet<(pg_sys::BlockNumber, pg_sys::OffsetNumber)>,
+}
+
+impl VersionChain {
+    pub(crate) unsafe fn open(
+        relid: pg_sys::Oid,
+        ctid: pg_sys::ItemPointerData,
+        snapshot_data: *mut pg_sys::SnapshotData,
+    ) -> VersionChain {
+        let table = OpenRelation::open(relid);
+
+        VersionChain {
+            relid,
+            nblocks: pg_sys::RelationGetNumberOfBlocksInFork(
+                table.rel,
+                pg_sys::ForkNumber_MAIN_FORKNUM,
+            ),
+            table,
+            snapshot_data,
+            next_tid: Some(item_pointer_get_both(ctid)),
+            prior_xmax: None,
+            visited: HashSet::new(),
+        }
+    }
+
+    pub(crate) unsafe fn tupdesc(&self) -> pg_sys::TupleDesc {
+        (*self.table.rel).rd_att
+    }
+}
+
+impl Iterator for VersionChain {
+    /* The ctid of the hop and its line pointer */
+    type Item = (pg_sys::ItemPointerData, LinePointer);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tid = self.next_tid.take()?;
+
+        if !self.visited.insert(tid) {
+            return None;
+        }
+
+        let (blkno, offnum) = tid;
+
+        /* The ctid of a tuple moved to another partition points to an invalid block */
+        if blkno >= self.nblocks {
+            return None;
+        }
+
+        unsafe {
+            let (relid, snapshot_data) = (self.relid, self.snapshot_data);
+            let line_pointer = with_locked_page(self.table.rel, blkno, |buffer, page| {
+                (1..=PageGetMaxOffsetNumber(page))
+                    .contains(&offnum)
+                    .then(|| read_line_pointer(relid, buffer, page, blkno, offnum, snapshot_data))
+            })?;
+
+            match (line_pointer.redirect_to, &line_pointer.tuple) {
+                (_, Some((htup, _))) => {
+                    let tup = (**htup).t_data;
+                    let xmin = pg_sys::HeapTupleHeaderGetXmin(tup);
+
+                    /* The line pointer was reused for a tuple of another row */
+                    if self.prior_xmax.is_some_and(|prior_xmax| prior_xmax != xmin) {
+                        return None;
+                    }
+
+                    /* Only updated tuples lead to further versions */
+                    let infomask = (*tup).t_infomask;
+                    if infomask as u32 & pg_sys::HEAP_XMAX_INVALID == 0
+                        && !HEAP_XMAX_IS_LOCKED_ONLY(infomask)
+                    {
+                        self.prior_xmax = Some(HeapTupleHeaderGetUpdateXid(tup));
+                        self.next_tid = Some(item_pointer_get_both((*tup).t_ctid));
+                    }
+                }
+                (Some(redirect_to), None) => self.next_tid = Some((blkno, redirect_to)),
+                (None, None) => {}
+            }
+
+            let mut ctid = pg_sys::ItemPointerData::default();
+            item_pointer_set_all(&mut ctid, blkno, offnum);
+
+            Some((ctid, line_pointer))
+        }
+    }
+}
+
+/*
+ * Follow the version chain of a row, starting at the given ctid, see VersionChain.
+ * The chain ends if the next tuple was not created by the updating transaction
+ * (e.g., because it has been pruned and the line pointer was reused). For each hop,
+ * the ctid, the header fields and the visibility under the snapshot are returned.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_chain(
+    table: &str,
+    ctid: pg_sys::ItemPointerData,
+    snapshot: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(hop, i32),
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(lp_state, String),
+        name!(xmin, Option<Xid8>),
+        name!(xmax, Option<Xid8>),
+        name!(infomask, Option<i32>),
+        name!(flags, Option<Vec<String>>),
+        name!(visible, Option<bool>),
+        name!(data, Option<String>),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let relid = get_relid_from_name(table);
+    let chain = VersionChain::open(relid, ctid, snapshot_data);
+    let tupdesc = chain.tupdesc();
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    TableIterator::new(chain.zip(1..).map(move |((hop_ctid, line_pointer), hop)| {
+        let state = line_pointer.state.to_string();
+
+        let Some((htup, visible)) = line_pointer.tuple else {
+            return (hop, hop_ctid, state, None, None, None, None, None, None);
+        };
+
+        let tup = (*htup).t_data;
+        let infomask = (*tup).t_infomask;
+
+        (
+            hop,
+            hop_ctid,
+            state,
+            Some(Xid8::from_xid(
+                pg_sys::HeapTupleHeaderGetXmin(tup),
+                next_xid,
+            )),
+            Some(Xid8::from_xid(HeapTupleHeaderGetXmax(tup), next_xid)),
+            Some(i32::from(infomask)),
+            Some(infomask_flags(infomask, (*tup).t_infomask2)),
+            Some(visible),
+            Some(format_tuple(
+                &decode_tuple(relid, htup, tupdesc),
+                output_format,
+            )),
+        )
+    }))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_scan_chains() {
+        pgrx::Spi::run("CREATE TABLE chain_test (id int, value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO chain_test VALUES (1, 1);").unwrap();
+        pgrx::Spi::run("UPDATE chain_test SET value = 2 WHERE id = 1;").unwrap();
+        pgrx::Spi::run("UPDATE chain_test SET value = 3 WHERE id = 1;").unwrap();
+
+        let position = pgrx::Spi::get_one::<i32>(
+            "SELECT chain_position FROM pg_debug_scan_chains('chain_test');",
+        )
+        .unwrap();
+        assert_eq!(position, Some(3));
+
+        let length = pgrx::Spi::get_one::<i32>(
+            "SELECT chain_length FROM pg_debug_scan_chains('chain_test');",
+        )
+        .unwrap();
+        assert_eq!(length, Some(3));
+    }
+
+    #[pgrx::pg_test]
+    fn test_chain() {
+        pgrx::Spi::run("CREATE TABLE update_chain_test (id int, value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO update_chain_test VALUES (1, 1);").unwrap();
+        pgrx::Spi::run("UPDATE update_chain_test SET value = 2 WHERE id = 1;").unwrap();
+        pgrx::Spi::run("UPDATE update_chain_test SET value = 3 WHERE id = 1;").unwrap();
+
+        let hops = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_chain('update_chain_test', '(0,1)');",
+        )
+        .unwrap();
+        assert_eq!(hops, Some(3));
+
+        let last_hop = pgrx::Spi::get_one::<String>(
+            "SELECT ctid::text FROM pg_debug_chain('update_chain_test', '(0,1)') WHERE visible;",
+        )
+        .unwrap();
+        assert_eq!(last_hop, Some("(0,3)".to_string()));
+    }
+}
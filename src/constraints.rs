@@ -0,0 +1,266 @@
+use std::ffi::CStr;
+
+use pgrx::prelude::*;
+
+use crate::heap::HeapTupleHeaderGetXmax;
+use crate::xid8::Xid8;
+use crate::{try_in_subtransaction, TupleStream};
+
+/* A prepared CHECK constraint of the table */
+struct CheckConstraint {
+    name: String,
+    expr: *mut pg_sys::ExprState,
+}
+
+/*
+ * Prepare the CHECK constraints of the relation for the evaluation, see
+ * ExecRelCheck(). Constraints that are marked as NOT VALID are included.
+ */
+unsafe fn prepare_check_constraints(
+    table_rel: pg_sys::Relation,
+    estate: *mut pg_sys::EState,
+) -> Vec<CheckConstraint> {
+    let constr = (*(*table_rel).rd_att).constr;
+
+    if constr.is_null() || (*constr).num_check == 0 {
+        return Vec::new();
+    }
+
+    std::slice::from_raw_parts((*constr).check, (*constr).num_check as usize)
+        .iter()
+        .map(|check| {
+            let expr = pg_sys::stringToNode(check.ccbin) as *mut pg_sys::Expr;
+
+            CheckConstraint {
+                name: CStr::from_ptr(check.ccname).to_str().unwrap().to_string(),
+                expr: pg_sys::ExecPrepareExpr(expr, estate),
+            }
+        })
+        .collect()
+}
+
+/*
+ * The executor state to evaluate the CHECK constraints of a table. It is released
+ * when the check is dropped, e.g., after all tuples are checked.
+ */
+struct ConstraintCheck {
+    estate: *mut pg_sys::EState,
+    econtext: *mut pg_sys::ExprContext,
+    slot: *mut pg_sys::TupleTableSlot,
+    check_constraints: Vec<CheckConstraint>,
+}
+
+impl ConstraintCheck {
+    unsafe fn new(table_rel: pg_sys::Relation) -> ConstraintCheck {
+        let estate = pg_sys::CreateExecutorState();
+        let econtext = pg_sys::MakePerTupleExprContext(estate);
+        let check_constraints = prepare_check_constraints(table_rel, estate);
+
+        let slot = pg_sys::MakeSingleTupleTableSlot(
+            (*table_rel).rd_att,
+            std::ptr::addr_of!(pg_sys::TTSOpsHeapTuple),
+        );
+        (*econtext).ecxt_scantuple = slot;
+
+        ConstraintCheck {
+            estate,
+            econtext,
+            slot,
+            check_constraints,
+        }
+    }
+
+    /*
+     * Evaluate the CHECK constraints against the tuple and return the names of the
+     * violated constraints. All constraints are evaluated in a single subtransaction.
+     * If an evaluation fails (e.g., due to a missing TOAST chunk of a dead version),
+     * each constraint is evaluated in its own subtransaction and the error of a
+     * failed constraint is returned with its name.
+     */
+    unsafe fn violations(&self, htup: *mut pg_sys::HeapTupleData) -> Vec<(String, Option<String>)> {
+        let econtext = self.econtext;
+        pg_sys::ExecStoreHeapTuple(htup, self.slot, false);
+
+        let exprs: Vec<*mut pg_sys::ExprState> = self
+            .check_constraints
+            .iter()
+            .map(|check_constraint| check_constraint.expr)
+            .collect();
+
+        let violations = match try_in_subtransaction(|| {
+            exprs
+                .iter()
+                .map(|expr| pg_sys::ExecCheck(*expr, econtext))
+                .collect::<Vec<bool>>()
+        }) {
+            Ok(satisfied) => self
+                .check_constraints
+                .iter()
+                .zip(satisfied)
+                .filter(|(_, satisfied)| !satisfied)
+                .map(|(check_constraint, _)| (check_constraint.name.clone(), None))
+                .collect(),
+            Err(_) => self
+                .check_constraints
+                .iter()
+                .filter_map(|check_constraint| {
+                    let expr = check_constraint.expr;
+
+                    match try_in_subtransaction(|| pg_sys::ExecCheck(expr, econtext)) {
+                        Ok(true) => None,
+                        Ok(false) => Some((check_constraint.name.clone(), None)),
+                        Err(error) => Some((check_constraint.name.clone(), Some(error))),
+                    }
+                })
+                .collect(),
+        };
+
+        pg_sys::ExecClearTuple(self.slot);
+        pg_sys::MemoryContextReset((*econtext).ecxt_per_tuple_memory);
+
+        violations
+    }
+}
+
+impl Drop for ConstraintCheck {
+    fn drop(&mut self) {
+        if crate::resources_releasable() {
+            unsafe {
+                pg_sys::ExecDropSingleTupleTableSlot(self.slot);
+                pg_sys::FreeExecutorState(self.estate);
+            }
+        }
+    }
+}
+
+/*
+ * Evaluate the CHECK and NOT NULL constraints of the table against all tuple
+ * versions (including dead ones) and return the violations. Old versions that
+ * violate constraints added later (e.g., with NOT VALID) can be found this way.
+ * If a CHECK constraint cannot be evaluated for a tuple, the error is returned
+ * with the constraint instead of aborting the check.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_check_constraints(
+    table: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(constraint_type, String),
+        name!(constraint_name, String),
+        name!(error, Option<String>),
+    ),
+> {
+    let mut tuples = TupleStream::open(
+        table,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        None,
+        None,
+        None,
+    );
+    let relid = (*tuples.table_rel).rd_id;
+    let constraint_check = ConstraintCheck::new(tuples.table_rel);
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    let rows = std::iter::from_fn(move || {
+        let (htup, tupdesc) = tuples.next_matching()?;
+
+        let violations = tuples.in_tuple_context(|| {
+            let mut violations = Vec::new();
+
+            /* NOT NULL constraints */
+            let nattrs = (*tupdesc).natts as usize;
+            for attr_form_data in (*tupdesc).attrs.as_slice(nattrs) {
+                if attr_form_data.attisdropped || !attr_form_data.attnotnull {
+                    continue;
+                }
+
+                if pg_sys::heap_attisnull(htup, attr_form_data.attnum.into(), tupdesc) {
+                    let colname_ptr = pg_sys::get_attname(relid, attr_form_data.attnum, false);
+                    let colname = CStr::from_ptr(colname_ptr).to_str().unwrap().to_string();
+                    violations.push(("not null".to_string(), colname, None));
+                }
+            }
+
+            /* CHECK constraints */
+            for (constraint_name, error) in constraint_check.violations(htup) {
+                violations.push(("check".to_string(), constraint_name, error));
+            }
+
+            violations
+        });
+
+        let ctid = (*htup).t_self;
+        let xmin = Xid8::from_xid(pg_sys::HeapTupleHeaderGetXmin((*htup).t_data), next_xid);
+        let xmax = Xid8::from_xid(HeapTupleHeaderGetXmax((*htup).t_data), next_xid);
+
+        Some(
+            violations
+                .into_iter()
+                .map(move |(constraint_type, constraint_name, error)| {
+                    (ctid, xmin, xmax, constraint_type, constraint_name, error)
+                }),
+        )
+    })
+    .flatten();
+
+    TableIterator::new(rows)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_check_constraints() {
+        pgrx::Spi::run("CREATE TABLE constraint_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO constraint_test VALUES (-1);").unwrap();
+        pgrx::Spi::run(
+            "ALTER TABLE constraint_test ADD CONSTRAINT positive CHECK (value > 0) NOT VALID;",
+        )
+        .unwrap();
+        pgrx::Spi::run("INSERT INTO constraint_test VALUES (5);").unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_check_constraints('constraint_test');",
+        )
+        .unwrap();
+        assert_eq!(count, Some(1));
+
+        let constraint_name = pgrx::Spi::get_one::<String>(
+            "SELECT constraint_name FROM pg_debug_check_constraints('constraint_test');",
+        )
+        .unwrap();
+        assert_eq!(constraint_name, Some("positive".to_string()));
+    }
+
+    #[pgrx::pg_test]
+    fn test_check_constraints_error() {
+        pgrx::Spi::run("CREATE TABLE constraint_error_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO constraint_error_test VALUES (0), (1), (-1);").unwrap();
+        pgrx::Spi::run(
+            "ALTER TABLE constraint_error_test ADD CONSTRAINT inverse CHECK (1 / value > 0) NOT VALID;",
+        )
+        .unwrap();
+
+        /* The division by zero is reported and the remaining tuples are checked */
+        let (ctid, error) = pgrx::Spi::get_two::<String, String>(
+            "SELECT ctid::text, error FROM pg_debug_check_constraints('constraint_error_test') WHERE error IS NOT NULL;",
+        )
+        .unwrap();
+        assert_eq!(ctid, Some("(0,1)".to_string()));
+        assert_eq!(error, Some("division by zero".to_string()));
+
+        let violated = pgrx::Spi::get_one::<String>(
+            "SELECT ctid::text FROM pg_debug_check_constraints('constraint_error_test') WHERE error IS NULL;",
+        )
+        .unwrap();
+        assert_eq!(violated, Some("(0,3)".to_string()));
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use pgrx::{itemptr::item_pointer_get_both, pg_sys::GetTransactionSnapshot, prelude::*};
+
+use crate::fingerprint::fingerprint;
+use crate::guc::INCLUDE_DROPPED_COLUMNS;
+use crate::heap::{with_locked_page, OpenRelation};
+use crate::output::{
+    quote_csv_field, to_copy_line, to_csv_line, to_json, to_typed_json, AttributeValue,
+};
+use crate::page::read_line_pointer;
+use crate::snapshot::get_snapshot_from_str;
+use crate::{decode_tuple, get_relid_from_name, ScannedTuple, TupleStream};
+
+/*
+ * Writing files on the server is restricted to superusers
+ */
+unsafe fn check_write_permission() {
+    if !pg_sys::superuser() {
+        error!("Must be superuser to dump a table into a file");
+    }
+}
+
+/*
+ * Create the given file on the server and write the lines into it as they are
+ * produced, so they do not have to be kept in memory. Returns the number of written
+ * lines.
+ */
+fn write_lines(path: &str, lines: impl Iterator<Item = String>) -> i64 {
+    let file =
+        File::create(path).unwrap_or_else(|err| error!("Unable to create file {path}: {err}"));
+    let mut writer = BufWriter::new(file);
+    let mut written = 0;
+
+    for line in lines {
+        writeln!(writer, "{line}")
+            .unwrap_or_else(|err| error!("Unable to write to file {path}: {err}"));
+        written += 1;
+    }
+
+    writer
+        .flush()
+        .unwrap_or_else(|err| error!("Unable to write to file {path}: {err}"));
+
+    written
+}
+
+/* The file formats of pg_debug_dump */
+#[derive(Clone, Copy, PartialEq)]
+enum FileFormat {
+    /* The CSV format of COPY with a header line */
+    Csv,
+    /* One JSON object with native JSON types per line */
+    Ndjson,
+    /* The text format of COPY */
+    Text,
+}
+
+impl FileFormat {
+    fn from_name(name: &str) -> FileFormat {
+        match name {
+            "csv" => FileFormat::Csv,
+            "ndjson" => FileFormat::Ndjson,
+            "text" => FileFormat::Text,
+            _ => error!("Unknown file format {name}, valid formats are csv, ndjson, and text"),
+        }
+    }
+
+    /*
+     * Convert the attributes of a tuple into a line of the format
+     */
+    fn line(self, attributes: &[AttributeValue]) -> String {
+        match self {
+            FileFormat::Csv => to_csv_line(attributes),
+            FileFormat::Ndjson => to_typed_json(attributes),
+            FileFormat::Text => to_copy_line(attributes),
+        }
+    }
+}
+
+/*
+ * Build the CSV header line from the names of the columns that are decoded by a
+ * scan of the relation
+ */
+unsafe fn csv_header(table_rel: pg_sys::Relation) -> String {
+    let relid = (*table_rel).rd_id;
+    let tupdesc = (*table_rel).rd_att;
+    let attrs = (*tupdesc).attrs.as_slice((*tupdesc).natts as usize);
+
+    let names: Vec<String> = attrs
+        .iter()
+        .filter(|attr_form_data| !attr_form_data.attisdropped || INCLUDE_DROPPED_COLUMNS.get())
+        .map(|attr_form_data| {
+            let name = CStr::from_ptr(pg_sys::get_attname(relid, attr_form_data.attnum, false));
+            quote_csv_field(&name.to_string_lossy())
+        })
+        .collect();
+
+    names.join(",")
+}
+
+/*
+ * Write the tuples that are visible under the given snapshot into a file on the
+ * server in the COPY text format (the default), as CSV, or as NDJSON. A file in the
+ * text or CSV format can be loaded with COPY ... FROM. The tuples are written while
+ * the table is scanned, so large tables can be exported without keeping the tuples
+ * in memory. Returns the number of written tuples.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_dump(
+    table: &str,
+    snapshot: Option<&str>,
+    path: &str,
+    format: default!(&str, "'text'"),
+) -> i64 {
+    check_write_permission();
+
+    let format = FileFormat::from_name(format);
+
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let tuples = TupleStream::open(table, snapshot_data, None, None, None);
+
+    /* The header line of the CSV format, which is written for empty tables as well */
+    let header = (format == FileFormat::Csv).then(|| csv_header(tuples.table_rel));
+    let header_lines = header.iter().count() as i64;
+
+    let lines = header
+        .into_iter()
+        .chain(tuples.map(|tuple| format.line(&tuple.attributes)));
+
+    write_lines(path, lines) - header_lines
+}
+
+/*
+ * Get the value of the key column of the given tuple
+ */
+fn tuple_key(tuple: &ScannedTuple, key_column: &str) -> String {
+    tuple
+        .attributes
+        .iter()
+        .find(|attribute| attribute.name == key_column)
+        .unwrap_or_else(|| error!("Key column {key_column} does not exist"))
+        .value
+        .clone()
+        .unwrap_or_else(|| error!("Key column {key_column} contains a NULL value"))
+}
+
+/* The ctid and the fingerprint of a tuple visible under the from snapshot */
+struct OldTuple {
+    ctid: pg_sys::ItemPointerData,
+    fingerprint: String,
+    /* A tuple with the same key is visible under the to snapshot */
+    matched: bool,
+}
+
+/*
+ * Index the old tuples by the value of their key column. Only the ctid and the
+ * fingerprint of a tuple are kept, so the old tuples are not held in memory.
+ */
+fn old_tuples_by_key(
+    old_tuples: impl Iterator<Item = ScannedTuple>,
+    key_column: &str,
+) -> HashMap<String, OldTuple> {
+    let mut keys = HashMap::new();
+
+    for tuple in old_tuples {
+        let key = tuple_key(&tuple, key_column);
+        let old_tuple = OldTuple {
+            ctid: tuple.ctid,
+            fingerprint: fingerprint(&to_json(&tuple.attributes)),
+            matched: false,
+        };
+
+        if keys.insert(key.clone(), old_tuple).is_some() {
+            error!("Key column {key_column} contains the duplicate value {key}");
+        }
+    }
+
+    keys
+}
+
+/*
+ * The differences between the old and the new tuples, matched by their key column.
+ * Each line starts with the operation (insert, update or delete) followed by the
+ * tuple in the COPY text format (the old tuple for deletes, the new tuple otherwise).
+ * The new tuples are compared with the fingerprints of the old tuples while they are
+ * scanned. Afterward, the deleted tuples are fetched by their ctid.
+ */
+struct DiffLines<N, F> {
+    old_tuples: HashMap<String, OldTuple>,
+    new_tuples: N,
+    key_column: String,
+    fetch_old: F,
+    /* The ctids of the deleted tuples, set once all new tuples are compared */
+    deleted: Option<std::vec::IntoIter<pg_sys::ItemPointerData>>,
+}
+
+impl<N, F> Iterator for DiffLines<N, F>
+where
+    N: Iterator<Item = ScannedTuple>,
+    F: FnMut(pg_sys::ItemPointerData) -> Vec<AttributeValue>,
+{
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if self.deleted.is_none() {
+            /* Inserted and updated tuples */
+            for tuple in self.new_tuples.by_ref() {
+                let key = tuple_key(&tuple, &self.key_column);
+
+                let operation = match self.old_tuples.get_mut(&key) {
+                    Some(old_tuple) if old_tuple.matched => error!(
+                        "Key column {} contains the duplicate value {key}",
+                        self.key_column
+                    ),
+                    Some(old_tuple) => {
+                        old_tuple.matched = true;
+
+                        if old_tuple.fingerprint == fingerprint(&to_json(&tuple.attributes)) {
+                            continue;
+                        }
+
+                        "update"
+                    }
+                    None => {
+                        /* Remember the key to detect duplicates of inserted tuples */
+                        let inserted = OldTuple {
+                            ctid: tuple.ctid,
+                            fingerprint: String::new(),
+                            matched: true,
+                        };
+                        self.old_tuples.insert(key, inserted);
+
+                        "insert"
+                    }
+                };
+
+                return Some(format!("{operation}\t{}", to_copy_line(&tuple.attributes)));
+            }
+
+            let mut deleted: Vec<pg_sys::ItemPointerData> = self
+                .old_tuples
+                .values()
+                .filter(|old_tuple| !old_tuple.matched)
+                .map(|old_tuple| old_tuple.ctid)
+                .collect();
+            deleted.sort_by_key(|ctid| item_pointer_get_both(*ctid));
+
+            self.deleted = Some(deleted.into_iter());
+        }
+
+        /* Deleted tuples */
+        let ctid = self.deleted.as_mut()?.next()?;
+        Some(format!("delete\t{}", to_copy_line(&(self.fetch_old)(ctid))))
+    }
+}
+
+/*
+ * Fetch the tuple with the given ctid that is visible under the snapshot
+ */
+unsafe fn fetch_tuple(
+    table_rel: pg_sys::Relation,
+    ctid: pg_sys::ItemPointerData,
+    snapshot_data: *mut pg_sys::SnapshotData,
+) -> Vec<AttributeValue> {
+    let relid = (*table_rel).rd_id;
+    let (blkno, offnum) = item_pointer_get_both(ctid);
+
+    let line_pointer = with_locked_page(table_rel, blkno, |buffer, page| {
+        read_line_pointer(relid, buffer, page, blkno, offnum, snapshot_data)
+    });
+
+    match line_pointer.tuple {
+        Some((htup, true)) => decode_tuple(relid, htup, (*table_rel).rd_att),
+        _ => error!("Tuple ({blkno},{offnum}) is no longer visible under the from snapshot"),
+    }
+}
+
+/*
+ * Write the differences between the tuples visible under the two snapshots into a
+ * file on the server. Tuples are matched by the given key column. If to_snapshot is
+ * NULL, the current transaction snapshot is used. Returns the number of written
+ * differences.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_dump_diff(
+    table: &str,
+    key_column: &str,
+    from_snapshot: &str,
+    to_snapshot: Option<&str>,
+    path: &str,
+) -> i64 {
+    check_write_permission();
+
+    let from_snapshot_data = get_snapshot_from_str(from_snapshot);
+    let to_snapshot_data = match to_snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let old_tuples = old_tuples_by_key(
+        TupleStream::open(table, from_snapshot_data, None, None, None),
+        key_column,
+    );
+
+    let table_rel = OpenRelation::open(get_relid_from_name(table));
+
+    let lines = DiffLines {
+        old_tuples,
+        new_tuples: TupleStream::open(table, to_snapshot_data, None, None, None),
+        key_column: key_column.to_string(),
+        fetch_old: |ctid| fetch_tuple(table_rel.rel, ctid, from_snapshot_data),
+        deleted: None,
+    };
+
+    write_lines(path, lines)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    use pgrx::itemptr::{item_pointer_get_both, item_pointer_set_all};
+
+    use crate::output::AttributeValue;
+    use crate::ScannedTuple;
+
+    /* Build a scanned tuple with the given offset and the columns id and value */
+    fn tuple(offnum: u16, id: &str, value: &str) -> ScannedTuple {
+        let attribute = |name: &str, value: &str| AttributeValue {
+            name: name.to_string(),
+            typid: pg_sys::TEXTOID,
+            value: Some(value.to_string()),
+        };
+
+        let mut ctid = pg_sys::ItemPointerData::default();
+        item_pointer_set_all(&mut ctid, 0, offnum);
+
+        ScannedTuple {
+            ctid,
+            next_ctid: ctid,
+            xmin: pg_sys::InvalidTransactionId,
+            xmax: pg_sys::InvalidTransactionId,
+            infomask: 0,
+            infomask2: 0,
+            attributes: vec![attribute("id", id), attribute("value", value)],
+            violations: Vec::new(),
+        }
+    }
+
+    #[pgrx::pg_test]
+    fn test_dump() {
+        let path = std::env::temp_dir().join("pg_debug_scan_dump_test.copy");
+        let path = path.to_str().unwrap();
+
+        pgrx::Spi::run("CREATE TABLE dump_test (id int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO dump_test VALUES (1, E'a\\tb'), (2, NULL);").unwrap();
+
+        let rows = pgrx::Spi::get_one::<i64>(&format!(
+            "SELECT pg_debug_dump('dump_test', NULL, '{path}');"
+        ))
+        .unwrap();
+        assert_eq!(rows, Some(2));
+
+        let content = std::fs::read_to_string(path).unwrap();
+        assert_eq!(content, "1\ta\\tb\n2\t\\N\n");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[pgrx::pg_test]
+    fn test_dump_formats() {
+        let path = std::env::temp_dir().join("pg_debug_scan_dump_formats_test.csv");
+        let path = path.to_str().unwrap();
+
+        pgrx::Spi::run("CREATE TABLE dump_formats_test (id int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO dump_formats_test VALUES (1, 'a,b'), (2, NULL);").unwrap();
+
+        let rows = pgrx::Spi::get_one::<i64>(&format!(
+            "SELECT pg_debug_dump('dump_formats_test', NULL, '{path}', 'csv');"
+        ))
+        .unwrap();
+        assert_eq!(rows, Some(2));
+
+        let content = std::fs::read_to_string(path).unwrap();
+        assert_eq!(content, "id,value\n1,\"a,b\"\n2,\n");
+
+        pgrx::Spi::run(&format!(
+            "SELECT pg_debug_dump('dump_formats_test', NULL, '{path}', 'ndjson');"
+        ))
+        .unwrap();
+
+        let content = std::fs::read_to_string(path).unwrap();
+        assert_eq!(
+            content,
+            "{\"id\":1,\"value\":\"a,b\"}\n{\"id\":2,\"value\":null}\n"
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[pgrx::pg_test]
+    fn test_dump_csv_empty_table() {
+        let path = std::env::temp_dir().join("pg_debug_scan_dump_empty_test.csv");
+        let path = path.to_str().unwrap();
+
+        pgrx::Spi::run("CREATE TABLE dump_empty_test (id int, \"Value\" text);").unwrap();
+
+        let rows = pgrx::Spi::get_one::<i64>(&format!(
+            "SELECT pg_debug_dump('dump_empty_test', NULL, '{path}', 'csv');"
+        ))
+        .unwrap();
+        assert_eq!(rows, Some(0));
+
+        let content = std::fs::read_to_string(path).unwrap();
+        assert_eq!(content, "id,Value\n");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[pgrx::pg_test]
+    fn test_diff_lines() {
+        let old_tuples = || vec![tuple(1, "1", "a"), tuple(2, "2", "b"), tuple(3, "3", "c")];
+        let new_tuples = vec![tuple(4, "2", "x"), tuple(5, "3", "c"), tuple(6, "4", "d")];
+
+        let lines = super::DiffLines {
+            old_tuples: super::old_tuples_by_key(old_tuples().into_iter(), "id"),
+            new_tuples: new_tuples.into_iter(),
+            key_column: "id".to_string(),
+            fetch_old: |ctid| {
+                old_tuples()
+                    .into_iter()
+                    .find(|old| item_pointer_get_both(old.ctid) == item_pointer_get_both(ctid))
+                    .unwrap()
+                    .attributes
+            },
+            deleted: None,
+        };
+
+        assert_eq!(
+            lines.collect::<Vec<_>>(),
+            vec!["update\t2\tx", "insert\t4\td", "delete\t1\ta"]
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::ffi::CString;
+
+use pgrx::prelude::*;
+
+/*
+ * The characters an operator can consist of, see scan.l. The minus sign is left out,
+ * so it is parsed as the sign of a negative value (e.g., value>=-1).
+ */
+const OPERATOR_CHARS: &str = "~!@#^&|`?+*/%<>=";
+
+/* A filter of the form column operator value, e.g., id = 42 */
+#[derive(PartialEq, Debug)]
+struct FilterSpec {
+    column: String,
+    operator: String,
+    value: String,
+}
+
+/*
+ * Parse a filter like id = 42, "Name" = 'foo bar' or value >= 10. Quoted identifiers
+ * and quoted literals are unquoted.
+ */
+fn parse_filter(filter: &str) -> Option<FilterSpec> {
+    let filter = filter.trim();
+
+    let (column, rest) = match filter.strip_prefix('"') {
+        Some(quoted) => {
+            let end = quoted.find('"')?;
+            (quoted[..end].to_string(), &quoted[end + 1..])
+        }
+        None => {
+            let end = filter
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(filter.len());
+            (filter[..end].to_lowercase(), &filter[end..])
+        }
+    };
+
+    let rest = rest.trim_start();
+    let operator_end = rest
+        .find(|c: char| !OPERATOR_CHARS.contains(c))
+        .unwrap_or(rest.len());
+    let (operator, value) = rest.split_at(operator_end);
+
+    let value = value.trim();
+    let value = match value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        Some(quoted) => quoted.replace("''", "'"),
+        None => value.to_string(),
+    };
+
+    if column.is_empty() || operator.is_empty() || value.is_empty() {
+        return None;
+    }
+
+    Some(FilterSpec {
+        column,
+        operator: operator.to_string(),
+        value,
+    })
+}
+
+/*
+ * Convert the given filter into a scan key of the relation. The scan key is checked
+ * by the heap scan before a tuple is returned, so only matching tuples are decoded.
+ */
+pub(crate) unsafe fn filter_scan_key(
+    table_rel: pg_sys::Relation,
+    filter: &str,
+) -> pg_sys::ScanKeyData {
+    let spec = parse_filter(filter).unwrap_or_else(|| {
+        error!("Unable to parse filter {filter}, expected column operator value")
+    });
+
+    let relid = (*table_rel).rd_id;
+    let column = CString::new(spec.column.as_str()).expect("Unable to convert to string");
+    let attnum = pg_sys::get_attnum(relid, column.as_ptr());
+
+    if attnum <= 0 {
+        error!("Column {} of filter {filter} does not exist", spec.column);
+    }
+
+    let tupdesc = (*table_rel).rd_att;
+    let attrs = (*tupdesc).attrs.as_slice((*tupdesc).natts as usize);
+    let attr_form_data = &attrs[attnum as usize - 1];
+
+    /* Convert the value into the type of the column */
+    let mut typinput = pg_sys::Oid::default();
+    let mut typioparam = pg_sys::Oid::default();
+    let value = CString::new(spec.value.as_str()).expect("Unable to convert to string");
+
+    pg_sys::getTypeInputInfo(attr_form_data.atttypid, &mut typinput, &mut typioparam);
+    let argument = pg_sys::OidInputFunctionCall(
+        typinput,
+        value.as_ptr() as *mut std::os::raw::c_char,
+        typioparam,
+        attr_form_data.atttypmod,
+    );
+
+    /* Look up the operator and the function that implements it */
+    let operator = CString::new(spec.operator.as_str()).expect("Unable to convert to string");
+    let operator_name = pg_sys::makeString(pg_sys::pstrdup(operator.as_ptr()));
+    let operator_list =
+        pg_sys::lappend(std::ptr::null_mut(), operator_name as *mut std::ffi::c_void);
+
+    let operator_oid = pg_sys::compatible_oper_opid(
+        operator_list,
+        attr_form_data.atttypid,
+        attr_form_data.atttypid,
+        false,
+    );
+
+    let mut scan_key = pg_sys::ScanKeyData::default();
+    pg_sys::ScanKeyEntryInitialize(
+        &mut scan_key,
+        0,
+        attnum,
+        pg_sys::InvalidStrategy as pg_sys::StrategyNumber,
+        pg_sys::InvalidOid,
+        attr_form_data.attcollation,
+        pg_sys::get_opcode(operator_oid),
+        argument,
+    );
+
+    scan_key
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_parse_filter() {
+        let spec = super::parse_filter("\"Name\" <> 'it''s'").unwrap();
+        assert_eq!(spec.column, "Name");
+        assert_eq!(spec.operator, "<>");
+        assert_eq!(spec.value, "it's");
+
+        let spec = super::parse_filter("value>=-1").unwrap();
+        assert_eq!(spec.column, "value");
+        assert_eq!(spec.operator, ">=");
+        assert_eq!(spec.value, "-1");
+
+        assert_eq!(super::parse_filter("id 42"), None);
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_filter() {
+        pgrx::Spi::run("CREATE TABLE scan_filter_test (id int, name text);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_filter_test VALUES (1, 'a'), (2, 'b'), (3, 'c');")
+            .unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('scan_filter_test', filter => 'id >= 2');",
+        )
+        .unwrap();
+        assert_eq!(count, Some(2));
+
+        let data = pgrx::Spi::get_one::<String>(
+            "SELECT data FROM pg_debug_scan('scan_filter_test', filter => 'name = ''c''');",
+        )
+        .unwrap();
+        assert_eq!(data, Some("{\"id\":\"3\",\"name\":\"c\"}".to_string()));
+    }
+}
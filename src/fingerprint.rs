@@ -0,0 +1,89 @@
+use pgrx::{pg_sys::GetTransactionSnapshot, prelude::*};
+use sha2::{Digest, Sha256};
+
+use crate::output::to_json;
+use crate::snapshot::get_snapshot_from_str;
+use crate::xid8::Xid8;
+use crate::TupleStream;
+
+/*
+ * Calculate the fingerprint of the given data as hex encoded sha256 hash
+ */
+pub(crate) fn fingerprint(data: &str) -> String {
+    Sha256::digest(data.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/*
+ * Perform a table scan and return a fingerprint of each tuple instead of its data.
+ * The fingerprint is the sha256 hash of the json output format, which has sorted keys
+ * and does not depend on the output settings. This allows to compare scans of
+ * different servers (e.g., primary and standby) without transferring the data.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_fingerprints(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    xids: default!(Option<Vec<i64>>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(fingerprint, String),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    let rows = TupleStream::open(table, snapshot_data, xids, None, None).map(move |tuple| {
+        (
+            tuple.ctid,
+            Xid8::from_xid(tuple.xmin, next_xid),
+            Xid8::from_xid(tuple.xmax, next_xid),
+            fingerprint(&to_json(&tuple.attributes)),
+        )
+    });
+
+    TableIterator::new(rows)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_scan_fingerprints() {
+        pgrx::Spi::run("CREATE TABLE fingerprint_test (id int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO fingerprint_test VALUES (1, 'a');").unwrap();
+
+        let fingerprint = pgrx::Spi::get_one::<String>(
+            "SELECT fingerprint FROM pg_debug_scan_fingerprints('fingerprint_test');",
+        )
+        .unwrap();
+
+        let expected = pgrx::Spi::get_one::<String>(
+            "SELECT encode(sha256(convert_to('{\"id\":\"1\",\"value\":\"a\"}', 'UTF8')), 'hex');",
+        )
+        .unwrap();
+        assert_eq!(fingerprint, expected);
+
+        /* The fingerprint does not depend on the output format */
+        pgrx::Spi::run("SET pg_debug_scan.output_format = 'typed-json';").unwrap();
+        let typed_fingerprint = pgrx::Spi::get_one::<String>(
+            "SELECT fingerprint FROM pg_debug_scan_fingerprints('fingerprint_test');",
+        )
+        .unwrap();
+        assert_eq!(typed_fingerprint, expected);
+    }
+}
@@ -0,0 +1,160 @@
+use pgrx::guc::{GucContext, GucFlags, GucRegistry, GucSetting};
+
+/* The format of the data column returned by the scan functions */
+pub(crate) static OUTPUT_FORMAT: GucSetting<Option<&'static str>> =
+    GucSetting::<Option<&'static str>>::new(Some("json"));
+
+/* The text that represents NULL values in the json and jsonb output formats */
+pub(crate) static NULL_REPRESENTATION: GucSetting<Option<&'static str>> =
+    GucSetting::<Option<&'static str>>::new(Some("NULL"));
+
+/* Return the raw bytes of dropped columns that are still stored in the tuples */
+pub(crate) static INCLUDE_DROPPED_COLUMNS: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/* Log the scanned table and the snapshot of each scan as INFO message */
+pub(crate) static LOG_SNAPSHOT: GucSetting<bool> = GucSetting::<bool>::new(true);
+
+/* The maximal size of the JSON data of a tuple, 0 disables the limit */
+pub(crate) static MAX_JSON_BYTES: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+/* Raise an error instead of a warning if a snapshot predates the frozen horizon */
+pub(crate) static STRICT_FROZEN_HORIZON: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/* Allow users with the SELECT privilege on a table to scan it, not only its owner */
+pub(crate) static ALLOW_SELECT_PRIVILEGE: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/* The maximal number of debug scans running at the same time, 0 disables the limit */
+pub(crate) static MAX_CONCURRENT_SCANS: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+/* Wait for a free scan slot instead of raising an error */
+pub(crate) static WAIT_FOR_SCAN_SLOT: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/* Fetch the values stored in the TOAST relation instead of reporting their TOAST pointer */
+pub(crate) static DETOAST: GucSetting<bool> = GucSetting::<bool>::new(true);
+
+/* The default maximal number of rows returned by pg_debug_scan, 0 disables the limit */
+pub(crate) static MAX_ROWS: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+/* Emit a notice with the progress of a scan every this many blocks, 0 disables the notices */
+pub(crate) static PROGRESS_INTERVAL: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+/*
+ * Register the configuration options of the extension
+ */
+pub(crate) fn init() {
+    GucRegistry::define_string_guc(
+        "pg_debug_scan.output_format",
+        "Format of the tuple data returned by pg_debug_scan.",
+        "Valid values are json, jsonb, typed-json and record-compatible.",
+        &OUTPUT_FORMAT,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        "pg_debug_scan.null_representation",
+        "Text that represents NULL values in the tuple data returned by pg_debug_scan.",
+        "Used by the json and jsonb output formats.",
+        &NULL_REPRESENTATION,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_bool_guc(
+        "pg_debug_scan.include_dropped_columns",
+        "Include the dropped columns in the tuple data returned by pg_debug_scan.",
+        "The type of a dropped column is unknown, so the raw bytes of its values are returned.",
+        &INCLUDE_DROPPED_COLUMNS,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_bool_guc(
+        "pg_debug_scan.log_snapshot",
+        "Log the scanned table and the snapshot of each scan.",
+        "The messages are emitted with the INFO log level.",
+        &LOG_SNAPSHOT,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "pg_debug_scan.max_json_bytes",
+        "Maximal size of the JSON tuple data returned by pg_debug_scan.",
+        "Larger JSON documents are truncated and marked with _truncated. 0 disables the limit.",
+        &MAX_JSON_BYTES,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::UNIT_BYTE,
+    );
+
+    GucRegistry::define_int_guc(
+        "pg_debug_scan.max_rows",
+        "Default maximal number of rows returned by pg_debug_scan.",
+        "Used if no max_rows argument is passed. 0 disables the limit.",
+        &MAX_ROWS,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "pg_debug_scan.progress_interval",
+        "Number of blocks after which the progress of a scan is reported.",
+        "The scanned blocks and the read and returned tuples are emitted as notice. 0 disables the notices.",
+        &PROGRESS_INTERVAL,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_bool_guc(
+        "pg_debug_scan.detoast",
+        "Fetch the values that are stored in the TOAST relation.",
+        "If disabled, the metadata of the TOAST pointer is returned instead of the value.",
+        &DETOAST,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_bool_guc(
+        "pg_debug_scan.strict_frozen_horizon",
+        "Raise an error if the snapshot of a scan predates the frozen horizon of the table.",
+        "By default, only a warning is emitted if the xmin of the snapshot precedes relfrozenxid.",
+        &STRICT_FROZEN_HORIZON,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_bool_guc(
+        "pg_debug_scan.allow_select_privilege",
+        "Allow users with the SELECT privilege on a table to scan it.",
+        "By default, only the owner of a table and superusers can scan it, since a scan also returns dead and historical tuples.",
+        &ALLOW_SELECT_PRIVILEGE,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "pg_debug_scan.max_concurrent_scans",
+        "Maximal number of debug scans running at the same time in the cluster.",
+        "Requires pg_debug_scan in shared_preload_libraries. 0 disables the limit.",
+        &MAX_CONCURRENT_SCANS,
+        0,
+        i32::MAX,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_bool_guc(
+        "pg_debug_scan.wait_for_scan_slot",
+        "Wait for a free scan slot if pg_debug_scan.max_concurrent_scans is reached.",
+        "By default, an error is raised if all scan slots are in use.",
+        &WAIT_FOR_SCAN_SLOT,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+}
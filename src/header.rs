@@ -0,0 +1,566 @@
+use pgrx::{
+    itemptr::item_pointer_get_both,
+    pg_sys::{AccessShareLock, CommandId, GetTransactionSnapshot, TransactionId},
+    prelude::*,
+};
+
+use crate::block::xid_age;
+use crate::heap::{
+    open_heap_relation, page_get_heap_tuple, with_locked_page, HeapTupleHeaderGetUpdateXid,
+    HeapTupleHeaderGetXmax, PageGetItemId, PageGetMaxOffsetNumber, HEAP_XMAX_IS_LOCKED_ONLY,
+};
+use crate::output::{format_tuple, OutputFormat};
+use crate::snapshot::get_snapshot_from_str;
+use crate::xid8::Xid8;
+use crate::{get_relid_from_name, TupleStream};
+
+/* The flags of t_infomask, see access/htup_details.h */
+const INFOMASK_FLAGS: [(u32, &str); 16] = [
+    (pg_sys::HEAP_HASNULL, "HEAP_HASNULL"),
+    (pg_sys::HEAP_HASVARWIDTH, "HEAP_HASVARWIDTH"),
+    (pg_sys::HEAP_HASEXTERNAL, "HEAP_HASEXTERNAL"),
+    (pg_sys::HEAP_HASOID_OLD, "HEAP_HASOID_OLD"),
+    (pg_sys::HEAP_XMAX_KEYSHR_LOCK, "HEAP_XMAX_KEYSHR_LOCK"),
+    (pg_sys::HEAP_COMBOCID, "HEAP_COMBOCID"),
+    (pg_sys::HEAP_XMAX_EXCL_LOCK, "HEAP_XMAX_EXCL_LOCK"),
+    (pg_sys::HEAP_XMAX_LOCK_ONLY, "HEAP_XMAX_LOCK_ONLY"),
+    (pg_sys::HEAP_XMIN_COMMITTED, "HEAP_XMIN_COMMITTED"),
+    (pg_sys::HEAP_XMIN_INVALID, "HEAP_XMIN_INVALID"),
+    (pg_sys::HEAP_XMAX_COMMITTED, "HEAP_XMAX_COMMITTED"),
+    (pg_sys::HEAP_XMAX_INVALID, "HEAP_XMAX_INVALID"),
+    (pg_sys::HEAP_XMAX_IS_MULTI, "HEAP_XMAX_IS_MULTI"),
+    (pg_sys::HEAP_UPDATED, "HEAP_UPDATED"),
+    (pg_sys::HEAP_MOVED_OFF, "HEAP_MOVED_OFF"),
+    (pg_sys::HEAP_MOVED_IN, "HEAP_MOVED_IN"),
+];
+
+/* The flags of t_infomask2 */
+const INFOMASK2_FLAGS: [(u32, &str); 3] = [
+    (pg_sys::HEAP_KEYS_UPDATED, "HEAP_KEYS_UPDATED"),
+    (pg_sys::HEAP_HOT_UPDATED, "HEAP_HOT_UPDATED"),
+    (pg_sys::HEAP_ONLY_TUPLE, "HEAP_ONLY_TUPLE"),
+];
+
+/*
+ * Decode the flags that are set in t_infomask and t_infomask2
+ */
+pub(crate) fn infomask_flags(infomask: u16, infomask2: u16) -> Vec<String> {
+    let infomask_flags = INFOMASK_FLAGS
+        .iter()
+        .filter(|(flag, _)| infomask as u32 & flag != 0);
+
+    let infomask2_flags = INFOMASK2_FLAGS
+        .iter()
+        .filter(|(flag, _)| infomask2 as u32 & flag != 0);
+
+    infomask_flags
+        .chain(infomask2_flags)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+/*
+ * The offset number that marks t_ctid as speculative insertion token, see
+ * storage/itemptr.h
+ */
+const SPEC_TOKEN_OFFSET_NUMBER: u16 = 0xfffe;
+
+/*
+ * Interpret the special values of xmin, which are misleading as raw numbers. Tuples
+ * frozen by VACUUM keep their xmin and are marked as frozen in the infomask.
+ */
+pub(crate) fn xmin_special(xmin: TransactionId, infomask: u16) -> Option<String> {
+    let frozen = pg_sys::HEAP_XMIN_COMMITTED | pg_sys::HEAP_XMIN_INVALID;
+
+    let special = match xmin {
+        pg_sys::InvalidTransactionId => "invalid",
+        pg_sys::BootstrapTransactionId => "bootstrap",
+        pg_sys::FrozenTransactionId => "frozen",
+        _ if infomask as u32 & frozen == frozen => "frozen",
+        _ => return None,
+    };
+
+    Some(special.to_string())
+}
+
+/*
+ * Interpret the special values of xmax. While a tuple is inserted speculatively
+ * (INSERT ... ON CONFLICT), t_ctid holds the speculative insertion token instead of
+ * a tuple id and xmax is not set.
+ */
+pub(crate) fn xmax_special(
+    xmax: TransactionId,
+    infomask: u16,
+    t_ctid: pg_sys::ItemPointerData,
+) -> Option<String> {
+    let (token, offnum) = item_pointer_get_both(t_ctid);
+
+    if offnum == SPEC_TOKEN_OFFSET_NUMBER {
+        return Some(format!("speculative token {token}"));
+    }
+
+    let special = match xmax {
+        _ if infomask as u32 & pg_sys::HEAP_XMAX_INVALID != 0 => "invalid",
+        pg_sys::InvalidTransactionId => "invalid",
+        _ if infomask as u32 & pg_sys::HEAP_XMAX_IS_MULTI != 0 => "multixact",
+        _ if HEAP_XMAX_IS_LOCKED_ONLY(infomask) => "lock only",
+        pg_sys::BootstrapTransactionId => "bootstrap",
+        pg_sys::FrozenTransactionId => "frozen",
+        _ => return None,
+    };
+
+    Some(special.to_string())
+}
+
+/*
+ * Return all header fields of a single tuple. Only the block of the tuple is read,
+ * no table scan is performed. The raw values are returned (e.g., xmax is not
+ * resolved for multixacts and t_cid may contain a combo command id). The special
+ * values of xmin and xmax are interpreted in xmin_special and xmax_special.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_tuple_header(
+    table: &str,
+    tid: pg_sys::ItemPointerData,
+) -> TableIterator<
+    'static,
+    (
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(xmin_special, Option<String>),
+        name!(xmax_special, Option<String>),
+        name!(cid, i64),
+        name!(infomask, i32),
+        name!(infomask2, i32),
+        name!(flags, Vec<String>),
+        name!(hoff, i32),
+        name!(natts, i32),
+        name!(t_ctid, pg_sys::ItemPointerData),
+    ),
+> {
+    let (blkno, offnum) = item_pointer_get_both(tid);
+
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+
+    let nblocks =
+        pg_sys::RelationGetNumberOfBlocksInFork(table_rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+    if blkno >= nblocks {
+        error!("Block {blkno} is out of range, the table has {nblocks} blocks");
+    }
+
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    let header = with_locked_page(table_rel, blkno, |_buffer, page| {
+        if offnum < 1 || offnum > PageGetMaxOffsetNumber(page) {
+            error!("Offset {offnum} is out of range in block {blkno}");
+        }
+
+        if (*PageGetItemId(page, offnum)).lp_flags() != pg_sys::LP_NORMAL {
+            error!("Line pointer ({blkno},{offnum}) does not point to a tuple");
+        }
+
+        /* Copy the fields, since the page is released afterwards */
+        let tup = page_get_heap_tuple(relid, page, blkno, offnum).t_data;
+        let infomask = (*tup).t_infomask;
+        let infomask2 = (*tup).t_infomask2;
+
+        let xmin = (*tup).t_choice.t_heap.t_xmin;
+        let xmax = (*tup).t_choice.t_heap.t_xmax;
+
+        (
+            Xid8::from_xid(xmin, next_xid),
+            Xid8::from_xid(xmax, next_xid),
+            xmin_special(xmin, infomask),
+            xmax_special(xmax, infomask, (*tup).t_ctid),
+            i64::from((*tup).t_choice.t_heap.t_field3.t_cid),
+            i32::from(infomask),
+            i32::from(infomask2),
+            infomask_flags(infomask, infomask2),
+            i32::from((*tup).t_hoff),
+            (infomask2 as u32 & pg_sys::HEAP_NATTS_MASK) as i32,
+            (*tup).t_ctid,
+        )
+    });
+
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+
+    TableIterator::once(header)
+}
+
+/*
+ * Perform a table scan and return the decoded t_infomask and t_infomask2 flags of
+ * each visible tuple together with the interpretation of special xmin and xmax values.
+ * The flags are read after the visibility check, so they include the hint bits set by
+ * the scan itself.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_flags(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    xids: default!(Option<Vec<i64>>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(xmin_special, Option<String>),
+        name!(xmax_special, Option<String>),
+        name!(flags, Vec<String>),
+        name!(data, String),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    let rows = TupleStream::open(table, snapshot_data, xids, None, None).map(move |tuple| {
+        (
+            tuple.ctid,
+            Xid8::from_xid(tuple.xmin, next_xid),
+            Xid8::from_xid(tuple.xmax, next_xid),
+            xmin_special(tuple.xmin, tuple.infomask),
+            xmax_special(tuple.xmax, tuple.infomask, tuple.next_ctid),
+            infomask_flags(tuple.infomask, tuple.infomask2),
+            format_tuple(&tuple.attributes, output_format),
+        )
+    });
+
+    TableIterator::new(rows)
+}
+
+/*
+ * Get the command id that inserted the tuple. Command ids are only meaningful
+ * within the inserting transaction, and combo command ids can only be resolved by
+ * the backend that created them. So, the command id is only returned for tuples
+ * inserted by the current transaction.
+ */
+unsafe fn tuple_cmin(tup: pg_sys::HeapTupleHeader) -> Option<CommandId> {
+    let xmin = pg_sys::HeapTupleHeaderGetXmin(tup);
+
+    pg_sys::TransactionIdIsCurrentTransactionId(xmin).then(|| pg_sys::HeapTupleHeaderGetCmin(tup))
+}
+
+/*
+ * Get the command id that deleted the tuple, see tuple_cmin. Tuples that are only
+ * locked have no cmax.
+ */
+unsafe fn tuple_cmax(tup: pg_sys::HeapTupleHeader) -> Option<CommandId> {
+    let infomask = (*tup).t_infomask;
+
+    if infomask as u32 & pg_sys::HEAP_XMAX_INVALID != 0 || HEAP_XMAX_IS_LOCKED_ONLY(infomask) {
+        return None;
+    }
+
+    let xmax = HeapTupleHeaderGetUpdateXid(tup);
+
+    pg_sys::TransactionIdIsCurrentTransactionId(xmax).then(|| pg_sys::HeapTupleHeaderGetCmax(tup))
+}
+
+/*
+ * Perform a table scan and return the command ids that inserted (cmin) and deleted
+ * (cmax) each visible tuple. If a tuple was inserted and deleted by the same
+ * transaction, both command ids are stored as a combo command id, which is
+ * resolved. Command ids are only returned for tuples modified by the current
+ * transaction, since they can not be resolved for other transactions.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_cids(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    xids: default!(Option<Vec<i64>>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(cmin, Option<i64>),
+        name!(cmax, Option<i64>),
+        name!(combocid, bool),
+        name!(data, String),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut tuples = TupleStream::open(table, snapshot_data, xids, None, None);
+
+    let rows = std::iter::from_fn(move || {
+        let (htup, tupdesc) = tuples.next_matching()?;
+        let tup = (*htup).t_data;
+
+        Some((
+            (*htup).t_self,
+            Xid8::from_xid(pg_sys::HeapTupleHeaderGetXmin(tup), next_xid),
+            Xid8::from_xid(HeapTupleHeaderGetXmax(tup), next_xid),
+            tuple_cmin(tup).map(i64::from),
+            tuple_cmax(tup).map(i64::from),
+            (*tup).t_infomask as u32 & pg_sys::HEAP_COMBOCID != 0,
+            format_tuple(&tuples.decode(htup, tupdesc).attributes, output_format),
+        ))
+    });
+
+    TableIterator::new(rows)
+}
+
+/*
+ * Perform a table scan and return the freeze state of each visible tuple: whether
+ * the tuple is frozen, the age of its xmin, and whether the xmin of an unfrozen
+ * tuple precedes the relfrozenxid of the table. VACUUM freezes all tuples older than
+ * relfrozenxid, so such a tuple indicates a corruption. The raw xmin is returned, so
+ * frozen tuples still show the xid that inserted them.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_freeze(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    xids: default!(Option<Vec<i64>>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(frozen, bool),
+        name!(xmin_age, Option<i32>),
+        name!(xmin_before_relfrozenxid, bool),
+        name!(data, String),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut tuples = TupleStream::open(table, snapshot_data, xids, None, None);
+    let relfrozenxid = (*(*tuples.table_rel).rd_rel).relfrozenxid;
+
+    let rows = std::iter::from_fn(move || {
+        let (htup, tupdesc) = tuples.next_matching()?;
+        let tup = (*htup).t_data;
+        let xmin = (*tup).t_choice.t_heap.t_xmin;
+
+        let frozen = pg_sys::HeapTupleHeaderFrozen(tup);
+        let before_relfrozenxid = !frozen
+            && xmin >= pg_sys::FirstNormalTransactionId
+            && relfrozenxid >= pg_sys::FirstNormalTransactionId
+            && pg_sys::TransactionIdPrecedes(xmin, relfrozenxid);
+
+        Some((
+            (*htup).t_self,
+            Xid8::from_xid(xmin, next_xid),
+            Xid8::from_xid(HeapTupleHeaderGetXmax(tup), next_xid),
+            frozen,
+            if frozen { None } else { xid_age(xmin) },
+            before_relfrozenxid,
+            format_tuple(&tuples.decode(htup, tupdesc).attributes, output_format),
+        ))
+    });
+
+    TableIterator::new(rows)
+}
+
+/*
+ * Perform a table scan and return the raw bytes of each visible tuple (the tuple header
+ * and the data) for an offline analysis. With raw_only, the tuples are not decoded, so
+ * the scan also returns tuples whose values can not be decoded (e.g., because the output
+ * function fails on corrupted data).
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_raw(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    xids: default!(Option<Vec<i64>>, "NULL"),
+    raw_only: default!(bool, false),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(raw, Vec<u8>),
+        name!(data, Option<String>),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut tuples = TupleStream::open(table, snapshot_data, xids, None, None);
+
+    let rows = std::iter::from_fn(move || {
+        let (htup, tupdesc) = tuples.next_matching()?;
+        let tup = (*htup).t_data;
+
+        let raw = std::slice::from_raw_parts(tup as *const u8, (*htup).t_len as usize).to_vec();
+        let data = (!raw_only)
+            .then(|| format_tuple(&tuples.decode(htup, tupdesc).attributes, output_format));
+
+        Some((
+            (*htup).t_self,
+            Xid8::from_xid(pg_sys::HeapTupleHeaderGetXmin(tup), next_xid),
+            Xid8::from_xid(HeapTupleHeaderGetXmax(tup), next_xid),
+            raw,
+            data,
+        ))
+    });
+
+    TableIterator::new(rows)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_tuple_header() {
+        pgrx::Spi::run("CREATE TABLE header_test (id int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO header_test VALUES (1, NULL);").unwrap();
+
+        let natts = pgrx::Spi::get_one::<i32>(
+            "SELECT natts FROM pg_debug_tuple_header('header_test', '(0,1)');",
+        )
+        .unwrap();
+        assert_eq!(natts, Some(2));
+
+        let flags = pgrx::Spi::get_one::<Vec<String>>(
+            "SELECT flags FROM pg_debug_tuple_header('header_test', '(0,1)');",
+        )
+        .unwrap();
+        assert_eq!(
+            flags,
+            Some(vec![
+                "HEAP_HASNULL".to_string(),
+                "HEAP_XMAX_INVALID".to_string()
+            ])
+        );
+    }
+
+    #[pgrx::pg_test]
+    fn test_tuple_header_special() {
+        pgrx::Spi::run("CREATE TABLE special_test (id int);").unwrap();
+        pgrx::Spi::run("INSERT INTO special_test VALUES (1);").unwrap();
+
+        let xmax_special = pgrx::Spi::get_one::<String>(
+            "SELECT xmax_special FROM pg_debug_tuple_header('special_test', '(0,1)');",
+        )
+        .unwrap();
+        assert_eq!(xmax_special, Some("invalid".to_string()));
+
+        let xmin_special = pgrx::Spi::get_one::<String>(
+            "SELECT xmin_special FROM pg_debug_tuple_header('special_test', '(0,1)');",
+        )
+        .unwrap();
+        assert_eq!(xmin_special, None);
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_flags() {
+        pgrx::Spi::run("CREATE TABLE flags_test (id int PRIMARY KEY, value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO flags_test VALUES (1, 1);").unwrap();
+        pgrx::Spi::run("UPDATE flags_test SET value = 2;").unwrap();
+
+        let flags = pgrx::Spi::get_one::<Vec<String>>(
+            "SELECT flags FROM pg_debug_scan_flags('flags_test');",
+        )
+        .unwrap();
+        assert_eq!(
+            flags,
+            Some(vec![
+                "HEAP_XMAX_INVALID".to_string(),
+                "HEAP_UPDATED".to_string(),
+                "HEAP_ONLY_TUPLE".to_string()
+            ])
+        );
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_cids() {
+        pgrx::Spi::run("CREATE TABLE cids_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO cids_test VALUES (1);").unwrap();
+
+        let cmin = pgrx::Spi::get_one::<String>("SELECT cmin::text FROM cids_test;")
+            .unwrap()
+            .expect("unable to get cmin");
+
+        /* Delete the tuple in an aborted subtransaction, which stores a combo command id */
+        pgrx::Spi::run(
+            "DO $$ BEGIN
+                DELETE FROM cids_test;
+                RAISE EXCEPTION 'abort';
+            EXCEPTION WHEN OTHERS THEN NULL;
+            END $$;",
+        )
+        .unwrap();
+
+        let combocid =
+            pgrx::Spi::get_one::<bool>("SELECT combocid FROM pg_debug_scan_cids('cids_test');")
+                .unwrap();
+        assert_eq!(combocid, Some(true));
+
+        let resolved_cmin =
+            pgrx::Spi::get_one::<i64>("SELECT cmin FROM pg_debug_scan_cids('cids_test');").unwrap();
+        assert_eq!(resolved_cmin.map(|cmin| cmin.to_string()), Some(cmin));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_freeze() {
+        pgrx::Spi::run("CREATE TABLE freeze_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO freeze_test VALUES (1);").unwrap();
+
+        let frozen =
+            pgrx::Spi::get_one::<bool>("SELECT frozen FROM pg_debug_scan_freeze('freeze_test');")
+                .unwrap();
+        assert_eq!(frozen, Some(false));
+
+        /* The tuple was inserted by the current transaction */
+        let xmin_age = pgrx::Spi::get_one::<i32>(
+            "SELECT xmin_age FROM pg_debug_scan_freeze('freeze_test') WHERE NOT xmin_before_relfrozenxid;",
+        )
+        .unwrap();
+        assert_eq!(xmin_age, Some(0));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_raw() {
+        pgrx::Spi::run("CREATE TABLE raw_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO raw_test VALUES (1);").unwrap();
+
+        /* The 24 bytes of the aligned header followed by the int value */
+        let raw = pgrx::Spi::get_one::<Vec<u8>>(
+            "SELECT raw FROM pg_debug_scan_raw('raw_test', raw_only => true);",
+        )
+        .unwrap()
+        .expect("unable to get the raw tuple");
+        assert_eq!(raw.len(), 28);
+        assert_eq!(raw[24..], 1i32.to_ne_bytes());
+
+        let data = pgrx::Spi::get_one::<String>(
+            "SELECT data FROM pg_debug_scan_raw('raw_test', raw_only => true);",
+        )
+        .unwrap();
+        assert_eq!(data, None);
+    }
+}
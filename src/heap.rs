@@ -0,0 +1,578 @@
+/*
+ * Custom implementations of the PostgreSQL heap tuple and page macros that are
+ * currently not defined in pgrx.
+ */
+use std::ffi::CStr;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use pgrx::{pg_sys, prelude::*};
+
+use crate::guc::ALLOW_SELECT_PRIVILEGE;
+use crate::regclass::RegClass;
+
+/* The number of blocks that are prefetched ahead of the page-by-page scans */
+const PREFETCH_DISTANCE: pg_sys::BlockNumber = 16;
+
+/* The buffer ring of this backend used to read the pages, see bulk_read_strategy */
+static BULK_READ_STRATEGY: AtomicPtr<pg_sys::BufferAccessStrategyData> =
+    AtomicPtr::new(std::ptr::null_mut());
+
+/* Row-level security applies to the current user, see CheckEnableRlsResult in utils/rls.h */
+const RLS_ENABLED: i32 = 2;
+
+#[pg_guard]
+extern "C" {
+    /* Not exported by pgrx, see utils/rls.h */
+    fn check_enable_rls(relid: pg_sys::Oid, check_as_user: pg_sys::Oid, no_error: bool) -> i32;
+}
+
+/*
+ * Custom implementation for HeapTupleHeaderGetXmax. This function is currently not defined in pgrx.
+ */
+#[inline(always)]
+#[allow(non_snake_case)]
+pub(crate) unsafe fn HeapTupleHeaderGetXmax(
+    tup: *const pg_sys::HeapTupleHeaderData,
+) -> pg_sys::TransactionId {
+    unsafe {
+        // SAFETY:  caller has asserted `tup` is a valid HeapTupleHeader pointer
+        if pg_sys::HeapTupleHeaderFrozen(tup) {
+            pg_sys::FrozenTransactionId
+        } else {
+            (*tup).t_choice.t_heap.t_xmax
+        }
+    }
+}
+
+/*
+ * Custom implementation for HeapTupleHeaderGetRawXmax.
+ */
+#[inline(always)]
+#[allow(non_snake_case)]
+pub(crate) unsafe fn HeapTupleHeaderGetRawXmax(
+    tup: *const pg_sys::HeapTupleHeaderData,
+) -> pg_sys::TransactionId {
+    (*tup).t_choice.t_heap.t_xmax
+}
+
+/*
+ * Custom implementation for HeapTupleHeaderGetUpdateXid. For multixacts, the
+ * xid of the updating member is returned.
+ */
+#[allow(non_snake_case)]
+pub(crate) unsafe fn HeapTupleHeaderGetUpdateXid(
+    tup: *const pg_sys::HeapTupleHeaderData,
+) -> pg_sys::TransactionId {
+    let infomask = (*tup).t_infomask as u32;
+
+    if infomask & pg_sys::HEAP_XMAX_INVALID == 0
+        && infomask & pg_sys::HEAP_XMAX_IS_MULTI != 0
+        && infomask & pg_sys::HEAP_XMAX_LOCK_ONLY == 0
+    {
+        pg_sys::HeapTupleGetUpdateXid(tup as *mut pg_sys::HeapTupleHeaderData)
+    } else {
+        HeapTupleHeaderGetRawXmax(tup)
+    }
+}
+
+/*
+ * Custom implementation for HeapTupleHeaderIsHeapOnly.
+ */
+#[inline(always)]
+#[allow(non_snake_case)]
+pub(crate) unsafe fn HeapTupleHeaderIsHeapOnly(tup: *const pg_sys::HeapTupleHeaderData) -> bool {
+    (*tup).t_infomask2 as u32 & pg_sys::HEAP_ONLY_TUPLE != 0
+}
+
+/*
+ * Custom implementation for HeapTupleHeaderIsHotUpdated.
+ */
+#[inline(always)]
+#[allow(non_snake_case)]
+pub(crate) unsafe fn HeapTupleHeaderIsHotUpdated(tup: *const pg_sys::HeapTupleHeaderData) -> bool {
+    (*tup).t_infomask2 as u32 & pg_sys::HEAP_HOT_UPDATED != 0
+        && (*tup).t_infomask as u32 & pg_sys::HEAP_XMAX_INVALID == 0
+        && (*tup).t_infomask as u32 & pg_sys::HEAP_XMIN_INVALID == 0
+}
+
+/*
+ * Custom implementation for HEAP_XMAX_IS_LOCKED_ONLY.
+ */
+#[allow(non_snake_case)]
+pub(crate) fn HEAP_XMAX_IS_LOCKED_ONLY(infomask: u16) -> bool {
+    let infomask = infomask as u32;
+    /* HEAP_LOCK_MASK, HEAP_XMAX_SHR_LOCK is a combination of both flags */
+    let lock_mask = pg_sys::HEAP_XMAX_EXCL_LOCK | pg_sys::HEAP_XMAX_KEYSHR_LOCK;
+
+    infomask & pg_sys::HEAP_XMAX_LOCK_ONLY != 0
+        || infomask & (pg_sys::HEAP_XMAX_IS_MULTI | lock_mask) == pg_sys::HEAP_XMAX_EXCL_LOCK
+}
+
+/*
+ * Check if the tuple was deleted (or updated) by the same transaction that
+ * inserted it. Such a tuple never becomes visible to other transactions.
+ */
+pub(crate) unsafe fn deleted_by_inserting_transaction(
+    tup: *const pg_sys::HeapTupleHeaderData,
+) -> bool {
+    let infomask = (*tup).t_infomask;
+
+    if infomask as u32 & pg_sys::HEAP_XMAX_INVALID != 0 || HEAP_XMAX_IS_LOCKED_ONLY(infomask) {
+        return false;
+    }
+
+    HeapTupleHeaderGetUpdateXid(tup)
+        == pg_sys::HeapTupleHeaderGetXmin(tup as *mut pg_sys::HeapTupleHeaderData)
+}
+
+/*
+ * Check if the transaction that inserted the tuple has aborted. In contrast to
+ * the hint bits, this also works for tuples that were not yet hinted.
+ */
+pub(crate) unsafe fn xmin_aborted(tup: *const pg_sys::HeapTupleHeaderData) -> bool {
+    let infomask = (*tup).t_infomask as u32;
+
+    if infomask & pg_sys::HEAP_XMIN_COMMITTED != 0 && infomask & pg_sys::HEAP_XMIN_INVALID == 0 {
+        return false;
+    }
+
+    if infomask & pg_sys::HEAP_XMIN_INVALID != 0 && infomask & pg_sys::HEAP_XMIN_COMMITTED == 0 {
+        return true;
+    }
+
+    let xmin = pg_sys::HeapTupleHeaderGetXmin(tup as *mut pg_sys::HeapTupleHeaderData);
+
+    !pg_sys::TransactionIdIsCurrentTransactionId(xmin)
+        && !pg_sys::TransactionIdIsInProgress(xmin)
+        && !pg_sys::TransactionIdDidCommit(xmin)
+}
+
+/*
+ * Custom implementation for BufferGetPage.
+ */
+#[allow(non_snake_case)]
+pub(crate) unsafe fn BufferGetPage(buffer: pg_sys::Buffer) -> pg_sys::Page {
+    if buffer < 0 {
+        /* Local buffer of a temporary relation */
+        *pg_sys::LocalBufferBlockPointers.offset((-buffer - 1) as isize) as pg_sys::Page
+    } else {
+        pg_sys::BufferBlocks.add((buffer as usize - 1) * pg_sys::BLCKSZ as usize) as pg_sys::Page
+    }
+}
+
+/*
+ * Custom implementation for PageGetLSN.
+ */
+#[allow(non_snake_case)]
+pub(crate) unsafe fn PageGetLSN(page: pg_sys::Page) -> pg_sys::XLogRecPtr {
+    let header = page as pg_sys::PageHeader;
+    let lsn = (*header).pd_lsn;
+
+    ((lsn.xlogid as pg_sys::XLogRecPtr) << 32) | lsn.xrecoff as pg_sys::XLogRecPtr
+}
+
+/*
+ * Custom implementation for PageGetMaxOffsetNumber.
+ */
+#[allow(non_snake_case)]
+pub(crate) unsafe fn PageGetMaxOffsetNumber(page: pg_sys::Page) -> pg_sys::OffsetNumber {
+    let header = page as pg_sys::PageHeader;
+    let lower = (*header).pd_lower as usize;
+    let header_size = size_of::<pg_sys::PageHeaderData>();
+
+    if lower <= header_size {
+        0
+    } else {
+        ((lower - header_size) / size_of::<pg_sys::ItemIdData>()) as pg_sys::OffsetNumber
+    }
+}
+
+/*
+ * Custom implementation for PageGetItemId. Offset numbers start at 1.
+ */
+#[allow(non_snake_case)]
+pub(crate) unsafe fn PageGetItemId(
+    page: pg_sys::Page,
+    offnum: pg_sys::OffsetNumber,
+) -> *mut pg_sys::ItemIdData {
+    let header = page as pg_sys::PageHeader;
+    (*header).pd_linp.as_mut_ptr().add(offnum as usize - 1)
+}
+
+/*
+ * Custom implementation for PageGetItem, returning the heap tuple header stored
+ * at the given line pointer.
+ */
+#[allow(non_snake_case)]
+pub(crate) unsafe fn PageGetItem(
+    page: pg_sys::Page,
+    itemid: *const pg_sys::ItemIdData,
+) -> pg_sys::HeapTupleHeader {
+    page.add((*itemid).lp_off() as usize) as pg_sys::HeapTupleHeader
+}
+
+/*
+ * Build a HeapTupleData for the normal line pointer `offnum` on the given page
+ */
+pub(crate) unsafe fn page_get_heap_tuple(
+    relid: pg_sys::Oid,
+    page: pg_sys::Page,
+    blkno: pg_sys::BlockNumber,
+    offnum: pg_sys::OffsetNumber,
+) -> pg_sys::HeapTupleData {
+    let itemid = PageGetItemId(page, offnum);
+
+    let mut htup = pg_sys::HeapTupleData {
+        t_len: (*itemid).lp_len(),
+        t_self: pg_sys::ItemPointerData::default(),
+        t_tableOid: relid,
+        t_data: PageGetItem(page, itemid),
+    };
+    pgrx::itemptr::item_pointer_set_all(&mut htup.t_self, blkno, offnum);
+
+    htup
+}
+
+/*
+ * Build a HeapTupleData for all normal line pointers of the given page
+ */
+pub(crate) unsafe fn page_get_heap_tuples(
+    relid: pg_sys::Oid,
+    page: pg_sys::Page,
+    blkno: pg_sys::BlockNumber,
+) -> Vec<pg_sys::HeapTupleData> {
+    (1..=PageGetMaxOffsetNumber(page))
+        .filter(|offnum| (*PageGetItemId(page, *offnum)).lp_flags() == pg_sys::LP_NORMAL)
+        .map(|offnum| page_get_heap_tuple(relid, page, blkno, offnum))
+        .collect()
+}
+
+/*
+ * Get a description of a relation kind for error messages
+ */
+fn relkind_name(relkind: u8) -> &'static str {
+    match relkind {
+        pg_sys::RELKIND_VIEW => "view",
+        pg_sys::RELKIND_PARTITIONED_TABLE => "partitioned table",
+        pg_sys::RELKIND_FOREIGN_TABLE => "foreign table",
+        pg_sys::RELKIND_SEQUENCE => "sequence",
+        pg_sys::RELKIND_COMPOSITE_TYPE => "composite type",
+        pg_sys::RELKIND_INDEX | pg_sys::RELKIND_PARTITIONED_INDEX => "index",
+        _ => "relation",
+    }
+}
+
+/*
+ * Check if the given role owns the relation
+ */
+unsafe fn is_relation_owner(relid: pg_sys::Oid, roleid: pg_sys::Oid) -> bool {
+    #[cfg(any(feature = "pg12", feature = "pg13", feature = "pg14", feature = "pg15"))]
+    {
+        pg_sys::pg_class_ownercheck(relid, roleid)
+    }
+    #[cfg(feature = "pg16")]
+    {
+        pg_sys::object_ownercheck(pg_sys::RelationRelationId, relid, roleid)
+    }
+}
+
+/*
+ * Get the table that owns the given TOAST table
+ */
+fn toast_parent(toast_relid: pg_sys::Oid) -> Option<pg_sys::Oid> {
+    Spi::get_one_with_args::<pg_sys::Oid>(
+        "SELECT oid FROM pg_catalog.pg_class WHERE reltoastrelid = $1",
+        vec![(PgBuiltInOids::OIDOID.oid(), toast_relid.into_datum())],
+    )
+    .unwrap_or_else(|err| error!("Unable to find the table of a TOAST table: {err}"))
+}
+
+/*
+ * Check that the current user is allowed to read all tuples of the relation. The
+ * scan reads the pages directly, so neither column privileges nor row-level security
+ * policies can be applied to the returned tuples. In addition, dead and historical
+ * tuple versions can be read. Therefore, only the owner of the table can scan it, unless
+ * pg_debug_scan.allow_select_privilege permits users with the SELECT privilege on the
+ * whole table. Tables with row-level security for the user can only be scanned by their
+ * owner with bypass_rls. Superusers are not restricted. A TOAST table has no privileges
+ * of its own, so the privileges of its table are checked.
+ */
+unsafe fn check_scan_privileges(relid: pg_sys::Oid, relkind: u8, bypass_rls: bool) {
+    if pg_sys::superuser() {
+        return;
+    }
+
+    if relkind == pg_sys::RELKIND_TOASTVALUE {
+        let parent = toast_parent(relid).unwrap_or_else(|| {
+            error!(
+                "Unable to find the table of TOAST table {}",
+                RegClass(relid).name()
+            )
+        });
+
+        check_scan_privileges(parent, pg_sys::get_rel_relkind(parent) as u8, bypass_rls);
+        return;
+    }
+
+    let userid = pg_sys::GetUserId();
+    let is_owner = is_relation_owner(relid, userid);
+
+    if !is_owner {
+        let result = if ALLOW_SELECT_PRIVILEGE.get() {
+            pg_sys::pg_class_aclcheck(relid, userid, pg_sys::ACL_SELECT as pg_sys::AclMode)
+        } else {
+            pg_sys::AclResult_ACLCHECK_NOT_OWNER
+        };
+
+        if result != pg_sys::AclResult_ACLCHECK_OK {
+            pg_sys::aclcheck_error(
+                result,
+                pg_sys::get_relkind_objtype(relkind as std::os::raw::c_char),
+                pg_sys::get_rel_name(relid),
+            );
+        }
+    }
+
+    if check_enable_rls(relid, pg_sys::InvalidOid, true) == RLS_ENABLED && !(is_owner && bypass_rls)
+    {
+        ErrorReport::new(
+            PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+            format!(
+                "{} has row-level security enabled, which cannot be applied by a debug scan",
+                RegClass(relid).name()
+            ),
+            "pg_debug_scan",
+        )
+        .set_hint("The owner of the table can scan it with bypass_rls => true.")
+        .report(PgLogLevel::ERROR);
+    }
+}
+
+/*
+ * Open the given relation and check that its tuples are stored by the heap table
+ * access method. The pages of the relation are read directly, so other relation
+ * kinds (e.g., views) and table access methods are rejected with an error. The
+ * privileges of the current user are checked as well.
+ */
+pub(crate) unsafe fn open_heap_relation(relid: pg_sys::Oid) -> pg_sys::Relation {
+    open_heap_relation_extended(relid, false)
+}
+
+/*
+ * A relation opened by open_heap_relation for a result that is returned lazily. The
+ * relation is closed when the result is dropped, e.g., after all rows are returned.
+ */
+pub(crate) struct OpenRelation {
+    pub(crate) rel: pg_sys::Relation,
+}
+
+impl OpenRelation {
+    pub(crate) unsafe fn open(relid: pg_sys::Oid) -> OpenRelation {
+        OpenRelation {
+            rel: open_heap_relation(relid),
+        }
+    }
+}
+
+impl Drop for OpenRelation {
+    fn drop(&mut self) {
+        if crate::resources_releasable() {
+            unsafe { pg_sys::table_close(self.rel, pg_sys::AccessShareLock as i32) };
+        }
+    }
+}
+
+/*
+ * Open the given relation like open_heap_relation. With bypass_rls, the owner of a
+ * table with row-level security can scan it, see check_scan_privileges.
+ */
+pub(crate) unsafe fn open_heap_relation_extended(
+    relid: pg_sys::Oid,
+    bypass_rls: bool,
+) -> pg_sys::Relation {
+    let rel = pg_sys::table_open(relid, pg_sys::AccessShareLock as i32);
+    let relkind = (*(*rel).rd_rel).relkind as u8;
+
+    check_scan_privileges(relid, relkind, bypass_rls);
+
+    if !matches!(
+        relkind,
+        pg_sys::RELKIND_RELATION | pg_sys::RELKIND_MATVIEW | pg_sys::RELKIND_TOASTVALUE
+    ) {
+        error!(
+            "{} is a {}, only tables and materialized views are supported",
+            RegClass(relid).name(),
+            relkind_name(relkind)
+        );
+    }
+
+    if (*rel).rd_tableam != pg_sys::GetHeapamTableAmRoutine() {
+        let am_name = CStr::from_ptr(pg_sys::get_am_name((*(*rel).rd_rel).relam));
+
+        error!(
+            "{} uses the table access method {}, only heap is supported",
+            RegClass(relid).name(),
+            am_name.to_string_lossy()
+        );
+    }
+
+    rel
+}
+
+/*
+ * Get the bulk read buffer access strategy of this backend. Like a sequential scan
+ * of a large table, the pages are read into a small ring of buffers, so a debug scan
+ * does not evict the working set from shared_buffers. The ring is allocated once
+ * and reused by all scans of the backend.
+ */
+unsafe fn bulk_read_strategy() -> pg_sys::BufferAccessStrategy {
+    let strategy = BULK_READ_STRATEGY.load(Ordering::Relaxed);
+
+    if !strategy.is_null() {
+        return strategy;
+    }
+
+    let strategy = PgMemoryContexts::TopMemoryContext
+        .switch_to(|_| pg_sys::GetAccessStrategy(pg_sys::BufferAccessStrategyType_BAS_BULKREAD));
+    BULK_READ_STRATEGY.store(strategy, Ordering::Relaxed);
+
+    strategy
+}
+
+/*
+ * Issue prefetch requests for the blocks ahead of the given block, so the pages are
+ * already read when a page-by-page scan of the relation reaches them. At the start
+ * of the scan, the whole prefetch distance is requested.
+ */
+pub(crate) unsafe fn prefetch_ahead(
+    rel: pg_sys::Relation,
+    blkno: pg_sys::BlockNumber,
+    nblocks: pg_sys::BlockNumber,
+) {
+    let last_block = blkno
+        .saturating_add(PREFETCH_DISTANCE)
+        .min(nblocks.saturating_sub(1));
+    let first_block = if blkno == 0 { 1 } else { last_block };
+
+    for target in first_block..=last_block {
+        if target > blkno {
+            pg_sys::PrefetchBuffer(rel, pg_sys::ForkNumber_MAIN_FORKNUM, target);
+        }
+    }
+}
+
+/*
+ * Read the given block of the relation and call func with the buffer and the
+ * share locked page. Pending interrupts (e.g., a query cancel or statement_timeout)
+ * are processed before the page is read, so long page-by-page scans can be aborted.
+ * The buffer and the locks are released by the resource owner in this case. The
+ * page is read using the bulk read strategy, see bulk_read_strategy.
+ */
+pub(crate) unsafe fn with_locked_page<T>(
+    rel: pg_sys::Relation,
+    blkno: pg_sys::BlockNumber,
+    func: impl FnOnce(pg_sys::Buffer, pg_sys::Page) -> T,
+) -> T {
+    check_for_interrupts!();
+
+    let buffer = pg_sys::ReadBufferExtended(
+        rel,
+        pg_sys::ForkNumber_MAIN_FORKNUM,
+        blkno,
+        pg_sys::ReadBufferMode_RBM_NORMAL,
+        bulk_read_strategy(),
+    );
+    pg_sys::LockBuffer(buffer, pg_sys::BUFFER_LOCK_SHARE as i32);
+
+    let result = func(buffer, BufferGetPage(buffer));
+
+    pg_sys::UnlockReleaseBuffer(buffer);
+
+    result
+}
+
+/*
+ * Determine the HOT chains of a page. Each chain is identified by the offset of
+ * its root line pointer (a tuple that is not heap-only or a redirect) and contains
+ * the offsets of its tuples in chain order.
+ */
+pub(crate) unsafe fn page_hot_chains(
+    relid: pg_sys::Oid,
+    page: pg_sys::Page,
+    blkno: pg_sys::BlockNumber,
+) -> Vec<(pg_sys::OffsetNumber, Vec<pg_sys::OffsetNumber>)> {
+    let maxoff = PageGetMaxOffsetNumber(page);
+    let mut chains = Vec::new();
+
+    for rootoff in 1..=maxoff {
+        let itemid = PageGetItemId(page, rootoff);
+
+        let start = match (*itemid).lp_flags() {
+            pg_sys::LP_REDIRECT => (*itemid).lp_off() as pg_sys::OffsetNumber,
+            pg_sys::LP_NORMAL => {
+                let htup = page_get_heap_tuple(relid, page, blkno, rootoff);
+                if HeapTupleHeaderIsHeapOnly(htup.t_data) {
+                    continue;
+                }
+                rootoff
+            }
+            _ => continue,
+        };
+
+        let mut members = Vec::new();
+        let mut prior_xmax = pg_sys::InvalidTransactionId;
+        let mut offnum = start;
+
+        while (1..=maxoff).contains(&offnum) && !members.contains(&offnum) {
+            let itemid = PageGetItemId(page, offnum);
+            if (*itemid).lp_flags() != pg_sys::LP_NORMAL {
+                break;
+            }
+
+            let htup = page_get_heap_tuple(relid, page, blkno, offnum);
+            let xmin = pg_sys::HeapTupleHeaderGetXmin(htup.t_data);
+            if prior_xmax != pg_sys::InvalidTransactionId && xmin != prior_xmax {
+                break;
+            }
+
+            members.push(offnum);
+
+            if !HeapTupleHeaderIsHotUpdated(htup.t_data) {
+                break;
+            }
+
+            offnum = (*htup.t_data).t_ctid.ip_posid;
+            prior_xmax = HeapTupleHeaderGetUpdateXid(htup.t_data);
+        }
+
+        chains.push((rootoff, members));
+    }
+
+    chains
+}
+
+/*
+ * Change the given page of the table under an exclusive buffer lock. This allows the
+ * tests to create page states (e.g., corruptions or tuples of finished transactions)
+ * that cannot be reached within the single transaction of a test.
+ */
+#[cfg(any(test, feature = "pg_test"))]
+pub(crate) unsafe fn modify_page(
+    table: &str,
+    blkno: pg_sys::BlockNumber,
+    func: impl FnOnce(pg_sys::Page),
+) {
+    let table_rel = pg_sys::table_open(
+        crate::get_relid_from_name(table),
+        pg_sys::RowExclusiveLock as i32,
+    );
+    let buffer = pg_sys::ReadBuffer(table_rel, blkno);
+    pg_sys::LockBuffer(buffer, pg_sys::BUFFER_LOCK_EXCLUSIVE as i32);
+
+    func(BufferGetPage(buffer));
+
+    pg_sys::MarkBufferDirty(buffer);
+    pg_sys::UnlockReleaseBuffer(buffer);
+    pg_sys::table_close(table_rel, pg_sys::RowExclusiveLock as i32);
+}
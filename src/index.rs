@@ -0,0 +1,258 @@
+use std::ffi::CStr;
+
+use pgrx::{
+    itemptr::item_pointer_get_both,
+    pg_sys::{AccessShareLock, GetTransactionSnapshot},
+    prelude::*,
+};
+
+use crate::heap::{with_locked_page, OpenRelation, PageGetItemId, PageGetMaxOffsetNumber};
+use crate::output::{format_tuple, output_datum, AttributeValue, OutputFormat};
+use crate::page::line_pointer_state;
+use crate::regclass::RegClass;
+use crate::snapshot::get_snapshot_from_str;
+use crate::{get_relid_from_name, resources_releasable};
+
+/* The result of following an index entry into the heap */
+struct HeapLookup {
+    lp_state: &'static str,
+    visible_ctid: Option<pg_sys::ItemPointerData>,
+    all_dead: bool,
+}
+
+/*
+ * Decode the key of the given index tuple into its attributes
+ */
+unsafe fn decode_index_key(
+    itup: pg_sys::IndexTuple,
+    itupdesc: pg_sys::TupleDesc,
+) -> Vec<AttributeValue> {
+    let mut values = [pg_sys::Datum::from(0); pg_sys::INDEX_MAX_KEYS as usize];
+    let mut isnull = [false; pg_sys::INDEX_MAX_KEYS as usize];
+
+    pg_sys::index_deform_tuple(itup, itupdesc, values.as_mut_ptr(), isnull.as_mut_ptr());
+
+    let natts = (*itupdesc).natts as usize;
+    let attrs = (*itupdesc).attrs.as_slice(natts);
+
+    attrs
+        .iter()
+        .enumerate()
+        .map(|(position, attr_form_data)| AttributeValue {
+            name: CStr::from_ptr(attr_form_data.attname.data.as_ptr())
+                .to_string_lossy()
+                .to_string(),
+            typid: attr_form_data.atttypid,
+            value: (!isnull[position])
+                .then(|| output_datum(attr_form_data.atttypid, values[position])),
+        })
+        .collect()
+}
+
+/*
+ * Follow the heap tid of an index entry. The HOT chain starting at the tid is
+ * searched for a version that is visible under the snapshot, like an index scan
+ * does. Tids pointing behind the end of the relation or the line pointer array
+ * of the page are reported as MISSING.
+ */
+unsafe fn lookup_heap_tid(
+    heap_rel: pg_sys::Relation,
+    tid: pg_sys::ItemPointerData,
+    nblocks: pg_sys::BlockNumber,
+    snapshot_data: *mut pg_sys::SnapshotData,
+) -> HeapLookup {
+    let missing = HeapLookup {
+        lp_state: "MISSING",
+        visible_ctid: None,
+        all_dead: false,
+    };
+
+    let (blkno, offnum) = item_pointer_get_both(tid);
+
+    if blkno >= nblocks {
+        return missing;
+    }
+
+    with_locked_page(heap_rel, blkno, |buffer, page| {
+        if offnum < 1 || offnum > PageGetMaxOffsetNumber(page) {
+            return missing;
+        }
+
+        let lp_state = line_pointer_state((*PageGetItemId(page, offnum)).lp_flags());
+
+        let mut visible_ctid = tid;
+        let mut heap_tuple = pg_sys::HeapTupleData::default();
+        let mut all_dead = false;
+
+        let visible = pg_sys::heap_hot_search_buffer(
+            &mut visible_ctid,
+            heap_rel,
+            buffer,
+            snapshot_data,
+            &mut heap_tuple,
+            &mut all_dead,
+            true,
+        );
+
+        HeapLookup {
+            lp_state,
+            visible_ctid: visible.then_some(visible_ctid),
+            all_dead,
+        }
+    })
+}
+
+/* A scan of all entries of a btree index, see pg_debug_index_scan */
+struct IndexEntries {
+    index_rel: pg_sys::Relation,
+    heap: OpenRelation,
+    nblocks: pg_sys::BlockNumber,
+    scan: pg_sys::IndexScanDesc,
+    snapshot_data: *mut pg_sys::SnapshotData,
+}
+
+impl IndexEntries {
+    /*
+     * Open the index and its table and start a scan that returns all entries
+     */
+    unsafe fn open(index: &str, snapshot_data: *mut pg_sys::SnapshotData) -> IndexEntries {
+        let index_relid = get_relid_from_name(index);
+        let index_rel = pg_sys::index_open(index_relid, AccessShareLock as i32);
+
+        if (*(*index_rel).rd_rel).relam != pg_sys::BTREE_AM_OID {
+            error!(
+                "{} is not a btree index, only btree indexes are supported",
+                RegClass(index_relid).name()
+            );
+        }
+
+        let heap = OpenRelation::open((*(*index_rel).rd_index).indrelid);
+        let nblocks =
+            pg_sys::RelationGetNumberOfBlocksInFork(heap.rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+
+        /* All index entries are returned, their heap tuples are checked against the snapshot */
+        let scan = pg_sys::index_beginscan(
+            heap.rel,
+            index_rel,
+            std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+            0,
+            0,
+        );
+        (*scan).xs_want_itup = true;
+        (*scan).ignore_killed_tuples = false;
+        pg_sys::index_rescan(scan, std::ptr::null_mut(), 0, std::ptr::null_mut(), 0);
+
+        IndexEntries {
+            index_rel,
+            heap,
+            nblocks,
+            scan,
+            snapshot_data,
+        }
+    }
+}
+
+impl Iterator for IndexEntries {
+    /* The key of the entry, its heap tid and the result of following the tid */
+    type Item = (Vec<AttributeValue>, pg_sys::ItemPointerData, HeapLookup);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe {
+            let tid =
+                pg_sys::index_getnext_tid(self.scan, pg_sys::ScanDirection_ForwardScanDirection);
+            if tid.is_null() {
+                return None;
+            }
+
+            let heap_ctid = *tid;
+            let key = decode_index_key((*self.scan).xs_itup, (*self.scan).xs_itupdesc);
+            let lookup =
+                lookup_heap_tid(self.heap.rel, heap_ctid, self.nblocks, self.snapshot_data);
+
+            Some((key, heap_ctid, lookup))
+        }
+    }
+}
+
+impl Drop for IndexEntries {
+    fn drop(&mut self) {
+        if resources_releasable() {
+            unsafe {
+                pg_sys::index_endscan(self.scan);
+                pg_sys::index_close(self.index_rel, AccessShareLock as i32);
+            }
+        }
+    }
+}
+
+/*
+ * Walk all entries of a btree index, including entries that are marked as killed,
+ * and follow their heap tids. For each entry, the key, the state of the heap line
+ * pointer, and the version of the HOT chain that is visible under the snapshot are
+ * returned. all_dead shows that all versions of the chain are dead to all
+ * transactions. This reveals index entries pointing to missing or invisible tuples.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_index_scan(
+    index: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(key, String),
+        name!(heap_ctid, pg_sys::ItemPointerData),
+        name!(lp_state, String),
+        name!(visible, bool),
+        name!(visible_ctid, Option<pg_sys::ItemPointerData>),
+        name!(all_dead, bool),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let output_format = OutputFormat::from_guc();
+
+    TableIterator::new(IndexEntries::open(index, snapshot_data).map(
+        move |(key, heap_ctid, lookup)| {
+            (
+                format_tuple(&key, output_format),
+                heap_ctid,
+                lookup.lp_state.to_string(),
+                lookup.visible_ctid.is_some(),
+                lookup.visible_ctid,
+                lookup.all_dead,
+            )
+        },
+    ))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_index_scan() {
+        pgrx::Spi::run("CREATE TABLE index_scan_test (id int, value int);").unwrap();
+        pgrx::Spi::run("CREATE INDEX index_scan_test_id ON index_scan_test (id);").unwrap();
+        pgrx::Spi::run("INSERT INTO index_scan_test VALUES (1, 10), (2, 20);").unwrap();
+        pgrx::Spi::run("DELETE FROM index_scan_test WHERE id = 2;").unwrap();
+
+        /* The index entry of the deleted tuple still exists */
+        let entries = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_index_scan('index_scan_test_id');",
+        )
+        .unwrap();
+        assert_eq!(entries, Some(2));
+
+        let invisible_key = pgrx::Spi::get_one::<String>(
+            "SELECT key FROM pg_debug_index_scan('index_scan_test_id') WHERE NOT visible;",
+        )
+        .unwrap();
+        assert_eq!(invisible_key, Some("{\"id\":\"2\"}".to_string()));
+    }
+}
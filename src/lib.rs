@@ -1,292 +1,2824 @@
-use serde_json::{Map, Value};
+/* With the embedded feature, only the Rust API of the scanner module is used */
+#![cfg_attr(feature = "embedded", allow(dead_code, unused_imports))]
+
+use std::collections::HashSet;
 use std::ffi::CStr;
 use std::ffi::CString;
-use std::mem::size_of;
-use std::ptr;
+use std::ops::{Bound, RangeBounds, RangeInclusive};
 
 use pgrx::{
-    pg_sys::{
-        palloc, uint32, AccessShareLock, GetLatestSnapshot, GetTransactionSnapshot, SnapshotData,
-    },
+    itemptr::item_pointer_get_both,
+    pg_sys::{panic::CaughtError, GetTransactionSnapshot, SnapshotData},
     prelude::*,
+    AnyElement, Range, RangeBound,
+};
+use rand::Rng;
+
+mod aborted;
+mod assertion;
+mod bloat;
+mod block;
+mod catalog;
+mod chain;
+mod constraints;
+mod dump;
+mod filter;
+mod fingerprint;
+mod guc;
+mod header;
+mod heap;
+mod index;
+mod limiter;
+mod lock;
+mod lsn;
+mod multixact;
+mod output;
+mod page;
+mod parallel;
+mod progress;
+mod prune;
+mod regclass;
+mod relation;
+mod relfilenode;
+mod restore;
+mod rewrite;
+pub mod scanner;
+mod snapshot;
+mod stats;
+mod timeline;
+mod toast;
+mod unique;
+mod verify;
+mod versions;
+mod visibility;
+mod visibilitymap;
+mod wait;
+mod xid8;
+
+use filter::filter_scan_key;
+use guc::{DETOAST, INCLUDE_DROPPED_COLUMNS, LOG_SNAPSHOT, MAX_ROWS};
+use heap::{
+    open_heap_relation_extended, BufferGetPage, HeapTupleHeaderGetUpdateXid,
+    HeapTupleHeaderGetXmax, PageGetLSN,
 };
+use limiter::ScanSlot;
+use lock::RelationLock;
+use lsn::PgLsn;
+use output::{
+    attributes_to_jsonb, format_tuple, output_datum, tuple_to_jsonb, AttributeValue, JsonbDatum,
+    OutputFormat,
+};
+use progress::ScanProgress;
+use regclass::RegClass;
+use scanner::DebugScanner;
+#[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+use snapshot::PgSnapshot;
+use snapshot::{
+    check_frozen_horizon, get_snapshot, get_snapshot_from_str, parse_snapshot_data,
+    parse_snapshot_json, report_dirty_snapshot, xid_filter, xid_from_i64, SnapshotArguments,
+    TxidSnapshot,
+};
+use toast::{attribute_storage, varlena_storage, VarlenaStorage};
+use verify::TupleCheck;
+use xid8::Xid8;
 
+/*
+ * With the embedded feature, the crate is used by another extension (see scanner)
+ * and neither the module magic nor the SQL functions of this extension are defined
+ */
+#[cfg(not(feature = "embedded"))]
 pgrx::pg_module_magic!();
 
-struct SnapshotArguments {
-    xmin: uint32,
-    xmax: uint32,
-    xip: Vec<u32>,
+#[cfg(not(feature = "embedded"))]
+#[allow(non_snake_case)]
+#[pg_guard]
+pub extern "C" fn _PG_init() {
+    unsafe {
+        scanner::init();
+    }
+}
+
+/*
+ * Convert the user provided table name into the Oid of the relation
+ */
+pub(crate) unsafe fn get_relid_from_name(table: &str) -> pg_sys::Oid {
+    get_relid_from_name_extended(table, pg_sys::AccessShareLock as pg_sys::LOCKMODE, 0)
 }
 
 /*
- * Parse the provided snapshot data by the user. For example 4:45:23,35
- * means xmin:xmax:xip1,xip2 .
- *
- * See the PostgreSQL documentation - pg_current_snapshot() for more information
- * about the meaning of these values.
+ * Convert the table name into the Oid of the relation and lock it with the given
+ * lock mode. The flags are passed to RangeVarGetRelidExtended (e.g., RVR_NOWAIT).
  */
-fn parse_snapshot_data(snapshot_str: &str) -> SnapshotArguments {
-    let parts: Vec<&str> = snapshot_str.split(':').collect();
+pub(crate) unsafe fn get_relid_from_name_extended(
+    table: &str,
+    lockmode: pg_sys::LOCKMODE,
+    flags: u32,
+) -> pg_sys::Oid {
+    /* Convert the table name into a range var */
+    let range_list: *mut pg_sys::List;
+    let table_str = CString::new(table).expect("Unable to convert to string");
 
-    if parts.len() != 3 {
-        error!("Unable to parse snapshot data {snapshot_str}");
+    #[cfg(any(feature = "pg12", feature = "pg13", feature = "pg14", feature = "pg15"))]
+    {
+        range_list = pg_sys::stringToQualifiedNameList(table_str.as_ptr());
+    }
+    #[cfg(feature = "pg16")]
+    {
+        range_list = pg_sys::stringToQualifiedNameList(table_str.as_ptr(), std::ptr::null_mut());
     }
 
-    let xmin = parts[0].parse().expect("Unable to parse xmin value");
-    let xmax = parts[1].parse().expect("Unable to parse xmax value");
+    let rangevar = pg_sys::makeRangeVarFromNameList(range_list);
 
-    /* Parse xip members (2,3,54) */
-    let mut xip_values = Vec::new();
+    /* Get the Oid of the table */
+    pg_sys::RangeVarGetRelidExtended(rangevar, lockmode, flags, None, std::ptr::null_mut())
+}
 
-    if !parts[2].is_empty() {
-        for part in parts[2].split(',') {
-            let xip_value: u32 = part.parse().expect("unable to parse xip member: {part}");
+/*
+ * The overloads of pg_debug_scan for a table name and for a regclass share all further
+ * parameters. Both functions are defined from this single parameter list, only the
+ * relation parameter and the function that resolves it to an Oid differ.
+ */
+macro_rules! pg_debug_scan_function {
+    (fn $name:ident($rel:ident: $($rel_type:tt)+) => $relid:ident) => {
+        #[cfg(not(feature = "embedded"))]
+        #[pg_extern(name = "pg_debug_scan")]
+        #[allow(clippy::too_many_arguments)]
+        unsafe fn $name(
+            $rel: $($rel_type)+,
+            snapshot: default!(Option<&str>, "NULL"),
+            xids: default!(Option<Vec<i64>>, "NULL"),
+            strict: default!(bool, "true"),
+            sample_rows: default!(Option<i64>, "NULL"),
+            stored: default!(Option<&str>, "NULL"),
+            min_page_lsn: default!(Option<PgLsn>, "NULL"),
+            start_block: default!(Option<i64>, "NULL"),
+            end_block: default!(Option<i64>, "NULL"),
+            snapshot_mode: default!(Option<&str>, "NULL"),
+            filter: default!(Option<&str>, "NULL"),
+            columns: default!(Option<Vec<String>>, "NULL"),
+            direction: default!(&str, "'forward'"),
+            syncscan: default!(bool, false),
+            pagemode: default!(bool, false),
+            max_rows: default!(Option<i64>, "NULL"),
+            skip_rows: default!(i64, 0),
+            created_by: default!(Option<i64>, "NULL"),
+            deleted_by: default!(Option<i64>, "NULL"),
+            xmin_between: default!(Option<Range<i64>>, "NULL"),
+            sample_percent: default!(Option<f64>, "NULL"),
+            include_relation: default!(bool, false),
+            lock_mode: default!(&str, "'access share'"),
+            nowait: default!(bool, false),
+            lock_timeout: default!(Option<i32>, "NULL"),
+            bypass_rls: default!(bool, false),
+            check: default!(bool, false),
+        ) -> TableIterator<
+            'static,
+            (
+                name!(ctid, pg_sys::ItemPointerData),
+                name!(block_number, i64),
+                name!(offset_number, i32),
+                name!(xmin, Xid8),
+                name!(xmax, Xid8),
+                name!(data, String),
+                name!(tableoid, Option<pg_sys::Oid>),
+                name!(relname, Option<String>),
+                name!(relfilenode, Option<i64>),
+                name!(violations, Option<Vec<String>>),
+            ),
+        > {
+            let relid = $relid($rel, lock_mode, nowait, lock_timeout);
 
-            /* From PostgreSQL code:
-             * Note: all ids in xip[] satisfy xmin <= xip[i] < xmax
-             */
-            if xip_value >= xmin && xip_value < xmax {
-                xip_values.push(xip_value)
-            } else {
-                error!("Xip value {xip_value} is outside of {xmin}..{xmax}")
-            }
+            let scan = DebugScanner::new(relid)
+                .with_snapshot(snapshot)
+                .with_stored_snapshot(stored)
+                .with_snapshot_mode(snapshot_mode)
+                .with_strict(strict)
+                .with_xids(xids)
+                .with_min_page_lsn(min_page_lsn.map(|lsn| lsn.0))
+                .with_block_range(start_block, end_block)
+                .with_filter(filter)
+                .with_columns(columns)
+                .with_scan_options(direction, syncscan, pagemode)
+                .with_sample_percent(sample_percent)
+                .with_xid_conditions(created_by, deleted_by, xmin_between)
+                .with_lock(lock_mode, nowait, lock_timeout)
+                .with_bypass_rls(bypass_rls)
+                .with_check(check)
+                .scan();
+
+            let relation = include_relation.then(|| scan.relation_metadata());
+
+            let tuples: Box<dyn Iterator<Item = ScannedTuple>> = match sample_rows {
+                Some(sample_rows) => {
+                    let sample_rows = usize::try_from(sample_rows)
+                        .unwrap_or_else(|_| error!("Invalid number of sample rows {sample_rows}"));
+
+                    Box::new(scan.sample(sample_rows).into_iter())
+                }
+                None => Box::new(scan),
+            };
+
+            let output_format = OutputFormat::from_guc();
+            let next_xid = pg_sys::ReadNextFullTransactionId();
+
+            TableIterator::new(limit_rows(tuples, max_rows, skip_rows).map(move |tuple| {
+                let row = with_relation(tuple_to_row(&tuple, output_format, next_xid), &relation);
+                with_violations(row, check.then_some(tuple.violations))
+            }))
         }
-    }
+    };
+}
 
-    SnapshotArguments {
-        xmin,
-        xmax,
-        xip: xip_values,
-    }
+/*
+ * Resolve the name of the table with the lock of the scan, e.g., to fail early if the
+ * lock cannot be acquired within lock_timeout
+ */
+#[cfg(not(feature = "embedded"))]
+unsafe fn relid_from_name(
+    table: &str,
+    lock_mode: &str,
+    nowait: bool,
+    lock_timeout: Option<i32>,
+) -> pg_sys::Oid {
+    RelationLock::new(lock_mode, nowait, lock_timeout).relid_from_name(table)
+}
+
+/* The relation of a regclass is already resolved by PostgreSQL */
+#[cfg(not(feature = "embedded"))]
+unsafe fn relid_from_regclass(
+    rel: RegClass,
+    _lock_mode: &str,
+    _nowait: bool,
+    _lock_timeout: Option<i32>,
+) -> pg_sys::Oid {
+    rel.0
 }
 
+pg_debug_scan_function!(fn pg_debug_scan(table: &str) => relid_from_name);
+
+/*
+ * Overload of pg_debug_scan that takes the relation as regclass. The relation is
+ * resolved by PostgreSQL, e.g., pg_debug_scan('"Measurements"'::regclass).
+ */
+pg_debug_scan_function!(fn pg_debug_scan_regclass(rel: RegClass) => relid_from_regclass);
+
 /*
- * Take the user provided snapshot data and return a PostgreSQL snapshot data structure
+ * Skip the first skip_rows rows and return at most max_rows rows. If max_rows is not
+ * given, the limit of pg_debug_scan.max_rows is used (0 disables the limit) and a
+ * notice is emitted if further rows are omitted.
  */
-unsafe fn get_snapshot_from_str(snapshot_str: &str) -> *mut SnapshotData {
-    let snapshot_argument = parse_snapshot_data(snapshot_str);
+fn limit_rows<T>(
+    rows: impl Iterator<Item = T>,
+    max_rows: Option<i64>,
+    skip_rows: i64,
+) -> impl Iterator<Item = T> {
+    let skip_rows = usize::try_from(skip_rows)
+        .unwrap_or_else(|_| error!("Invalid number of rows to skip {skip_rows}"));
+
+    let limit = match max_rows {
+        Some(max_rows) => Some(
+            usize::try_from(max_rows)
+                .unwrap_or_else(|_| error!("Invalid maximal number of rows {max_rows}")),
+        ),
+        None => match MAX_ROWS.get() {
+            0 => None,
+            max_rows => Some(max_rows as usize),
+        },
+    };
+
+    let limit_from_guc = max_rows.is_none();
+    let mut rows = rows.skip(skip_rows);
+    let mut returned_rows = 0;
 
-    /* Get the latest snapshot as base */
-    let latest_snapshot = GetLatestSnapshot();
+    std::iter::from_fn(move || {
+        if limit.is_some_and(|limit| returned_rows >= limit) {
+            if limit_from_guc && rows.next().is_some() {
+                notice!(
+                    "Only the first {returned_rows} rows are returned, see pg_debug_scan.max_rows"
+                );
+            }
+
+            return None;
+        }
 
-    /* Take a copy of the snapshot */
-    let scan_snapshot = palloc(size_of::<SnapshotData>()) as *mut SnapshotData;
-    ptr::copy_nonoverlapping(latest_snapshot, scan_snapshot, 1);
+        returned_rows += 1;
+        rows.next()
+    })
+}
 
-    /* Modify the relevant values */
-    (*scan_snapshot).copied = true;
-    (*scan_snapshot).xmin = snapshot_argument.xmin;
-    (*scan_snapshot).xmax = snapshot_argument.xmax;
-    (*scan_snapshot).xip = palloc(snapshot_argument.xip.len() * size_of::<u32>()) as *mut u32;
-    ptr::copy_nonoverlapping(
-        snapshot_argument.xip.as_ptr(),
-        (*scan_snapshot).xip,
-        snapshot_argument.xip.len(),
+/*
+ * Overload of pg_debug_scan that takes the snapshot members as separate values. This
+ * allows to build snapshots programmatically without formatting them as a string.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern(name = "pg_debug_scan")]
+unsafe fn pg_debug_scan_structured(
+    table: &str,
+    xmin: i64,
+    xmax: i64,
+    xip: default!(Vec<i64>, "ARRAY[]::bigint[]"),
+    xids: default!(Option<Vec<i64>>, "NULL"),
+    strict: default!(bool, "true"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(block_number, i64),
+        name!(offset_number, i32),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(data, String),
+    ),
+> {
+    let snapshot_argument = SnapshotArguments::new(
+        xid_from_i64(xmin, "xmin"),
+        xid_from_i64(xmax, "xmax"),
+        xip.into_iter()
+            .map(|xip_value| xid_from_i64(xip_value, "xip"))
+            .collect(),
+        strict,
     );
-    (*scan_snapshot).xcnt = snapshot_argument.xip.len() as u32;
 
-    scan_snapshot
+    TableIterator::new(tuples_to_rows(TupleStream::open(
+        table,
+        get_snapshot(snapshot_argument),
+        xids,
+        None,
+        None,
+    )))
+}
+
+/*
+ * Overload of pg_debug_scan that takes the snapshot as a JSON specification like
+ * {"xmin": 774, "xmax": 778, "xip": [775, 777], "subxip": [776], "curcid": 2}
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern(name = "pg_debug_scan")]
+unsafe fn pg_debug_scan_json(
+    table: &str,
+    snapshot: pgrx::JsonB,
+    xids: default!(Option<Vec<i64>>, "NULL"),
+    strict: default!(bool, "true"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(block_number, i64),
+        name!(offset_number, i32),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(data, String),
+    ),
+> {
+    let snapshot_argument = parse_snapshot_json(snapshot.0, strict);
+
+    TableIterator::new(tuples_to_rows(TupleStream::open(
+        table,
+        get_snapshot(snapshot_argument),
+        xids,
+        None,
+        None,
+    )))
 }
 
 /*
- * Custom implementation for HeapTupleHeaderGetXmax. This function is currently not defined in pgrx.
+ * Overload of pg_debug_scan that takes a pg_snapshot value, e.g., the result of
+ * pg_current_snapshot(). The 64 bit transaction ids are checked against the current
+ * epoch before they are converted.
  */
-#[inline(always)]
-#[allow(non_snake_case)]
-unsafe fn HeapTupleHeaderGetXmax(
-    tup: *const pgrx::pg_sys::HeapTupleHeaderData,
-) -> pgrx::pg_sys::TransactionId {
-    unsafe {
-        // SAFETY:  caller has asserted `tup` is a valid HeapTupleHeader pointer
-        if pgrx::pg_sys::HeapTupleHeaderFrozen(tup) {
-            pgrx::pg_sys::FrozenTransactionId
-        } else {
-            (*tup).t_choice.t_heap.t_xmax
-        }
-    }
+#[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+#[cfg(not(feature = "embedded"))]
+#[pg_extern(name = "pg_debug_scan")]
+unsafe fn pg_debug_scan_pg_snapshot(
+    table: &str,
+    snapshot: PgSnapshot,
+    xids: default!(Option<Vec<i64>>, "NULL"),
+    strict: default!(bool, "true"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(block_number, i64),
+        name!(offset_number, i32),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(data, String),
+    ),
+> {
+    TableIterator::new(tuples_to_rows(TupleStream::open(
+        table,
+        get_snapshot(snapshot.0.snapshot_arguments(strict)),
+        xids,
+        None,
+        None,
+    )))
 }
 
-#[pg_extern]
-unsafe fn pg_debug_scan(
+/*
+ * Overload of pg_debug_scan that takes a txid_snapshot value, e.g., the result of
+ * txid_current_snapshot()
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern(name = "pg_debug_scan")]
+unsafe fn pg_debug_scan_txid_snapshot(
     table: &str,
+    snapshot: TxidSnapshot,
+    xids: default!(Option<Vec<i64>>, "NULL"),
+    strict: default!(bool, "true"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(block_number, i64),
+        name!(offset_number, i32),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(data, String),
+    ),
+> {
+    TableIterator::new(tuples_to_rows(TupleStream::open(
+        table,
+        get_snapshot(snapshot.0.snapshot_arguments(strict)),
+        xids,
+        None,
+        None,
+    )))
+}
+
+/*
+ * Overload of pg_debug_scan that scans multiple tables. The same snapshot is used
+ * for all tables, so the merged output shows a consistent state of all of them.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern(name = "pg_debug_scan")]
+unsafe fn pg_debug_scan_tables(
+    tables: Vec<String>,
     snapshot: default!(Option<&str>, "NULL"),
-) -> TableIterator<'static, (name!(xmin, i64), name!(xmax, i64), name!(data, String))> {
-    info!("Reading table {table}");
+    xids: default!(Option<Vec<i64>>, "NULL"),
+    strict: default!(bool, "true"),
+    include_relation: default!(bool, false),
+) -> TableIterator<
+    'static,
+    (
+        name!(relation, String),
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(block_number, i64),
+        name!(offset_number, i32),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(data, String),
+        name!(tableoid, Option<pg_sys::Oid>),
+        name!(relname, Option<String>),
+        name!(relfilenode, Option<i64>),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot(parse_snapshot_data(snapshot_data, strict)),
+        None => GetTransactionSnapshot(),
+    };
+
+    /* The tables are scanned one after another while the rows are consumed */
+    let rows = tables.into_iter().flat_map(move |table| {
+        let tuples = TupleStream::open(&table, snapshot_data, xids.clone(), None, None);
+        let relation = include_relation.then(|| relation_metadata(tuples.table_rel));
+
+        tuples_to_rows(tuples).map(move |row| {
+            let (
+                ctid,
+                block_number,
+                offset_number,
+                xmin,
+                xmax,
+                data,
+                tableoid,
+                relname,
+                relfilenode,
+            ) = with_relation(row, &relation);
+
+            (
+                table.clone(),
+                ctid,
+                block_number,
+                offset_number,
+                xmin,
+                xmax,
+                data,
+                tableoid,
+                relname,
+                relfilenode,
+            )
+        })
+    });
 
+    TableIterator::new(rows)
+}
+
+/* The leaf partitions of a partitioned table, or the table itself if it is not partitioned */
+const LEAF_PARTITIONS_QUERY: &str = "SELECT relid FROM pg_partition_tree($1)
+    WHERE isleaf
+    ORDER BY level, relid::text";
+
+/*
+ * Get the leaf partitions of the given relation
+ */
+fn leaf_partitions(relid: pg_sys::Oid) -> Vec<pg_sys::Oid> {
+    Spi::connect(|client| {
+        client
+            .select(
+                LEAF_PARTITIONS_QUERY,
+                None,
+                Some(vec![(PgBuiltInOids::REGCLASSOID.oid(), relid.into_datum())]),
+            )?
+            .filter_map(|row| row.get::<pg_sys::Oid>(1).transpose())
+            .collect::<Result<Vec<_>, pgrx::spi::Error>>()
+    })
+    .unwrap_or_else(|err| error!("Unable to get the partitions: {err}"))
+}
+
+/*
+ * Scan all leaf partitions of a partitioned table. The same snapshot is used for
+ * all partitions and the partition column shows which partition a tuple belongs to.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_partitions(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    xids: default!(Option<Vec<i64>>, "NULL"),
+    strict: default!(bool, "true"),
+    include_relation: default!(bool, false),
+) -> TableIterator<
+    'static,
+    (
+        name!(partition, RegClass),
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(block_number, i64),
+        name!(offset_number, i32),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(data, String),
+        name!(tableoid, Option<pg_sys::Oid>),
+        name!(relname, Option<String>),
+        name!(relfilenode, Option<i64>),
+    ),
+> {
     let snapshot_data = match snapshot {
-        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        Some(snapshot_data) => get_snapshot(parse_snapshot_data(snapshot_data, strict)),
         None => GetTransactionSnapshot(),
     };
 
-    info!(
-        "Snapshot is (xmin={}, xmax={}, xcnt={})",
-        (*snapshot_data).xmin,
-        (*snapshot_data).xmax,
-        (*snapshot_data).xcnt
-    );
+    let partitions = leaf_partitions(get_relid_from_name(table));
 
-    /* Convert the table name into a range var */
-    let range_list: *mut pg_sys::List;
-    let table_str = CString::new(table).expect("Unable to convert to string");
+    /* The partitions are scanned one after another while the rows are consumed */
+    let rows = partitions.into_iter().flat_map(move |relid| {
+        let partition = RegClass(relid);
+        let tuples = TupleStream::open_relation(
+            partition,
+            snapshot_data,
+            xids.clone(),
+            None,
+            None,
+            None,
+            HeapScanOptions::default(),
+        );
+        let relation = include_relation.then(|| relation_metadata(tuples.table_rel));
 
-    #[cfg(any(feature = "pg12", feature = "pg13", feature = "pg14", feature = "pg15"))]
-    {
-        range_list = pg_sys::stringToQualifiedNameList(table_str.as_ptr());
+        tuples_to_rows(tuples).map(move |row| {
+            let (
+                ctid,
+                block_number,
+                offset_number,
+                xmin,
+                xmax,
+                data,
+                tableoid,
+                relname,
+                relfilenode,
+            ) = with_relation(row, &relation);
+
+            (
+                partition,
+                ctid,
+                block_number,
+                offset_number,
+                xmin,
+                xmax,
+                data,
+                tableoid,
+                relname,
+                relfilenode,
+            )
+        })
+    });
+
+    TableIterator::new(rows)
+}
+
+/* The highest valid block number, see MaxBlockNumber in block.h */
+const MAX_BLOCK_NUMBER: pg_sys::BlockNumber = 0xFFFFFFFE;
+
+/*
+ * Get the range of blocks to scan. Both block numbers are inclusive. If no end
+ * block is given, the scan continues up to the end of the relation.
+ */
+fn block_range(
+    start_block: Option<i64>,
+    end_block: Option<i64>,
+) -> Option<RangeInclusive<pg_sys::BlockNumber>> {
+    if start_block.is_none() && end_block.is_none() {
+        return None;
     }
-    #[cfg(feature = "pg16")]
-    {
-        range_list = pg_sys::stringToQualifiedNameList(table_str.as_ptr(), std::ptr::null_mut());
+
+    let block_number = |value: i64, name: &str| {
+        pg_sys::BlockNumber::try_from(value)
+            .ok()
+            .filter(|block| *block <= MAX_BLOCK_NUMBER)
+            .unwrap_or_else(|| error!("Invalid {name} {value}"))
+    };
+
+    let start_block = block_number(start_block.unwrap_or(0), "start_block");
+    let end_block = end_block.map_or(MAX_BLOCK_NUMBER, |end_block| {
+        block_number(end_block, "end_block")
+    });
+
+    if start_block > end_block {
+        error!("start_block {start_block} is behind end_block {end_block}");
     }
 
-    let rangevar = pg_sys::makeRangeVarFromNameList(range_list);
+    Some(start_block..=end_block)
+}
 
-    /* Get the Oid of the table */
-    let relid = pg_sys::RangeVarGetRelidExtended(
-        rangevar,
-        pg_sys::AccessShareLock as pg_sys::LOCKMODE,
-        0,
-        None,
-        std::ptr::null_mut(),
-    );
+/* A result row of pg_debug_scan (ctid, block_number, offset_number, xmin, xmax, data) */
+type ScanRow = (pg_sys::ItemPointerData, i64, i32, Xid8, Xid8, String);
 
-    /* Preform the table scan */
-    let table_rel = pg_sys::table_open(relid, AccessShareLock as i32);
-    let slot = pg_sys::table_slot_create(table_rel, std::ptr::null_mut());
+/* The relation of a scanned tuple (tableoid, relname, relfilenode) */
+type RelationMetadata = (pg_sys::Oid, String, i64);
 
-    let scan = pg_sys::heap_beginscan(
-        table_rel,
-        snapshot_data,
-        0,
-        std::ptr::null_mut(),
-        std::ptr::null_mut(),
-        0,
-    );
+/* A result row of pg_debug_scan with the optional relation columns */
+type ScanRowWithRelation = (
+    pg_sys::ItemPointerData,
+    i64,
+    i32,
+    Xid8,
+    Xid8,
+    String,
+    Option<pg_sys::Oid>,
+    Option<String>,
+    Option<i64>,
+);
 
-    let mut results: Vec<(i64, i64, String)> = Vec::new();
+/* A result row of pg_debug_scan with the relation columns and the violations of the checks */
+type ScanRowWithChecks = (
+    pg_sys::ItemPointerData,
+    i64,
+    i32,
+    Xid8,
+    Xid8,
+    String,
+    Option<pg_sys::Oid>,
+    Option<String>,
+    Option<i64>,
+    Option<Vec<String>>,
+);
 
-    /* Build a result tuple for each scanned tupe */
-    while pg_sys::heap_getnextslot(scan, pg_sys::ScanDirection_ForwardScanDirection, slot) {
-        /* No Rust port for slot_getsysattr available, so use HeapTupleHeaderGetXmin on the heap tuple */
-        let get_heap_tuple_fn = (*(*slot).tts_ops).get_heap_tuple.unwrap();
-        let htup = get_heap_tuple_fn(slot);
-        let tupdesc = (*slot).tts_tupleDescriptor;
+/*
+ * Get the metadata of the relation that is attached to the rows with
+ * include_relation => true. When the rows of several relations are merged, they
+ * remain attributable, also across rewrites of a table (which change the relfilenode).
+ */
+unsafe fn relation_metadata(rel: pg_sys::Relation) -> RelationMetadata {
+    let relid = (*rel).rd_id;
 
-        let xmin = pg_sys::HeapTupleHeaderGetXmin((*htup).t_data);
-        let xmax = HeapTupleHeaderGetXmax((*htup).t_data);
-        let json = slot_to_json(relid, htup, tupdesc);
-        results.push((xmin.into(), xmax.into(), json));
-    }
+    (
+        relid,
+        RegClass(relid).name(),
+        (*(*rel).rd_rel).relfilenode.as_u32().into(),
+    )
+}
 
-    pg_sys::heap_endscan(scan);
-    pg_sys::ExecDropSingleTupleTableSlot(slot);
-    pg_sys::table_close(table_rel, AccessShareLock as i32);
+/*
+ * Append the relation columns to the row, they are NULL if no metadata is given
+ */
+fn with_relation(row: ScanRow, relation: &Option<RelationMetadata>) -> ScanRowWithRelation {
+    let (ctid, block_number, offset_number, xmin, xmax, data) = row;
+    let (tableoid, relname, relfilenode) = match relation {
+        Some((tableoid, relname, relfilenode)) => {
+            (Some(*tableoid), Some(relname.clone()), Some(*relfilenode))
+        }
+        None => (None, None, None),
+    };
 
-    TableIterator::new(results)
+    (
+        ctid,
+        block_number,
+        offset_number,
+        xmin,
+        xmax,
+        data,
+        tableoid,
+        relname,
+        relfilenode,
+    )
 }
 
 /*
- * Convert the given slot into a json string
+ * Append the violations of the header checks to the row, they are NULL if the
+ * headers are not checked
  */
-unsafe fn slot_to_json(
-    relid: pgrx::pg_sys::Oid,
-    htup: *mut pgrx::pg_sys::HeapTupleData,
-    tupdesc: *mut pgrx::pg_sys::TupleDescData,
-) -> String {
-    /* Build output JSON */
-    let mut map = Map::new();
+fn with_violations(row: ScanRowWithRelation, violations: Option<Vec<String>>) -> ScanRowWithChecks {
+    let (ctid, block_number, offset_number, xmin, xmax, data, tableoid, relname, relfilenode) = row;
 
-    let nattrs = (*tupdesc).natts as usize;
-    let attrs = (*tupdesc).attrs.as_slice(nattrs);
+    (
+        ctid,
+        block_number,
+        offset_number,
+        xmin,
+        xmax,
+        data,
+        tableoid,
+        relname,
+        relfilenode,
+        violations,
+    )
+}
 
-    for attr_form_data in attrs.iter().take(nattrs) {
-        if attr_form_data.attisdropped {
-            continue;
-        }
+/*
+ * Convert a scanned tuple into a result row of pg_debug_scan
+ */
+fn tuple_to_row(
+    tuple: &ScannedTuple,
+    output_format: OutputFormat,
+    next_xid: pg_sys::FullTransactionId,
+) -> ScanRow {
+    let (block_number, offset_number) = item_pointer_get_both(tuple.ctid);
 
-        /* Since we perform a plain table scan, each attribute should belong to the same base relation */
-        assert!(
-            relid == attr_form_data.attrelid,
-            "attr and base relation have a different Oids {relid} {}",
-            attr_form_data.attrelid
-        );
+    (
+        tuple.ctid,
+        block_number.into(),
+        offset_number.into(),
+        Xid8::from_xid(tuple.xmin, next_xid),
+        Xid8::from_xid(tuple.xmax, next_xid),
+        format_tuple(&tuple.attributes, output_format),
+    )
+}
 
-        let attno = attr_form_data.attnum;
-        assert!(attno > 0, "invalid attr no found during scan {attno}");
+/*
+ * Convert the scanned tuples into the result rows of pg_debug_scan
+ */
+unsafe fn tuples_to_rows(
+    tuples: impl Iterator<Item = ScannedTuple>,
+) -> impl Iterator<Item = ScanRow> {
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
 
-        let mut isnull: bool = false;
-        let attr = pg_sys::heap_getattr(htup, attno.into(), tupdesc, &mut isnull);
+    tuples.map(move |tuple| tuple_to_row(&tuple, output_format, next_xid))
+}
 
-        let colname_ptr = pg_sys::get_attname(relid, attno, false);
-        let colname = CStr::from_ptr(colname_ptr).to_str().unwrap().to_string();
+/*
+ * Return one row per attribute of each visible tuple. For ad-hoc analysis of wide
+ * tables this is often easier to query than the JSON representation. Besides the
+ * value, the number and type of the attribute and how it is stored are returned.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_attrs(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    xids: default!(Option<Vec<i64>>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(attnum, i32),
+        name!(attname, String),
+        name!(atttypid, pg_sys::Oid),
+        name!(value, Option<String>),
+        name!(is_null, bool),
+        name!(is_toasted, bool),
+        name!(storage_size, Option<i32>),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
 
-        if !isnull {
-            let mut typoutput = pgrx::pg_sys::Oid::default();
-            let mut typvarlena: bool = false;
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut tuples = TupleStream::open(table, snapshot_data, xids, None, None);
 
-            pg_sys::getTypeOutputInfo(attr_form_data.atttypid, &mut typoutput, &mut typvarlena);
-            let output_val = pg_sys::OidOutputFunctionCall(typoutput, attr);
-            let output_str = std::ffi::CStr::from_ptr(output_val);
+    let rows = std::iter::from_fn(move || {
+        let (htup, tupdesc) = tuples.next_matching()?;
 
-            map.insert(
-                colname,
-                Value::String(output_str.to_str().unwrap().to_string()),
-            );
-        } else {
-            map.insert(colname, Value::String("NULL".to_string()));
-        }
-    }
+        /* The decoded attributes are in the order of the tuple descriptor */
+        let attrs = (*tupdesc)
+            .attrs
+            .as_slice((*tupdesc).natts as usize)
+            .iter()
+            .filter(|attr_form_data| !attr_form_data.attisdropped || INCLUDE_DROPPED_COLUMNS.get());
 
-    serde_json::to_string(&map).expect("unable to generate JSON")
-}
+        let storage = attrs
+            .map(|attr_form_data| {
+                let mut isnull = false;
+                let attr =
+                    pg_sys::heap_getattr(htup, attr_form_data.attnum.into(), tupdesc, &mut isnull);
 
-#[cfg(any(test, feature = "pg_test"))]
-#[pg_schema]
-mod tests {
-    #[allow(unused_imports)]
-    use pgrx::prelude::*;
+                let (is_toasted, storage_size) = if isnull {
+                    (false, None)
+                } else {
+                    let (is_toasted, size) = attribute_storage(attr, attr_form_data.attlen);
+                    (is_toasted, Some(size))
+                };
 
-    use serde::{Deserialize, Serialize};
+                (
+                    attr_form_data.attnum.into(),
+                    attr_form_data.atttypid,
+                    is_toasted,
+                    storage_size,
+                )
+            })
+            .collect::<Vec<(i32, pg_sys::Oid, bool, Option<i32>)>>();
 
-    #[derive(Serialize, Deserialize)]
-    struct TemperatureJSON {
-        time: String,
-        value: String,
-    }
+        let tuple = tuples.decode(htup, tupdesc);
 
-    /* Get the SQL code for the integration test */
-    fn get_test_sql(attribute: &str, txid: i64) -> String {
-        format!(
-            "SELECT {} FROM pg_debug_scan('temperature', '{}:{}:');",
-            attribute, txid, txid
+        Some(
+            tuple
+                .attributes
+                .into_iter()
+                .zip(storage)
+                .map(
+                    move |(attribute, (attnum, atttypid, is_toasted, storage_size))| {
+                        (
+                            tuple.ctid,
+                            Xid8::from_xid(tuple.xmin, next_xid),
+                            Xid8::from_xid(tuple.xmax, next_xid),
+                            attnum,
+                            attribute.name,
+                            atttypid,
+                            attribute.value.clone(),
+                            attribute.value.is_none(),
+                            is_toasted,
+                            storage_size,
+                        )
+                    },
+                )
+                .collect::<Vec<_>>(),
         )
+    })
+    .flatten();
+
+    TableIterator::new(rows)
+}
+
+/*
+ * Like pg_debug_scan, but the data of the tuples is returned as jsonb with typed
+ * values. Numbers and booleans are native JSON types, NULL values are JSON null and
+ * arrays and composite values are nested JSON structures. This allows to query the
+ * data with the jsonb operators. If the tuple cannot be converted as a whole, its
+ * attributes are decoded one by one and the failed ones are reported like in
+ * pg_debug_scan.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_jsonb(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    xids: default!(Option<Vec<i64>>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(block_number, i64),
+        name!(offset_number, i32),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(data, JsonbDatum),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut tuples = TupleStream::open(table, snapshot_data, xids, None, None);
+    let relid = (*tuples.table_rel).rd_id;
+
+    let rows = std::iter::from_fn(move || {
+        let (htup, tupdesc) = tuples.next_matching()?;
+        let (block_number, offset_number) = item_pointer_get_both((*htup).t_self);
+
+        let data = tuples.in_tuple_context(|| {
+            try_in_subtransaction(|| tuple_to_jsonb(htup, tupdesc))
+                .unwrap_or_else(|_| attributes_to_jsonb(&decode_tuple(relid, htup, tupdesc)))
+        });
+
+        Some((
+            (*htup).t_self,
+            block_number.into(),
+            offset_number.into(),
+            Xid8::from_xid(pg_sys::HeapTupleHeaderGetXmin((*htup).t_data), next_xid),
+            Xid8::from_xid(HeapTupleHeaderGetXmax((*htup).t_data), next_xid),
+            data,
+        ))
+    });
+
+    TableIterator::new(rows)
+}
+
+/*
+ * Return the visible tuples as values of the row type of the table, so the recovered
+ * rows can be joined, filtered and aggregated with plain SQL. The rowtype argument
+ * determines the result type, e.g., pg_debug_scan_rows('t', NULL, NULL::t). If a
+ * tuple cannot be copied (e.g., a TOAST chunk is missing), the tuple is NULL and
+ * the error is returned instead.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_rows(
+    rel: RegClass,
+    snapshot: Option<&str>,
+    _rowtype: Option<AnyElement>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> TableIterator<
+    'static,
+    (
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(tuple, Option<AnyElement>),
+        name!(error, Option<String>),
+    ),
+> {
+    let reltype = pg_sys::get_rel_type_id(rel.0);
+
+    if pg_sys::get_fn_expr_argtype((*fcinfo).flinfo, 2) != reltype {
+        error!("rowtype has to be of the row type of {}", rel.name());
     }
 
-    #[pgrx::pg_test]
-    fn test_parse_snapshot_data() {
-        pgrx::Spi::run("CREATE TABLE temperature (time timestamptz NOT NULL, value float);")
-            .unwrap();
-        pgrx::Spi::run("INSERT INTO temperature VALUES('2024-04-12 15:59:23+02', 1);").unwrap();
-        let txid = pgrx::Spi::get_one::<i64>("SELECT * FROM txid_current();")
-            .unwrap()
-            .expect("unable to get txid");
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
 
-        /* Test returned xmin value */
-        let xmin = pgrx::Spi::get_one::<i64>(get_test_sql("xmin", txid).as_str()).unwrap();
-        assert_eq!(xmin, Some(txid));
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut tuples = TupleStream::open_relation(
+        rel,
+        snapshot_data,
+        None,
+        None,
+        None,
+        None,
+        HeapScanOptions::default(),
+    );
 
-        /* Test returned xmax value */
-        let xmax = pgrx::Spi::get_one::<i64>(get_test_sql("xmax", txid).as_str()).unwrap();
-        assert_eq!(xmax, Some(0));
+    let rows = std::iter::from_fn(move || {
+        let (htup, tupdesc) = tuples.next_matching()?;
 
-        /* Test returned xmax value */
-        let json_value = pgrx::Spi::get_one::<String>(get_test_sql("data", txid).as_str())
-            .unwrap()
-            .expect("unable to get json output");
+        /* The external values are fetched while the row is copied */
+        let row = tuples.in_tuple_context(|| {
+            try_in_subtransaction(|| pg_sys::heap_copy_tuple_as_datum(htup, tupdesc))
+        });
+
+        let (tuple, error) = match row {
+            Ok(row) => (
+                Some(
+                    AnyElement::from_polymorphic_datum(row, false, reltype)
+                        .expect("unable to convert the row"),
+                ),
+                None,
+            ),
+            Err(message) => (None, Some(message)),
+        };
+
+        Some((
+            Xid8::from_xid(pg_sys::HeapTupleHeaderGetXmin((*htup).t_data), next_xid),
+            Xid8::from_xid(HeapTupleHeaderGetXmax((*htup).t_data), next_xid),
+            tuple,
+            error,
+        ))
+    });
+
+    TableIterator::new(rows)
+}
+
+/* A tuple returned by the table scan */
+pub struct ScannedTuple {
+    pub ctid: pg_sys::ItemPointerData,
+    /* The ctid of the newer version of the tuple or the ctid of the tuple itself */
+    pub next_ctid: pg_sys::ItemPointerData,
+    pub xmin: pg_sys::TransactionId,
+    pub xmax: pg_sys::TransactionId,
+    pub infomask: u16,
+    pub infomask2: u16,
+    pub attributes: Vec<AttributeValue>,
+    /* The failed header checks of the tuple, see TupleCheck */
+    pub violations: Vec<String>,
+}
+
+impl ScannedTuple {
+    /*
+     * Take the ctids, the transaction ids and the infomasks from the header of
+     * the given tuple
+     */
+    pub(crate) unsafe fn from_heap_tuple(
+        htup: *mut pg_sys::HeapTupleData,
+        attributes: Vec<AttributeValue>,
+    ) -> ScannedTuple {
+        let tup = (*htup).t_data;
+
+        ScannedTuple {
+            ctid: (*htup).t_self,
+            next_ctid: (*tup).t_ctid,
+            xmin: pg_sys::HeapTupleHeaderGetXmin(tup),
+            xmax: HeapTupleHeaderGetXmax(tup),
+            infomask: (*tup).t_infomask,
+            infomask2: (*tup).t_infomask2,
+            attributes,
+            violations: Vec::new(),
+        }
+    }
+}
+
+/*
+ * Read all tuples of the scan and return a uniform random sample of sample_rows tuples
+ * (reservoir sampling). Only the tuples that are taken into the sample are decoded.
+ * The sample is ordered by ctid.
+ */
+unsafe fn scan_table_sampled(mut tuples: TupleStream, sample_rows: usize) -> Vec<ScannedTuple> {
+    let mut results: Vec<ScannedTuple> = Vec::new();
+    let mut rng = rand::thread_rng();
+    let mut seen: usize = 0;
+
+    while let Some((htup, tupdesc)) = tuples.next_matching() {
+        /* Determine the position of the tuple in the reservoir (Algorithm R) */
+        let position = if seen < sample_rows {
+            Some(results.len())
+        } else {
+            let position = rng.gen_range(0..=seen);
+            (position < sample_rows).then_some(position)
+        };
+        seen += 1;
+
+        let position = match position {
+            Some(position) => position,
+            None => continue,
+        };
+
+        let tuple = tuples.decode(htup, tupdesc);
+
+        if position < results.len() {
+            results[position] = tuple;
+        } else {
+            results.push(tuple);
+        }
+    }
+
+    results.sort_by_key(|tuple| item_pointer_get_both(tuple.ctid));
+
+    results
+}
+
+/*
+ * A table scan that decodes the visible tuples one at a time, so the table does
+ * not have to be materialized in memory. The scan is kept open between the calls
+ * and is closed when all tuples are returned or the stream is dropped.
+ */
+struct TupleStream {
+    table_rel: pg_sys::Relation,
+    heap_scan: Option<HeapScan>,
+    /* Only tuples created or deleted by one of these transactions are returned */
+    xid_filter: Option<HashSet<pg_sys::TransactionId>>,
+    /* Only tuples matching the created_by, deleted_by and xmin_between conditions */
+    xid_conditions: Option<XidConditions>,
+    /* Only these attributes are decoded */
+    columns: Option<Vec<String>>,
+    /* The checks of the tuple headers, if enabled */
+    tuple_check: Option<TupleCheck>,
+    /*
+     * Memory context for decoding a tuple, which is reset after each tuple. It is
+     * released together with the memory context the stream is opened in.
+     */
+    tuple_context: pg_sys::MemoryContext,
+}
+
+impl TupleStream {
+    /*
+     * Open the table with the given name and start the scan, see open_relation
+     */
+    unsafe fn open(
+        table: &str,
+        snapshot_data: *mut SnapshotData,
+        xids: Option<Vec<i64>>,
+        min_page_lsn: Option<pg_sys::XLogRecPtr>,
+        block_range: Option<RangeInclusive<pg_sys::BlockNumber>>,
+    ) -> TupleStream {
+        TupleStream::open_relation(
+            RegClass(get_relid_from_name(table)),
+            snapshot_data,
+            xids,
+            min_page_lsn,
+            block_range,
+            None,
+            HeapScanOptions::default(),
+        )
+    }
+
+    /*
+     * Open the relation and start the scan. If min_page_lsn is set, only the tuples of
+     * pages modified since this LSN are returned. If block_range is set, only the
+     * blocks of this range are read. If filter is set, only the tuples matching the
+     * filter are returned. The scan options define the direction and the flags of
+     * the heap scan.
+     */
+    unsafe fn open_relation(
+        rel: RegClass,
+        snapshot_data: *mut SnapshotData,
+        xids: Option<Vec<i64>>,
+        min_page_lsn: Option<pg_sys::XLogRecPtr>,
+        block_range: Option<RangeInclusive<pg_sys::BlockNumber>>,
+        filter: Option<&str>,
+        scan_options: HeapScanOptions,
+    ) -> TupleStream {
+        let table = rel.name();
+
+        if LOG_SNAPSHOT.get() {
+            info!("Reading table {table}");
+
+            info!(
+                "Snapshot is (xmin={}, xmax={}, xcnt={})",
+                (*snapshot_data).xmin,
+                (*snapshot_data).xmax,
+                (*snapshot_data).xcnt
+            );
+        }
+
+        let table_rel = open_heap_relation_extended(rel.0, scan_options.bypass_rls);
+        check_frozen_horizon(&table, table_rel, snapshot_data);
+
+        TupleStream {
+            table_rel,
+            heap_scan: Some(HeapScan::begin(
+                table_rel,
+                snapshot_data,
+                min_page_lsn,
+                block_range,
+                filter,
+                scan_options,
+            )),
+            xid_filter: xids.map(|xids| xid_filter(&xids)),
+            xid_conditions: None,
+            columns: None,
+            tuple_check: None,
+            tuple_context: pg_sys::AllocSetContextCreateExtended(
+                pg_sys::CurrentMemoryContext,
+                b"pg_debug_scan tuple context\0".as_ptr() as *const std::os::raw::c_char,
+                pg_sys::ALLOCSET_DEFAULT_MINSIZE as usize,
+                pg_sys::ALLOCSET_DEFAULT_INITSIZE as usize,
+                pg_sys::ALLOCSET_DEFAULT_MAXSIZE as usize,
+            ),
+        }
+    }
+
+    /*
+     * Only decode the given attributes of the tuples
+     */
+    unsafe fn with_columns(mut self, columns: Option<Vec<String>>) -> TupleStream {
+        if let Some(columns) = &columns {
+            let relid = (*self.table_rel).rd_id;
+
+            for column in columns {
+                let column_name =
+                    CString::new(column.as_str()).expect("Unable to convert to string");
+
+                if pg_sys::get_attnum(relid, column_name.as_ptr()) <= 0 {
+                    error!("Column {column} does not exist");
+                }
+            }
+        }
+
+        self.columns = columns;
+        self
+    }
+
+    /*
+     * Only return the tuples matching the given transaction conditions
+     */
+    fn with_xid_conditions(mut self, xid_conditions: Option<XidConditions>) -> TupleStream {
+        self.xid_conditions = xid_conditions;
+        self
+    }
+
+    /*
+     * Check the header of each tuple before it is decoded, see TupleCheck
+     */
+    unsafe fn with_check(mut self, check: bool) -> TupleStream {
+        self.tuple_check = check.then(|| TupleCheck::new(self.table_rel));
+        self
+    }
+
+    /*
+     * Get the next tuple that matches the xid filter and the xid conditions without
+     * decoding it. The tuple
+     * is valid until the next call.
+     */
+    unsafe fn next_matching(&mut self) -> Option<(*mut pg_sys::HeapTupleData, pg_sys::TupleDesc)> {
+        let heap_scan = self.heap_scan.as_mut()?;
+
+        while let Some((htup, tupdesc)) = heap_scan.next_tuple() {
+            if !tuple_matches(
+                (*htup).t_data,
+                self.xid_filter.as_ref(),
+                self.xid_conditions.as_ref(),
+            ) {
+                continue;
+            }
+
+            if let Some(progress) = heap_scan.progress.as_mut() {
+                progress.tuple_returned();
+            }
+
+            return Some((htup, tupdesc));
+        }
+
+        /* Release the scan and the relation as soon as all tuples are read */
+        self.close();
+
+        None
+    }
+
+    /*
+     * Check if the current tuple of the scan is visible under the given snapshot,
+     * which may differ from the snapshot of the scan. Like in heapgetpage, the
+     * buffer of the tuple is locked during the check, since hint bits may be set.
+     */
+    unsafe fn satisfies_snapshot(
+        &self,
+        htup: *mut pg_sys::HeapTupleData,
+        snapshot_data: *mut SnapshotData,
+    ) -> bool {
+        self.with_current_page(|buffer, _page| {
+            pg_sys::HeapTupleSatisfiesVisibility(htup, snapshot_data, buffer)
+        })
+    }
+
+    /*
+     * Call func with the buffer and the page of the current tuple of the scan. The
+     * buffer is pinned by the scan and share locked while func is called.
+     */
+    unsafe fn with_current_page<T>(
+        &self,
+        func: impl FnOnce(pg_sys::Buffer, pg_sys::Page) -> T,
+    ) -> T {
+        let heap_scan = self
+            .heap_scan
+            .as_ref()
+            .expect("the scan of the tuple is closed");
+        let buffer = (*(heap_scan.scan as pg_sys::HeapScanDesc)).rs_cbuf;
+
+        pg_sys::LockBuffer(buffer, pg_sys::BUFFER_LOCK_SHARE as i32);
+        let result = func(buffer, BufferGetPage(buffer));
+        pg_sys::LockBuffer(buffer, pg_sys::BUFFER_LOCK_UNLOCK as i32);
+
+        result
+    }
+
+    /*
+     * Decode the given tuple of the scan. The memory allocated by the output functions
+     * and for detoasted values is released after each tuple, so the memory usage does
+     * not grow with the size of the table. If the header checks are enabled, a tuple
+     * with a failed check is not decoded, since its attributes cannot be located.
+     */
+    unsafe fn decode(
+        &self,
+        htup: *mut pg_sys::HeapTupleData,
+        tupdesc: pg_sys::TupleDesc,
+    ) -> ScannedTuple {
+        let violations = match &self.tuple_check {
+            Some(tuple_check) => {
+                let (_, offnum) = item_pointer_get_both((*htup).t_self);
+                self.with_current_page(|_buffer, page| tuple_check.check(page, offnum))
+            }
+            None => Vec::new(),
+        };
+
+        if !violations.is_empty() {
+            let mut tuple = ScannedTuple::from_heap_tuple(htup, Vec::new());
+            tuple.violations = violations
+                .into_iter()
+                .map(|(check, message)| format!("{check}: {message}"))
+                .collect();
+            return tuple;
+        }
+
+        let relid = (*self.table_rel).rd_id;
+        let columns = self.columns.as_deref();
+
+        let attributes =
+            self.in_tuple_context(|| decode_tuple_columns(relid, htup, tupdesc, columns));
+
+        ScannedTuple::from_heap_tuple(htup, attributes)
+    }
+
+    /*
+     * Call func in the memory context for the current tuple. The context is reset
+     * before func is called, so the memory of the previous tuple is released and the
+     * result of func (e.g., a datum of the returned row) stays valid until the next
+     * tuple is processed.
+     */
+    unsafe fn in_tuple_context<T>(&self, func: impl FnOnce() -> T) -> T {
+        pg_sys::MemoryContextReset(self.tuple_context);
+        PgMemoryContexts::For(self.tuple_context).switch_to(|_| func())
+    }
+
+    /*
+     * End the scan and close the relation
+     */
+    fn close(&mut self) {
+        let Some(heap_scan) = self.heap_scan.take() else {
+            return;
+        };
+
+        drop(heap_scan);
+
+        if resources_releasable() {
+            unsafe { pg_sys::table_close(self.table_rel, pg_sys::AccessShareLock as i32) };
+        }
+    }
+}
+
+impl Iterator for TupleStream {
+    type Item = ScannedTuple;
+
+    fn next(&mut self) -> Option<ScannedTuple> {
+        unsafe {
+            let (htup, tupdesc) = self.next_matching()?;
+            Some(self.decode(htup, tupdesc))
+        }
+    }
+}
+
+impl Drop for TupleStream {
+    fn drop(&mut self) {
+        /* The stream is dropped before all tuples are read (e.g., due to a LIMIT) */
+        self.close();
+    }
+}
+
+/*
+ * Check if the tuple was created or deleted by one of the transactions of the xid
+ * filter and matches the xid conditions
+ */
+unsafe fn tuple_matches(
+    tup: *mut pg_sys::HeapTupleHeaderData,
+    xid_filter: Option<&HashSet<pg_sys::TransactionId>>,
+    xid_conditions: Option<&XidConditions>,
+) -> bool {
+    if let Some(xid_filter) = xid_filter {
+        let xmin = pg_sys::HeapTupleHeaderGetXmin(tup);
+        let xmax = HeapTupleHeaderGetXmax(tup);
+
+        if !xid_filter.contains(&xmin) && !xid_filter.contains(&xmax) {
+            return false;
+        }
+    }
+
+    xid_conditions.map_or(true, |xid_conditions| xid_conditions.matches(tup))
+}
+
+/*
+ * Check if the resources of a scan can be released explicitly. During an error, the
+ * resources are released by the resource owner of the aborted transaction.
+ */
+fn resources_releasable() -> bool {
+    !std::thread::panicking() && unsafe { pg_sys::IsTransactionState() }
+}
+
+/*
+ * Conditions on the transactions that created and deleted a tuple, which are
+ * checked before the tuple is decoded
+ */
+struct XidConditions {
+    created_by: Option<pg_sys::TransactionId>,
+    deleted_by: Option<pg_sys::TransactionId>,
+    xmin_range: Option<(Bound<pg_sys::TransactionId>, Bound<pg_sys::TransactionId>)>,
+}
+
+impl XidConditions {
+    /*
+     * Create the conditions from the parameters of pg_debug_scan. None is returned if
+     * no condition is given, so the tuples do not have to be checked.
+     */
+    fn new(
+        created_by: Option<i64>,
+        deleted_by: Option<i64>,
+        xmin_between: Option<Range<i64>>,
+    ) -> Option<XidConditions> {
+        if created_by.is_none() && deleted_by.is_none() && xmin_between.is_none() {
+            return None;
+        }
+
+        let xmin_range = xmin_between.map(|range| {
+            if range.is_empty() {
+                /* An empty range does not contain any xid */
+                return (Bound::Excluded(0), Bound::Excluded(0));
+            }
+
+            let bound = |bound: Option<&RangeBound<i64>>| match bound {
+                Some(RangeBound::Inclusive(xid)) => {
+                    Bound::Included(xid_from_i64(*xid, "xmin_between"))
+                }
+                Some(RangeBound::Exclusive(xid)) => {
+                    Bound::Excluded(xid_from_i64(*xid, "xmin_between"))
+                }
+                Some(RangeBound::Infinite) | None => Bound::Unbounded,
+            };
+
+            (bound(range.lower()), bound(range.upper()))
+        });
+
+        Some(XidConditions {
+            created_by: created_by.map(|xid| xid_from_i64(xid, "created_by")),
+            deleted_by: deleted_by.map(|xid| xid_from_i64(xid, "deleted_by")),
+            xmin_range,
+        })
+    }
+
+    /*
+     * Check if the tuple matches all conditions. A tuple is deleted by a transaction
+     * if the transaction is the updating xid of xmax, so tuples that are only locked
+     * by the transaction do not match.
+     */
+    unsafe fn matches(&self, tup: *const pg_sys::HeapTupleHeaderData) -> bool {
+        let xmin = pg_sys::HeapTupleHeaderGetXmin(tup);
+
+        if self.created_by.is_some_and(|created_by| created_by != xmin) {
+            return false;
+        }
+
+        if let Some(deleted_by) = self.deleted_by {
+            let infomask = (*tup).t_infomask as u32;
+
+            if infomask & pg_sys::HEAP_XMAX_INVALID != 0
+                || infomask & pg_sys::HEAP_XMAX_LOCK_ONLY != 0
+                || HeapTupleHeaderGetUpdateXid(tup) != deleted_by
+            {
+                return false;
+            }
+        }
+
+        self.xmin_range
+            .map_or(true, |xmin_range| xmin_range.contains(&xmin))
+    }
+}
+
+/* The direction and the flags of a heap scan */
+#[derive(Clone, Copy)]
+struct HeapScanOptions {
+    direction: pg_sys::ScanDirection,
+    flags: u32,
+    /* Only read a random sample of the blocks, see BlockSampler */
+    sample_percent: Option<f64>,
+    /* Allow the owner to scan a table with row-level security, see check_scan_privileges */
+    bypass_rls: bool,
+}
+
+impl Default for HeapScanOptions {
+    /*
+     * A forward scan that reads the pages one tuple at a time from the first block
+     */
+    fn default() -> HeapScanOptions {
+        HeapScanOptions::new("forward", false, false)
+    }
+}
+
+impl HeapScanOptions {
+    /*
+     * Create the scan options from the given direction (forward or backward). With
+     * syncscan, the scan may start in the middle of the table to join other scans
+     * (synchronize_seqscans), with pagemode, the visibility of all tuples of a page
+     * is checked at once, like the sequential scans of the executor do. Large tables
+     * are always read using a bulk read buffer ring, so the scan does not evict the
+     * working set from shared_buffers.
+     */
+    fn new(direction: &str, syncscan: bool, pagemode: bool) -> HeapScanOptions {
+        let direction = match direction {
+            "forward" => pg_sys::ScanDirection_ForwardScanDirection,
+            "backward" => pg_sys::ScanDirection_BackwardScanDirection,
+            _ => error!(
+                "Unknown scan direction {direction}, valid directions are forward and backward"
+            ),
+        };
+
+        let mut flags = pg_sys::ScanOptions_SO_ALLOW_STRAT;
+
+        if syncscan {
+            flags |= pg_sys::ScanOptions_SO_ALLOW_SYNC;
+        }
+
+        if pagemode {
+            flags |= pg_sys::ScanOptions_SO_ALLOW_PAGEMODE;
+        }
+
+        HeapScanOptions {
+            direction,
+            flags,
+            sample_percent: None,
+            bypass_rls: false,
+        }
+    }
+
+    /*
+     * Only read the given percentage of the blocks, like TABLESAMPLE SYSTEM. The
+     * blocks are skipped by the scan, so they are not read at all.
+     */
+    fn with_sample_percent(mut self, sample_percent: Option<f64>) -> HeapScanOptions {
+        if let Some(sample_percent) = sample_percent {
+            if !(0.0..=100.0).contains(&sample_percent) {
+                error!(
+                    "Invalid sample percentage {sample_percent}, it has to be between 0 and 100"
+                );
+            }
+        }
+
+        self.sample_percent = sample_percent;
+        self
+    }
+
+    /*
+     * Allow the owner of a table with row-level security to scan all of its tuples.
+     * The policies cannot be applied by the scan.
+     */
+    fn with_bypass_rls(mut self, bypass_rls: bool) -> HeapScanOptions {
+        self.bypass_rls = bypass_rls;
+        self
+    }
+}
+
+/*
+ * Selects each block of a range with the given probability. The selected blocks
+ * are scanned one after another by limiting the heap scan to each of them.
+ */
+struct BlockSampler {
+    next_block: pg_sys::BlockNumber,
+    end_block: pg_sys::BlockNumber,
+    sample_percent: f64,
+}
+
+impl BlockSampler {
+    /*
+     * Get the next selected block of the range
+     */
+    fn next_block(&mut self) -> Option<pg_sys::BlockNumber> {
+        let mut rng = rand::thread_rng();
+
+        while self.next_block < self.end_block {
+            let blkno = self.next_block;
+            self.next_block += 1;
+
+            if rng.gen::<f64>() * 100.0 < self.sample_percent {
+                return Some(blkno);
+            }
+        }
+
+        None
+    }
+}
+
+/* An open heap scan of a relation, which is ended when dropped */
+struct HeapScan {
+    scan: pg_sys::TableScanDesc,
+    slot: *mut pg_sys::TupleTableSlot,
+    /* Skip the tuples of pages that were not modified since this LSN */
+    min_page_lsn: Option<pg_sys::XLogRecPtr>,
+    direction: pg_sys::ScanDirection,
+    /* Only the blocks selected by the sampler are read */
+    block_sampler: Option<BlockSampler>,
+    /* Report the progress of the scan, see pg_debug_scan.progress_interval */
+    progress: Option<ScanProgress>,
+    /* Limit the number of concurrent scans, see pg_debug_scan.max_concurrent_scans */
+    _scan_slot: ScanSlot,
+}
+
+impl HeapScan {
+    /*
+     * Start a heap scan of the given relation using the snapshot. The scan can be
+     * limited to a range of blocks and to the tuples matching a filter.
+     */
+    unsafe fn begin(
+        table_rel: pg_sys::Relation,
+        snapshot_data: *mut SnapshotData,
+        min_page_lsn: Option<pg_sys::XLogRecPtr>,
+        block_range: Option<RangeInclusive<pg_sys::BlockNumber>>,
+        filter: Option<&str>,
+        scan_options: HeapScanOptions,
+    ) -> HeapScan {
+        let scan_slot = ScanSlot::acquire();
+
+        let slot = pg_sys::table_slot_create(table_rel, std::ptr::null_mut());
+
+        /* The scan key is copied by heap_beginscan */
+        let mut scan_key = filter.map(|filter| filter_scan_key(table_rel, filter));
+        let (nkeys, key) = match scan_key.as_mut() {
+            Some(scan_key) => (1, scan_key as *mut pg_sys::ScanKeyData),
+            None => (0, std::ptr::null_mut()),
+        };
+
+        /*
+         * Register a copy of an MVCC snapshot for the duration of the scan, like
+         * table_beginscan_catalog. A registered snapshot keeps the xmin of the backend
+         * from advancing past the snapshot, so the tuples visible under it (e.g., of an
+         * imported snapshot) are not removed while the table is scanned. The copy is
+         * unregistered by heap_endscan.
+         */
+        let (snapshot_data, flags) =
+            if (*snapshot_data).snapshot_type == pg_sys::SnapshotType_SNAPSHOT_MVCC {
+                let mut snapshot_copy = std::ptr::read(snapshot_data);
+                snapshot_copy.copied = false;
+
+                (
+                    pg_sys::RegisterSnapshot(&mut snapshot_copy),
+                    scan_options.flags | pg_sys::ScanOptions_SO_TEMP_SNAPSHOT,
+                )
+            } else {
+                (snapshot_data, scan_options.flags)
+            };
+
+        let scan = pg_sys::heap_beginscan(
+            table_rel,
+            snapshot_data,
+            nkeys,
+            key,
+            std::ptr::null_mut(),
+            flags,
+        );
+
+        let nblocks = (*(scan as pg_sys::HeapScanDesc)).rs_nblocks;
+        let mut block_sampler = None;
+
+        if let Some(block_range) = &block_range {
+            let start_block = *block_range.start();
+
+            /* The end of the range might be behind the end of the relation */
+            let numblocks = match nblocks.checked_sub(1) {
+                Some(last_block) if start_block <= last_block => {
+                    (*block_range.end()).min(last_block) - start_block + 1
+                }
+                _ => 0,
+            };
+
+            pg_sys::heap_setscanlimits(scan, start_block, numblocks);
+        }
+
+        /* The progress is relative to the block range, not to the sampled blocks */
+        let progress = ScanProgress::new(scan, scan_options.direction);
+
+        if let Some(sample_percent) = scan_options.sample_percent {
+            let (next_block, end_block) = match block_range {
+                Some(block_range) => (
+                    *block_range.start(),
+                    block_range.end().saturating_add(1).min(nblocks),
+                ),
+                None => (0, nblocks),
+            };
+
+            let mut sampler = BlockSampler {
+                next_block,
+                end_block,
+                sample_percent,
+            };
+
+            match sampler.next_block() {
+                Some(blkno) => pg_sys::heap_setscanlimits(scan, blkno, 1),
+                None => pg_sys::heap_setscanlimits(scan, 0, 0),
+            }
+
+            block_sampler = Some(sampler);
+        }
+
+        HeapScan {
+            scan,
+            slot,
+            min_page_lsn,
+            direction: scan_options.direction,
+            block_sampler,
+            progress,
+            _scan_slot: scan_slot,
+        }
+    }
+
+    /*
+     * Continue the scan with the next block selected by the block sampler. The scan
+     * limits are reset by a rescan, so they are set again afterwards.
+     */
+    unsafe fn next_sampled_block(&mut self) -> bool {
+        let Some(blkno) = self
+            .block_sampler
+            .as_mut()
+            .and_then(BlockSampler::next_block)
+        else {
+            return false;
+        };
+
+        pg_sys::heap_rescan(self.scan, std::ptr::null_mut(), false, false, false, false);
+        pg_sys::heap_setscanlimits(self.scan, blkno, 1);
+
+        true
+    }
+
+    /*
+     * Get the next tuple of the scan and its descriptor. The tuple is valid until
+     * the next call.
+     */
+    unsafe fn next_tuple(&mut self) -> Option<(*mut pg_sys::HeapTupleData, pg_sys::TupleDesc)> {
+        loop {
+            if let Some(tuple) = self.next_tuple_of_limits() {
+                return Some(tuple);
+            }
+
+            if !self.next_sampled_block() {
+                if let Some(mut progress) = self.progress.take() {
+                    progress.finish();
+                }
+
+                return None;
+            }
+        }
+    }
+
+    /*
+     * Get the next tuple within the current scan limits, see next_tuple
+     */
+    unsafe fn next_tuple_of_limits(
+        &mut self,
+    ) -> Option<(*mut pg_sys::HeapTupleData, pg_sys::TupleDesc)> {
+        while pg_sys::heap_getnextslot(self.scan, self.direction, self.slot) {
+            /* Allow to cancel long scans and honor statement_timeout */
+            check_for_interrupts!();
+
+            if let Some(min_page_lsn) = self.min_page_lsn {
+                let buffer = (*(self.scan as pg_sys::HeapScanDesc)).rs_cbuf;
+
+                if PageGetLSN(BufferGetPage(buffer)) < min_page_lsn {
+                    continue;
+                }
+            }
+
+            /* No Rust port for slot_getsysattr available, so use HeapTupleHeaderGetXmin on the heap tuple */
+            let get_heap_tuple_fn = (*(*self.slot).tts_ops).get_heap_tuple.unwrap();
+            let htup = get_heap_tuple_fn(self.slot);
+            let tupdesc = (*self.slot).tts_tupleDescriptor;
+
+            report_dirty_snapshot((*self.scan).rs_snapshot, (*htup).t_self);
+
+            if let Some(progress) = self.progress.as_mut() {
+                progress.tuple_read((*(self.scan as pg_sys::HeapScanDesc)).rs_cblock);
+            }
+
+            return Some((htup, tupdesc));
+        }
+
+        None
+    }
+}
+
+impl Drop for HeapScan {
+    fn drop(&mut self) {
+        if resources_releasable() {
+            unsafe {
+                pg_sys::heap_endscan(self.scan);
+                pg_sys::ExecDropSingleTupleTableSlot(self.slot);
+            }
+        }
+    }
+}
+
+/*
+ * Perform a heap scan of the given relation using the snapshot and call func for
+ * each scanned tuple
+ */
+pub(crate) unsafe fn scan_heap(
+    table_rel: pg_sys::Relation,
+    snapshot_data: *mut SnapshotData,
+    mut func: impl FnMut(*mut pg_sys::HeapTupleData, pg_sys::TupleDesc),
+) {
+    let mut heap_scan = HeapScan::begin(
+        table_rel,
+        snapshot_data,
+        None,
+        None,
+        None,
+        HeapScanOptions::default(),
+    );
+
+    while let Some((htup, tupdesc)) = heap_scan.next_tuple() {
+        func(htup, tupdesc);
+    }
+}
+
+/*
+ * Decode the attributes of the given tuple into their text representation
+ */
+pub(crate) unsafe fn decode_tuple(
+    relid: pgrx::pg_sys::Oid,
+    htup: *mut pgrx::pg_sys::HeapTupleData,
+    tupdesc: *mut pgrx::pg_sys::TupleDescData,
+) -> Vec<AttributeValue> {
+    decode_tuple_columns(relid, htup, tupdesc, None)
+}
+
+/*
+ * Get the raw bytes of a value of a dropped column as hex string. Only the length
+ * and the alignment of the column are known, values passed by value are returned
+ * in the byte order of the server.
+ */
+unsafe fn dropped_attribute_bytes(
+    attr: pg_sys::Datum,
+    attr_form_data: &pg_sys::FormData_pg_attribute,
+) -> String {
+    let attlen = attr_form_data.attlen;
+
+    let bytes = if attr_form_data.attbyval {
+        attr.value().to_ne_bytes()[..attlen as usize].to_vec()
+    } else {
+        let ptr = attr.cast_mut_ptr::<u8>();
+        let len = match attlen {
+            -1 => pgrx::varlena::varsize_any(ptr as *const pg_sys::varlena),
+            -2 => CStr::from_ptr(ptr as *const std::os::raw::c_char)
+                .to_bytes()
+                .len(),
+            attlen => attlen as usize,
+        };
+
+        std::slice::from_raw_parts(ptr, len).to_vec()
+    };
+
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("\\x{hex}")
+}
+
+/*
+ * Decode a single attribute of the tuple into its type and text representation
+ */
+pub(crate) unsafe fn decode_attribute(
+    htup: *mut pgrx::pg_sys::HeapTupleData,
+    tupdesc: *mut pgrx::pg_sys::TupleDescData,
+    attr_form_data: &pg_sys::FormData_pg_attribute,
+) -> (pg_sys::Oid, Option<String>) {
+    let mut isnull: bool = false;
+    let attr = pg_sys::heap_getattr(htup, attr_form_data.attnum.into(), tupdesc, &mut isnull);
+
+    /* The type of a dropped column is unknown, so its raw bytes are returned */
+    if attr_form_data.attisdropped {
+        let value = (!isnull).then(|| dropped_attribute_bytes(attr, attr_form_data));
+        return (pg_sys::TEXTOID, value);
+    }
+
+    let typid = attr_form_data.atttypid;
+
+    if isnull {
+        return (typid, None);
+    }
+
+    if attr_form_data.attlen == -1 && !DETOAST.get() {
+        /* Report the TOAST pointer of external values instead of fetching them */
+        if let VarlenaStorage::External(toast_pointer) =
+            varlena_storage(attr.cast_mut_ptr::<pg_sys::varlena>())
+        {
+            return (pg_sys::JSONOID, Some(toast_pointer.to_json()));
+        }
+    }
+
+    (typid, Some(output_datum(typid, attr)))
+}
+
+/*
+ * Call func in a subtransaction and return the message of the error it raises. Like
+ * the exception blocks of PL/pgSQL, the subtransaction is rolled back on an error,
+ * so the buffer pins, locks and memory of the failed call are released. No
+ * subtransaction can be started in parallel mode, so the error is raised there.
+ */
+pub(crate) unsafe fn try_in_subtransaction<T>(
+    func: impl FnOnce() -> T + std::panic::UnwindSafe,
+) -> Result<T, String> {
+    if pg_sys::IsInParallelMode() {
+        return Ok(func());
+    }
+
+    let oldcontext = pg_sys::CurrentMemoryContext;
+    let oldowner = pg_sys::CurrentResourceOwner;
+
+    pg_sys::BeginInternalSubTransaction(std::ptr::null());
+    /* The result of func is allocated in the memory context of the caller */
+    pg_sys::CurrentMemoryContext = oldcontext;
+
+    PgTryBuilder::new(move || {
+        let result = func();
+
+        pg_sys::ReleaseCurrentSubTransaction();
+        pg_sys::CurrentMemoryContext = oldcontext;
+        pg_sys::CurrentResourceOwner = oldowner;
+
+        Ok(result)
+    })
+    .catch_others(|cause| {
+        pg_sys::CurrentMemoryContext = oldcontext;
+        pg_sys::FlushErrorState();
+
+        pg_sys::RollbackAndReleaseCurrentSubTransaction();
+        pg_sys::CurrentMemoryContext = oldcontext;
+        pg_sys::CurrentResourceOwner = oldowner;
+
+        match cause {
+            CaughtError::PostgresError(report)
+            | CaughtError::ErrorReport(report)
+            | CaughtError::RustPanic {
+                ereport: report, ..
+            } => Err(report.message().to_string()),
+        }
+    })
+    .execute()
+}
+
+/*
+ * Decode a single attribute of the tuple, see decode_attribute. A corrupted value
+ * must not abort the whole scan, so errors are reported as value.
+ */
+pub(crate) unsafe fn decode_attribute_or_error(
+    htup: *mut pgrx::pg_sys::HeapTupleData,
+    tupdesc: *mut pgrx::pg_sys::TupleDescData,
+    attr_form_data: &pg_sys::FormData_pg_attribute,
+) -> (pg_sys::Oid, Option<String>) {
+    try_in_subtransaction(|| decode_attribute(htup, tupdesc, attr_form_data))
+        .unwrap_or_else(|message| (pg_sys::TEXTOID, Some(format!("<decode error: {message}>"))))
+}
+
+/*
+ * Decode the given attributes of the tuple into their text representation. If no
+ * attributes are given, all attributes are decoded. The output functions are only
+ * called for the decoded attributes. If an attribute can not be decoded (e.g., a
+ * corrupted varlena), the error is returned as its value and the scan continues.
+ * The whole tuple is decoded in a single subtransaction. Only if this fails, the
+ * attributes are decoded again, each in its own subtransaction.
+ */
+unsafe fn decode_tuple_columns(
+    relid: pgrx::pg_sys::Oid,
+    htup: *mut pgrx::pg_sys::HeapTupleData,
+    tupdesc: *mut pgrx::pg_sys::TupleDescData,
+    columns: Option<&[String]>,
+) -> Vec<AttributeValue> {
+    try_in_subtransaction(|| decode_attributes(relid, htup, tupdesc, columns, decode_attribute))
+        .unwrap_or_else(|_| {
+            decode_attributes(relid, htup, tupdesc, columns, decode_attribute_or_error)
+        })
+}
+
+/*
+ * Decode the given attributes of the tuple with the given function, see
+ * decode_tuple_columns
+ */
+unsafe fn decode_attributes(
+    relid: pgrx::pg_sys::Oid,
+    htup: *mut pgrx::pg_sys::HeapTupleData,
+    tupdesc: *mut pgrx::pg_sys::TupleDescData,
+    columns: Option<&[String]>,
+    decode: unsafe fn(
+        *mut pgrx::pg_sys::HeapTupleData,
+        *mut pgrx::pg_sys::TupleDescData,
+        &pg_sys::FormData_pg_attribute,
+    ) -> (pg_sys::Oid, Option<String>),
+) -> Vec<AttributeValue> {
+    let mut attributes = Vec::new();
+
+    let nattrs = (*tupdesc).natts as usize;
+    let attrs = (*tupdesc).attrs.as_slice(nattrs);
+
+    for attr_form_data in attrs.iter().take(nattrs) {
+        if attr_form_data.attisdropped && !INCLUDE_DROPPED_COLUMNS.get() {
+            continue;
+        }
+
+        /* Since we perform a plain table scan, each attribute should belong to the same base relation */
+        assert!(
+            relid == attr_form_data.attrelid,
+            "attr and base relation have a different Oids {relid} {}",
+            attr_form_data.attrelid
+        );
+
+        let attno = attr_form_data.attnum;
+        assert!(attno > 0, "invalid attr no found during scan {attno}");
+
+        let colname_ptr = pg_sys::get_attname(relid, attno, false);
+        let colname = CStr::from_ptr(colname_ptr).to_str().unwrap().to_string();
+
+        if columns.is_some_and(|columns| !columns.contains(&colname)) {
+            continue;
+        }
+
+        let (typid, value) = decode(htup, tupdesc, attr_form_data);
+
+        attributes.push(AttributeValue {
+            name: colname,
+            typid,
+            value,
+        });
+    }
+
+    attributes
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct TemperatureJSON {
+        time: String,
+        value: String,
+    }
+
+    /* Get the SQL code for the integration test */
+    fn get_test_sql(attribute: &str, txid: i64) -> String {
+        format!(
+            "SELECT {} FROM pg_debug_scan('temperature', '{}:{}:');",
+            attribute, txid, txid
+        )
+    }
+
+    #[pgrx::pg_test]
+    fn test_parse_snapshot_data() {
+        pgrx::Spi::run("CREATE TABLE temperature (time timestamptz NOT NULL, value float);")
+            .unwrap();
+        pgrx::Spi::run("INSERT INTO temperature VALUES('2024-04-12 15:59:23+02', 1);").unwrap();
+        let txid = pgrx::Spi::get_one::<i64>("SELECT * FROM txid_current();")
+            .unwrap()
+            .expect("unable to get txid");
+
+        /* Test returned xmin value */
+        let xmin =
+            pgrx::Spi::get_one::<i64>(get_test_sql("xmin::text::bigint", txid).as_str()).unwrap();
+        assert_eq!(xmin, Some(txid));
+
+        /* Test returned xmax value */
+        let xmax =
+            pgrx::Spi::get_one::<i64>(get_test_sql("xmax::text::bigint", txid).as_str()).unwrap();
+        assert_eq!(xmax, Some(0));
+
+        /* Test returned xmax value */
+        let json_value = pgrx::Spi::get_one::<String>(get_test_sql("data", txid).as_str())
+            .unwrap()
+            .expect("unable to get json output");
         let tuple_data: TemperatureJSON = serde_json::from_str(json_value.as_str())
             .expect("failed to parse json response from SPI");
-        assert_eq!(tuple_data.time, "2024-04-12 13:59:23+00");
-        assert_eq!(tuple_data.value, "1");
+        assert_eq!(tuple_data.time, "2024-04-12 13:59:23+00");
+        assert_eq!(tuple_data.value, "1");
+    }
+
+    #[pgrx::pg_test]
+    fn test_xid_filter() {
+        pgrx::Spi::run("CREATE TABLE temperature (time timestamptz NOT NULL, value float);")
+            .unwrap();
+        pgrx::Spi::run("INSERT INTO temperature VALUES('2024-04-12 15:59:23+02', 1);").unwrap();
+        let txid = pgrx::Spi::get_one::<i64>("SELECT * FROM txid_current();")
+            .unwrap()
+            .expect("unable to get txid");
+
+        let matching = pgrx::Spi::get_one::<i64>(
+            format!(
+                "SELECT count(*) FROM pg_debug_scan('temperature', xids => ARRAY[{}]::bigint[]);",
+                txid
+            )
+            .as_str(),
+        )
+        .unwrap();
+        assert_eq!(matching, Some(1));
+
+        let other = pgrx::Spi::get_one::<i64>(
+            format!(
+                "SELECT count(*) FROM pg_debug_scan('temperature', xids => ARRAY[{}]::bigint[]);",
+                txid + 1
+            )
+            .as_str(),
+        )
+        .unwrap();
+        assert_eq!(other, Some(0));
+    }
+
+    #[pgrx::pg_test]
+    fn test_xid_conditions() {
+        pgrx::Spi::run("CREATE TABLE temperature (time timestamptz NOT NULL, value float);")
+            .unwrap();
+        pgrx::Spi::run("INSERT INTO temperature VALUES('2024-04-12 15:59:23+02', 1);").unwrap();
+        let txid = pgrx::Spi::get_one::<i64>("SELECT * FROM txid_current();")
+            .unwrap()
+            .expect("unable to get txid");
+
+        let created = pgrx::Spi::get_one::<i64>(
+            format!("SELECT count(*) FROM pg_debug_scan('temperature', created_by => {txid});")
+                .as_str(),
+        )
+        .unwrap();
+        assert_eq!(created, Some(1));
+
+        let deleted = pgrx::Spi::get_one::<i64>(
+            format!("SELECT count(*) FROM pg_debug_scan('temperature', deleted_by => {txid});")
+                .as_str(),
+        )
+        .unwrap();
+        assert_eq!(deleted, Some(0));
+
+        let in_range = pgrx::Spi::get_one::<i64>(
+            format!(
+                "SELECT count(*) FROM pg_debug_scan('temperature', xmin_between => int8range({}, {}));",
+                txid,
+                txid + 1
+            )
+            .as_str(),
+        )
+        .unwrap();
+        assert_eq!(in_range, Some(1));
+
+        let out_of_range = pgrx::Spi::get_one::<i64>(
+            format!(
+                "SELECT count(*) FROM pg_debug_scan('temperature', xmin_between => int8range({}, NULL));",
+                txid + 1
+            )
+            .as_str(),
+        )
+        .unwrap();
+        assert_eq!(out_of_range, Some(0));
+    }
+
+    #[pgrx::pg_test]
+    fn test_structured_snapshot() {
+        pgrx::Spi::run("CREATE TABLE temperature (time timestamptz NOT NULL, value float);")
+            .unwrap();
+        pgrx::Spi::run("INSERT INTO temperature VALUES('2024-04-12 15:59:23+02', 1);").unwrap();
+        let txid = pgrx::Spi::get_one::<i64>("SELECT * FROM txid_current();")
+            .unwrap()
+            .expect("unable to get txid");
+
+        let xmin = pgrx::Spi::get_one::<i64>(
+            format!("SELECT xmin::text::bigint FROM pg_debug_scan('temperature', {txid}, {txid});")
+                .as_str(),
+        )
+        .unwrap();
+        assert_eq!(xmin, Some(txid));
+    }
+
+    #[pgrx::pg_test]
+    fn test_json_snapshot() {
+        pgrx::Spi::run("CREATE TABLE temperature (time timestamptz NOT NULL, value float);")
+            .unwrap();
+        pgrx::Spi::run("INSERT INTO temperature VALUES('2024-04-12 15:59:23+02', 1);").unwrap();
+        let txid = pgrx::Spi::get_one::<i64>("SELECT * FROM txid_current();")
+            .unwrap()
+            .expect("unable to get txid");
+
+        let xmin = pgrx::Spi::get_one::<i64>(
+            format!(
+                "SELECT xmin::text::bigint FROM pg_debug_scan('temperature', '{{\"xmin\": {txid}, \"xmax\": {txid}}}'::jsonb);"
+            )
+            .as_str(),
+        )
+        .unwrap();
+        assert_eq!(xmin, Some(txid));
+    }
+
+    #[pgrx::pg_test]
+    fn test_xid8_columns() {
+        pgrx::Spi::run("CREATE TABLE xid8_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO xid8_test VALUES (1);").unwrap();
+
+        /* The xmin includes the epoch like txid_current() */
+        let matches = pgrx::Spi::get_one::<bool>(
+            "SELECT xmin::text = txid_current()::text FROM pg_debug_scan('xid8_test');",
+        )
+        .unwrap();
+        assert_eq!(matches, Some(true));
+
+        /* Snapshots can be specified with 64 bit transaction ids */
+        let rows = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('xid8_test', format('%s:%s:', txid_current(), txid_current()));",
+        )
+        .unwrap();
+        assert_eq!(rows, Some(1));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_tables() {
+        pgrx::Spi::run("CREATE TABLE scan_tables_a (value int);").unwrap();
+        pgrx::Spi::run("CREATE TABLE scan_tables_b (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_tables_a VALUES (1), (2);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_tables_b VALUES (3);").unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan(ARRAY['scan_tables_a', 'scan_tables_b']) WHERE relation = 'scan_tables_a';",
+        )
+        .unwrap();
+        assert_eq!(count, Some(2));
+
+        let data = pgrx::Spi::get_one::<String>(
+            "SELECT data FROM pg_debug_scan(ARRAY['scan_tables_a', 'scan_tables_b']) WHERE relation = 'scan_tables_b';",
+        )
+        .unwrap();
+        assert_eq!(data, Some("{\"value\":\"3\"}".to_string()));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_include_relation() {
+        pgrx::Spi::run("CREATE TABLE include_relation_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO include_relation_test VALUES (1);").unwrap();
+
+        let matches = pgrx::Spi::get_one::<bool>(
+            "SELECT s.tableoid = c.oid AND s.relname = c.relname AND s.relfilenode = c.relfilenode \
+             FROM pg_debug_scan('include_relation_test', include_relation => true) s \
+             JOIN pg_class c ON c.relname = 'include_relation_test';",
+        )
+        .unwrap();
+        assert_eq!(matches, Some(true));
+
+        let omitted = pgrx::Spi::get_one::<bool>(
+            "SELECT tableoid IS NULL AND relname IS NULL AND relfilenode IS NULL \
+             FROM pg_debug_scan('include_relation_test');",
+        )
+        .unwrap();
+        assert_eq!(omitted, Some(true));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_regclass() {
+        pgrx::Spi::run("CREATE TABLE \"Regclass_Test\" (value int);").unwrap();
+        pgrx::Spi::run("CREATE TEMPORARY TABLE regclass_temp (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO \"Regclass_Test\" VALUES (1), (2);").unwrap();
+        pgrx::Spi::run("INSERT INTO regclass_temp VALUES (3);").unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('\"Regclass_Test\"'::regclass);",
+        )
+        .unwrap();
+        assert_eq!(count, Some(2));
+
+        let data = pgrx::Spi::get_one::<String>(
+            "SELECT data FROM pg_debug_scan('regclass_temp'::regclass, snapshot_mode => 'any');",
+        )
+        .unwrap();
+        assert_eq!(data, Some("{\"value\":\"3\"}".to_string()));
+    }
+
+    #[pgrx::pg_test(
+        error = "scan_view_test is a view, only tables and materialized views are supported"
+    )]
+    fn test_scan_view() {
+        pgrx::Spi::run("CREATE VIEW scan_view_test AS SELECT 1 AS value;").unwrap();
+        pgrx::Spi::run("SELECT * FROM pg_debug_scan('scan_view_test');").unwrap();
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_partitions() {
+        pgrx::Spi::run("CREATE TABLE scan_partitions (id int) PARTITION BY RANGE (id);").unwrap();
+        pgrx::Spi::run(
+            "CREATE TABLE scan_partitions_1 PARTITION OF scan_partitions FOR VALUES FROM (0) TO (10);",
+        )
+        .unwrap();
+        pgrx::Spi::run(
+            "CREATE TABLE scan_partitions_2 PARTITION OF scan_partitions FOR VALUES FROM (10) TO (20);",
+        )
+        .unwrap();
+        pgrx::Spi::run("INSERT INTO scan_partitions VALUES (1), (2), (11);").unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan_partitions('scan_partitions') WHERE partition = 'scan_partitions_1'::regclass;",
+        )
+        .unwrap();
+        assert_eq!(count, Some(2));
+
+        let data = pgrx::Spi::get_one::<String>(
+            "SELECT data FROM pg_debug_scan_partitions('scan_partitions') WHERE partition = 'scan_partitions_2'::regclass;",
+        )
+        .unwrap();
+        assert_eq!(data, Some("{\"id\":\"11\"}".to_string()));
+    }
+
+    #[pgrx::pg_test]
+    fn test_native_snapshot() {
+        pgrx::Spi::run("CREATE TABLE native_snapshot_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO native_snapshot_test VALUES (1), (2);").unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('native_snapshot_test', txid_current_snapshot());",
+        )
+        .unwrap();
+        assert_eq!(count, Some(2));
+
+        #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+        {
+            let count = pgrx::Spi::get_one::<i64>(
+                "SELECT count(*) FROM pg_debug_scan('native_snapshot_test', pg_current_snapshot());",
+            )
+            .unwrap();
+            assert_eq!(count, Some(2));
+        }
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_ctid() {
+        pgrx::Spi::run("CREATE TABLE ctid_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO ctid_test VALUES (1), (2);").unwrap();
+
+        let location = pgrx::Spi::get_two::<i64, i32>(
+            "SELECT block_number, offset_number FROM pg_debug_scan('ctid_test') WHERE ctid = '(0,2)';",
+        )
+        .unwrap();
+        assert_eq!(location, (Some(0), Some(2)));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_stopped_early() {
+        pgrx::Spi::run("CREATE TABLE stream_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO stream_test SELECT generate_series(1, 1000);").unwrap();
+
+        /* The scan is closed before all tuples are read */
+        let xmin_values = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM (SELECT (pg_debug_scan('stream_test')).xmin LIMIT 10) s;",
+        )
+        .unwrap();
+        assert_eq!(xmin_values, Some(10));
+
+        let count = pgrx::Spi::get_one::<i64>("SELECT count(*) FROM pg_debug_scan('stream_test');")
+            .unwrap();
+        assert_eq!(count, Some(1000));
+    }
+
+    #[pgrx::pg_test]
+    fn test_sample_rows() {
+        pgrx::Spi::run("CREATE TABLE sample_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO sample_test SELECT generate_series(1, 100);").unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('sample_test', sample_rows => 10);",
+        )
+        .unwrap();
+        assert_eq!(count, Some(10));
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('sample_test', sample_rows => 1000);",
+        )
+        .unwrap();
+        assert_eq!(count, Some(100));
+    }
+
+    #[pgrx::pg_test]
+    fn test_min_page_lsn() {
+        pgrx::Spi::run("CREATE TABLE page_lsn_test (value text);").unwrap();
+        pgrx::Spi::run("ALTER TABLE page_lsn_test ALTER COLUMN value SET STORAGE PLAIN;").unwrap();
+
+        /* Two tuples fill the first page */
+        pgrx::Spi::run(
+            "INSERT INTO page_lsn_test SELECT repeat('x', 3000) FROM generate_series(1, 2);",
+        )
+        .unwrap();
+
+        let lsn = pgrx::Spi::get_one::<String>("SELECT pg_current_wal_lsn()::text;")
+            .unwrap()
+            .expect("unable to get the current LSN");
+
+        /* The third tuple is stored on the second page */
+        pgrx::Spi::run("INSERT INTO page_lsn_test VALUES (repeat('y', 3000));").unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(&format!(
+            "SELECT count(*) FROM pg_debug_scan('page_lsn_test', min_page_lsn => '{lsn}');"
+        ))
+        .unwrap();
+        assert_eq!(count, Some(1));
+    }
+
+    #[pgrx::pg_test]
+    fn test_block_range() {
+        pgrx::Spi::run("CREATE TABLE block_range_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO block_range_test SELECT generate_series(1, 1000);").unwrap();
+
+        let blocks = pgrx::Spi::get_one::<Vec<i64>>(
+            "SELECT array_agg(DISTINCT block_number) FROM pg_debug_scan('block_range_test', start_block => 1, end_block => 2);",
+        )
+        .unwrap();
+        assert_eq!(blocks, Some(vec![1, 2]));
+
+        /* Without an end block, the scan continues up to the end of the relation */
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('block_range_test', start_block => 1);",
+        )
+        .unwrap();
+        let expected = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('block_range_test') WHERE block_number >= 1;",
+        )
+        .unwrap();
+        assert_eq!(count, expected);
+    }
+
+    #[pgrx::pg_test]
+    fn test_snapshot_mode() {
+        pgrx::Spi::run("CREATE TABLE snapshot_mode_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO snapshot_mode_test VALUES (1), (2);").unwrap();
+        pgrx::Spi::run("DELETE FROM snapshot_mode_test WHERE value = 1;").unwrap();
+
+        /* SnapshotAny returns the deleted tuple as well */
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('snapshot_mode_test', snapshot_mode => 'any');",
+        )
+        .unwrap();
+        assert_eq!(count, Some(2));
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('snapshot_mode_test', snapshot_mode => 'self');",
+        )
+        .unwrap();
+        assert_eq!(count, Some(1));
+    }
+
+    #[pgrx::pg_test]
+    fn test_max_json_bytes() {
+        pgrx::Spi::run("CREATE TABLE max_json_test (id int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO max_json_test VALUES (1, repeat('x', 1000));").unwrap();
+        pgrx::Spi::run("SET pg_debug_scan.max_json_bytes = 100;").unwrap();
+
+        let data = pgrx::Spi::get_one::<String>("SELECT data FROM pg_debug_scan('max_json_test');")
+            .unwrap()
+            .expect("unable to get json output");
+        assert_eq!(
+            data,
+            "{\"_original_size\":1021,\"_truncated\":true,\"id\":\"1\"}"
+        );
+    }
+
+    #[pgrx::pg_test]
+    fn test_output_format() {
+        pgrx::Spi::run("CREATE TABLE temperature (time timestamptz NOT NULL, value float);")
+            .unwrap();
+        pgrx::Spi::run("INSERT INTO temperature VALUES('2024-04-12 15:59:23+02', 1);").unwrap();
+
+        pgrx::Spi::run("SET pg_debug_scan.output_format = 'typed-json';").unwrap();
+        let json_value =
+            pgrx::Spi::get_one::<String>("SELECT data FROM pg_debug_scan('temperature');")
+                .unwrap()
+                .expect("unable to get json output");
+        let tuple_data: serde_json::Value = serde_json::from_str(json_value.as_str())
+            .expect("failed to parse json response from SPI");
+        assert_eq!(tuple_data["value"], serde_json::json!(1));
+
+        pgrx::Spi::run("SET pg_debug_scan.output_format = 'record-compatible';").unwrap();
+        let value = pgrx::Spi::get_one::<f64>(
+            "SELECT (data::temperature).value FROM pg_debug_scan('temperature');",
+        )
+        .unwrap();
+        assert_eq!(value, Some(1.0));
+    }
+
+    #[pgrx::pg_test]
+    fn test_typed_json_precision() {
+        pgrx::Spi::run("CREATE TABLE typed_json_precision_test (big int8, exact numeric);")
+            .unwrap();
+        pgrx::Spi::run(
+            "INSERT INTO typed_json_precision_test VALUES (9223372036854775807, 0.12345678901234567890123456789);",
+        )
+        .unwrap();
+
+        pgrx::Spi::run("SET pg_debug_scan.output_format = 'typed-json';").unwrap();
+        let data = pgrx::Spi::get_one::<String>(
+            "SELECT data FROM pg_debug_scan('typed_json_precision_test');",
+        )
+        .unwrap();
+        assert_eq!(
+            data,
+            Some(
+                "{\"big\":9223372036854775807,\"exact\":0.12345678901234567890123456789}"
+                    .to_string()
+            )
+        );
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_columns() {
+        pgrx::Spi::run("CREATE TABLE scan_columns_test (id int, name text, payload text);")
+            .unwrap();
+        pgrx::Spi::run("INSERT INTO scan_columns_test VALUES (1, 'a', repeat('x', 100));").unwrap();
+
+        let data = pgrx::Spi::get_one::<String>(
+            "SELECT data FROM pg_debug_scan('scan_columns_test', columns => ARRAY['id', 'name']);",
+        )
+        .unwrap();
+        assert_eq!(data, Some("{\"id\":\"1\",\"name\":\"a\"}".to_string()));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_jsonb() {
+        pgrx::Spi::run("CREATE TABLE scan_jsonb_test (id int, tags text[], note text);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_jsonb_test VALUES (1, ARRAY['a', 'b'], NULL);").unwrap();
+
+        let data = pgrx::Spi::get_one::<String>(
+            "SELECT data::text FROM pg_debug_scan_jsonb('scan_jsonb_test');",
+        )
+        .unwrap();
+        assert_eq!(
+            data,
+            Some("{\"id\": 1, \"note\": null, \"tags\": [\"a\", \"b\"]}".to_string())
+        );
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_jsonb_decode_error() {
+        pgrx::Spi::run("CREATE TYPE jsonb_error_mood AS ENUM ('ok');").unwrap();
+        pgrx::Spi::run("CREATE TABLE scan_jsonb_error_test (id int, mood jsonb_error_mood);")
+            .unwrap();
+        pgrx::Spi::run("INSERT INTO scan_jsonb_error_test VALUES (1, 'ok');").unwrap();
+
+        /* Without its pg_enum entry, the output function of the enum fails */
+        pgrx::Spi::run("DELETE FROM pg_enum WHERE enumtypid = 'jsonb_error_mood'::regtype;")
+            .unwrap();
+
+        let (id, mood) = pgrx::Spi::get_two::<i32, String>(
+            "SELECT (data->>'id')::int, data->>'mood' FROM pg_debug_scan_jsonb('scan_jsonb_error_test');",
+        )
+        .unwrap();
+        assert_eq!(id, Some(1));
+        assert!(mood
+            .expect("unable to get the mood")
+            .starts_with("<decode error: invalid internal value for enum"));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_rows() {
+        pgrx::Spi::run("CREATE TABLE scan_rows_test (id int, value float);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_rows_test VALUES (1, 1.5), (2, 2.5);").unwrap();
+
+        let sum = pgrx::Spi::get_one::<f64>(
+            "SELECT sum((tuple).value) FROM pg_debug_scan_rows('scan_rows_test', NULL, NULL::scan_rows_test);",
+        )
+        .unwrap();
+        assert_eq!(sum, Some(4.0));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_rows_missing_toast_chunk() {
+        pgrx::Spi::run("CREATE TABLE scan_rows_toast_test (id int, value text);").unwrap();
+        pgrx::Spi::run("ALTER TABLE scan_rows_toast_test ALTER COLUMN value SET STORAGE EXTERNAL;")
+            .unwrap();
+        pgrx::Spi::run(
+            "INSERT INTO scan_rows_toast_test VALUES (1, repeat('x', 10000)), (2, 'b');",
+        )
+        .unwrap();
+
+        /* Point the TOAST pointer of the first tuple to a value without chunks */
+        unsafe {
+            crate::heap::modify_page("scan_rows_toast_test", 0, |page| {
+                let tup = crate::heap::PageGetItem(page, crate::heap::PageGetItemId(page, 1));
+                let toast_pointer = (tup as *mut u8).add((*tup).t_hoff as usize + 4);
+                std::ptr::write_unaligned(toast_pointer.add(10) as *mut u32, 0);
+            });
+        }
+
+        let failed = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan_rows('scan_rows_toast_test', NULL, NULL::scan_rows_toast_test) \
+             WHERE tuple IS NULL AND error IS NOT NULL;",
+        )
+        .unwrap();
+        assert_eq!(failed, Some(1));
+
+        let id = pgrx::Spi::get_one::<i32>(
+            "SELECT (tuple).id FROM pg_debug_scan_rows('scan_rows_toast_test', NULL, NULL::scan_rows_toast_test) \
+             WHERE error IS NULL;",
+        )
+        .unwrap();
+        assert_eq!(id, Some(2));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_attrs() {
+        pgrx::Spi::run("CREATE TABLE temperature (time timestamptz NOT NULL, value float);")
+            .unwrap();
+        pgrx::Spi::run("INSERT INTO temperature VALUES('2024-04-12 15:59:23+02', NULL);").unwrap();
+
+        let rows =
+            pgrx::Spi::get_one::<i64>("SELECT count(*) FROM pg_debug_scan_attrs('temperature');")
+                .unwrap();
+        assert_eq!(rows, Some(2));
+
+        let is_null = pgrx::Spi::get_one::<bool>(
+            "SELECT is_null FROM pg_debug_scan_attrs('temperature') WHERE attname = 'value';",
+        )
+        .unwrap();
+        assert_eq!(is_null, Some(true));
+
+        let time = pgrx::Spi::get_one::<bool>(
+            "SELECT attnum = 1 AND atttypid = 'timestamptz'::regtype AND storage_size = 8 \
+             FROM pg_debug_scan_attrs('temperature') WHERE attname = 'time';",
+        )
+        .unwrap();
+        assert_eq!(time, Some(true));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_attrs_toasted() {
+        pgrx::Spi::run("CREATE TABLE scan_attrs_toast_test (value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_attrs_toast_test VALUES (repeat('x', 10000)), ('x');")
+            .unwrap();
+
+        let toasted = pgrx::Spi::get_one::<Vec<bool>>(
+            "SELECT array_agg(is_toasted ORDER BY ctid) FROM pg_debug_scan_attrs('scan_attrs_toast_test');",
+        )
+        .unwrap();
+        assert_eq!(toasted, Some(vec![true, false]));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_direction() {
+        pgrx::Spi::run("CREATE TABLE scan_direction_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_direction_test VALUES (1), (2), (3);").unwrap();
+
+        let offsets = pgrx::Spi::get_one::<Vec<i32>>(
+            "SELECT array_agg(offset_number) FROM pg_debug_scan('scan_direction_test', direction => 'backward', pagemode => true);",
+        )
+        .unwrap();
+        assert_eq!(offsets, Some(vec![3, 2, 1]));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_sample_percent() {
+        pgrx::Spi::run("CREATE TABLE sample_percent_test (id int, value text);").unwrap();
+        pgrx::Spi::run(
+            "INSERT INTO sample_percent_test SELECT i, repeat('x', 100) FROM generate_series(1, 1000) i;",
+        )
+        .unwrap();
+
+        let all = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('sample_percent_test', sample_percent => 100);",
+        )
+        .unwrap();
+        assert_eq!(all, Some(1000));
+
+        let none = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('sample_percent_test', sample_percent => 0);",
+        )
+        .unwrap();
+        assert_eq!(none, Some(0));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_max_rows() {
+        pgrx::Spi::run("CREATE TABLE scan_max_rows_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_max_rows_test SELECT generate_series(1, 10);").unwrap();
+
+        let offsets = pgrx::Spi::get_one::<Vec<i32>>(
+            "SELECT array_agg(offset_number) FROM pg_debug_scan('scan_max_rows_test', max_rows => 2, skip_rows => 3);",
+        )
+        .unwrap();
+        assert_eq!(offsets, Some(vec![4, 5]));
+
+        pgrx::Spi::run("SET pg_debug_scan.max_rows = 4;").unwrap();
+        let count =
+            pgrx::Spi::get_one::<i64>("SELECT count(*) FROM pg_debug_scan('scan_max_rows_test');")
+                .unwrap();
+        assert_eq!(count, Some(4));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_output_gucs() {
+        pgrx::Spi::run("CREATE TABLE output_gucs_test (id int, dropped int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO output_gucs_test VALUES (1, 2, NULL);").unwrap();
+        pgrx::Spi::run("ALTER TABLE output_gucs_test DROP COLUMN dropped;").unwrap();
+
+        pgrx::Spi::run("SET pg_debug_scan.null_representation = '<null>';").unwrap();
+        pgrx::Spi::run("SET pg_debug_scan.include_dropped_columns = on;").unwrap();
+
+        let data =
+            pgrx::Spi::get_one::<String>("SELECT data FROM pg_debug_scan('output_gucs_test');")
+                .unwrap();
+        assert_eq!(
+            data,
+            Some(format!(
+                "{{\"........pg.dropped.2........\":\"\\\\x{}\",\"id\":\"1\",\"value\":\"<null>\"}}",
+                2i32.to_ne_bytes()
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect::<String>()
+            ))
+        );
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_decode_error() {
+        pgrx::Spi::run("CREATE TYPE decode_error_mood AS ENUM ('ok');").unwrap();
+        pgrx::Spi::run("CREATE TABLE decode_error_test (id int, mood decode_error_mood);").unwrap();
+        pgrx::Spi::run("INSERT INTO decode_error_test VALUES (1, 'ok');").unwrap();
+
+        /* Without its pg_enum entry, the output function of the enum fails */
+        pgrx::Spi::run("DELETE FROM pg_enum WHERE enumtypid = 'decode_error_mood'::regtype;")
+            .unwrap();
+
+        let data =
+            pgrx::Spi::get_one::<String>("SELECT data FROM pg_debug_scan('decode_error_test');")
+                .unwrap()
+                .expect("unable to get the data");
+        assert!(data.starts_with(
+            "{\"id\":\"1\",\"mood\":\"<decode error: invalid internal value for enum"
+        ));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_privileges() {
+        pgrx::Spi::run("CREATE ROLE scan_privileges_user;").unwrap();
+        pgrx::Spi::run("CREATE TABLE public.scan_privileges_test (id int);").unwrap();
+        pgrx::Spi::run("INSERT INTO public.scan_privileges_test VALUES (1);").unwrap();
+
+        /* The error is caught in a subtransaction to check the SQLSTATE */
+        pgrx::Spi::run(
+            "CREATE FUNCTION public.scan_privileges_error(bypass_rls bool DEFAULT false)
+            RETURNS text AS $$
+            BEGIN
+                PERFORM pg_debug_scan('public.scan_privileges_test', bypass_rls => bypass_rls);
+                RETURN NULL;
+            EXCEPTION WHEN OTHERS THEN
+                RETURN SQLSTATE || ': ' || SQLERRM;
+            END $$ LANGUAGE plpgsql;",
+        )
+        .unwrap();
+
+        let scan_error_with = |query: &str| {
+            pgrx::Spi::run("SET ROLE scan_privileges_user;").unwrap();
+            let error = pgrx::Spi::get_one::<String>(query);
+            pgrx::Spi::run("RESET ROLE;").unwrap();
+            error.unwrap()
+        };
+        let scan_error = || scan_error_with("SELECT public.scan_privileges_error();");
+
+        /* Only the owner can scan the table by default */
+        pgrx::Spi::run("GRANT SELECT ON public.scan_privileges_test TO scan_privileges_user;")
+            .unwrap();
+        assert_eq!(
+            scan_error(),
+            Some("42501: must be owner of table scan_privileges_test".to_string())
+        );
+
+        /* The SELECT privilege is sufficient with allow_select_privilege */
+        pgrx::Spi::run("SET pg_debug_scan.allow_select_privilege = on;").unwrap();
+        assert_eq!(scan_error(), None);
+
+        pgrx::Spi::run("REVOKE SELECT ON public.scan_privileges_test FROM scan_privileges_user;")
+            .unwrap();
+        assert_eq!(
+            scan_error(),
+            Some("42501: permission denied for table scan_privileges_test".to_string())
+        );
+
+        pgrx::Spi::run("GRANT SELECT ON public.scan_privileges_test TO scan_privileges_user;")
+            .unwrap();
+
+        /* The policies of row-level security cannot be applied */
+        pgrx::Spi::run("ALTER TABLE public.scan_privileges_test ENABLE ROW LEVEL SECURITY;")
+            .unwrap();
+        assert_eq!(
+            scan_error(),
+            Some("42501: scan_privileges_test has row-level security enabled, which cannot be applied by a debug scan".to_string())
+        );
+
+        /* The owner can bypass the policies */
+        pgrx::Spi::run("ALTER TABLE public.scan_privileges_test OWNER TO scan_privileges_user;")
+            .unwrap();
+        pgrx::Spi::run("ALTER TABLE public.scan_privileges_test FORCE ROW LEVEL SECURITY;")
+            .unwrap();
+        assert!(scan_error().is_some());
+
+        assert_eq!(
+            scan_error_with("SELECT public.scan_privileges_error(bypass_rls => true);"),
+            None
+        );
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_toast_privileges() {
+        pgrx::Spi::run("CREATE ROLE scan_toast_user;").unwrap();
+        pgrx::Spi::run("CREATE TABLE public.scan_toast_test (id int, value text);").unwrap();
+        pgrx::Spi::run("GRANT SELECT ON public.scan_toast_test TO scan_toast_user;").unwrap();
+
+        let toast_relid = pgrx::Spi::get_one::<pg_sys::Oid>(
+            "SELECT reltoastrelid FROM pg_class WHERE oid = 'public.scan_toast_test'::regclass;",
+        )
+        .unwrap()
+        .expect("unable to get the TOAST table");
+
+        /* The TOAST table can be scanned with the privileges of its table */
+        pgrx::Spi::run("SET pg_debug_scan.allow_select_privilege = on;").unwrap();
+        pgrx::Spi::run("SET ROLE scan_toast_user;").unwrap();
+        let count = pgrx::Spi::get_one::<i64>(&format!(
+            "SELECT count(*) FROM pg_debug_scan({}::oid::regclass);",
+            toast_relid.as_u32()
+        ));
+        pgrx::Spi::run("RESET ROLE;").unwrap();
+        assert_eq!(count.unwrap(), Some(0));
     }
 }
 
@@ -300,6 +2832,10 @@ pub mod pg_test {
 
     pub fn postgresql_conf_options() -> Vec<&'static str> {
         // return any postgresql.conf settings that are required for your tests
-        vec!["timezone = UTC"]
+        vec![
+            "timezone = UTC",
+            "shared_preload_libraries = 'pg_debug_scan'",
+            "track_commit_timestamp = on",
+        ]
     }
 }
@@ -0,0 +1,149 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use pgrx::{pg_shmem_init, prelude::*, shmem::*, PgAtomic};
+
+use crate::guc::{MAX_CONCURRENT_SCANS, WAIT_FOR_SCAN_SLOT};
+
+/* The interval in which a free scan slot is checked */
+const WAIT_INTERVAL_MS: i64 = 10;
+
+/* The number of running scans of all backends */
+static ACTIVE_SCANS: PgAtomic<AtomicU32> = PgAtomic::new();
+
+/* Is the shared memory available (i.e., loaded via shared_preload_libraries) */
+static SHMEM_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/* The number of scan slots held by this backend */
+static BACKEND_SLOTS: AtomicU32 = AtomicU32::new(0);
+
+/* Is the exit callback that releases the slots of this backend registered */
+static EXIT_CALLBACK_REGISTERED: AtomicBool = AtomicBool::new(false);
+
+/*
+ * Request the shared memory for the scan counter. This is only possible if the
+ * extension is loaded via shared_preload_libraries.
+ */
+pub(crate) unsafe fn init() {
+    if !pg_sys::process_shared_preload_libraries_in_progress {
+        return;
+    }
+
+    pg_shmem_init!(ACTIVE_SCANS);
+    SHMEM_INITIALIZED.store(true, Ordering::Relaxed);
+}
+
+/*
+ * Release the scan slots that are still held when the backend exits (e.g., after
+ * a FATAL error, which does not unwind the stack)
+ */
+#[pg_guard]
+unsafe extern "C" fn release_backend_slots(_code: i32, _arg: pg_sys::Datum) {
+    let slots = BACKEND_SLOTS.swap(0, Ordering::SeqCst);
+    ACTIVE_SCANS.get().fetch_sub(slots, Ordering::SeqCst);
+}
+
+/* A slot of pg_debug_scan.max_concurrent_scans, released when dropped */
+pub(crate) struct ScanSlot {
+    acquired: bool,
+}
+
+impl ScanSlot {
+    /*
+     * Acquire a scan slot. If all slots are in use, an error is raised or, if
+     * pg_debug_scan.wait_for_scan_slot is enabled, the backend waits for a free slot.
+     */
+    pub(crate) unsafe fn acquire() -> ScanSlot {
+        let max_concurrent_scans = MAX_CONCURRENT_SCANS.get();
+
+        if max_concurrent_scans == 0 {
+            return ScanSlot { acquired: false };
+        }
+
+        if !SHMEM_INITIALIZED.load(Ordering::Relaxed) {
+            error!("pg_debug_scan.max_concurrent_scans requires pg_debug_scan in shared_preload_libraries");
+        }
+
+        if !EXIT_CALLBACK_REGISTERED.swap(true, Ordering::Relaxed) {
+            pg_sys::before_shmem_exit(Some(release_backend_slots), pg_sys::Datum::from(0));
+        }
+
+        let active_scans = ACTIVE_SCANS.get();
+
+        loop {
+            let running = active_scans.load(Ordering::SeqCst);
+
+            if running < max_concurrent_scans as u32 {
+                if active_scans
+                    .compare_exchange(running, running + 1, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    BACKEND_SLOTS.fetch_add(1, Ordering::SeqCst);
+                    return ScanSlot { acquired: true };
+                }
+
+                continue;
+            }
+
+            if !WAIT_FOR_SCAN_SLOT.get() {
+                error!(
+                    "Too many concurrent debug scans, pg_debug_scan.max_concurrent_scans is {max_concurrent_scans}"
+                );
+            }
+
+            pg_sys::WaitLatch(
+                pg_sys::MyLatch,
+                (pg_sys::WL_LATCH_SET | pg_sys::WL_TIMEOUT | pg_sys::WL_EXIT_ON_PM_DEATH) as i32,
+                WAIT_INTERVAL_MS,
+                pg_sys::PG_WAIT_EXTENSION,
+            );
+            pg_sys::ResetLatch(pg_sys::MyLatch);
+            check_for_interrupts!();
+        }
+    }
+}
+
+impl Drop for ScanSlot {
+    fn drop(&mut self) {
+        if self.acquired {
+            BACKEND_SLOTS.fetch_sub(1, Ordering::SeqCst);
+            ACTIVE_SCANS.get().fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    use super::ScanSlot;
+
+    #[pgrx::pg_test(
+        error = "Too many concurrent debug scans, pg_debug_scan.max_concurrent_scans is 1"
+    )]
+    fn test_limiter_exceeded() {
+        pgrx::Spi::run("SET pg_debug_scan.max_concurrent_scans = 1;").unwrap();
+        pgrx::Spi::run("SET pg_debug_scan.wait_for_scan_slot = off;").unwrap();
+
+        unsafe {
+            let _slot = ScanSlot::acquire();
+            ScanSlot::acquire();
+        }
+    }
+
+    #[pgrx::pg_test]
+    fn test_limiter_releases_slot() {
+        pgrx::Spi::run("CREATE TABLE limiter_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO limiter_test VALUES (1);").unwrap();
+        pgrx::Spi::run("SET pg_debug_scan.max_concurrent_scans = 1;").unwrap();
+
+        /* The slot of the first scan is released when the scan is finished */
+        for _ in 0..2 {
+            let count =
+                pgrx::Spi::get_one::<i64>("SELECT count(*) FROM pg_debug_scan('limiter_test');")
+                    .unwrap();
+            assert_eq!(count, Some(1));
+        }
+    }
+}
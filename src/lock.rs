@@ -0,0 +1,215 @@
+use std::ffi::CString;
+
+use pgrx::{pg_sys::panic::ErrorReport, prelude::*};
+
+use crate::get_relid_from_name_extended;
+use crate::regclass::RegClass;
+
+/* From PostgreSQL: RVR_NOWAIT, raise an error instead of waiting for the lock */
+const RVR_NOWAIT: u32 = 1 << 1;
+
+/*
+ * The lock that is taken on the scanned relation. By default, an AccessShareLock
+ * is acquired and the scan waits behind DDL that holds a conflicting lock (e.g.,
+ * a rewrite of the table). With nowait or a lock timeout, the scan fails fast
+ * instead. Superusers can scan without any lock. Since a relation must not be opened
+ * without a lock, its current file is then read without opening the relation, see
+ * RelationFileScan::open_unlocked.
+ */
+pub(crate) struct RelationLock {
+    mode: pg_sys::LOCKMODE,
+    nowait: bool,
+    lock_timeout: Option<i32>,
+}
+
+impl RelationLock {
+    /*
+     * Create the lock with the given mode name (access share or none)
+     */
+    pub(crate) unsafe fn new(
+        lock_mode: &str,
+        nowait: bool,
+        lock_timeout: Option<i32>,
+    ) -> RelationLock {
+        let mode = match lock_mode {
+            "access share" => pg_sys::AccessShareLock as pg_sys::LOCKMODE,
+            "none" => {
+                if !pg_sys::superuser() {
+                    ErrorReport::new(
+                        PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+                        "Must be superuser to scan a relation without a lock",
+                        "RelationLock::new",
+                    )
+                    .set_hint("Use lock_mode => 'access share' with nowait or lock_timeout to fail fast instead.")
+                    .report(PgLogLevel::ERROR);
+                }
+
+                pg_sys::NoLock as pg_sys::LOCKMODE
+            }
+            _ => error!("Unknown lock mode {lock_mode}, valid modes are access share and none"),
+        };
+
+        if let Some(lock_timeout) = lock_timeout {
+            if lock_timeout < 0 {
+                error!("Invalid lock timeout {lock_timeout}, it must not be negative");
+            }
+        }
+
+        RelationLock {
+            mode,
+            nowait,
+            lock_timeout,
+        }
+    }
+
+    /*
+     * Check if the relation is scanned without a lock
+     */
+    pub(crate) fn unlocked(&self) -> bool {
+        self.mode == pg_sys::NoLock as pg_sys::LOCKMODE
+    }
+
+    /*
+     * Resolve the table name and lock the relation
+     */
+    pub(crate) unsafe fn relid_from_name(&self, table: &str) -> pg_sys::Oid {
+        let flags = if self.nowait { RVR_NOWAIT } else { 0 };
+
+        self.with_lock_timeout(|| get_relid_from_name_extended(table, self.mode, flags))
+    }
+
+    /*
+     * Lock the relation with the given oid. Nothing is done if the lock is
+     * already held (e.g., it was taken when the name was resolved).
+     */
+    pub(crate) unsafe fn lock_relation(&self, relid: pg_sys::Oid) {
+        if self.unlocked() {
+            return;
+        }
+
+        if !self.nowait {
+            self.with_lock_timeout(|| pg_sys::LockRelationOid(relid, self.mode));
+            return;
+        }
+
+        if !pg_sys::ConditionalLockRelationOid(relid, self.mode) {
+            ErrorReport::new(
+                PgSqlErrorCode::ERRCODE_LOCK_NOT_AVAILABLE,
+                format!(
+                    "Could not obtain lock on relation {}",
+                    RegClass(relid).name()
+                ),
+                "RelationLock::lock_relation",
+            )
+            .report(PgLogLevel::ERROR);
+        }
+    }
+
+    /*
+     * Call func with the lock timeout of the lock as lock_timeout setting. The
+     * previous value is restored afterwards (or at the end of the transaction if
+     * an error is raised).
+     */
+    unsafe fn with_lock_timeout<T>(&self, func: impl FnOnce() -> T) -> T {
+        let Some(lock_timeout) = self.lock_timeout else {
+            return func();
+        };
+
+        let name = CString::new("lock_timeout").expect("Unable to convert to string");
+        let value = CString::new(lock_timeout.to_string()).expect("Unable to convert to string");
+
+        let nest_level = pg_sys::NewGUCNestLevel();
+        pg_sys::set_config_option(
+            name.as_ptr(),
+            value.as_ptr(),
+            pg_sys::GucContext_PGC_USERSET,
+            pg_sys::GucSource_PGC_S_SESSION,
+            pg_sys::GucAction_GUC_ACTION_SAVE,
+            true,
+            0,
+            false,
+        );
+
+        let result = func();
+
+        pg_sys::AtEOXact_GUC(true, nest_level);
+
+        result
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    /* Created with the extension, so the table is not locked by the transaction of a test */
+    pgrx::extension_sql!(
+        r#"
+CREATE TABLE public.lock_mode_committed_test (id int, value text);
+INSERT INTO public.lock_mode_committed_test VALUES (1, 'a'), (2, 'b'), (3, 'c');
+DELETE FROM public.lock_mode_committed_test WHERE id = 3;
+"#,
+        name = "lock_mode_committed_test",
+    );
+
+    #[pgrx::pg_test]
+    fn test_scan_lock_mode() {
+        pgrx::Spi::run("CREATE TABLE lock_mode_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO lock_mode_test VALUES (1), (2);").unwrap();
+
+        let nowait = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('lock_mode_test', nowait => true, lock_timeout => 100);",
+        )
+        .unwrap();
+        assert_eq!(nowait, Some(2));
+
+        let unlocked = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('lock_mode_test', lock_mode => 'none');",
+        )
+        .unwrap();
+        assert_eq!(unlocked, Some(2));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_lock_mode_none_committed() {
+        let data = pgrx::Spi::get_one::<Vec<String>>(
+            "SELECT array_agg(data ORDER BY ctid) \
+             FROM pg_debug_scan('public.lock_mode_committed_test', lock_mode => 'none');",
+        )
+        .unwrap();
+        assert_eq!(
+            data,
+            Some(vec![
+                "{\"id\":\"1\",\"value\":\"a\"}".to_string(),
+                "{\"id\":\"2\",\"value\":\"b\"}".to_string()
+            ])
+        );
+
+        /* The relation was neither opened nor locked by the scan */
+        let locks = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_locks \
+             WHERE relation = 'public.lock_mode_committed_test'::regclass \
+             AND pid = pg_backend_pid();",
+        )
+        .unwrap();
+        assert_eq!(locks, Some(0));
+
+        let columns = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('public.lock_mode_committed_test', \
+             lock_mode => 'none', columns => ARRAY['value'], snapshot_mode => 'any');",
+        )
+        .unwrap();
+        assert_eq!(columns, Some(3));
+    }
+
+    #[pgrx::pg_test(error = "Unknown lock mode exclusive, valid modes are access share and none")]
+    fn test_scan_lock_mode_unknown() {
+        pgrx::Spi::run("CREATE TABLE lock_mode_unknown_test (value int);").unwrap();
+        pgrx::Spi::run(
+            "SELECT * FROM pg_debug_scan('lock_mode_unknown_test', lock_mode => 'exclusive');",
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+use pgrx::{
+    pg_sys::{GetTransactionSnapshot, MultiXactId, TransactionId},
+    prelude::*,
+};
+
+use crate::heap::HEAP_XMAX_IS_LOCKED_ONLY;
+use crate::output::{format_tuple, OutputFormat};
+use crate::snapshot::get_snapshot_from_str;
+use crate::xid8::Xid8;
+use crate::TupleStream;
+
+/* A member of a multixact, see MultiXactMember in access/multixact.h */
+#[repr(C)]
+struct MultiXactMember {
+    xid: TransactionId,
+    status: u32,
+}
+
+#[pg_guard]
+extern "C" {
+    /* Not exported by pgrx, see access/multixact.h */
+    fn GetMultiXactIdMembers(
+        multi: MultiXactId,
+        members: *mut *mut MultiXactMember,
+        from_pgupgrade: bool,
+        is_lock_only: bool,
+    ) -> i32;
+}
+
+/* The lock modes of the multixact members (MultiXactStatus), named like pgrowlocks does */
+const MULTIXACT_STATUS_NAMES: [&str; 6] = [
+    "For Key Share",
+    "For Share",
+    "For No Key Update",
+    "For Update",
+    "No Key Update",
+    "Update",
+];
+
+/*
+ * Get the member transactions of the multixact and their lock modes. Multixacts
+ * that are older than the oldest multixact still of interest have no members.
+ */
+pub(crate) unsafe fn multixact_members(
+    multi: MultiXactId,
+    infomask: u16,
+) -> Vec<(TransactionId, &'static str)> {
+    let mut members: *mut MultiXactMember = std::ptr::null_mut();
+    let nmembers = GetMultiXactIdMembers(
+        multi,
+        &mut members,
+        false,
+        HEAP_XMAX_IS_LOCKED_ONLY(infomask),
+    );
+
+    if nmembers <= 0 {
+        return Vec::new();
+    }
+
+    let result = std::slice::from_raw_parts(members, nmembers as usize)
+        .iter()
+        .map(|member| {
+            let status = MULTIXACT_STATUS_NAMES
+                .get(member.status as usize)
+                .copied()
+                .unwrap_or("Unknown");
+
+            (member.xid, status)
+        })
+        .collect();
+
+    pg_sys::pfree(members as *mut std::ffi::c_void);
+
+    result
+}
+
+/*
+ * Perform a table scan and decode the xmax of each visible tuple that is a
+ * multixact (HEAP_XMAX_IS_MULTI) into its member transactions and their lock
+ * modes. For other tuples, the member columns are empty.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_multixact(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    xids: default!(Option<Vec<i64>>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(xmax_is_multi, bool),
+        name!(member_xids, Vec<Xid8>),
+        name!(member_modes, Vec<String>),
+        name!(data, String),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    let rows = TupleStream::open(table, snapshot_data, xids, None, None).map(move |tuple| {
+        let infomask = tuple.infomask as u32;
+        let is_multi =
+            infomask & pg_sys::HEAP_XMAX_IS_MULTI != 0 && infomask & pg_sys::HEAP_XMAX_INVALID == 0;
+
+        let members = if is_multi {
+            multixact_members(tuple.xmax, tuple.infomask)
+        } else {
+            Vec::new()
+        };
+
+        /* Multixact ids are counted separately from xids and have no epoch */
+        let xmax = if is_multi {
+            Xid8(tuple.xmax.into())
+        } else {
+            Xid8::from_xid(tuple.xmax, next_xid)
+        };
+
+        (
+            tuple.ctid,
+            Xid8::from_xid(tuple.xmin, next_xid),
+            xmax,
+            is_multi,
+            members
+                .iter()
+                .map(|(xid, _)| Xid8::from_xid(*xid, next_xid))
+                .collect(),
+            members
+                .iter()
+                .map(|(_, status)| status.to_string())
+                .collect(),
+            format_tuple(&tuple.attributes, output_format),
+        )
+    });
+
+    TableIterator::new(rows)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_scan_multixact() {
+        pgrx::Spi::run("CREATE TABLE multixact_test (id int, value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO multixact_test VALUES (1, 1);").unwrap();
+
+        /* Lock the row in the transaction and a subtransaction to create a multixact */
+        pgrx::Spi::run("SELECT * FROM multixact_test FOR SHARE;").unwrap();
+        pgrx::Spi::run(
+            "DO $$ BEGIN
+                PERFORM * FROM multixact_test FOR UPDATE;
+            EXCEPTION WHEN OTHERS THEN NULL;
+            END $$;",
+        )
+        .unwrap();
+
+        let is_multi = pgrx::Spi::get_one::<bool>(
+            "SELECT xmax_is_multi FROM pg_debug_scan_multixact('multixact_test');",
+        )
+        .unwrap();
+        assert_eq!(is_multi, Some(true));
+
+        let modes = pgrx::Spi::get_one::<Vec<String>>(
+            "SELECT member_modes FROM pg_debug_scan_multixact('multixact_test');",
+        )
+        .unwrap();
+        assert_eq!(
+            modes,
+            Some(vec!["For Share".to_string(), "For Update".to_string()])
+        );
+    }
+}
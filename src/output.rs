@@ -0,0 +1,389 @@
+use serde_json::{Map, Number, Value};
+use std::ffi::{CStr, CString};
+
+use pgrx::{
+    pgrx_sql_entity_graph::metadata::{
+        ArgumentError, Returns, ReturnsError, SqlMapping, SqlTranslatable,
+    },
+    prelude::*,
+};
+
+use crate::guc::{MAX_JSON_BYTES, NULL_REPRESENTATION, OUTPUT_FORMAT};
+
+/* The representation of the tuple data returned by the scan functions */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum OutputFormat {
+    /* JSON object with all values as strings (the default) */
+    Json,
+    /* Like json, but normalized by the jsonb data type */
+    Jsonb,
+    /* JSON object with native JSON types for numbers, booleans and NULL */
+    TypedJson,
+    /* Record literal that can be cast into the row type of the table */
+    RecordCompatible,
+}
+
+impl OutputFormat {
+    /*
+     * Get the output format configured by pg_debug_scan.output_format
+     */
+    pub(crate) fn from_guc() -> OutputFormat {
+        let format = OUTPUT_FORMAT.get();
+
+        match format.as_deref() {
+            None | Some("json") => OutputFormat::Json,
+            Some("jsonb") => OutputFormat::Jsonb,
+            Some("typed-json") => OutputFormat::TypedJson,
+            Some("record-compatible") => OutputFormat::RecordCompatible,
+            Some(other) => error!(
+                "Unknown output format {other}, valid formats are json, jsonb, typed-json and record-compatible"
+            ),
+        }
+    }
+}
+
+/* A decoded attribute of a tuple */
+pub struct AttributeValue {
+    pub name: String,
+    pub typid: pg_sys::Oid,
+    pub value: Option<String>,
+}
+
+/*
+ * Convert the given datum into a string using the output function of the type
+ */
+pub(crate) unsafe fn output_datum(typid: pg_sys::Oid, datum: pg_sys::Datum) -> String {
+    let mut typoutput = pg_sys::Oid::default();
+    let mut typvarlena: bool = false;
+
+    pg_sys::getTypeOutputInfo(typid, &mut typoutput, &mut typvarlena);
+    let output_val = pg_sys::OidOutputFunctionCall(typoutput, datum);
+
+    CStr::from_ptr(output_val).to_str().unwrap().to_string()
+}
+
+/*
+ * Convert the decoded attributes of a tuple into the requested output format
+ */
+pub(crate) unsafe fn format_tuple(attributes: &[AttributeValue], format: OutputFormat) -> String {
+    let output = match format {
+        OutputFormat::Json => to_json_with_null(attributes, &null_representation()),
+        OutputFormat::Jsonb => {
+            normalize_jsonb(&to_json_with_null(attributes, &null_representation()))
+        }
+        OutputFormat::TypedJson => to_typed_json(attributes),
+        OutputFormat::RecordCompatible => return to_record(attributes),
+    };
+
+    limit_json_size(output, format)
+}
+
+/*
+ * Truncate the JSON document if it is larger than pg_debug_scan.max_json_bytes. The
+ * attributes are added to the truncated document as long as they fit and the
+ * document is marked with _truncated and the original size.
+ */
+unsafe fn limit_json_size(json: String, format: OutputFormat) -> String {
+    let max_json_bytes = MAX_JSON_BYTES.get() as usize;
+
+    if max_json_bytes == 0 || json.len() <= max_json_bytes {
+        return json;
+    }
+
+    let document: Map<String, Value> =
+        serde_json::from_str(&json).expect("unable to parse generated JSON");
+
+    let mut truncated = Map::new();
+    truncated.insert("_truncated".to_string(), Value::Bool(true));
+    truncated.insert("_original_size".to_string(), Value::from(json.len()));
+
+    /* The size is tracked while adding the attributes, so the document is serialized once */
+    let mut size = serde_json::to_string(&truncated)
+        .expect("unable to generate JSON")
+        .len();
+
+    for (key, value) in document {
+        /* The quoted key, the colon, the value, and the separating comma */
+        let entry_size = serde_json::to_string(&key)
+            .expect("unable to generate JSON")
+            .len()
+            + serde_json::to_string(&value)
+                .expect("unable to generate JSON")
+                .len()
+            + 2;
+
+        if size + entry_size <= max_json_bytes {
+            truncated.insert(key, value);
+            size += entry_size;
+        }
+    }
+
+    let truncated = serde_json::to_string(&truncated).expect("unable to generate JSON");
+
+    match format {
+        OutputFormat::Jsonb => normalize_jsonb(&truncated),
+        _ => truncated,
+    }
+}
+
+/*
+ * Get the text that represents NULL values in the json and jsonb output formats,
+ * see pg_debug_scan.null_representation
+ */
+fn null_representation() -> String {
+    NULL_REPRESENTATION
+        .get()
+        .unwrap_or_else(|| "NULL".to_string())
+}
+
+/*
+ * Convert the attributes into a json string. NULL values are represented as "NULL".
+ */
+pub(crate) fn to_json(attributes: &[AttributeValue]) -> String {
+    to_json_with_null(attributes, "NULL")
+}
+
+/*
+ * Convert the attributes into a json string and represent NULL values by the given text
+ */
+fn to_json_with_null(attributes: &[AttributeValue], null: &str) -> String {
+    let mut map = Map::new();
+
+    for attribute in attributes {
+        let value = attribute.value.clone().unwrap_or_else(|| null.to_string());
+        map.insert(attribute.name.clone(), Value::String(value));
+    }
+
+    serde_json::to_string(&map).expect("unable to generate JSON")
+}
+
+/*
+ * Convert the given json string into a jsonb datum
+ */
+unsafe fn json_to_jsonb(json: &str) -> pg_sys::Datum {
+    let json_str = CString::new(json).expect("Unable to convert to string");
+
+    let mut typinput = pg_sys::Oid::default();
+    let mut typioparam = pg_sys::Oid::default();
+
+    pg_sys::getTypeInputInfo(pg_sys::JSONBOID, &mut typinput, &mut typioparam);
+    pg_sys::OidInputFunctionCall(
+        typinput,
+        json_str.as_ptr() as *mut std::os::raw::c_char,
+        typioparam,
+        -1,
+    )
+}
+
+/*
+ * Normalize the given json string by converting it into jsonb and back
+ */
+unsafe fn normalize_jsonb(json: &str) -> String {
+    output_datum(pg_sys::JSONBOID, json_to_jsonb(json))
+}
+
+/*
+ * A jsonb value that is passed as datum, so numbers keep their full precision
+ */
+pub(crate) struct JsonbDatum(pg_sys::Datum);
+
+unsafe impl SqlTranslatable for JsonbDatum {
+    fn argument_sql() -> Result<SqlMapping, ArgumentError> {
+        Ok(SqlMapping::literal("jsonb"))
+    }
+
+    fn return_sql() -> Result<Returns, ReturnsError> {
+        Ok(Returns::One(SqlMapping::literal("jsonb")))
+    }
+}
+
+impl IntoDatum for JsonbDatum {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        Some(self.0)
+    }
+
+    fn type_oid() -> pg_sys::Oid {
+        pg_sys::JSONBOID
+    }
+}
+
+/*
+ * Convert the tuple into jsonb using row_to_json(). In contrast to the text output
+ * formats, numbers, booleans and NULL values are mapped to native JSON types and
+ * arrays and composite values are converted into nested JSON structures.
+ */
+pub(crate) unsafe fn tuple_to_jsonb(
+    htup: *mut pg_sys::HeapTupleData,
+    tupdesc: pg_sys::TupleDesc,
+) -> JsonbDatum {
+    let row_to_json = CString::new("row_to_json").expect("Unable to convert to string");
+    let row_to_json = pg_sys::fmgr_internal_function(row_to_json.as_ptr());
+
+    /* The record contains the detoasted values and the row type of the table */
+    let record = pg_sys::heap_copy_tuple_as_datum(htup, tupdesc);
+    let json = pg_sys::OidFunctionCall1Coll(row_to_json, pg_sys::InvalidOid, record);
+
+    JsonbDatum(json_to_jsonb(&output_datum(pg_sys::JSONOID, json)))
+}
+
+/*
+ * Convert the decoded attributes of a tuple into a jsonb value, see to_typed_json
+ */
+pub(crate) unsafe fn attributes_to_jsonb(attributes: &[AttributeValue]) -> JsonbDatum {
+    JsonbDatum(json_to_jsonb(&to_typed_json(attributes)))
+}
+
+/*
+ * Convert the attributes into a json string and use native JSON types where possible
+ */
+pub(crate) fn to_typed_json(attributes: &[AttributeValue]) -> String {
+    let mut map = Map::new();
+
+    for attribute in attributes {
+        map.insert(
+            attribute.name.clone(),
+            typed_json_value(attribute.typid, &attribute.value),
+        );
+    }
+
+    serde_json::to_string(&map).expect("unable to generate JSON")
+}
+
+/*
+ * Map the text representation of a value to a JSON value of a matching type. Numbers keep
+ * their digits (serde_json is built with arbitrary_precision), so numeric and int8 values
+ * are not rounded to a double.
+ */
+fn typed_json_value(typid: pg_sys::Oid, value: &Option<String>) -> Value {
+    let value = match value {
+        Some(value) => value,
+        None => return Value::Null,
+    };
+
+    match typid {
+        pg_sys::BOOLOID => Value::Bool(value == "t"),
+        pg_sys::INT2OID
+        | pg_sys::INT4OID
+        | pg_sys::INT8OID
+        | pg_sys::OIDOID
+        | pg_sys::FLOAT4OID
+        | pg_sys::FLOAT8OID
+        | pg_sys::NUMERICOID => {
+            /* Values like NaN or Infinity have no JSON representation */
+            value
+                .parse::<Number>()
+                .map(Value::Number)
+                .unwrap_or_else(|_| Value::String(value.clone()))
+        }
+        pg_sys::JSONOID | pg_sys::JSONBOID => {
+            serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.clone()))
+        }
+        _ => Value::String(value.clone()),
+    }
+}
+
+/*
+ * Convert the attributes into a record literal, see record_out()
+ */
+fn to_record(attributes: &[AttributeValue]) -> String {
+    let fields: Vec<String> = attributes
+        .iter()
+        .map(|attribute| match &attribute.value {
+            Some(value) => quote_record_field(value),
+            None => String::new(),
+        })
+        .collect();
+
+    format!("({})", fields.join(","))
+}
+
+/*
+ * Quote a field of a record literal if needed
+ */
+fn quote_record_field(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| matches!(c, '"' | '\\' | '(' | ')' | ',') || c.is_ascii_whitespace());
+
+    if !needs_quotes {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+/*
+ * Convert the attributes into a line of the COPY text format, see CopyAttributeOutText()
+ */
+pub(crate) fn to_copy_line(attributes: &[AttributeValue]) -> String {
+    let fields: Vec<String> = attributes
+        .iter()
+        .map(|attribute| match &attribute.value {
+            Some(value) => escape_copy_field(value),
+            None => "\\N".to_string(),
+        })
+        .collect();
+
+    fields.join("\t")
+}
+
+/*
+ * Convert the attributes into a line of the CSV format, see CopyAttributeOutCSV().
+ * NULL values are written as unquoted empty fields, so empty strings are quoted to
+ * distinguish them.
+ */
+pub(crate) fn to_csv_line(attributes: &[AttributeValue]) -> String {
+    let fields: Vec<String> = attributes
+        .iter()
+        .map(|attribute| match &attribute.value {
+            Some(value) => quote_csv_field(value),
+            None => String::new(),
+        })
+        .collect();
+
+    fields.join(",")
+}
+
+/*
+ * Quote a field of the CSV format if it is empty or contains a delimiter, a quote,
+ * or a line break
+ */
+pub(crate) fn quote_csv_field(value: &str) -> String {
+    if !value.is_empty() && !value.contains([',', '"', '\n', '\r']) {
+        return value.to_string();
+    }
+
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+/*
+ * Escape the special characters of a field in the COPY text format
+ */
+fn escape_copy_field(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\x08' => escaped.push_str("\\b"),
+            '\x0c' => escaped.push_str("\\f"),
+            '\x0b' => escaped.push_str("\\v"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,342 @@
+use pgrx::{
+    itemptr::item_pointer_set_all,
+    pg_sys::{AccessShareLock, GetTransactionSnapshot},
+    prelude::*,
+};
+
+use crate::heap::{
+    open_heap_relation, page_get_heap_tuple, prefetch_ahead, with_locked_page,
+    HeapTupleHeaderGetXmax, OpenRelation, PageGetItemId, PageGetMaxOffsetNumber,
+};
+use crate::output::{format_tuple, OutputFormat};
+use crate::snapshot::get_snapshot_from_str;
+use crate::visibility::visibility_reason;
+use crate::xid8::Xid8;
+use crate::{decode_tuple, get_relid_from_name};
+
+/* A line pointer of a page */
+pub(crate) struct LinePointer {
+    pub(crate) offnum: pg_sys::OffsetNumber,
+    pub(crate) state: &'static str,
+    pub(crate) length: i32,
+    pub(crate) redirect_to: Option<pg_sys::OffsetNumber>,
+    /* A copy of the tuple and its visibility, only set for normal line pointers */
+    pub(crate) tuple: Option<(pg_sys::HeapTuple, bool)>,
+}
+
+/*
+ * Get the name of the state of a line pointer, see storage/itemid.h
+ */
+pub(crate) fn line_pointer_state(lp_flags: u32) -> &'static str {
+    match lp_flags {
+        pg_sys::LP_UNUSED => "UNUSED",
+        pg_sys::LP_NORMAL => "NORMAL",
+        pg_sys::LP_REDIRECT => "REDIRECT",
+        pg_sys::LP_DEAD => "DEAD",
+        _ => "UNKNOWN",
+    }
+}
+
+/*
+ * Read the given line pointer of the share locked page. Normal tuples are checked
+ * against the snapshot and copied, so they can be used after the page is released.
+ */
+pub(crate) unsafe fn read_line_pointer(
+    relid: pg_sys::Oid,
+    buffer: pg_sys::Buffer,
+    page: pg_sys::Page,
+    blkno: pg_sys::BlockNumber,
+    offnum: pg_sys::OffsetNumber,
+    snapshot_data: *mut pg_sys::SnapshotData,
+) -> LinePointer {
+    let itemid = PageGetItemId(page, offnum);
+    let lp_flags = (*itemid).lp_flags();
+
+    let redirect_to =
+        (lp_flags == pg_sys::LP_REDIRECT).then(|| (*itemid).lp_off() as pg_sys::OffsetNumber);
+
+    let tuple = (lp_flags == pg_sys::LP_NORMAL).then(|| {
+        let mut htup = page_get_heap_tuple(relid, page, blkno, offnum);
+        let visible = pg_sys::HeapTupleSatisfiesVisibility(&mut htup, snapshot_data, buffer);
+
+        (pg_sys::heap_copytuple(&mut htup), visible)
+    });
+
+    LinePointer {
+        offnum,
+        state: line_pointer_state(lp_flags),
+        length: (*itemid).lp_len() as i32,
+        redirect_to,
+        tuple,
+    }
+}
+
+/* A row of pg_debug_scan_page and pg_debug_scan_pages */
+type PageRow = (
+    pg_sys::ItemPointerData,
+    String,
+    i32,
+    Option<i32>,
+    Option<Xid8>,
+    Option<Xid8>,
+    Option<bool>,
+    Option<String>,
+    Option<String>,
+);
+
+/*
+ * Read the line pointers of the given page and return the rows of the line pointers
+ * accepted by include. The tuples are decoded after the page is released.
+ */
+unsafe fn read_page_rows(
+    table_rel: pg_sys::Relation,
+    blkno: pg_sys::BlockNumber,
+    snapshot_data: *mut pg_sys::SnapshotData,
+    output_format: OutputFormat,
+    include: impl Fn(&LinePointer) -> bool,
+) -> Vec<PageRow> {
+    let relid = (*table_rel).rd_id;
+
+    let line_pointers = with_locked_page(table_rel, blkno, |buffer, page| {
+        (1..=PageGetMaxOffsetNumber(page))
+            .map(|offnum| read_line_pointer(relid, buffer, page, blkno, offnum, snapshot_data))
+            .filter(|line_pointer| include(line_pointer))
+            .collect::<Vec<_>>()
+    });
+
+    let tupdesc = (*table_rel).rd_att;
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut results = Vec::new();
+
+    for line_pointer in line_pointers {
+        let mut ctid = pg_sys::ItemPointerData::default();
+        item_pointer_set_all(&mut ctid, blkno, line_pointer.offnum);
+
+        let (xmin, xmax, visible, reason, data) = match line_pointer.tuple {
+            Some((htup, visible)) => {
+                let tup = (*htup).t_data;
+
+                (
+                    Some(Xid8::from_xid(
+                        pg_sys::HeapTupleHeaderGetXmin(tup),
+                        next_xid,
+                    )),
+                    Some(Xid8::from_xid(HeapTupleHeaderGetXmax(tup), next_xid)),
+                    Some(visible),
+                    Some(visibility_reason(tup, snapshot_data, visible)),
+                    Some(format_tuple(
+                        &decode_tuple(relid, htup, tupdesc),
+                        output_format,
+                    )),
+                )
+            }
+            None => (None, None, None, None, None),
+        };
+
+        results.push((
+            ctid,
+            line_pointer.state.to_string(),
+            line_pointer.length,
+            line_pointer.redirect_to.map(i32::from),
+            xmin,
+            xmax,
+            visible,
+            reason,
+            data,
+        ));
+    }
+
+    results
+}
+
+/*
+ * Read a single page of the table and return all of its line pointers, similar to
+ * heap_page_items() of pageinspect. In addition, the tuples are checked against the
+ * snapshot and decoded, so the visibility verdict and the data of each tuple can be
+ * seen next to the line pointer state.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_page(
+    table: &str,
+    blkno: i64,
+    snapshot: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(lp_state, String),
+        name!(lp_len, i32),
+        name!(redirect_to, Option<i32>),
+        name!(xmin, Option<Xid8>),
+        name!(xmax, Option<Xid8>),
+        name!(visible, Option<bool>),
+        name!(visibility_reason, Option<String>),
+        name!(data, Option<String>),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+
+    let nblocks =
+        pg_sys::RelationGetNumberOfBlocksInFork(table_rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+    let blkno = pg_sys::BlockNumber::try_from(blkno)
+        .ok()
+        .filter(|blkno| *blkno < nblocks)
+        .unwrap_or_else(|| error!("Block {blkno} is out of range, the table has {nblocks} blocks"));
+
+    let output_format = OutputFormat::from_guc();
+    let results = read_page_rows(table_rel, blkno, snapshot_data, output_format, |_| true);
+
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+
+    TableIterator::new(results)
+}
+
+/*
+ * Scan all pages of the table and return the visible tuples in the format of
+ * pg_debug_scan_page. With include_line_pointers, the dead and redirect line pointers
+ * left behind by HOT pruning are returned as well, so pruning can be followed with
+ * the snapshot context of the scan.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_pages(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    include_line_pointers: default!(bool, false),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(lp_state, String),
+        name!(lp_len, i32),
+        name!(redirect_to, Option<i32>),
+        name!(xmin, Option<Xid8>),
+        name!(xmax, Option<Xid8>),
+        name!(visible, Option<bool>),
+        name!(visibility_reason, Option<String>),
+        name!(data, Option<String>),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let table = OpenRelation::open(get_relid_from_name(table));
+
+    let nblocks =
+        pg_sys::RelationGetNumberOfBlocksInFork(table.rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+    let output_format = OutputFormat::from_guc();
+
+    let include = move |line_pointer: &LinePointer| match line_pointer.tuple {
+        Some((_, visible)) => visible,
+        None => {
+            include_line_pointers
+                && (line_pointer.state == "DEAD" || line_pointer.state == "REDIRECT")
+        }
+    };
+
+    /* The pages are read one at a time while the rows are returned */
+    TableIterator::new((0..nblocks).flat_map(move |blkno| {
+        prefetch_ahead(table.rel, blkno, nblocks);
+        read_page_rows(table.rel, blkno, snapshot_data, output_format, &include)
+    }))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_scan_page() {
+        pgrx::Spi::run("CREATE TABLE scan_page_test (id int, value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_page_test VALUES (1, 1), (2, 2);").unwrap();
+        pgrx::Spi::run("DELETE FROM scan_page_test WHERE id = 2;").unwrap();
+
+        let line_pointers = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan_page('scan_page_test', 0) WHERE lp_state = 'NORMAL';",
+        )
+        .unwrap();
+        assert_eq!(line_pointers, Some(2));
+
+        let visible = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan_page('scan_page_test', 0) WHERE visible;",
+        )
+        .unwrap();
+        assert_eq!(visible, Some(1));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_pages() {
+        pgrx::Spi::run("CREATE TABLE scan_pages_test (id int, value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_pages_test VALUES (1, 1), (2, 2);").unwrap();
+        pgrx::Spi::run("DELETE FROM scan_pages_test WHERE id = 2;").unwrap();
+
+        let visible = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan_pages('scan_pages_test') WHERE visible;",
+        )
+        .unwrap();
+        assert_eq!(visible, Some(1));
+
+        let line_pointers = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan_pages('scan_pages_test', include_line_pointers => true) \
+             WHERE lp_state <> 'NORMAL';",
+        )
+        .unwrap();
+        assert_eq!(line_pointers, Some(0));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_pages_pruned_line_pointers() {
+        pgrx::Spi::run("CREATE TABLE scan_pages_pruned_test (id int, value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_pages_pruned_test VALUES (1, 1), (2, 2), (3, 3);")
+            .unwrap();
+
+        /* Leave the line pointers of a pruned HOT chain behind, like heap_page_prune */
+        unsafe {
+            crate::heap::modify_page("scan_pages_pruned_test", 0, |page| {
+                let root = crate::heap::PageGetItemId(page, 1);
+                (*root).set_lp_flags(pg_sys::LP_REDIRECT);
+                (*root).set_lp_off(3);
+                (*root).set_lp_len(0);
+
+                let dead = crate::heap::PageGetItemId(page, 2);
+                (*dead).set_lp_flags(pg_sys::LP_DEAD);
+                (*dead).set_lp_off(0);
+                (*dead).set_lp_len(0);
+            });
+        }
+
+        let line_pointers = pgrx::Spi::get_one::<String>(
+            "SELECT string_agg(ctid::text || ':' || lp_state || ':' || coalesce(redirect_to::text, ''), ',' ORDER BY ctid) \
+             FROM pg_debug_scan_pages('scan_pages_pruned_test', include_line_pointers => true);",
+        )
+        .unwrap();
+        assert_eq!(
+            line_pointers,
+            Some("(0,1):REDIRECT:3,(0,2):DEAD:,(0,3):NORMAL:".to_string())
+        );
+
+        let rows = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan_pages('scan_pages_pruned_test');",
+        )
+        .unwrap();
+        assert_eq!(rows, Some(1));
+    }
+
+    #[pgrx::pg_test(error = "Block 5 is out of range, the table has 1 blocks")]
+    fn test_scan_page_out_of_range() {
+        pgrx::Spi::run("CREATE TABLE scan_page_range_test (id int);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_page_range_test VALUES (1);").unwrap();
+        pgrx::Spi::run("SELECT * FROM pg_debug_scan_page('scan_page_range_test', 5);").unwrap();
+    }
+}
@@ -0,0 +1,517 @@
+use std::ffi::c_void;
+use std::mem::size_of;
+use std::ptr;
+
+use pgrx::{
+    itemptr::{item_pointer_get_both, item_pointer_set_all},
+    pg_sys::{AccessShareLock, GetTransactionSnapshot, TransactionId},
+    prelude::*,
+    PgMemoryContexts,
+};
+
+use crate::heap::{open_heap_relation, HeapTupleHeaderGetXmax};
+use crate::output::{format_tuple, OutputFormat};
+use crate::snapshot::get_snapshot_from_str;
+use crate::xid8::Xid8;
+use crate::{decode_tuple, get_relid_from_name, resources_releasable, ScanRow};
+
+/* The keys of the shared memory table of contents of a parallel scan */
+const KEY_SCAN_STATE: u64 = 1;
+const KEY_PARALLEL_SCAN: u64 = 2;
+const KEY_TUPLE_QUEUES: u64 = 3;
+
+/* The size of the queue of each worker, see PARALLEL_TUPLE_QUEUE_SIZE */
+const TUPLE_QUEUE_SIZE: usize = 65536;
+
+/* The alignment of the chunks in shared memory, see ALIGNOF_BUFFER */
+const ALIGNOF_BUFFER: usize = 32;
+
+/* The size of the ctid, xmin, and xmax that precede the data of a row message */
+const ROW_HEADER_SIZE: usize = 14;
+
+/* The state of a parallel scan that is shared with the workers */
+#[repr(C)]
+struct ParallelScanState {
+    relid: pg_sys::Oid,
+}
+
+/*
+ * Reserve space for a chunk in the dynamic shared memory segment, see the
+ * shm_toc_estimate_chunk and shm_toc_estimate_keys macros
+ */
+fn estimate_chunk(estimator: &mut pg_sys::shm_toc_estimator, size: usize) {
+    let aligned_size = (size + ALIGNOF_BUFFER - 1) & !(ALIGNOF_BUFFER - 1);
+
+    estimator.space_for_chunks += aligned_size;
+    estimator.number_of_keys += 1;
+}
+
+/*
+ * Encode a scanned tuple as message of a tuple queue
+ */
+fn encode_row(
+    ctid: pg_sys::ItemPointerData,
+    xmin: TransactionId,
+    xmax: TransactionId,
+    data: &str,
+) -> Vec<u8> {
+    let (block_number, offset_number) = item_pointer_get_both(ctid);
+
+    let mut message = Vec::with_capacity(ROW_HEADER_SIZE + data.len());
+    message.extend_from_slice(&block_number.to_ne_bytes());
+    message.extend_from_slice(&offset_number.to_ne_bytes());
+    message.extend_from_slice(&xmin.to_ne_bytes());
+    message.extend_from_slice(&xmax.to_ne_bytes());
+    message.extend_from_slice(data.as_bytes());
+
+    message
+}
+
+/*
+ * Decode a message of a tuple queue into a result row, see encode_row
+ */
+fn decode_row(message: &[u8], next_xid: pg_sys::FullTransactionId) -> ScanRow {
+    let u32_at = |pos: usize| u32::from_ne_bytes(message[pos..pos + 4].try_into().unwrap());
+
+    let block_number = u32_at(0);
+    let offset_number = u16::from_ne_bytes(message[4..6].try_into().unwrap());
+
+    let mut ctid = pg_sys::ItemPointerData::default();
+    item_pointer_set_all(&mut ctid, block_number, offset_number);
+
+    (
+        ctid,
+        block_number.into(),
+        offset_number.into(),
+        Xid8::from_xid(u32_at(6), next_xid),
+        Xid8::from_xid(u32_at(10), next_xid),
+        String::from_utf8_lossy(&message[ROW_HEADER_SIZE..]).into_owned(),
+    )
+}
+
+/* A tuple of a parallel scan (ctid, xmin, xmax, and data) */
+type ParallelRow = (
+    pg_sys::ItemPointerData,
+    TransactionId,
+    TransactionId,
+    String,
+);
+
+/*
+ * A scan of the blocks of a parallel scan that are assigned to this process. The
+ * scan is ended when dropped.
+ */
+struct AssignedBlocks {
+    scan: pg_sys::TableScanDesc,
+    slot: *mut pg_sys::TupleTableSlot,
+    relid: pg_sys::Oid,
+    output_format: OutputFormat,
+    /* Released after each tuple, like the tuple context of TupleStream */
+    tuple_context: pg_sys::MemoryContext,
+}
+
+impl AssignedBlocks {
+    /*
+     * Join the parallel scan of the given relation
+     */
+    unsafe fn begin(
+        table_rel: pg_sys::Relation,
+        pscan: pg_sys::ParallelTableScanDesc,
+    ) -> AssignedBlocks {
+        AssignedBlocks {
+            scan: pg_sys::table_beginscan_parallel(table_rel, pscan),
+            slot: pg_sys::table_slot_create(table_rel, ptr::null_mut()),
+            relid: (*table_rel).rd_id,
+            output_format: OutputFormat::from_guc(),
+            tuple_context: pg_sys::AllocSetContextCreateExtended(
+                pg_sys::CurrentMemoryContext,
+                b"pg_debug_scan parallel tuple context\0".as_ptr() as *const std::os::raw::c_char,
+                pg_sys::ALLOCSET_DEFAULT_MINSIZE as usize,
+                pg_sys::ALLOCSET_DEFAULT_INITSIZE as usize,
+                pg_sys::ALLOCSET_DEFAULT_MAXSIZE as usize,
+            ),
+        }
+    }
+
+    /*
+     * Get the next visible tuple of the blocks assigned to this process
+     */
+    unsafe fn next_row(&mut self) -> Option<ParallelRow> {
+        if !pg_sys::heap_getnextslot(
+            self.scan,
+            pg_sys::ScanDirection_ForwardScanDirection,
+            self.slot,
+        ) {
+            return None;
+        }
+
+        check_for_interrupts!();
+
+        let get_heap_tuple_fn = (*(*self.slot).tts_ops).get_heap_tuple.unwrap();
+        let htup = get_heap_tuple_fn(self.slot);
+        let tupdesc = (*self.slot).tts_tupleDescriptor;
+
+        let (relid, output_format) = (self.relid, self.output_format);
+        let data = PgMemoryContexts::For(self.tuple_context)
+            .switch_to(|_| format_tuple(&decode_tuple(relid, htup, tupdesc), output_format));
+        pg_sys::MemoryContextReset(self.tuple_context);
+
+        Some((
+            (*htup).t_self,
+            pg_sys::HeapTupleHeaderGetXmin((*htup).t_data),
+            HeapTupleHeaderGetXmax((*htup).t_data),
+            data,
+        ))
+    }
+}
+
+impl Drop for AssignedBlocks {
+    fn drop(&mut self) {
+        if resources_releasable() {
+            unsafe {
+                pg_sys::heap_endscan(self.scan);
+                pg_sys::ExecDropSingleTupleTableSlot(self.slot);
+                pg_sys::MemoryContextDelete(self.tuple_context);
+            }
+        }
+    }
+}
+
+/*
+ * Send a row to the leader. The queue is only full if the leader is behind, so
+ * waiting is fine.
+ */
+#[cfg(any(feature = "pg12", feature = "pg13", feature = "pg14"))]
+unsafe fn send_row(mqh: *mut pg_sys::shm_mq_handle, message: &[u8]) -> pg_sys::shm_mq_result {
+    pg_sys::shm_mq_send(mqh, message.len(), message.as_ptr() as *const c_void, false)
+}
+
+#[cfg(any(feature = "pg15", feature = "pg16"))]
+unsafe fn send_row(mqh: *mut pg_sys::shm_mq_handle, message: &[u8]) -> pg_sys::shm_mq_result {
+    pg_sys::shm_mq_send(
+        mqh,
+        message.len(),
+        message.as_ptr() as *const c_void,
+        false,
+        false,
+    )
+}
+
+/*
+ * The entry point of the parallel workers. The transaction and the snapshot of the
+ * leader are restored by PostgreSQL before this function is called. Each worker
+ * scans the blocks assigned to it and sends the rows through its tuple queue.
+ */
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn pg_debug_scan_parallel_main(
+    seg: *mut pg_sys::dsm_segment,
+    toc: *mut pg_sys::shm_toc,
+) {
+    unsafe {
+        let state = pg_sys::shm_toc_lookup(toc, KEY_SCAN_STATE, false) as *mut ParallelScanState;
+        let pscan =
+            pg_sys::shm_toc_lookup(toc, KEY_PARALLEL_SCAN, false) as pg_sys::ParallelTableScanDesc;
+        let queues = pg_sys::shm_toc_lookup(toc, KEY_TUPLE_QUEUES, false) as *mut u8;
+
+        let mq = queues.add(pg_sys::ParallelWorkerNumber as usize * TUPLE_QUEUE_SIZE)
+            as *mut pg_sys::shm_mq;
+        pg_sys::shm_mq_set_sender(mq, pg_sys::MyProc);
+        let mqh = pg_sys::shm_mq_attach(mq, seg, ptr::null_mut());
+
+        let table_rel = pg_sys::table_open((*state).relid, AccessShareLock as i32);
+        let mut blocks = AssignedBlocks::begin(table_rel, pscan);
+
+        while let Some((ctid, xmin, xmax, data)) = blocks.next_row() {
+            let message = encode_row(ctid, xmin, xmax, &data);
+
+            if send_row(mqh, &message) != pg_sys::shm_mq_result_SHM_MQ_SUCCESS {
+                /* The leader has stopped reading, e.g., because of a LIMIT */
+                break;
+            }
+        }
+
+        drop(blocks);
+        pg_sys::table_close(table_rel, AccessShareLock as i32);
+        pg_sys::shm_mq_detach(mqh);
+    }
+}
+
+/*
+ * A running parallel scan, which returns the rows of the workers as they arrive.
+ * If no row of a worker is available, the leader scans a tuple of the blocks that
+ * are not yet assigned, like the leader of a Gather node does. So the rows are
+ * neither collected in memory nor is the leader idle while the workers scan. The
+ * workers and the parallel context are released when all rows are read or the scan
+ * is dropped.
+ */
+struct ParallelScan {
+    pcxt: *mut pg_sys::ParallelContext,
+    table_rel: pg_sys::Relation,
+    /* The tuple queues of the workers that have not finished yet */
+    readers: Vec<*mut pg_sys::shm_mq_handle>,
+    /* The queue that is read next, so the workers are read in turn */
+    next_reader: usize,
+    /* The blocks scanned by the leader, None once all blocks are assigned */
+    leader: Option<AssignedBlocks>,
+    next_xid: pg_sys::FullTransactionId,
+    finished: bool,
+}
+
+impl ParallelScan {
+    /*
+     * Receive a row of one of the workers without waiting
+     */
+    unsafe fn receive_row(&mut self) -> Option<ScanRow> {
+        let mut checked = 0;
+
+        while checked < self.readers.len() {
+            let i = self.next_reader % self.readers.len();
+            let mut nbytes: pg_sys::Size = 0;
+            let mut data: *mut c_void = ptr::null_mut();
+
+            match pg_sys::shm_mq_receive(self.readers[i], &mut nbytes, &mut data, true) {
+                pg_sys::shm_mq_result_SHM_MQ_SUCCESS => {
+                    self.next_reader = i + 1;
+
+                    let message = std::slice::from_raw_parts(data as *const u8, nbytes);
+                    return Some(decode_row(message, self.next_xid));
+                }
+                pg_sys::shm_mq_result_SHM_MQ_WOULD_BLOCK => {
+                    self.next_reader = i + 1;
+                    checked += 1;
+                }
+                _ => {
+                    /* The worker has finished or did not start */
+                    pg_sys::shm_mq_detach(self.readers.swap_remove(i));
+                }
+            }
+        }
+
+        None
+    }
+
+    /*
+     * Convert a row scanned by the leader into a result row
+     */
+    fn leader_row(&self, row: ParallelRow) -> ScanRow {
+        let (ctid, xmin, xmax, data) = row;
+        let (block_number, offset_number) = item_pointer_get_both(ctid);
+
+        (
+            ctid,
+            block_number.into(),
+            offset_number.into(),
+            Xid8::from_xid(xmin, self.next_xid),
+            Xid8::from_xid(xmax, self.next_xid),
+            data,
+        )
+    }
+
+    /*
+     * Wait for the workers to finish and release the parallel context
+     */
+    unsafe fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        self.finished = true;
+
+        /* The scan of the leader uses the shared memory of the parallel context */
+        self.leader = None;
+
+        /* Workers that are still sending rows stop when their queue is detached */
+        for mqh in self.readers.drain(..) {
+            pg_sys::shm_mq_detach(mqh);
+        }
+
+        pg_sys::WaitForParallelWorkersToFinish(self.pcxt);
+        pg_sys::DestroyParallelContext(self.pcxt);
+        pg_sys::ExitParallelMode();
+
+        pg_sys::table_close(self.table_rel, AccessShareLock as i32);
+    }
+}
+
+impl Iterator for ParallelScan {
+    type Item = ScanRow;
+
+    fn next(&mut self) -> Option<ScanRow> {
+        unsafe {
+            while !self.finished {
+                /* Also raises the errors reported by the workers */
+                check_for_interrupts!();
+
+                if let Some(row) = self.receive_row() {
+                    return Some(row);
+                }
+
+                if let Some(leader) = self.leader.as_mut() {
+                    match leader.next_row() {
+                        Some(row) => return Some(self.leader_row(row)),
+                        None => self.leader = None,
+                    }
+
+                    continue;
+                }
+
+                if self.readers.is_empty() {
+                    self.finish();
+                    break;
+                }
+
+                /* Wait until a worker sends a row or detaches from its queue */
+                pg_sys::WaitLatch(
+                    pg_sys::MyLatch,
+                    (pg_sys::WL_LATCH_SET | pg_sys::WL_EXIT_ON_PM_DEATH) as i32,
+                    0,
+                    pg_sys::PG_WAIT_EXTENSION,
+                );
+                pg_sys::ResetLatch(pg_sys::MyLatch);
+            }
+
+            None
+        }
+    }
+}
+
+impl Drop for ParallelScan {
+    fn drop(&mut self) {
+        /* The scan is dropped before all rows are read (e.g., due to a LIMIT) */
+        if resources_releasable() {
+            unsafe { self.finish() };
+        }
+    }
+}
+
+/*
+ * Scan the table with parallel workers. The blocks of the table are assigned to the
+ * workers by the parallel block allocator of PostgreSQL, like in a parallel
+ * sequential scan. Each worker decodes the visible tuples of its blocks and sends
+ * them through a shared memory queue to the leader, which also scans blocks while
+ * no row of a worker is available (e.g., if no worker could be started).
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_parallel(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    workers: default!(i32, 2),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(block_number, i64),
+        name!(offset_number, i32),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(data, String),
+    ),
+> {
+    if workers < 1 {
+        error!("Invalid number of workers {workers}, at least one worker is required");
+    }
+
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+
+    pg_sys::EnterParallelMode();
+
+    let pcxt = pg_sys::CreateParallelContext(
+        b"pg_debug_scan\0".as_ptr() as *const std::os::raw::c_char,
+        b"pg_debug_scan_parallel_main\0".as_ptr() as *const std::os::raw::c_char,
+        workers,
+    );
+
+    let pscan_size = pg_sys::table_parallelscan_estimate(table_rel, snapshot_data);
+    let queues_size = workers as usize * TUPLE_QUEUE_SIZE;
+
+    estimate_chunk(&mut (*pcxt).estimator, size_of::<ParallelScanState>());
+    estimate_chunk(&mut (*pcxt).estimator, pscan_size);
+    estimate_chunk(&mut (*pcxt).estimator, queues_size);
+
+    pg_sys::InitializeParallelDSM(pcxt);
+
+    let state = pg_sys::shm_toc_allocate((*pcxt).toc, size_of::<ParallelScanState>())
+        as *mut ParallelScanState;
+    (*state).relid = relid;
+    pg_sys::shm_toc_insert((*pcxt).toc, KEY_SCAN_STATE, state as *mut c_void);
+
+    /* The snapshot is serialized, so the workers use the same snapshot */
+    let pscan = pg_sys::shm_toc_allocate((*pcxt).toc, pscan_size) as pg_sys::ParallelTableScanDesc;
+    pg_sys::table_parallelscan_initialize(table_rel, pscan, snapshot_data);
+    pg_sys::shm_toc_insert((*pcxt).toc, KEY_PARALLEL_SCAN, pscan as *mut c_void);
+
+    let queues = pg_sys::shm_toc_allocate((*pcxt).toc, queues_size) as *mut u8;
+    for i in 0..workers as usize {
+        let mq = pg_sys::shm_mq_create(
+            queues.add(i * TUPLE_QUEUE_SIZE) as *mut c_void,
+            TUPLE_QUEUE_SIZE,
+        );
+        pg_sys::shm_mq_set_receiver(mq, pg_sys::MyProc);
+    }
+    pg_sys::shm_toc_insert((*pcxt).toc, KEY_TUPLE_QUEUES, queues as *mut c_void);
+
+    pg_sys::LaunchParallelWorkers(pcxt);
+
+    /* Only the queues of the launched workers are read */
+    let readers: Vec<_> = (0..(*pcxt).nworkers_launched as usize)
+        .map(|i| {
+            let mq = queues.add(i * TUPLE_QUEUE_SIZE) as *mut pg_sys::shm_mq;
+            let mqh = pg_sys::shm_mq_attach(mq, (*pcxt).seg, ptr::null_mut());
+            pg_sys::shm_mq_set_handle(mqh, (*(*pcxt).worker.add(i)).bgwhandle);
+            mqh
+        })
+        .collect();
+
+    TableIterator::new(ParallelScan {
+        pcxt,
+        table_rel,
+        readers,
+        next_reader: 0,
+        leader: Some(AssignedBlocks::begin(table_rel, pscan)),
+        next_xid: pg_sys::ReadNextFullTransactionId(),
+        finished: false,
+    })
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_scan_parallel() {
+        pgrx::Spi::run("CREATE TABLE parallel_test (id int, value text);").unwrap();
+        pgrx::Spi::run(
+            "INSERT INTO parallel_test SELECT i, repeat('x', 100) FROM generate_series(1, 5000) i;",
+        )
+        .unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan_parallel('parallel_test', workers => 2);",
+        )
+        .unwrap();
+        assert_eq!(count, Some(5000));
+
+        let distinct = pgrx::Spi::get_one::<i64>(
+            "SELECT count(DISTINCT ctid) FROM pg_debug_scan_parallel('parallel_test');",
+        )
+        .unwrap();
+        assert_eq!(distinct, Some(5000));
+
+        /* The workers stop when the scan ends before all rows are read */
+        let limited = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM (SELECT * FROM pg_debug_scan_parallel('parallel_test') LIMIT 10) s;",
+        )
+        .unwrap();
+        assert_eq!(limited, Some(10));
+    }
+}
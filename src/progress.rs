@@ -0,0 +1,152 @@
+use pgrx::prelude::*;
+
+use crate::guc::PROGRESS_INTERVAL;
+use crate::regclass::RegClass;
+
+/* From PostgreSQL: #define InvalidBlockNumber ((BlockNumber) 0xFFFFFFFF) */
+const INVALID_BLOCK_NUMBER: pg_sys::BlockNumber = 0xFFFFFFFF;
+
+/*
+ * The progress of a heap scan, which is reported as notice every
+ * pg_debug_scan.progress_interval blocks. The position of the scan is determined by
+ * the block of the last read tuple, so pages without tuples visible under the
+ * snapshot are counted when the next tuple is read.
+ */
+pub(crate) struct ScanProgress {
+    relid: pg_sys::Oid,
+    interval: u64,
+    forward: bool,
+    /* The first block of the scan (in scan direction) */
+    start_block: pg_sys::BlockNumber,
+    /* The number of blocks of the relation, the scan might wrap around at the end */
+    nblocks: pg_sys::BlockNumber,
+    /* The number of blocks the scan is limited to */
+    total_blocks: pg_sys::BlockNumber,
+    blocks_scanned: u64,
+    tuples_read: u64,
+    tuples_returned: u64,
+    next_report: u64,
+}
+
+impl ScanProgress {
+    /*
+     * Track the progress of the given scan, which has to be called before the scan
+     * reads the first tuple. None is returned if no progress is reported.
+     */
+    pub(crate) unsafe fn new(
+        scan: pg_sys::TableScanDesc,
+        direction: pg_sys::ScanDirection,
+    ) -> Option<ScanProgress> {
+        let interval = PROGRESS_INTERVAL.get();
+
+        if interval == 0 {
+            return None;
+        }
+
+        let heap_scan = scan as pg_sys::HeapScanDesc;
+        let nblocks = (*heap_scan).rs_nblocks;
+        let total_blocks = match (*heap_scan).rs_numblocks {
+            INVALID_BLOCK_NUMBER => nblocks,
+            numblocks => numblocks,
+        };
+        let forward = direction != pg_sys::ScanDirection_BackwardScanDirection;
+
+        /* See heapgettup, a backward scan starts with the last block of the limits */
+        let start_block = if forward || nblocks == 0 {
+            (*heap_scan).rs_startblock
+        } else {
+            ((*heap_scan).rs_startblock + total_blocks.max(1) - 1) % nblocks
+        };
+
+        Some(ScanProgress {
+            relid: (*(*scan).rs_rd).rd_id,
+            interval: interval as u64,
+            forward,
+            start_block,
+            nblocks,
+            total_blocks,
+            blocks_scanned: 0,
+            tuples_read: 0,
+            tuples_returned: 0,
+            next_report: interval as u64,
+        })
+    }
+
+    /*
+     * Count a tuple of the given block that is read by the heap scan
+     */
+    pub(crate) unsafe fn tuple_read(&mut self, blkno: pg_sys::BlockNumber) {
+        let nblocks = self.nblocks.max(1) as u64;
+        let distance = if self.forward {
+            (blkno as u64 + nblocks - self.start_block as u64) % nblocks
+        } else {
+            (self.start_block as u64 + nblocks - blkno as u64) % nblocks
+        };
+
+        self.blocks_scanned = self.blocks_scanned.max(distance + 1);
+        self.tuples_read += 1;
+
+        if self.blocks_scanned >= self.next_report {
+            self.report();
+            self.next_report = (self.blocks_scanned / self.interval + 1) * self.interval;
+        }
+    }
+
+    /*
+     * Count a tuple that is returned by the scan, i.e., it matches all filters
+     */
+    pub(crate) fn tuple_returned(&mut self) {
+        self.tuples_returned += 1;
+    }
+
+    /*
+     * Report the final numbers when all blocks are read
+     */
+    pub(crate) unsafe fn finish(&mut self) {
+        self.blocks_scanned = self.total_blocks as u64;
+        self.report();
+    }
+
+    /*
+     * Emit the progress of the scan as notice
+     */
+    unsafe fn report(&self) {
+        notice!(
+            "Scanned {} of {} blocks of {}, {} tuples read, {} tuples returned",
+            self.blocks_scanned,
+            self.total_blocks,
+            RegClass(self.relid).name(),
+            self.tuples_read,
+            self.tuples_returned
+        );
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_scan_progress() {
+        pgrx::Spi::run("CREATE TABLE progress_test (id int, value text);").unwrap();
+        pgrx::Spi::run(
+            "INSERT INTO progress_test SELECT i, repeat('x', 100) FROM generate_series(1, 1000) i;",
+        )
+        .unwrap();
+        pgrx::Spi::run("DELETE FROM progress_test WHERE id <= 500;").unwrap();
+        pgrx::Spi::run("SET pg_debug_scan.progress_interval = 1;").unwrap();
+
+        let forward =
+            pgrx::Spi::get_one::<i64>("SELECT count(*) FROM pg_debug_scan('progress_test');")
+                .unwrap();
+        assert_eq!(forward, Some(500));
+
+        let backward = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('progress_test', direction => 'backward');",
+        )
+        .unwrap();
+        assert_eq!(backward, Some(500));
+    }
+}
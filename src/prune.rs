@@ -0,0 +1,453 @@
+use pgrx::{
+    pg_sys::{AccessShareLock, OffsetNumber, TransactionId},
+    prelude::*,
+};
+
+use crate::get_relid_from_name;
+use crate::heap::{
+    open_heap_relation, page_get_heap_tuple, prefetch_ahead, with_locked_page, BufferGetPage,
+    HeapTupleHeaderGetUpdateXid, HeapTupleHeaderIsHeapOnly, HeapTupleHeaderIsHotUpdated,
+    PageGetItemId, PageGetMaxOffsetNumber,
+};
+use crate::snapshot::input_error;
+
+/* Not exported by pgrx, see storage/procarray.h */
+#[cfg(any(feature = "pg12", feature = "pg13"))]
+const PROCARRAY_FLAGS_VACUUM: i32 = 0x02;
+
+#[pg_guard]
+extern "C" {
+    #[cfg(any(feature = "pg12", feature = "pg13"))]
+    fn GetOldestXmin(rel: pg_sys::Relation, flags: i32) -> TransactionId;
+
+    #[cfg(any(feature = "pg14", feature = "pg15", feature = "pg16"))]
+    fn GetOldestNonRemovableTransactionId(rel: pg_sys::Relation) -> TransactionId;
+}
+
+/* The change opportunistic pruning would apply to a line pointer */
+struct PruneAction {
+    offnum: OffsetNumber,
+    action: &'static str,
+    redirect_to: Option<OffsetNumber>,
+}
+
+/*
+ * Get the horizon that is used by pruning to decide whether a deleted tuple is
+ * still visible to any running transaction.
+ */
+pub(crate) unsafe fn get_prune_horizon(rel: pg_sys::Relation) -> TransactionId {
+    #[cfg(any(feature = "pg12", feature = "pg13"))]
+    {
+        GetOldestXmin(rel, PROCARRAY_FLAGS_VACUUM)
+    }
+    #[cfg(any(feature = "pg14", feature = "pg15", feature = "pg16"))]
+    {
+        GetOldestNonRemovableTransactionId(rel)
+    }
+}
+
+/*
+ * Check if heap_page_prune_opt would try to prune the page at all. Pruning is
+ * only attempted if the page contains a prunable xid older than the horizon and
+ * the page is (almost) full.
+ */
+unsafe fn page_would_be_pruned(
+    rel: pg_sys::Relation,
+    page: pg_sys::Page,
+    horizon: TransactionId,
+) -> bool {
+    let header = page as pg_sys::PageHeader;
+    let prune_xid = (*header).pd_prune_xid;
+
+    if prune_xid == pg_sys::InvalidTransactionId
+        || !pg_sys::TransactionIdPrecedes(prune_xid, horizon)
+    {
+        return false;
+    }
+
+    /* See RelationGetTargetPageFreeSpace */
+    let fillfactor = if (*rel).rd_options.is_null() {
+        pg_sys::HEAP_DEFAULT_FILLFACTOR as usize
+    } else {
+        (*((*rel).rd_options as *mut pg_sys::StdRdOptions)).fillfactor as usize
+    };
+
+    let target_free_space = pg_sys::BLCKSZ as usize * (100 - fillfactor) / 100;
+    let minfree = target_free_space.max(pg_sys::BLCKSZ as usize / 10);
+
+    let page_full = (*header).pd_flags as u32 & pg_sys::PD_PAGE_FULL != 0;
+    page_full || pg_sys::PageGetHeapFreeSpace(page) < minfree
+}
+
+/*
+ * Simulate heap_prune_chain for all HOT chains of the page. The page is not
+ * modified; the changes pruning would apply are returned instead.
+ */
+unsafe fn simulate_page_prune(
+    relid: pg_sys::Oid,
+    buffer: pg_sys::Buffer,
+    blkno: pg_sys::BlockNumber,
+    horizon: TransactionId,
+) -> Vec<PruneAction> {
+    let page = BufferGetPage(buffer);
+    let maxoff = PageGetMaxOffsetNumber(page) as usize;
+
+    /* Determine the vacuum status of all tuples on the page */
+    let status: Vec<Option<pg_sys::HTSV_Result>> = (0..=maxoff)
+        .map(|offnum| {
+            if offnum == 0 {
+                return None;
+            }
+
+            let itemid = PageGetItemId(page, offnum as OffsetNumber);
+            if (*itemid).lp_flags() != pg_sys::LP_NORMAL {
+                return None;
+            }
+
+            let mut htup = page_get_heap_tuple(relid, page, blkno, offnum as OffsetNumber);
+            Some(pg_sys::HeapTupleSatisfiesVacuum(&mut htup, horizon, buffer))
+        })
+        .collect();
+
+    let mut processed = vec![false; maxoff + 1];
+    let mut actions = Vec::new();
+
+    for rootoff in 1..=maxoff {
+        let itemid = PageGetItemId(page, rootoff as OffsetNumber);
+        let lp_flags = (*itemid).lp_flags();
+
+        if processed[rootoff] || (lp_flags != pg_sys::LP_NORMAL && lp_flags != pg_sys::LP_REDIRECT)
+        {
+            continue;
+        }
+
+        /* Heap-only tuples are handled as members of their chain */
+        if lp_flags == pg_sys::LP_NORMAL
+            && HeapTupleHeaderIsHeapOnly(
+                page_get_heap_tuple(relid, page, blkno, rootoff as OffsetNumber).t_data,
+            )
+        {
+            continue;
+        }
+
+        /* Follow the HOT chain and remember the latest dead member */
+        let mut chain: Vec<usize> = Vec::new();
+        let mut latestdead = None;
+        let mut recent_dead = false;
+        let mut prior_xmax = pg_sys::InvalidTransactionId;
+        let mut offnum = rootoff;
+
+        while (1..=maxoff).contains(&offnum) && !processed[offnum] {
+            let itemid = PageGetItemId(page, offnum as OffsetNumber);
+
+            if (*itemid).lp_flags() == pg_sys::LP_REDIRECT {
+                if !chain.is_empty() {
+                    break;
+                }
+                chain.push(offnum);
+                offnum = (*itemid).lp_off() as usize;
+                continue;
+            }
+
+            if (*itemid).lp_flags() != pg_sys::LP_NORMAL {
+                break;
+            }
+
+            let htup = page_get_heap_tuple(relid, page, blkno, offnum as OffsetNumber);
+            let xmin = pg_sys::HeapTupleHeaderGetXmin(htup.t_data);
+
+            if prior_xmax != pg_sys::InvalidTransactionId && xmin != prior_xmax {
+                break;
+            }
+
+            chain.push(offnum);
+
+            match status[offnum] {
+                /* Dead members behind a recently dead one are kept, see heap_prune_chain */
+                Some(pg_sys::HTSV_Result_HEAPTUPLE_DEAD) => {
+                    if !recent_dead {
+                        latestdead = Some(offnum);
+                    }
+                }
+                Some(pg_sys::HTSV_Result_HEAPTUPLE_RECENTLY_DEAD) => recent_dead = true,
+                _ => {
+                    if !recent_dead {
+                        break;
+                    }
+                }
+            }
+
+            if !HeapTupleHeaderIsHotUpdated(htup.t_data) {
+                break;
+            }
+
+            offnum = (*htup.t_data).t_ctid.ip_posid as usize;
+            prior_xmax = HeapTupleHeaderGetUpdateXid(htup.t_data);
+        }
+
+        for member in &chain {
+            processed[*member] = true;
+        }
+
+        let latestdead = match latestdead {
+            Some(latestdead) => latestdead,
+            None => {
+                /*
+                 * A redirect that does not point to a valid chain member is set to
+                 * dead, since nothing reachable from it is left
+                 */
+                if chain.len() < 2 && lp_flags == pg_sys::LP_REDIRECT {
+                    actions.push(PruneAction {
+                        offnum: rootoff as OffsetNumber,
+                        action: "dead",
+                        redirect_to: None,
+                    });
+                }
+                continue;
+            }
+        };
+
+        /* All members up to the latest dead one become unused */
+        let mut i = 1;
+        while i < chain.len() && chain[i - 1] != latestdead {
+            actions.push(PruneAction {
+                offnum: chain[i] as OffsetNumber,
+                action: "unused",
+                redirect_to: None,
+            });
+            i += 1;
+        }
+
+        /* The root is marked as dead or redirected to the first surviving member */
+        if i >= chain.len() {
+            actions.push(PruneAction {
+                offnum: rootoff as OffsetNumber,
+                action: "dead",
+                redirect_to: None,
+            });
+        } else if lp_flags != pg_sys::LP_REDIRECT || (*itemid).lp_off() as usize != chain[i] {
+            actions.push(PruneAction {
+                offnum: rootoff as OffsetNumber,
+                action: "redirect",
+                redirect_to: Some(chain[i] as OffsetNumber),
+            });
+        }
+    }
+
+    /* Dead heap-only tuples that are not part of any chain are removed as well */
+    for (offnum, (status, processed)) in status.iter().zip(processed.iter()).enumerate() {
+        if !processed && *status == Some(pg_sys::HTSV_Result_HEAPTUPLE_DEAD) {
+            actions.push(PruneAction {
+                offnum: offnum as OffsetNumber,
+                action: "unused",
+                redirect_to: None,
+            });
+        }
+    }
+
+    actions.sort_by_key(|action| action.offnum);
+    actions
+}
+
+/*
+ * Report per block which line pointers opportunistic pruning (heap_page_prune_opt)
+ * would mark as unused, dead or redirect right now. The column would_prune shows
+ * if pruning would currently be triggered for the page at all.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_prune_preview(
+    table: &str,
+    horizon: default!(Option<i64>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(block_number, i64),
+        name!(offset_number, i32),
+        name!(action, String),
+        name!(redirect_to, Option<i32>),
+        name!(would_prune, bool),
+    ),
+> {
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+
+    let horizon = match horizon {
+        Some(horizon) => TransactionId::try_from(horizon).unwrap_or_else(|_| {
+            input_error(
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                format!("horizon value {horizon} is not a valid xid"),
+                None,
+            )
+        }),
+        None => get_prune_horizon(table_rel),
+    };
+
+    info!("Simulating pruning of table {table} with horizon {horizon}");
+
+    let nblocks =
+        pg_sys::RelationGetNumberOfBlocksInFork(table_rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+
+    let mut results: Vec<(i64, i32, String, Option<i32>, bool)> = Vec::new();
+
+    for blkno in 0..nblocks {
+        prefetch_ahead(table_rel, blkno, nblocks);
+
+        let (would_prune, actions) = with_locked_page(table_rel, blkno, |buffer, page| {
+            (
+                page_would_be_pruned(table_rel, page, horizon),
+                simulate_page_prune(relid, buffer, blkno, horizon),
+            )
+        });
+
+        for action in actions {
+            results.push((
+                blkno.into(),
+                action.offnum.into(),
+                action.action.to_string(),
+                action.redirect_to.map(|offnum| offnum.into()),
+                would_prune,
+            ));
+        }
+    }
+
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+
+    TableIterator::new(results)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_prune_preview_aborted_insert() {
+        pgrx::Spi::run("CREATE TABLE prune_test (value int);").unwrap();
+
+        /* Insert a tuple in an aborted subtransaction */
+        pgrx::Spi::run(
+            "DO $$ BEGIN
+                INSERT INTO prune_test VALUES (1);
+                RAISE EXCEPTION 'abort';
+            EXCEPTION WHEN OTHERS THEN NULL;
+            END $$;",
+        )
+        .unwrap();
+
+        let action = pgrx::Spi::get_one::<String>(
+            "SELECT action FROM pg_debug_prune_preview('prune_test') WHERE offset_number = 1;",
+        )
+        .unwrap();
+        assert_eq!(action, Some("dead".to_string()));
+    }
+
+    /* The xmin of the given tuple of the table */
+    fn tuple_xmin(table: &str, value: i32) -> u32 {
+        pgrx::Spi::get_one::<i64>(&format!(
+            "SELECT xmin::text::bigint FROM {table} WHERE value = {value};"
+        ))
+        .unwrap()
+        .expect("unable to get xmin") as u32
+    }
+
+    /* The actions of the preview as offset:action list */
+    fn prune_actions(table: &str, horizon: u32) -> Option<String> {
+        pgrx::Spi::get_one::<String>(&format!(
+            "SELECT string_agg(offset_number || ':' || action, ',' ORDER BY offset_number)
+             FROM pg_debug_prune_preview('{table}', horizon => {horizon});"
+        ))
+        .unwrap()
+    }
+
+    #[pgrx::pg_test]
+    fn test_prune_preview_recently_dead_member() {
+        pgrx::Spi::run("CREATE TABLE prune_chain_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO prune_chain_test VALUES (1);").unwrap();
+        pgrx::Spi::run("UPDATE prune_chain_test SET value = 2;").unwrap();
+        pgrx::Spi::run("UPDATE prune_chain_test SET value = 3;").unwrap();
+
+        let xid = tuple_xmin("prune_chain_test", 3);
+
+        /*
+         * Turn the HOT chain (0,1) -> (0,2) -> (0,3) into a committed history where the
+         * first member is deleted after the horizon (recently dead) and the second one
+         * before the horizon (dead)
+         */
+        unsafe {
+            crate::heap::modify_page("prune_chain_test", 0, |page| {
+                for offnum in 1..=3 {
+                    let tup =
+                        crate::heap::PageGetItem(page, crate::heap::PageGetItemId(page, offnum));
+                    (*tup).t_infomask |= pg_sys::HEAP_XMIN_COMMITTED as u16;
+
+                    match offnum {
+                        1 => (*tup).t_infomask |= pg_sys::HEAP_XMAX_COMMITTED as u16,
+                        2 => {
+                            (*tup).t_choice.t_heap.t_xmax = xid - 1;
+                            (*tup).t_infomask |= pg_sys::HEAP_XMAX_COMMITTED as u16;
+                        }
+                        _ => (*tup).t_choice.t_heap.t_xmin = xid - 1,
+                    }
+                }
+            });
+        }
+
+        /* The dead member is still reachable from the recently dead one */
+        assert_eq!(prune_actions("prune_chain_test", xid), None);
+    }
+
+    #[pgrx::pg_test]
+    fn test_prune_preview_redirect() {
+        pgrx::Spi::run("CREATE TABLE prune_redirect_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO prune_redirect_test VALUES (1);").unwrap();
+        pgrx::Spi::run("UPDATE prune_redirect_test SET value = 2;").unwrap();
+        pgrx::Spi::run("DELETE FROM prune_redirect_test;").unwrap();
+
+        pgrx::Spi::run("CREATE TABLE prune_dangling_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO prune_dangling_test VALUES (1);").unwrap();
+        pgrx::Spi::run("UPDATE prune_dangling_test SET value = 2;").unwrap();
+
+        let xid = tuple_xmin("prune_dangling_test", 2);
+
+        /*
+         * Replace the root of the HOT chains by a redirect, as a previous pruning would
+         * do. The only member of the first chain is deleted, the redirect of the second
+         * chain points to a dead line pointer.
+         */
+        unsafe {
+            crate::heap::modify_page("prune_redirect_test", 0, |page| {
+                let root = crate::heap::PageGetItemId(page, 1);
+                (*root).set_lp_flags(pg_sys::LP_REDIRECT);
+                (*root).set_lp_off(2);
+                (*root).set_lp_len(0);
+
+                let tup = crate::heap::PageGetItem(page, crate::heap::PageGetItemId(page, 2));
+                (*tup).t_infomask |=
+                    (pg_sys::HEAP_XMIN_COMMITTED | pg_sys::HEAP_XMAX_COMMITTED) as u16;
+            });
+
+            crate::heap::modify_page("prune_dangling_test", 0, |page| {
+                let root = crate::heap::PageGetItemId(page, 1);
+                (*root).set_lp_flags(pg_sys::LP_REDIRECT);
+                (*root).set_lp_off(2);
+                (*root).set_lp_len(0);
+
+                let member = crate::heap::PageGetItemId(page, 2);
+                (*member).set_lp_flags(pg_sys::LP_DEAD);
+                (*member).set_lp_off(0);
+                (*member).set_lp_len(0);
+            });
+        }
+
+        assert_eq!(
+            prune_actions("prune_redirect_test", xid + 1),
+            Some("1:dead,2:unused".to_string())
+        );
+        assert_eq!(
+            prune_actions("prune_dangling_test", xid + 1),
+            Some("1:dead".to_string())
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use pgrx::{
+    pgrx_sql_entity_graph::metadata::{
+        ArgumentError, Returns, ReturnsError, SqlMapping, SqlTranslatable,
+    },
+    prelude::*,
+};
+
+use crate::output::output_datum;
+
+/*
+ * A value of the SQL type regclass. The relation is resolved by PostgreSQL when the
+ * value is parsed, so search_path, temporary tables and quoted identifiers are
+ * handled the same way as in any other query.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct RegClass(pub(crate) pg_sys::Oid);
+
+impl RegClass {
+    /*
+     * Get the name of the relation as it is shown by the regclass output function
+     */
+    pub(crate) unsafe fn name(&self) -> String {
+        output_datum(pg_sys::REGCLASSOID, pg_sys::Datum::from(self.0.as_u32()))
+    }
+}
+
+unsafe impl SqlTranslatable for RegClass {
+    fn argument_sql() -> Result<SqlMapping, ArgumentError> {
+        Ok(SqlMapping::literal("regclass"))
+    }
+
+    fn return_sql() -> Result<Returns, ReturnsError> {
+        Ok(Returns::One(SqlMapping::literal("regclass")))
+    }
+}
+
+impl FromDatum for RegClass {
+    unsafe fn from_polymorphic_datum(
+        datum: pg_sys::Datum,
+        is_null: bool,
+        typoid: pg_sys::Oid,
+    ) -> Option<Self> {
+        pg_sys::Oid::from_polymorphic_datum(datum, is_null, typoid).map(RegClass)
+    }
+}
+
+impl IntoDatum for RegClass {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        self.0.into_datum()
+    }
+
+    fn type_oid() -> pg_sys::Oid {
+        pg_sys::REGCLASSOID
+    }
+}
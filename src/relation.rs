@@ -0,0 +1,158 @@
+use pgrx::{
+    pg_sys::{AccessShareLock, MultiXactId},
+    prelude::*,
+};
+
+use crate::block::xid_age;
+use crate::get_relid_from_name;
+use crate::heap::open_heap_relation;
+use crate::prune::get_prune_horizon;
+
+#[pg_guard]
+extern "C" {
+    /* Not exported by pgrx, see access/visibilitymap.h and access/multixact.h */
+    fn visibilitymap_count(
+        rel: pg_sys::Relation,
+        all_visible: *mut pg_sys::BlockNumber,
+        all_frozen: *mut pg_sys::BlockNumber,
+    );
+    fn ReadNextMultiXactId() -> MultiXactId;
+}
+
+/* See InvalidMultiXactId in access/multixact.h */
+const INVALID_MULTIXACT_ID: MultiXactId = 0;
+
+/* The last maintenance times of a table, see pg_stat_all_tables */
+const MAINTENANCE_TIMES_QUERY: &str = "SELECT pg_stat_get_last_vacuum_time($1),
+    pg_stat_get_last_autovacuum_time($1),
+    pg_stat_get_last_analyze_time($1),
+    pg_stat_get_last_autoanalyze_time($1)";
+
+type MaintenanceTimes = (
+    Option<TimestampWithTimeZone>,
+    Option<TimestampWithTimeZone>,
+    Option<TimestampWithTimeZone>,
+    Option<TimestampWithTimeZone>,
+);
+
+/*
+ * Calculate the age of a multixact id like mxid_age() does
+ */
+unsafe fn mxid_age(mxid: MultiXactId) -> i32 {
+    if mxid == INVALID_MULTIXACT_ID {
+        return i32::MAX;
+    }
+
+    ReadNextMultiXactId().wrapping_sub(mxid) as i32
+}
+
+/*
+ * Get the times of the last manual and automatic vacuum and analyze runs
+ */
+fn get_maintenance_times(relid: pg_sys::Oid) -> MaintenanceTimes {
+    Spi::connect(|client| {
+        let row = client
+            .select(
+                MAINTENANCE_TIMES_QUERY,
+                None,
+                Some(vec![(PgBuiltInOids::OIDOID.oid(), relid.into_datum())]),
+            )?
+            .first();
+
+        Ok::<_, pgrx::spi::Error>((row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+    })
+    .unwrap_or_else(|err| error!("Unable to get the maintenance times: {err}"))
+}
+
+/*
+ * Summarize the MVCC related metadata of a table. This provides the context that is
+ * needed to interpret the result of a scan: the frozen horizons, the state of the
+ * visibility map, the last maintenance runs and the horizon used to remove dead
+ * tuple versions.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_relation_info(
+    table: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(relfrozenxid, i64),
+        name!(relfrozenxid_age, i32),
+        name!(relminmxid, i64),
+        name!(relminmxid_age, i32),
+        name!(pages, i64),
+        name!(all_visible_pages, i64),
+        name!(all_frozen_pages, i64),
+        name!(last_vacuum, Option<TimestampWithTimeZone>),
+        name!(last_autovacuum, Option<TimestampWithTimeZone>),
+        name!(last_analyze, Option<TimestampWithTimeZone>),
+        name!(last_autoanalyze, Option<TimestampWithTimeZone>),
+        name!(removal_horizon, i64),
+    ),
+> {
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+
+    let relfrozenxid = (*(*table_rel).rd_rel).relfrozenxid;
+    let relminmxid = (*(*table_rel).rd_rel).relminmxid;
+
+    let pages = pg_sys::RelationGetNumberOfBlocksInFork(table_rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+
+    let mut all_visible_pages: pg_sys::BlockNumber = 0;
+    let mut all_frozen_pages: pg_sys::BlockNumber = 0;
+    visibilitymap_count(table_rel, &mut all_visible_pages, &mut all_frozen_pages);
+
+    let removal_horizon = get_prune_horizon(table_rel);
+
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+
+    let (last_vacuum, last_autovacuum, last_analyze, last_autoanalyze) =
+        get_maintenance_times(relid);
+
+    TableIterator::once((
+        relfrozenxid.into(),
+        xid_age(relfrozenxid).unwrap_or(i32::MAX),
+        relminmxid.into(),
+        mxid_age(relminmxid),
+        pages.into(),
+        all_visible_pages.into(),
+        all_frozen_pages.into(),
+        last_vacuum,
+        last_autovacuum,
+        last_analyze,
+        last_autoanalyze,
+        removal_horizon.into(),
+    ))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_relation_info() {
+        pgrx::Spi::run("CREATE TABLE relation_info_test (id int);").unwrap();
+        pgrx::Spi::run("INSERT INTO relation_info_test VALUES (1);").unwrap();
+
+        let pages = pgrx::Spi::get_one::<i64>(
+            "SELECT pages FROM pg_debug_relation_info('relation_info_test');",
+        )
+        .unwrap();
+        assert_eq!(pages, Some(1));
+
+        let all_visible_pages = pgrx::Spi::get_one::<i64>(
+            "SELECT all_visible_pages FROM pg_debug_relation_info('relation_info_test');",
+        )
+        .unwrap();
+        assert_eq!(all_visible_pages, Some(0));
+
+        let never_vacuumed = pgrx::Spi::get_one::<bool>(
+            "SELECT last_vacuum IS NULL AND relfrozenxid_age >= 0 FROM pg_debug_relation_info('relation_info_test');",
+        )
+        .unwrap();
+        assert_eq!(never_vacuumed, Some(true));
+    }
+}
@@ -0,0 +1,702 @@
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::ops::{Range, RangeInclusive};
+
+use pgrx::{
+    pg_sys::{GetTransactionSnapshot, SnapshotData},
+    prelude::*,
+    PgMemoryContexts,
+};
+
+use crate::guc::INCLUDE_DROPPED_COLUMNS;
+use crate::heap::{BufferGetPage, PageGetLSN, PageGetMaxOffsetNumber};
+use crate::output::AttributeValue;
+use crate::page::read_line_pointer;
+use crate::regclass::RegClass;
+use crate::snapshot::{get_snapshot_from_str, xid_filter, SnapshotMode};
+use crate::xid8::Xid8;
+use crate::{
+    decode_attribute, decode_attribute_or_error, try_in_subtransaction, tuple_matches,
+    tuples_to_rows, RelationMetadata, ScannedTuple, XidConditions,
+};
+
+/* From PostgreSQL: #define InvalidBackendId (-1), the relation is not temporary */
+const INVALID_BACKEND_ID: pg_sys::BackendId = -1;
+
+#[cfg(any(feature = "pg12", feature = "pg13", feature = "pg14", feature = "pg15"))]
+type RelFileLocator = pg_sys::RelFileNode;
+
+#[cfg(feature = "pg16")]
+type RelFileLocator = pg_sys::RelFileLocator;
+
+/*
+ * Build the physical location of a relation. Tablespace 0 is the default tablespace
+ * of the current database, like in pg_class.reltablespace.
+ */
+unsafe fn rel_file_locator(
+    tablespace: pg_sys::Oid,
+    dbnode: pg_sys::Oid,
+    relnode: pg_sys::Oid,
+) -> RelFileLocator {
+    let tablespace = if tablespace == pg_sys::InvalidOid {
+        pg_sys::MyDatabaseTableSpace
+    } else {
+        tablespace
+    };
+
+    #[cfg(any(feature = "pg12", feature = "pg13", feature = "pg14", feature = "pg15"))]
+    {
+        pg_sys::RelFileNode {
+            spcNode: tablespace,
+            dbNode: dbnode,
+            relNode: relnode,
+        }
+    }
+    #[cfg(feature = "pg16")]
+    {
+        pg_sys::RelFileLocator {
+            spcOid: tablespace,
+            dbOid: dbnode,
+            relNumber: relnode,
+        }
+    }
+}
+
+/*
+ * Get the number of blocks of the main fork of the relation file
+ */
+unsafe fn relation_file_blocks(
+    locator: RelFileLocator,
+    relnode: pg_sys::Oid,
+) -> pg_sys::BlockNumber {
+    let smgr = pg_sys::smgropen(locator, INVALID_BACKEND_ID);
+
+    if !pg_sys::smgrexists(smgr, pg_sys::ForkNumber_MAIN_FORKNUM) {
+        error!("The file of relfilenode {relnode} does not exist");
+    }
+
+    pg_sys::smgrnblocks(smgr, pg_sys::ForkNumber_MAIN_FORKNUM)
+}
+
+/*
+ * Read the given block of the relation file and call func with the buffer and the
+ * share locked page. The buffer is identified by its physical location only, so
+ * no relation cache entry (and no catalog access) is needed.
+ */
+unsafe fn with_locked_file_page<T>(
+    locator: RelFileLocator,
+    blkno: pg_sys::BlockNumber,
+    func: impl FnOnce(pg_sys::Buffer, pg_sys::Page) -> T,
+) -> T {
+    check_for_interrupts!();
+
+    #[cfg(any(feature = "pg12", feature = "pg13", feature = "pg14"))]
+    let buffer = pg_sys::ReadBufferWithoutRelcache(
+        locator,
+        pg_sys::ForkNumber_MAIN_FORKNUM,
+        blkno,
+        pg_sys::ReadBufferMode_RBM_NORMAL,
+        std::ptr::null_mut(),
+    );
+    #[cfg(any(feature = "pg15", feature = "pg16"))]
+    let buffer = pg_sys::ReadBufferWithoutRelcache(
+        locator,
+        pg_sys::ForkNumber_MAIN_FORKNUM,
+        blkno,
+        pg_sys::ReadBufferMode_RBM_NORMAL,
+        std::ptr::null_mut(),
+        true,
+    );
+
+    pg_sys::LockBuffer(buffer, pg_sys::BUFFER_LOCK_SHARE as i32);
+
+    let result = func(buffer, BufferGetPage(buffer));
+
+    pg_sys::UnlockReleaseBuffer(buffer);
+
+    result
+}
+
+/*
+ * Split the column type list at the commas that are not part of a type modifier,
+ * e.g., "int4, numeric(10,2), text"
+ */
+fn split_type_list(tupdesc_spec: &str) -> Vec<String> {
+    let mut types = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+
+    for c in tupdesc_spec.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                types.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+
+        current.push(c);
+    }
+
+    types.push(current.trim().to_string());
+
+    if types.iter().any(String::is_empty) {
+        error!("Invalid column type list \"{tupdesc_spec}\"");
+    }
+
+    types
+}
+
+/*
+ * Build a tuple descriptor from the given column types. The columns are named col1,
+ * col2, ... since their names are not known without the catalog. Only the types are
+ * looked up in pg_type, which is required to decode the values.
+ */
+unsafe fn tupdesc_from_spec(tupdesc_spec: &str) -> pg_sys::TupleDesc {
+    let types = split_type_list(tupdesc_spec);
+    let tupdesc = pg_sys::CreateTemplateTupleDesc(types.len() as i32);
+
+    for (index, type_name) in types.iter().enumerate() {
+        let typid = Spi::get_one_with_args::<pg_sys::Oid>(
+            "SELECT $1::regtype::oid",
+            vec![(
+                PgBuiltInOids::TEXTOID.oid(),
+                type_name.as_str().into_datum(),
+            )],
+        )
+        .unwrap_or_else(|err| error!("Unable to resolve the column type {type_name}: {err}"))
+        .unwrap_or_else(|| error!("Unknown column type {type_name}"));
+
+        let attname =
+            CString::new(format!("col{}", index + 1)).expect("Unable to convert to string");
+
+        pg_sys::TupleDescInitEntry(
+            tupdesc,
+            (index + 1) as pg_sys::AttrNumber,
+            attname.as_ptr(),
+            typid,
+            -1,
+            0,
+        );
+    }
+
+    tupdesc
+}
+
+/* The columns of a relation, including the dropped ones */
+const ATTRIBUTES_QUERY: &str = "SELECT attname::text, atttypid, atttypmod, attlen, attbyval,
+    attalign::text, attisdropped
+    FROM pg_attribute
+    WHERE attrelid = $1 AND attnum > 0
+    ORDER BY attnum";
+
+/* A column of a relation as stored in pg_attribute */
+struct CatalogAttribute {
+    name: String,
+    typid: pg_sys::Oid,
+    typmod: i32,
+    len: i16,
+    byval: bool,
+    align: String,
+    dropped: bool,
+}
+
+/*
+ * Build the tuple descriptor of the relation from pg_attribute, without opening
+ * the relation. The type of a dropped column is gone, so only its length and
+ * alignment are set, which is enough to skip over its values.
+ */
+unsafe fn tupdesc_from_catalog(relid: pg_sys::Oid) -> pg_sys::TupleDesc {
+    let attributes = Spi::connect(|client| {
+        client
+            .select(
+                ATTRIBUTES_QUERY,
+                None,
+                Some(vec![(PgBuiltInOids::OIDOID.oid(), relid.into_datum())]),
+            )?
+            .map(|row| {
+                Ok(CatalogAttribute {
+                    name: row.get::<String>(1)?.unwrap_or_default(),
+                    typid: row.get::<pg_sys::Oid>(2)?.unwrap_or_default(),
+                    typmod: row.get::<i32>(3)?.unwrap_or(-1),
+                    len: row.get::<i16>(4)?.unwrap_or_default(),
+                    byval: row.get::<bool>(5)?.unwrap_or_default(),
+                    align: row.get::<String>(6)?.unwrap_or_default(),
+                    dropped: row.get::<bool>(7)?.unwrap_or_default(),
+                })
+            })
+            .collect::<Result<Vec<_>, pgrx::spi::Error>>()
+    })
+    .unwrap_or_else(|err| error!("Unable to get the columns of the relation: {err}"));
+
+    let natts = attributes.len();
+    let tupdesc = pg_sys::CreateTemplateTupleDesc(natts as i32);
+
+    for (index, attribute) in attributes.iter().enumerate() {
+        let attnum = (index + 1) as pg_sys::AttrNumber;
+        let attname = CString::new(attribute.name.as_str()).expect("Unable to convert to string");
+
+        if !attribute.dropped {
+            pg_sys::TupleDescInitEntry(
+                tupdesc,
+                attnum,
+                attname.as_ptr(),
+                attribute.typid,
+                attribute.typmod,
+                0,
+            );
+        } else {
+            let attr = &mut (*tupdesc).attrs.as_mut_slice(natts)[index];
+            std::ptr::write_bytes(attr as *mut pg_sys::FormData_pg_attribute, 0, 1);
+
+            pg_sys::namestrcpy(&mut attr.attname, attname.as_ptr());
+            attr.attnum = attnum;
+            attr.attlen = attribute.len;
+            attr.attbyval = attribute.byval;
+            attr.attalign = attribute.align.as_bytes()[0] as std::os::raw::c_char;
+            attr.attstorage = b'p' as std::os::raw::c_char;
+            attr.atttypmod = -1;
+            attr.attcacheoff = -1;
+            attr.attisdropped = true;
+        }
+
+        (*tupdesc).attrs.as_mut_slice(natts)[index].attrelid = relid;
+    }
+
+    tupdesc
+}
+
+/*
+ * Decode the attributes of the tuple using the given tuple descriptor. If columns
+ * are given, only these attributes are decoded. Like decode_tuple_columns, the
+ * attributes are only decoded in a subtransaction each if decoding the whole tuple
+ * fails.
+ */
+unsafe fn decode_file_tuple(
+    htup: *mut pg_sys::HeapTupleData,
+    tupdesc: pg_sys::TupleDesc,
+    columns: Option<&[String]>,
+) -> Vec<AttributeValue> {
+    try_in_subtransaction(|| decode_file_attributes(htup, tupdesc, columns, decode_attribute))
+        .unwrap_or_else(|_| {
+            decode_file_attributes(htup, tupdesc, columns, decode_attribute_or_error)
+        })
+}
+
+/*
+ * Decode the attributes of the tuple with the given function, see decode_file_tuple
+ */
+unsafe fn decode_file_attributes(
+    htup: *mut pg_sys::HeapTupleData,
+    tupdesc: pg_sys::TupleDesc,
+    columns: Option<&[String]>,
+    decode: unsafe fn(
+        *mut pg_sys::HeapTupleData,
+        pg_sys::TupleDesc,
+        &pg_sys::FormData_pg_attribute,
+    ) -> (pg_sys::Oid, Option<String>),
+) -> Vec<AttributeValue> {
+    let attrs = (*tupdesc).attrs.as_slice((*tupdesc).natts as usize);
+
+    attrs
+        .iter()
+        .filter(|attr_form_data| !attr_form_data.attisdropped || INCLUDE_DROPPED_COLUMNS.get())
+        .map(|attr_form_data| {
+            let name = CStr::from_ptr(attr_form_data.attname.data.as_ptr())
+                .to_string_lossy()
+                .into_owned();
+
+            (attr_form_data, name)
+        })
+        .filter(|(_, name)| columns.map_or(true, |columns| columns.contains(name)))
+        .map(|(attr_form_data, name)| {
+            let (typid, value) = decode(htup, tupdesc, attr_form_data);
+
+            AttributeValue { name, typid, value }
+        })
+        .collect()
+}
+
+/* The location of a relation in pg_class */
+const RELATION_FILE_QUERY: &str = "SELECT c.reltablespace, c.relfilenode, c.relkind::text,
+    c.relpersistence::text, c.relisshared, a.amname::text
+    FROM pg_class c LEFT JOIN pg_am a ON c.relam = a.oid
+    WHERE c.oid = $1";
+
+/*
+ * A scan of the tuples of a relation file that are visible under the snapshot. The
+ * blocks are read one after another while the tuples are consumed. The visible
+ * tuples of a block are copied, so they are decoded without holding the page lock.
+ */
+pub(crate) struct RelationFileScan {
+    locator: RelFileLocator,
+    /* The oid of the relation, which is stored as tableoid in the tuples */
+    relid: pg_sys::Oid,
+    relnode: pg_sys::Oid,
+    tupdesc: pg_sys::TupleDesc,
+    snapshot_data: *mut SnapshotData,
+    /* The blocks that are not read yet */
+    blocks: Range<pg_sys::BlockNumber>,
+    /* The visible tuples of the current block that are not returned yet */
+    tuples: std::vec::IntoIter<*mut pg_sys::HeapTupleData>,
+    /* Skip the pages that were not modified since this LSN */
+    min_page_lsn: Option<pg_sys::XLogRecPtr>,
+    xid_filter: Option<HashSet<pg_sys::TransactionId>>,
+    xid_conditions: Option<XidConditions>,
+    columns: Option<Vec<String>>,
+    /* Memory context for decoding a tuple, see TupleStream */
+    tuple_context: pg_sys::MemoryContext,
+}
+
+impl RelationFileScan {
+    /*
+     * Start the scan of the given relation file. The tuples are decoded with the
+     * given tuple descriptor.
+     */
+    unsafe fn open(
+        locator: RelFileLocator,
+        relid: pg_sys::Oid,
+        relnode: pg_sys::Oid,
+        tupdesc: pg_sys::TupleDesc,
+        snapshot_data: *mut SnapshotData,
+    ) -> RelationFileScan {
+        RelationFileScan {
+            locator,
+            relid,
+            relnode,
+            tupdesc,
+            snapshot_data,
+            blocks: 0..relation_file_blocks(locator, relnode),
+            tuples: Vec::new().into_iter(),
+            min_page_lsn: None,
+            xid_filter: None,
+            xid_conditions: None,
+            columns: None,
+            tuple_context: pg_sys::AllocSetContextCreateExtended(
+                pg_sys::CurrentMemoryContext,
+                b"pg_debug_scan file tuple context\0".as_ptr() as *const std::os::raw::c_char,
+                pg_sys::ALLOCSET_DEFAULT_MINSIZE as usize,
+                pg_sys::ALLOCSET_DEFAULT_INITSIZE as usize,
+                pg_sys::ALLOCSET_DEFAULT_MAXSIZE as usize,
+            ),
+        }
+    }
+
+    /*
+     * Scan the current file of the given relation without locking the relation.
+     * The location and the columns of the relation are read from the catalog, so
+     * the relation is not opened and a concurrent rewrite or drop is not waited
+     * for. If the file is removed during the scan, reading its blocks fails.
+     */
+    pub(crate) unsafe fn open_unlocked(
+        rel: RegClass,
+        snapshot_data: *mut SnapshotData,
+    ) -> RelationFileScan {
+        let (tablespace, relnode, relkind, relpersistence, shared, amname) =
+            Spi::connect(|client| {
+                let row = client
+                    .select(
+                        RELATION_FILE_QUERY,
+                        None,
+                        Some(vec![(PgBuiltInOids::OIDOID.oid(), rel.0.into_datum())]),
+                    )?
+                    .first();
+
+                Ok::<_, pgrx::spi::Error>((
+                    row.get::<pg_sys::Oid>(1)?,
+                    row.get::<pg_sys::Oid>(2)?,
+                    row.get::<String>(3)?,
+                    row.get::<String>(4)?,
+                    row.get::<bool>(5)?,
+                    row.get::<String>(6)?,
+                ))
+            })
+            .unwrap_or_else(|err| error!("Unable to get the file of the relation: {err}"));
+
+        let (Some(tablespace), Some(relnode), Some(relkind), Some(relpersistence)) =
+            (tablespace, relnode, relkind, relpersistence)
+        else {
+            error!("Relation with oid {} does not exist", rel.0);
+        };
+
+        if !matches!(relkind.as_str(), "r" | "m" | "t") || amname.as_deref() != Some("heap") {
+            error!(
+                "{} is not a heap table, only tables and materialized views are supported",
+                rel.name()
+            );
+        }
+
+        if relpersistence == "t" {
+            error!(
+                "{} is a temporary table, which can not be scanned without a lock",
+                rel.name()
+            );
+        }
+
+        /* Mapped relations (e.g., pg_class) have no relfilenode in pg_class */
+        if relnode == pg_sys::InvalidOid {
+            error!(
+                "{} is a mapped relation, which can not be scanned without a lock",
+                rel.name()
+            );
+        }
+
+        /* Shared relations are not stored in the directory of a database */
+        let dbnode = if shared == Some(true) {
+            pg_sys::InvalidOid
+        } else {
+            pg_sys::MyDatabaseId
+        };
+
+        RelationFileScan::open(
+            rel_file_locator(tablespace, dbnode, relnode),
+            rel.0,
+            relnode,
+            tupdesc_from_catalog(rel.0),
+            snapshot_data,
+        )
+    }
+
+    /*
+     * Only read the blocks of the given range, both block numbers are inclusive
+     */
+    pub(crate) fn with_block_range(
+        mut self,
+        block_range: Option<RangeInclusive<pg_sys::BlockNumber>>,
+    ) -> RelationFileScan {
+        if let Some(block_range) = block_range {
+            self.blocks =
+                *block_range.start()..block_range.end().saturating_add(1).min(self.blocks.end);
+        }
+
+        self
+    }
+
+    /*
+     * Only return the tuples of pages modified since the given LSN
+     */
+    pub(crate) fn with_min_page_lsn(
+        mut self,
+        min_page_lsn: Option<pg_sys::XLogRecPtr>,
+    ) -> RelationFileScan {
+        self.min_page_lsn = min_page_lsn;
+        self
+    }
+
+    /*
+     * Only return tuples created or deleted by one of the given transactions
+     */
+    pub(crate) fn with_xids(mut self, xids: Option<Vec<i64>>) -> RelationFileScan {
+        self.xid_filter = xids.map(|xids| xid_filter(&xids));
+        self
+    }
+
+    /*
+     * Only return the tuples matching the given transaction conditions
+     */
+    pub(crate) fn with_xid_conditions(
+        mut self,
+        xid_conditions: Option<XidConditions>,
+    ) -> RelationFileScan {
+        self.xid_conditions = xid_conditions;
+        self
+    }
+
+    /*
+     * Only decode the given attributes of the tuples
+     */
+    pub(crate) unsafe fn with_columns(mut self, columns: Option<Vec<String>>) -> RelationFileScan {
+        if let Some(columns) = &columns {
+            let attrs = (*self.tupdesc)
+                .attrs
+                .as_slice((*self.tupdesc).natts as usize);
+
+            for column in columns {
+                let exists = attrs.iter().any(|attr_form_data| {
+                    !attr_form_data.attisdropped
+                        && CStr::from_ptr(attr_form_data.attname.data.as_ptr()).to_string_lossy()
+                            == column.as_str()
+                });
+
+                if !exists {
+                    error!("Column {column} does not exist");
+                }
+            }
+        }
+
+        self.columns = columns;
+        self
+    }
+
+    /*
+     * Get the metadata of the scanned relation, see relation_metadata
+     */
+    pub(crate) unsafe fn relation_metadata(&self) -> RelationMetadata {
+        (
+            self.relid,
+            RegClass(self.relid).name(),
+            self.relnode.as_u32().into(),
+        )
+    }
+
+    /*
+     * Read the visible tuples of the next block. False is returned if all blocks
+     * are read.
+     */
+    unsafe fn read_next_block(&mut self) -> bool {
+        let Some(blkno) = self.blocks.next() else {
+            return false;
+        };
+
+        let (relid, snapshot_data, min_page_lsn) =
+            (self.relid, self.snapshot_data, self.min_page_lsn);
+
+        let tuples = with_locked_file_page(self.locator, blkno, |buffer, page| {
+            if min_page_lsn.is_some_and(|min_page_lsn| PageGetLSN(page) < min_page_lsn) {
+                return Vec::new();
+            }
+
+            (1..=PageGetMaxOffsetNumber(page))
+                .filter_map(|offnum| {
+                    read_line_pointer(relid, buffer, page, blkno, offnum, snapshot_data).tuple
+                })
+                .filter_map(|(htup, visible)| {
+                    if visible {
+                        Some(htup)
+                    } else {
+                        pg_sys::heap_freetuple(htup);
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+        });
+
+        self.tuples = tuples.into_iter();
+        true
+    }
+}
+
+impl Iterator for RelationFileScan {
+    type Item = ScannedTuple;
+
+    fn next(&mut self) -> Option<ScannedTuple> {
+        unsafe {
+            loop {
+                let Some(htup) = self.tuples.next() else {
+                    if !self.read_next_block() {
+                        return None;
+                    }
+
+                    continue;
+                };
+
+                let tup = (*htup).t_data;
+
+                if !tuple_matches(tup, self.xid_filter.as_ref(), self.xid_conditions.as_ref()) {
+                    pg_sys::heap_freetuple(htup);
+                    continue;
+                }
+
+                let (tupdesc, columns) = (self.tupdesc, self.columns.as_deref());
+                let attributes = PgMemoryContexts::For(self.tuple_context)
+                    .switch_to(|_| decode_file_tuple(htup, tupdesc, columns));
+                pg_sys::MemoryContextReset(self.tuple_context);
+
+                let tuple = ScannedTuple::from_heap_tuple(htup, attributes);
+
+                pg_sys::heap_freetuple(htup);
+                return Some(tuple);
+            }
+        }
+    }
+}
+
+/*
+ * Scan the relation file with the given tablespace, database, and relfilenode oids
+ * without accessing the catalog entry of the relation. This allows to read the
+ * tuples of a table whose catalog entries are corrupted or lost. The tuples are
+ * decoded with the given column types (e.g., 'int4, text, timestamptz'), which have
+ * to match the columns of the table, including dropped ones. The visibility is
+ * checked with the snapshot like in pg_debug_scan.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_relfilenode(
+    tablespace: pg_sys::Oid,
+    dbnode: pg_sys::Oid,
+    relnode: pg_sys::Oid,
+    tupdesc_spec: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    snapshot_mode: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(block_number, i64),
+        name!(offset_number, i32),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(data, String),
+    ),
+> {
+    if !pg_sys::superuser() {
+        error!("Must be superuser to scan a relation file");
+    }
+
+    let snapshot_mode = snapshot_mode.map_or(SnapshotMode::Mvcc, SnapshotMode::from_name);
+
+    let snapshot_data: *mut SnapshotData = match (snapshot, snapshot_mode.special_snapshot()) {
+        (Some(_), Some(_)) => error!("A snapshot can only be specified in the mvcc snapshot mode"),
+        (None, Some(special_snapshot)) => special_snapshot,
+        (Some(snapshot_data), None) => get_snapshot_from_str(snapshot_data),
+        (None, None) => GetTransactionSnapshot(),
+    };
+
+    let tupdesc = tupdesc_from_spec(tupdesc_spec);
+    let locator = rel_file_locator(tablespace, dbnode, relnode);
+
+    TableIterator::new(tuples_to_rows(RelationFileScan::open(
+        locator,
+        relnode,
+        relnode,
+        tupdesc,
+        snapshot_data,
+    )))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_scan_relfilenode() {
+        pgrx::Spi::run("CREATE TABLE relfilenode_test (id int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO relfilenode_test VALUES (1, 'a'), (2, 'b');").unwrap();
+        pgrx::Spi::run("DELETE FROM relfilenode_test WHERE id = 2;").unwrap();
+
+        let data = pgrx::Spi::get_one::<Vec<String>>(
+            "SELECT array_agg(s.data ORDER BY s.ctid) \
+             FROM pg_class c, pg_database d, \
+             pg_debug_scan_relfilenode(c.reltablespace, d.oid, c.relfilenode, 'int4, text') s \
+             WHERE c.relname = 'relfilenode_test' AND d.datname = current_database();",
+        )
+        .unwrap();
+        assert_eq!(
+            data,
+            Some(vec!["{\"col1\":\"1\",\"col2\":\"a\"}".to_string()])
+        );
+
+        let all = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_class c, pg_database d, \
+             pg_debug_scan_relfilenode(c.reltablespace, d.oid, c.relfilenode, 'int4, text', snapshot_mode => 'any') s \
+             WHERE c.relname = 'relfilenode_test' AND d.datname = current_database();",
+        )
+        .unwrap();
+        assert_eq!(all, Some(2));
+    }
+}
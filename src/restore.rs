@@ -0,0 +1,139 @@
+use pgrx::{
+    pg_sys::{AccessShareLock, GetTransactionSnapshot},
+    prelude::*,
+    PgMemoryContexts,
+};
+
+use crate::heap::open_heap_relation;
+use crate::regclass::RegClass;
+use crate::scan_heap;
+use crate::snapshot::get_snapshot_from_str;
+
+/*
+ * Get the types of the columns of the relation, dropped columns are skipped
+ */
+unsafe fn column_types(rel: pg_sys::Relation) -> Vec<pg_sys::Oid> {
+    let tupdesc = (*rel).rd_att;
+    let attrs = (*tupdesc).attrs.as_slice((*tupdesc).natts as usize);
+
+    attrs
+        .iter()
+        .filter(|attr_form_data| !attr_form_data.attisdropped)
+        .map(|attr_form_data| attr_form_data.atttypid)
+        .collect()
+}
+
+/*
+ * Insert all tuples of the source table that are visible under the snapshot into
+ * the target table, which needs the same columns. The tuples are inserted with a
+ * regular INSERT, so indexes, constraints and triggers of the target table are
+ * applied. This allows to recover rows that were deleted or overwritten by a buggy
+ * transaction. Returns the number of copied rows.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_restore(source: RegClass, snapshot: Option<&str>, target: RegClass) -> i64 {
+    /* The rows are inserted into the target table, which is not possible on a standby */
+    pg_sys::PreventCommandDuringRecovery(
+        b"pg_debug_scan_restore()\0".as_ptr() as *const std::os::raw::c_char
+    );
+
+    if source == target {
+        error!("The target table has to be different from the source table");
+    }
+
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let source_rel = open_heap_relation(source.0);
+    let target_rel = open_heap_relation(target.0);
+
+    if column_types(source_rel) != column_types(target_rel) {
+        error!(
+            "The columns of {} do not match the columns of {}",
+            target.name(),
+            source.name()
+        );
+    }
+
+    /* The source tuples are passed as a value of the row type of the source table */
+    let rowtype = (*(*source_rel).rd_rel).reltype;
+    let query = format!("INSERT INTO {} SELECT ($1).*", target.name());
+
+    /* Released after each tuple, like the tuple context of TupleStream */
+    let tuple_context = pg_sys::AllocSetContextCreateExtended(
+        pg_sys::CurrentMemoryContext,
+        b"pg_debug_scan restore tuple context\0".as_ptr() as *const std::os::raw::c_char,
+        pg_sys::ALLOCSET_DEFAULT_MINSIZE as usize,
+        pg_sys::ALLOCSET_DEFAULT_INITSIZE as usize,
+        pg_sys::ALLOCSET_DEFAULT_MAXSIZE as usize,
+    );
+
+    let copied = Spi::connect(|mut client| {
+        let statement = client
+            .prepare(&query, Some(vec![PgOid::from(rowtype)]))
+            .unwrap_or_else(|err| error!("Unable to prepare the insert: {err}"))
+            .keep();
+
+        let mut copied = 0;
+
+        scan_heap(source_rel, snapshot_data, |htup, tupdesc| {
+            let datum = PgMemoryContexts::For(tuple_context)
+                .switch_to(|_| pg_sys::heap_copy_tuple_as_datum(htup, tupdesc));
+
+            client
+                .update(&statement, None, Some(vec![Some(datum)]))
+                .unwrap_or_else(|err| error!("Unable to insert a row: {err}"));
+
+            pg_sys::MemoryContextReset(tuple_context);
+            copied += 1;
+        });
+
+        copied
+    });
+
+    pg_sys::MemoryContextDelete(tuple_context);
+    pg_sys::table_close(target_rel, AccessShareLock as i32);
+    pg_sys::table_close(source_rel, AccessShareLock as i32);
+
+    copied
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_scan_restore() {
+        pgrx::Spi::run("CREATE TABLE restore_source (id int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO restore_source VALUES (1, 'a'), (2, NULL);").unwrap();
+        pgrx::Spi::run("CREATE TABLE restore_target (LIKE restore_source);").unwrap();
+
+        let copied = pgrx::Spi::get_one::<i64>(
+            "SELECT pg_debug_scan_restore('restore_source', NULL, 'restore_target');",
+        )
+        .unwrap();
+        assert_eq!(copied, Some(2));
+
+        let restored = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM restore_target JOIN restore_source USING (id) \
+             WHERE restore_target.value IS NOT DISTINCT FROM restore_source.value;",
+        )
+        .unwrap();
+        assert_eq!(restored, Some(2));
+    }
+
+    #[pgrx::pg_test(
+        error = "The columns of restore_target do not match the columns of restore_source"
+    )]
+    fn test_scan_restore_columns() {
+        pgrx::Spi::run("CREATE TABLE restore_source (id int, value text);").unwrap();
+        pgrx::Spi::run("CREATE TABLE restore_target (id int);").unwrap();
+        pgrx::Spi::run("SELECT pg_debug_scan_restore('restore_source', NULL, 'restore_target');")
+            .unwrap();
+    }
+}
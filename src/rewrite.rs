@@ -0,0 +1,131 @@
+use pgrx::{pg_sys::AccessShareLock, prelude::*};
+
+use crate::get_relid_from_name;
+use crate::heap::open_heap_relation;
+
+/*
+ * Files in the directory of the current database that do not belong to any
+ * relation. Only the first segment of the main fork is considered.
+ */
+const ORPHANED_FILES_QUERY: &str = "SELECT file::oid, path, (pg_stat_file(path)).size
+    FROM pg_database d,
+    LATERAL pg_ls_dir('base/' || d.oid) file,
+    LATERAL (SELECT 'base/' || d.oid || '/' || file AS path) p
+    WHERE d.datname = current_database()
+    AND file ~ '^[0-9]+$'
+    AND pg_filenode_relation(0, file::oid) IS NULL
+    ORDER BY file::oid";
+
+/*
+ * Report whether the table was rewritten (e.g., by TRUNCATE, VACUUM FULL, CLUSTER
+ * or ALTER TABLE). A new table uses its oid as relfilenode, each rewrite assigns a
+ * new relfilenode. PostgreSQL does not record the previous relfilenode, use
+ * pg_debug_orphaned_files to find candidates for it.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_rewrite_info(
+    table: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(relfilenode, i64),
+        name!(rewritten, bool),
+        name!(path, String),
+    ),
+> {
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+    let relfilenode = (*(*table_rel).rd_rel).relfilenode;
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+
+    let path = Spi::get_one_with_args::<String>(
+        "SELECT pg_relation_filepath($1)",
+        vec![(PgBuiltInOids::OIDOID.oid(), relid.into_datum())],
+    )
+    .unwrap_or_else(|err| error!("Unable to get the file path of table {table}: {err}"))
+    .unwrap_or_else(|| error!("Table {table} has no storage"));
+
+    TableIterator::once((relfilenode.as_u32().into(), relfilenode != relid, path))
+}
+
+/*
+ * List the files in the directory of the current database that do not belong to
+ * any relation. After a table is rewritten, the old file is kept until the end
+ * of the transaction and (truncated to zero bytes) until the next checkpoint. A
+ * file restored from a backup can be found the same way. Files of relations that
+ * are created by running transactions are reported as well.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+fn pg_debug_orphaned_files() -> TableIterator<
+    'static,
+    (
+        name!(relfilenode, i64),
+        name!(path, String),
+        name!(bytes, i64),
+    ),
+> {
+    let files: Vec<(i64, String, i64)> = Spi::connect(|client| {
+        client
+            .select(ORPHANED_FILES_QUERY, None, None)?
+            .map(|row| {
+                Ok((
+                    row.get::<pg_sys::Oid>(1)?,
+                    row.get::<String>(2)?,
+                    row.get::<i64>(3)?,
+                ))
+            })
+            .filter_map(|file| match file {
+                Ok((Some(relfilenode), Some(path), Some(bytes))) => {
+                    Some(Ok((relfilenode.as_u32().into(), path, bytes)))
+                }
+                Ok(_) => None,
+                Err(err) => Some(Err(err)),
+            })
+            .collect::<Result<Vec<_>, pgrx::spi::Error>>()
+    })
+    .unwrap_or_else(|err| error!("Unable to list the orphaned files: {err}"));
+
+    TableIterator::new(files)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_rewrite() {
+        pgrx::Spi::run("CREATE TABLE rewrite_test (id int);").unwrap();
+
+        let rewritten = pgrx::Spi::get_one::<bool>(
+            "SELECT rewritten FROM pg_debug_rewrite_info('rewrite_test');",
+        )
+        .unwrap();
+        assert_eq!(rewritten, Some(false));
+
+        let old_relfilenode = pgrx::Spi::get_one::<i64>(
+            "SELECT relfilenode FROM pg_debug_rewrite_info('rewrite_test');",
+        )
+        .unwrap()
+        .expect("unable to get relfilenode");
+
+        /* Changing the type of a column rewrites the table */
+        pgrx::Spi::run("ALTER TABLE rewrite_test ALTER COLUMN id TYPE bigint;").unwrap();
+
+        let rewritten = pgrx::Spi::get_one::<bool>(
+            "SELECT rewritten FROM pg_debug_rewrite_info('rewrite_test');",
+        )
+        .unwrap();
+        assert_eq!(rewritten, Some(true));
+
+        /* The old file is removed at the end of the transaction */
+        let orphaned = pgrx::Spi::get_one::<bool>(&format!(
+            "SELECT EXISTS (SELECT FROM pg_debug_orphaned_files() WHERE relfilenode = {old_relfilenode});"
+        ))
+        .unwrap();
+        assert_eq!(orphaned, Some(true));
+    }
+}
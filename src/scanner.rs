@@ -0,0 +1,407 @@
+/*
+ * The scan core of pg_debug_scan as Rust API. Other pgrx extensions can depend on
+ * this crate (with the embedded feature) and scan tables with arbitrary snapshots
+ * without going through SQL, e.g.:
+ *
+ * let scan = DebugScanner::new(relid).with_snapshot(Some("774:778:775,777")).scan();
+ * for tuple in scan { ... }
+ */
+use std::ops::RangeInclusive;
+
+use pgrx::{pg_sys::GetTransactionSnapshot, prelude::*, Range};
+
+use crate::catalog::get_stored_snapshot;
+use crate::guc;
+use crate::limiter;
+use crate::lock::RelationLock;
+use crate::regclass::RegClass;
+use crate::relfilenode::RelationFileScan;
+use crate::snapshot::{get_snapshot, parse_snapshot_data, SnapshotMode};
+use crate::{
+    block_range, relation_metadata, scan_table_sampled, HeapScanOptions, RelationMetadata,
+    TupleStream, XidConditions,
+};
+
+pub use crate::output::AttributeValue;
+pub use crate::ScannedTuple;
+
+/*
+ * Register the GUCs of pg_debug_scan (e.g., pg_debug_scan.output_format and
+ * pg_debug_scan.max_rows) and request the shared memory for
+ * pg_debug_scan.max_concurrent_scans. An extension that embeds this crate has to call
+ * this function in its _PG_init. Otherwise, the GUCs cannot be set and the scans
+ * silently use their default values.
+ */
+pub unsafe fn init() {
+    guc::init();
+    limiter::init();
+}
+
+/* The configuration of a debug scan, the scan is started with scan() */
+pub struct DebugScanner {
+    rel: RegClass,
+    snapshot: Option<String>,
+    stored: Option<String>,
+    snapshot_mode: SnapshotMode,
+    strict: bool,
+    xids: Option<Vec<i64>>,
+    min_page_lsn: Option<pg_sys::XLogRecPtr>,
+    block_range: Option<RangeInclusive<pg_sys::BlockNumber>>,
+    filter: Option<String>,
+    columns: Option<Vec<String>>,
+    scan_options: HeapScanOptions,
+    sample_percent: Option<f64>,
+    bypass_rls: bool,
+    xid_conditions: Option<XidConditions>,
+    check: bool,
+    lock: Option<RelationLock>,
+}
+
+impl DebugScanner {
+    /*
+     * Scan the relation with the given oid using the current transaction snapshot
+     */
+    pub fn new(relid: pg_sys::Oid) -> DebugScanner {
+        DebugScanner {
+            rel: RegClass(relid),
+            snapshot: None,
+            stored: None,
+            snapshot_mode: SnapshotMode::Mvcc,
+            strict: true,
+            xids: None,
+            min_page_lsn: None,
+            block_range: None,
+            filter: None,
+            columns: None,
+            scan_options: HeapScanOptions::default(),
+            sample_percent: None,
+            bypass_rls: false,
+            xid_conditions: None,
+            check: false,
+            lock: None,
+        }
+    }
+
+    /*
+     * Use the given snapshot definition (e.g., 774:778:775,777), see
+     * parse_snapshot_data for the accepted formats
+     */
+    pub fn with_snapshot(mut self, snapshot: Option<&str>) -> DebugScanner {
+        self.snapshot = snapshot.map(str::to_string);
+        self
+    }
+
+    /*
+     * Use the snapshot stored with the given name in pg_debug_snapshots. The table
+     * only exists if the pg_debug_scan extension is installed in the database.
+     */
+    pub fn with_stored_snapshot(mut self, stored: Option<&str>) -> DebugScanner {
+        self.stored = stored.map(str::to_string);
+        self
+    }
+
+    /*
+     * Use one of the special snapshots (mvcc, any, self or dirty) instead of an
+     * MVCC snapshot
+     */
+    pub fn with_snapshot_mode(mut self, snapshot_mode: Option<&str>) -> DebugScanner {
+        self.snapshot_mode = snapshot_mode.map_or(SnapshotMode::Mvcc, SnapshotMode::from_name);
+        self
+    }
+
+    /*
+     * Raise an error for invalid xip values instead of ignoring them
+     */
+    pub fn with_strict(mut self, strict: bool) -> DebugScanner {
+        self.strict = strict;
+        self
+    }
+
+    /*
+     * Only return tuples created or deleted by one of the given transactions
+     */
+    pub fn with_xids(mut self, xids: Option<Vec<i64>>) -> DebugScanner {
+        self.xids = xids;
+        self
+    }
+
+    /*
+     * Only return the tuples of pages modified since the given LSN
+     */
+    pub fn with_min_page_lsn(mut self, min_page_lsn: Option<pg_sys::XLogRecPtr>) -> DebugScanner {
+        self.min_page_lsn = min_page_lsn;
+        self
+    }
+
+    /*
+     * Only read the blocks of the given range, both block numbers are inclusive
+     */
+    pub fn with_block_range(
+        mut self,
+        start_block: Option<i64>,
+        end_block: Option<i64>,
+    ) -> DebugScanner {
+        self.block_range = block_range(start_block, end_block);
+        self
+    }
+
+    /*
+     * Only return the tuples matching the filter (e.g., id = 42)
+     */
+    pub fn with_filter(mut self, filter: Option<&str>) -> DebugScanner {
+        self.filter = filter.map(str::to_string);
+        self
+    }
+
+    /*
+     * Only decode the given columns of the tuples
+     */
+    pub fn with_columns(mut self, columns: Option<Vec<String>>) -> DebugScanner {
+        self.columns = columns;
+        self
+    }
+
+    /*
+     * Set the direction (forward or backward) and the flags of the heap scan
+     */
+    pub fn with_scan_options(
+        mut self,
+        direction: &str,
+        syncscan: bool,
+        pagemode: bool,
+    ) -> DebugScanner {
+        self.scan_options = HeapScanOptions::new(direction, syncscan, pagemode);
+        self
+    }
+
+    /*
+     * Only read the given percentage of the blocks
+     */
+    pub fn with_sample_percent(mut self, sample_percent: Option<f64>) -> DebugScanner {
+        self.sample_percent = sample_percent;
+        self
+    }
+
+    /*
+     * Allow the owner of a table with row-level security to scan all of its tuples,
+     * the policies cannot be applied by the scan
+     */
+    pub fn with_bypass_rls(mut self, bypass_rls: bool) -> DebugScanner {
+        self.bypass_rls = bypass_rls;
+        self
+    }
+
+    /*
+     * Only return the tuples created or deleted by the given transactions, or
+     * created by a transaction of the given range
+     */
+    pub fn with_xid_conditions(
+        mut self,
+        created_by: Option<i64>,
+        deleted_by: Option<i64>,
+        xmin_between: Option<Range<i64>>,
+    ) -> DebugScanner {
+        self.xid_conditions = XidConditions::new(created_by, deleted_by, xmin_between);
+        self
+    }
+
+    /*
+     * Check the header of each tuple (e.g., t_hoff and the transaction ids) before it
+     * is decoded. The failed checks are returned in ScannedTuple::violations.
+     */
+    pub fn with_check(mut self, check: bool) -> DebugScanner {
+        self.check = check;
+        self
+    }
+
+    /*
+     * Lock the relation with the given lock mode (access share or none), see
+     * RelationLock. Without a lock, the current file of the relation is read, see
+     * RelationFileScan::open_unlocked.
+     */
+    pub unsafe fn with_lock(
+        mut self,
+        lock_mode: &str,
+        nowait: bool,
+        lock_timeout: Option<i32>,
+    ) -> DebugScanner {
+        self.lock = Some(RelationLock::new(lock_mode, nowait, lock_timeout));
+        self
+    }
+
+    /*
+     * Build the snapshot of the scan
+     */
+    unsafe fn snapshot_data(&self) -> *mut pg_sys::SnapshotData {
+        let snapshot = match (self.snapshot.as_deref(), self.stored.as_deref()) {
+            (Some(_), Some(_)) => error!("Only one of snapshot and stored can be specified"),
+            (None, Some(stored)) => Some(get_stored_snapshot(stored)),
+            (snapshot, None) => snapshot.map(str::to_string),
+        };
+
+        match (snapshot.as_deref(), self.snapshot_mode.special_snapshot()) {
+            (Some(_), Some(_)) => {
+                error!("A snapshot can only be specified in the mvcc snapshot mode")
+            }
+            (None, Some(special_snapshot)) => special_snapshot,
+            (Some(snapshot_data), None) => {
+                get_snapshot(parse_snapshot_data(snapshot_data, self.strict))
+            }
+            (None, None) => GetTransactionSnapshot(),
+        }
+    }
+
+    /*
+     * Lock the relation and start the scan
+     */
+    pub unsafe fn scan(mut self) -> DebugScan {
+        let snapshot_data = self.snapshot_data();
+
+        let relation_lock = self
+            .lock
+            .take()
+            .unwrap_or_else(|| RelationLock::new("access share", false, None));
+
+        if relation_lock.unlocked() {
+            return DebugScan {
+                source: ScanSource::File(self.scan_file(snapshot_data)),
+            };
+        }
+
+        relation_lock.lock_relation(self.rel.0);
+
+        let scan_options = self
+            .scan_options
+            .with_sample_percent(self.sample_percent)
+            .with_bypass_rls(self.bypass_rls);
+
+        let tuples = TupleStream::open_relation(
+            self.rel,
+            snapshot_data,
+            self.xids,
+            self.min_page_lsn,
+            self.block_range,
+            self.filter.as_deref(),
+            scan_options,
+        )
+        .with_columns(self.columns)
+        .with_xid_conditions(self.xid_conditions)
+        .with_check(self.check);
+
+        DebugScan {
+            source: ScanSource::Relation(tuples),
+        }
+    }
+
+    /*
+     * Start the scan of the relation file without a lock. The options that need the
+     * relation (e.g., the filter) are not supported.
+     */
+    unsafe fn scan_file(self, snapshot_data: *mut pg_sys::SnapshotData) -> RelationFileScan {
+        let unsupported = [
+            ("filter", self.filter.is_some()),
+            ("sample_percent", self.sample_percent.is_some()),
+            ("check", self.check),
+            (
+                "direction",
+                self.scan_options.direction != pg_sys::ScanDirection_ForwardScanDirection,
+            ),
+        ];
+
+        if let Some((option, _)) = unsupported.iter().find(|(_, set)| *set) {
+            error!("{option} is not supported with lock_mode none");
+        }
+
+        RelationFileScan::open_unlocked(self.rel, snapshot_data)
+            .with_xids(self.xids)
+            .with_min_page_lsn(self.min_page_lsn)
+            .with_block_range(self.block_range)
+            .with_columns(self.columns)
+            .with_xid_conditions(self.xid_conditions)
+    }
+}
+
+/*
+ * A running debug scan, which returns the decoded tuples. The scan and the relation
+ * are closed when all tuples are read or the scan is dropped.
+ */
+pub struct DebugScan {
+    source: ScanSource,
+}
+
+/* The scan of the relation, or of its file if the relation is not locked */
+enum ScanSource {
+    Relation(TupleStream),
+    File(RelationFileScan),
+}
+
+impl DebugScan {
+    /*
+     * Get the metadata of the scanned relation, see relation_metadata
+     */
+    pub(crate) unsafe fn relation_metadata(&self) -> RelationMetadata {
+        match &self.source {
+            ScanSource::Relation(tuples) => relation_metadata(tuples.table_rel),
+            ScanSource::File(tuples) => tuples.relation_metadata(),
+        }
+    }
+
+    /*
+     * Read all tuples and return a random sample of them, see scan_table_sampled
+     */
+    pub(crate) unsafe fn sample(self, sample_rows: usize) -> Vec<ScannedTuple> {
+        match self.source {
+            ScanSource::Relation(tuples) => scan_table_sampled(tuples, sample_rows),
+            ScanSource::File(_) => error!("sample_rows is not supported with lock_mode none"),
+        }
+    }
+}
+
+impl Iterator for DebugScan {
+    type Item = ScannedTuple;
+
+    fn next(&mut self) -> Option<ScannedTuple> {
+        match &mut self.source {
+            ScanSource::Relation(tuples) => tuples.next(),
+            ScanSource::File(tuples) => tuples.next(),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    use crate::scanner::DebugScanner;
+
+    #[pgrx::pg_test]
+    fn test_debug_scanner() {
+        pgrx::Spi::run("CREATE TABLE debug_scanner_test (id int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO debug_scanner_test VALUES (1, 'a'), (2, 'b');").unwrap();
+        pgrx::Spi::run("DELETE FROM debug_scanner_test WHERE id = 2;").unwrap();
+
+        let relid =
+            pgrx::Spi::get_one::<pg_sys::Oid>("SELECT 'debug_scanner_test'::regclass::oid;")
+                .unwrap()
+                .unwrap();
+
+        let values = unsafe {
+            DebugScanner::new(relid)
+                .with_columns(Some(vec!["value".to_string()]))
+                .scan()
+                .map(|tuple| tuple.attributes[0].value.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values, vec![Some("a".to_string())]);
+
+        let all = unsafe {
+            DebugScanner::new(relid)
+                .with_snapshot_mode(Some("any"))
+                .scan()
+                .count()
+        };
+        assert_eq!(all, 2);
+    }
+}
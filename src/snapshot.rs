@@ -0,0 +1,1170 @@
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::ptr;
+
+use pgrx::pg_sys::panic::ErrorReport;
+use pgrx::pgrx_sql_entity_graph::metadata::{
+    ArgumentError, Returns, ReturnsError, SqlMapping, SqlTranslatable,
+};
+use pgrx::{
+    pg_sys::{palloc, uint32, CommandId, GetLatestSnapshot, GetTransactionSnapshot, SnapshotData},
+    prelude::*,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::guc::STRICT_FROZEN_HORIZON;
+use crate::xid8::Xid8;
+
+/* The format of a snapshot definition, which is shown as hint for invalid input */
+const SNAPSHOT_FORMAT_HINT: &str =
+    "The snapshot has to be in the format xmin:xmax:xip1,xip2 (e.g., 774:778:775,777).";
+
+/*
+ * Raise an error about an invalid input value with the given SQLSTATE and hint
+ */
+pub(crate) fn input_error(code: PgSqlErrorCode, message: String, hint: Option<&str>) -> ! {
+    let mut report = ErrorReport::new(code, message, "pg_debug_scan");
+
+    if let Some(hint) = hint {
+        report = report.set_hint(hint);
+    }
+
+    report.report(PgLogLevel::ERROR);
+    unreachable!("an error report does not return")
+}
+
+/*
+ * Parse a transaction id of the snapshot definition
+ */
+fn parse_xid(value: &str, name: &str, snapshot_str: &str) -> u32 {
+    let xid = value.trim().parse().unwrap_or_else(|_| {
+        input_error(
+            PgSqlErrorCode::ERRCODE_INVALID_TEXT_REPRESENTATION,
+            format!("Invalid {name} value \"{value}\" in snapshot {snapshot_str}"),
+            Some(SNAPSHOT_FORMAT_HINT),
+        )
+    });
+
+    xid_from_u64(xid, name)
+}
+
+pub(crate) struct SnapshotArguments {
+    xmin: uint32,
+    xmax: uint32,
+    xip: Vec<u32>,
+    subxip: Vec<u32>,
+    suboverflowed: bool,
+    curcid: Option<CommandId>,
+}
+
+/*
+ * Snapshot specification in JSON format. For example
+ * {"xmin": 774, "xmax": 778, "xip": [775, 777], "subxip": [776], "curcid": 2}
+ * If the subtransactions of a running transaction did not fit into the snapshot,
+ * "suboverflowed": true can be specified instead of subxip.
+ */
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SnapshotSpecJSON {
+    xmin: u64,
+    xmax: u64,
+    #[serde(default)]
+    xip: Vec<u64>,
+    #[serde(default)]
+    subxip: Vec<u64>,
+    #[serde(default)]
+    suboverflowed: bool,
+    curcid: Option<u32>,
+}
+
+impl SnapshotArguments {
+    /*
+     * Create new snapshot arguments and check that the values form a valid snapshot.
+     * In non-strict mode, invalid xip values only raise a warning and are ignored.
+     */
+    pub(crate) fn new(xmin: u32, xmax: u32, xip: Vec<u32>, strict: bool) -> SnapshotArguments {
+        let xip = xip
+            .into_iter()
+            .filter(|xip_value| {
+                /* From PostgreSQL code:
+                 * Note: all ids in xip[] satisfy xmin <= xip[i] < xmax
+                 */
+                if *xip_value >= xmin && *xip_value < xmax {
+                    return true;
+                }
+
+                if strict {
+                    input_error(
+                        PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                        format!("Xip value {xip_value} is outside of {xmin}..{xmax}"),
+                        Some("Use strict => false to ignore invalid xip values."),
+                    )
+                }
+
+                warning!("Ignoring xip value {xip_value}, it is outside of {xmin}..{xmax}");
+                false
+            })
+            .collect();
+
+        SnapshotArguments {
+            xmin,
+            xmax,
+            xip,
+            subxip: Vec::new(),
+            suboverflowed: false,
+            curcid: None,
+        }
+    }
+
+    /*
+     * Set the in-progress subtransaction ids of the snapshot
+     */
+    pub(crate) fn with_subxip(mut self, subxip: Vec<u32>) -> SnapshotArguments {
+        for subxip_value in &subxip {
+            /* From PostgreSQL code:
+             * Note: all ids in subxip[] are >= xmin, but we don't bother filtering
+             * out any that are >= xmax
+             */
+            if *subxip_value < self.xmin {
+                input_error(
+                    PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                    format!(
+                        "Subxip value {subxip_value} is smaller than xmin {}",
+                        self.xmin
+                    ),
+                    None,
+                )
+            }
+        }
+
+        self.subxip = subxip;
+        self
+    }
+
+    /*
+     * Mark the subtransaction ids of the snapshot as overflowed. In this case, the
+     * top-level transaction of a subtransaction is looked up in pg_subtrans and
+     * checked against xip instead.
+     */
+    pub(crate) fn with_suboverflowed(mut self, suboverflowed: bool) -> SnapshotArguments {
+        self.suboverflowed = suboverflowed;
+        self
+    }
+
+    /*
+     * Set the command id that is used to check the visibility of tuples
+     * modified by the current transaction
+     */
+    pub(crate) fn with_curcid(mut self, curcid: Option<CommandId>) -> SnapshotArguments {
+        self.curcid = curcid;
+        self
+    }
+
+    /*
+     * Take the members of the given PostgreSQL snapshot. The snapshot was built by
+     * PostgreSQL, so its members are not validated again.
+     */
+    pub(crate) unsafe fn from_snapshot(snapshot_data: *const SnapshotData) -> SnapshotArguments {
+        let xids = |xids: *const u32, count: usize| -> Vec<u32> {
+            if count == 0 {
+                return Vec::new();
+            }
+
+            std::slice::from_raw_parts(xids, count).to_vec()
+        };
+
+        SnapshotArguments {
+            xmin: (*snapshot_data).xmin,
+            xmax: (*snapshot_data).xmax,
+            xip: xids((*snapshot_data).xip, (*snapshot_data).xcnt as usize),
+            subxip: xids((*snapshot_data).subxip, (*snapshot_data).subxcnt as usize),
+            suboverflowed: (*snapshot_data).suboverflowed,
+            curcid: None,
+        }
+    }
+
+    /*
+     * Get the in-progress transaction ids in the layout of a snapshot that is taken
+     * during recovery. On a hot standby, PostgreSQL does not know which of the
+     * running xids are top-level transactions, so all of them are stored in subxip
+     * and xip is not used by the visibility checks.
+     */
+    pub(crate) fn recovery_subxip(&self) -> Vec<u32> {
+        self.xip.iter().chain(&self.subxip).copied().collect()
+    }
+
+    /*
+     * Format the snapshot in the format accepted by parse_snapshot_data. The
+     * subtransaction ids are only added if the snapshot contains any, or if they
+     * overflowed.
+     */
+    pub(crate) fn to_text(&self) -> String {
+        let join = |xids: &[u32]| -> String {
+            let mut xids = xids.to_vec();
+            xids.sort_unstable();
+
+            xids.iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        let mut text = format!("{}:{}:{}", self.xmin, self.xmax, join(&self.xip));
+
+        if self.suboverflowed {
+            text.push_str(":overflowed");
+        } else if !self.subxip.is_empty() {
+            text.push(':');
+            text.push_str(&join(&self.subxip));
+        }
+
+        text
+    }
+}
+
+/*
+ * Parse the provided snapshot data by the user. For example 4:45:23,35
+ * means xmin:xmax:xip1,xip2 . See SnapshotArguments::new for the strict mode.
+ * Optionally, the in-progress subtransactions can be provided as fourth part
+ * (e.g., 4:45:23,35:24,25), or overflowed if they did not fit into the snapshot.
+ * The name of a snapshot exported by pg_export_snapshot() is imported instead,
+ * see import_snapshot.
+ *
+ * See the PostgreSQL documentation - pg_current_snapshot() for more information
+ * about the meaning of these values.
+ */
+pub(crate) fn parse_snapshot_data(snapshot_str: &str, strict: bool) -> SnapshotArguments {
+    if is_exported_snapshot_name(snapshot_str) {
+        return import_snapshot(snapshot_str, strict);
+    }
+
+    let parts: Vec<&str> = snapshot_str.split(':').collect();
+
+    if parts.len() != 3 && parts.len() != 4 {
+        input_error(
+            PgSqlErrorCode::ERRCODE_INVALID_TEXT_REPRESENTATION,
+            format!("Unable to parse snapshot data {snapshot_str}"),
+            Some(SNAPSHOT_FORMAT_HINT),
+        );
+    }
+
+    let xmin = parse_xid(parts[0], "xmin", snapshot_str);
+    let xmax = parse_xid(parts[1], "xmax", snapshot_str);
+
+    /* Parse xip members (2,3,54) */
+    let mut xip_values = Vec::new();
+
+    if !parts[2].is_empty() {
+        for part in parts[2].split(',') {
+            xip_values.push(parse_xid(part, "xip", snapshot_str));
+        }
+    }
+
+    let snapshot_argument = SnapshotArguments::new(xmin, xmax, xip_values, strict);
+
+    /* Parse the subxip members (24,25) or the overflowed marker */
+    match parts.get(3) {
+        Some(&"overflowed") => snapshot_argument.with_suboverflowed(true),
+        Some(part) if !part.is_empty() => {
+            let subxip_values = part
+                .split(',')
+                .map(|part| parse_xid(part, "subxip", snapshot_str))
+                .collect();
+
+            snapshot_argument.with_subxip(subxip_values)
+        }
+        _ => snapshot_argument,
+    }
+}
+
+/*
+ * Check if the given value is the name of a snapshot exported by pg_export_snapshot()
+ * (e.g., 00000003-0000001B-1). Like in ImportSnapshot, only upper case hex digits and
+ * dashes are allowed, so the name cannot refer to a file outside of pg_snapshots.
+ */
+fn is_exported_snapshot_name(value: &str) -> bool {
+    value.contains('-')
+        && value
+            .chars()
+            .all(|c| c == '-' || c.is_ascii_digit() || ('A'..='F').contains(&c))
+}
+
+/* The position of ProcArrayLock in MainLWLockArray, see storage/lwlocknames.h */
+const PROC_ARRAY_LOCK: usize = 4;
+
+#[pg_guard]
+extern "C" {
+    /* Not exported by pgrx, see storage/procarray.h */
+    fn ProcArrayInstallImportedXmin(
+        xmin: pg_sys::TransactionId,
+        sourcevxid: *mut pg_sys::VirtualTransactionId,
+    ) -> bool;
+}
+
+/*
+ * Check if the transaction that exported a snapshot is still running. The
+ * transaction is looked up like in ProcArrayInstallImportedXmin while holding
+ * ProcArrayLock, so the process array is not changed concurrently.
+ */
+unsafe fn exporting_transaction_running(vxid: &pg_sys::VirtualTransactionId) -> bool {
+    let proc_array_lock = ptr::addr_of_mut!((*pg_sys::MainLWLockArray.add(PROC_ARRAY_LOCK)).lock);
+    pg_sys::LWLockAcquire(proc_array_lock, pg_sys::LWLockMode_LW_SHARED);
+
+    let procs = std::slice::from_raw_parts(
+        (*pg_sys::ProcGlobal).allProcs,
+        (*pg_sys::ProcGlobal).allProcCount as usize,
+    );
+
+    let running = procs.iter().any(|proc| {
+        proc.pid != 0 && proc.backendId == vxid.backendId && proc.lxid == vxid.localTransactionId
+    });
+
+    pg_sys::LWLockRelease(proc_array_lock);
+
+    running
+}
+
+/*
+ * Protect the tuples that are visible under an imported snapshot from removal, like
+ * ImportSnapshot does. The exported snapshot contains the virtual transaction id
+ * (backend id/local transaction id) of the exporting transaction, whose xmin covers
+ * the snapshot as long as it is running. ProcArrayInstallImportedXmin checks that the
+ * transaction is still running and installs the xmin of the snapshot as the xmin of
+ * this backend. If the xmin of this backend is already older, it covers the snapshot
+ * and is kept, so only the exporting transaction is checked. The scan registers the
+ * snapshot afterwards, so the xmin does not advance while the table is scanned.
+ */
+unsafe fn protect_imported_xmin(xmin: pg_sys::TransactionId, vxid: &str) -> bool {
+    let Some((backend_id, lxid)) = vxid
+        .split_once('/')
+        .and_then(|(backend_id, lxid)| Some((backend_id.parse().ok()?, lxid.parse().ok()?)))
+    else {
+        return false;
+    };
+
+    let mut source_vxid = pg_sys::VirtualTransactionId {
+        backendId: backend_id,
+        localTransactionId: lxid,
+    };
+
+    let own_xmin = (*pg_sys::MyProc).xmin;
+
+    if own_xmin >= pg_sys::FirstNormalTransactionId
+        && pg_sys::TransactionIdPrecedesOrEquals(own_xmin, xmin)
+    {
+        return exporting_transaction_running(&source_vxid);
+    }
+
+    ProcArrayInstallImportedXmin(xmin, &mut source_vxid)
+}
+
+/*
+ * Import a snapshot exported by another session with pg_export_snapshot(). The
+ * snapshot is read from the file in pg_snapshots, which exists as long as the
+ * exporting transaction is running. Like in ImportSnapshot, snapshots of other
+ * databases and of transactions that are no longer running cannot be imported.
+ */
+fn import_snapshot(name: &str, strict: bool) -> SnapshotArguments {
+    let path = std::path::Path::new("pg_snapshots").join(name);
+
+    let contents = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        input_error(
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("Snapshot \"{name}\" does not exist"),
+            Some(
+                "Exported snapshots are only available while the exporting transaction is running.",
+            ),
+        )
+    });
+
+    let mut values: Vec<(&str, &str)> = Vec::new();
+
+    for line in contents.lines() {
+        let (key, value) = line.split_once(':').unwrap_or_else(|| {
+            input_error(
+                PgSqlErrorCode::ERRCODE_INVALID_TEXT_REPRESENTATION,
+                format!("Invalid line \"{line}\" in exported snapshot {name}"),
+                None,
+            )
+        });
+
+        values.push((key, value));
+    }
+
+    let value = |key: &str| -> &str {
+        values
+            .iter()
+            .find(|(entry_key, _)| *entry_key == key)
+            .map(|(_, value)| *value)
+            .unwrap_or_else(|| {
+                input_error(
+                    PgSqlErrorCode::ERRCODE_INVALID_TEXT_REPRESENTATION,
+                    format!("Missing {key} in exported snapshot {name}"),
+                    None,
+                )
+            })
+    };
+
+    let xids = |key: &str| -> Vec<u32> {
+        values
+            .iter()
+            .filter(|(entry_key, _)| *entry_key == key)
+            .map(|(_, value)| parse_xid(value, key, name))
+            .collect()
+    };
+
+    let dbid: pg_sys::Oid = value("dbid").parse::<u32>().map_or_else(
+        |_| {
+            input_error(
+                PgSqlErrorCode::ERRCODE_INVALID_TEXT_REPRESENTATION,
+                format!("Invalid dbid in exported snapshot {name}"),
+                None,
+            )
+        },
+        pg_sys::Oid::from,
+    );
+
+    if dbid != unsafe { pg_sys::MyDatabaseId } {
+        input_error(
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            format!("Snapshot \"{name}\" was exported from a different database"),
+            None,
+        )
+    }
+
+    let xmin = parse_xid(value("xmin"), "xmin", name);
+
+    if xmin < pg_sys::FirstNormalTransactionId {
+        input_error(
+            PgSqlErrorCode::ERRCODE_INVALID_TEXT_REPRESENTATION,
+            format!("Invalid xmin in exported snapshot {name}"),
+            None,
+        )
+    }
+
+    /*
+     * The file is removed when the exporting transaction ends, but it might have
+     * ended since the file was read. Afterwards, VACUUM can remove the tuples that
+     * are visible under the snapshot.
+     */
+    if !unsafe { protect_imported_xmin(xmin, value("vxid")) } {
+        input_error(
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            format!("The transaction that exported snapshot \"{name}\" is not running anymore"),
+            None,
+        )
+    }
+
+    SnapshotArguments::new(
+        xmin,
+        parse_xid(value("xmax"), "xmax", name),
+        xids("xip"),
+        strict,
+    )
+    .with_subxip(xids("sxp"))
+    .with_suboverflowed(value("sof") == "1")
+}
+
+/*
+ * Parse a snapshot specification in JSON format
+ */
+pub(crate) fn parse_snapshot_json(snapshot_json: Value, strict: bool) -> SnapshotArguments {
+    let spec: SnapshotSpecJSON = serde_json::from_value(snapshot_json).unwrap_or_else(|err| {
+        input_error(
+            PgSqlErrorCode::ERRCODE_INVALID_TEXT_REPRESENTATION,
+            format!("Unable to parse snapshot specification: {err}"),
+            None,
+        )
+    });
+
+    let xids = |values: Vec<u64>, name: &str| -> Vec<u32> {
+        values
+            .into_iter()
+            .map(|value| xid_from_u64(value, name))
+            .collect()
+    };
+
+    SnapshotArguments::new(
+        xid_from_u64(spec.xmin, "xmin"),
+        xid_from_u64(spec.xmax, "xmax"),
+        xids(spec.xip, "xip"),
+        strict,
+    )
+    .with_subxip(xids(spec.subxip, "subxip"))
+    .with_suboverflowed(spec.suboverflowed)
+    .with_curcid(spec.curcid)
+}
+
+/*
+ * Convert a bigint value provided by the user into a transaction id, see xid_from_u64
+ */
+pub(crate) fn xid_from_i64(value: i64, name: &str) -> u32 {
+    let value = u64::try_from(value).unwrap_or_else(|_| {
+        input_error(
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("{name} value {value} is not a valid xid"),
+            None,
+        )
+    });
+
+    xid_from_u64(value, name)
+}
+
+/*
+ * Convert the xids provided by the user to filter the scanned tuples into
+ * transaction ids
+ */
+pub(crate) fn xid_filter(xids: &[i64]) -> HashSet<pg_sys::TransactionId> {
+    xids.iter()
+        .map(|xid| xid_from_i64(*xid, "xid filter"))
+        .collect()
+}
+
+/*
+ * Convert a value provided by the user into a transaction id. Values that fit into
+ * 32 bit are used as they are. Larger values are 64 bit transaction ids (as returned
+ * by pg_current_xact_id() or txid_current()) and include the epoch.
+ */
+pub(crate) fn xid_from_u64(value: u64, name: &str) -> u32 {
+    match u32::try_from(value) {
+        Ok(xid) => xid,
+        Err(_) => unsafe { xid_from_full(value, name) },
+    }
+}
+
+/*
+ * Convert a 64 bit transaction id (including the epoch) into a transaction id. The
+ * value has to be in the past and must not be wrapped around relative to the next
+ * transaction id, otherwise the transaction id would refer to another transaction.
+ */
+pub(crate) unsafe fn xid_from_full(value: u64, name: &str) -> u32 {
+    let next_xid = pg_sys::ReadNextFullTransactionId().value;
+
+    if value > next_xid {
+        input_error(
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("{name} value {value} is in the future, the next xid is {next_xid}"),
+            None,
+        );
+    }
+
+    if next_xid - value >= 1 << 31 {
+        input_error(
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("{name} value {value} is too old, it has been wrapped around"),
+            None,
+        );
+    }
+
+    /* See XidFromFullTransactionId */
+    value as u32
+}
+
+/*
+ * The members of a pg_snapshot or txid_snapshot value. Both types share the same
+ * on-disk format (see pg_snapshot in xid8funcs.c)
+ */
+pub(crate) struct FullSnapshot {
+    xmin: u64,
+    xmax: u64,
+    xip: Vec<u64>,
+}
+
+impl FullSnapshot {
+    /*
+     * Read the snapshot from the given varlena datum
+     */
+    unsafe fn from_datum(datum: pg_sys::Datum) -> FullSnapshot {
+        let value = pg_sys::pg_detoast_datum(datum.cast_mut_ptr()) as *const u8;
+
+        /* Layout: int32 vl_len_, uint32 nxip, uint64 xmin, uint64 xmax, uint64 xip[] */
+        let nxip = std::ptr::read_unaligned(value.add(4) as *const u32) as usize;
+        let xmin = std::ptr::read_unaligned(value.add(8) as *const u64);
+        let xmax = std::ptr::read_unaligned(value.add(16) as *const u64);
+        let xip = (0..nxip)
+            .map(|position| std::ptr::read_unaligned(value.add(24 + 8 * position) as *const u64))
+            .collect();
+
+        FullSnapshot { xmin, xmax, xip }
+    }
+
+    /*
+     * Convert the epoch aware snapshot into snapshot arguments
+     */
+    pub(crate) unsafe fn snapshot_arguments(&self, strict: bool) -> SnapshotArguments {
+        SnapshotArguments::new(
+            xid_from_full(self.xmin, "xmin"),
+            xid_from_full(self.xmax, "xmax"),
+            self.xip
+                .iter()
+                .map(|xip_value| xid_from_full(*xip_value, "xip"))
+                .collect(),
+            strict,
+        )
+    }
+}
+
+/* A value of the SQL type pg_snapshot */
+#[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+pub(crate) struct PgSnapshot(pub(crate) FullSnapshot);
+
+#[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+unsafe impl SqlTranslatable for PgSnapshot {
+    fn argument_sql() -> Result<SqlMapping, ArgumentError> {
+        Ok(SqlMapping::literal("pg_snapshot"))
+    }
+
+    fn return_sql() -> Result<Returns, ReturnsError> {
+        Ok(Returns::One(SqlMapping::literal("pg_snapshot")))
+    }
+}
+
+#[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+impl FromDatum for PgSnapshot {
+    unsafe fn from_polymorphic_datum(
+        datum: pg_sys::Datum,
+        is_null: bool,
+        _typoid: pg_sys::Oid,
+    ) -> Option<Self> {
+        if is_null {
+            None
+        } else {
+            Some(PgSnapshot(FullSnapshot::from_datum(datum)))
+        }
+    }
+}
+
+/* A value of the SQL type txid_snapshot */
+pub(crate) struct TxidSnapshot(pub(crate) FullSnapshot);
+
+unsafe impl SqlTranslatable for TxidSnapshot {
+    fn argument_sql() -> Result<SqlMapping, ArgumentError> {
+        Ok(SqlMapping::literal("txid_snapshot"))
+    }
+
+    fn return_sql() -> Result<Returns, ReturnsError> {
+        Ok(Returns::One(SqlMapping::literal("txid_snapshot")))
+    }
+}
+
+impl FromDatum for TxidSnapshot {
+    unsafe fn from_polymorphic_datum(
+        datum: pg_sys::Datum,
+        is_null: bool,
+        _typoid: pg_sys::Oid,
+    ) -> Option<Self> {
+        if is_null {
+            None
+        } else {
+            Some(TxidSnapshot(FullSnapshot::from_datum(datum)))
+        }
+    }
+}
+
+/* The kind of snapshot that is used for a scan */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum SnapshotMode {
+    /* A regular MVCC snapshot (the default) */
+    Mvcc,
+    /* All tuples are visible (SnapshotAny) */
+    Any,
+    /* All committed tuples and the changes of the current transaction (SnapshotSelf) */
+    OwnChanges,
+    /* Like SnapshotSelf, but tuples of in-progress transactions are visible (SnapshotDirty) */
+    Dirty,
+}
+
+impl SnapshotMode {
+    /*
+     * Get the snapshot mode with the given name
+     */
+    pub(crate) fn from_name(name: &str) -> SnapshotMode {
+        match name {
+            "mvcc" => SnapshotMode::Mvcc,
+            "any" => SnapshotMode::Any,
+            "self" => SnapshotMode::OwnChanges,
+            "dirty" => SnapshotMode::Dirty,
+            _ => error!("Unknown snapshot mode {name}, valid modes are mvcc, any, self and dirty"),
+        }
+    }
+
+    /*
+     * Get the special snapshot of the mode, None is returned for MVCC snapshots
+     */
+    pub(crate) unsafe fn special_snapshot(self) -> Option<*mut SnapshotData> {
+        match self {
+            SnapshotMode::Mvcc => None,
+            SnapshotMode::Any => Some(ptr::addr_of_mut!(pg_sys::SnapshotAnyData)),
+            SnapshotMode::OwnChanges => Some(ptr::addr_of_mut!(pg_sys::SnapshotSelfData)),
+            SnapshotMode::Dirty => {
+                /* See InitDirtySnapshot */
+                let snapshot = pg_sys::palloc0(size_of::<SnapshotData>()) as *mut SnapshotData;
+                (*snapshot).snapshot_type = pg_sys::SnapshotType_SNAPSHOT_DIRTY;
+                Some(snapshot)
+            }
+        }
+    }
+}
+
+/*
+ * A dirty snapshot records the in-progress transactions that inserted or deleted
+ * the last checked tuple. Report them for the given tuple.
+ */
+pub(crate) unsafe fn report_dirty_snapshot(
+    snapshot_data: *mut SnapshotData,
+    tid: pg_sys::ItemPointerData,
+) {
+    if (*snapshot_data).snapshot_type != pg_sys::SnapshotType_SNAPSHOT_DIRTY {
+        return;
+    }
+
+    let (blkno, offnum) = pgrx::itemptr::item_pointer_get_both(tid);
+
+    if (*snapshot_data).xmin != pg_sys::InvalidTransactionId {
+        info!(
+            "Tuple ({blkno},{offnum}) is inserted by the in-progress transaction {}",
+            (*snapshot_data).xmin
+        );
+    }
+
+    if (*snapshot_data).xmax != pg_sys::InvalidTransactionId {
+        info!(
+            "Tuple ({blkno},{offnum}) is deleted by the in-progress transaction {}",
+            (*snapshot_data).xmax
+        );
+    }
+}
+
+/*
+ * Take the user provided snapshot data and return a PostgreSQL snapshot data structure
+ */
+pub(crate) unsafe fn get_snapshot_from_str(snapshot_str: &str) -> *mut SnapshotData {
+    get_snapshot(parse_snapshot_data(snapshot_str, true))
+}
+
+/*
+ * Build a PostgreSQL snapshot data structure from the given snapshot arguments
+ */
+pub(crate) unsafe fn get_snapshot(snapshot_argument: SnapshotArguments) -> *mut SnapshotData {
+    /* Get the latest snapshot as base */
+    let latest_snapshot = GetLatestSnapshot();
+
+    /* Take a copy of the snapshot */
+    let scan_snapshot = palloc(size_of::<SnapshotData>()) as *mut SnapshotData;
+    ptr::copy_nonoverlapping(latest_snapshot, scan_snapshot, 1);
+
+    /*
+     * On a hot standby, the latest snapshot is taken during recovery and its
+     * visibility checks only look at subxip. Use the same layout, so the given xip
+     * values are not ignored.
+     */
+    let (xip, subxip) = if (*scan_snapshot).takenDuringRecovery {
+        (Vec::new(), snapshot_argument.recovery_subxip())
+    } else {
+        (
+            snapshot_argument.xip.clone(),
+            snapshot_argument.subxip.clone(),
+        )
+    };
+
+    /* Modify the relevant values */
+    (*scan_snapshot).copied = true;
+    (*scan_snapshot).xmin = snapshot_argument.xmin;
+    (*scan_snapshot).xmax = snapshot_argument.xmax;
+    (*scan_snapshot).xip = palloc(xip.len() * size_of::<u32>()) as *mut u32;
+    ptr::copy_nonoverlapping(xip.as_ptr(), (*scan_snapshot).xip, xip.len());
+    (*scan_snapshot).xcnt = xip.len() as u32;
+    (*scan_snapshot).subxip = palloc(subxip.len() * size_of::<u32>()) as *mut u32;
+    ptr::copy_nonoverlapping(subxip.as_ptr(), (*scan_snapshot).subxip, subxip.len());
+    (*scan_snapshot).subxcnt = subxip.len() as i32;
+    (*scan_snapshot).suboverflowed = snapshot_argument.suboverflowed;
+
+    if (*scan_snapshot).takenDuringRecovery {
+        check_recovery_horizon(scan_snapshot);
+    }
+
+    if let Some(curcid) = snapshot_argument.curcid {
+        (*scan_snapshot).curcid = curcid;
+    }
+
+    scan_snapshot
+}
+
+/*
+ * On a hot standby, the tuples that are removed by replayed cleanup records only
+ * cause a recovery conflict if they are needed by the snapshot of a running
+ * transaction. A user provided snapshot with an older xmin is not protected, so
+ * tuples visible under it may already be gone. Emit a warning in this case.
+ */
+unsafe fn check_recovery_horizon(snapshot_data: *mut SnapshotData) {
+    let transaction_xmin = (*GetTransactionSnapshot()).xmin;
+    let xmin = (*snapshot_data).xmin;
+
+    if xmin < pg_sys::FirstNormalTransactionId
+        || !pg_sys::TransactionIdPrecedes(xmin, transaction_xmin)
+    {
+        return;
+    }
+
+    ErrorReport::new(
+        PgSqlErrorCode::ERRCODE_WARNING,
+        format!(
+            "Snapshot xmin {xmin} precedes the xmin {transaction_xmin} of the transaction on this standby"
+        ),
+        "check_recovery_horizon",
+    )
+    .set_detail(
+        "Tuples that are only visible under this snapshot may have been removed by replayed cleanup records.",
+    )
+    .set_hint("Compare the result with a scan on the primary, or enable hot_standby_feedback while the snapshot is needed.")
+    .report(PgLogLevel::WARNING);
+}
+
+/*
+ * Check that the xmin of the snapshot does not precede the relfrozenxid of the
+ * relation. Tuples inserted by older transactions have been frozen, so their
+ * visibility under such a snapshot is misleading. A warning is emitted, or an error
+ * if pg_debug_scan.strict_frozen_horizon is enabled.
+ */
+pub(crate) unsafe fn check_frozen_horizon(
+    table: &str,
+    rel: pg_sys::Relation,
+    snapshot_data: *mut SnapshotData,
+) {
+    if (*snapshot_data).snapshot_type != pg_sys::SnapshotType_SNAPSHOT_MVCC {
+        return;
+    }
+
+    let relfrozenxid = (*(*rel).rd_rel).relfrozenxid;
+    let xmin = (*snapshot_data).xmin;
+
+    if relfrozenxid < pg_sys::FirstNormalTransactionId
+        || xmin < pg_sys::FirstNormalTransactionId
+        || !pg_sys::TransactionIdPrecedes(xmin, relfrozenxid)
+    {
+        return;
+    }
+
+    let level = if STRICT_FROZEN_HORIZON.get() {
+        PgLogLevel::ERROR
+    } else {
+        PgLogLevel::WARNING
+    };
+
+    ErrorReport::new(
+        PgSqlErrorCode::ERRCODE_SNAPSHOT_TOO_OLD,
+        format!("Snapshot xmin {xmin} precedes the relfrozenxid {relfrozenxid} of table {table}"),
+        "check_frozen_horizon",
+    )
+    .set_detail(format!(
+        "Tuples inserted by transactions older than {relfrozenxid} have been frozen, their visibility under this snapshot is misleading."
+    ))
+    .set_hint(format!("Use a snapshot with an xmin of at least {relfrozenxid}."))
+    .report(level);
+}
+
+/*
+ * Parse the provided snapshot data and return its members. This allows to validate
+ * and inspect snapshot definitions in SQL before using them in a scan.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+fn pg_debug_parse_snapshot(
+    snapshot: &str,
+    strict: default!(bool, "true"),
+) -> TableIterator<
+    'static,
+    (
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(xip, Vec<Xid8>),
+        name!(subxip, Vec<Xid8>),
+        name!(suboverflowed, bool),
+    ),
+> {
+    let snapshot_argument = parse_snapshot_data(snapshot, strict);
+    let next_xid = unsafe { pg_sys::ReadNextFullTransactionId() };
+    let xid8s = |xids: Vec<pg_sys::TransactionId>| -> Vec<Xid8> {
+        xids.into_iter()
+            .map(|xid| Xid8::from_xid(xid, next_xid))
+            .collect()
+    };
+
+    TableIterator::once((
+        Xid8::from_xid(snapshot_argument.xmin, next_xid),
+        Xid8::from_xid(snapshot_argument.xmax, next_xid),
+        xid8s(snapshot_argument.xip),
+        xid8s(snapshot_argument.subxip),
+        snapshot_argument.suboverflowed,
+    ))
+}
+
+/*
+ * Return the snapshot of the current transaction in the format accepted by
+ * pg_debug_scan. Unlike pg_current_snapshot(), the in-progress subtransactions are
+ * included.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_snapshot_to_text() -> String {
+    SnapshotArguments::from_snapshot(pg_sys::GetTransactionSnapshot()).to_text()
+}
+
+/*
+ * Validate the given snapshot members and return them in the format accepted by
+ * pg_debug_scan. This is the inverse of pg_debug_parse_snapshot.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern(name = "pg_debug_snapshot_to_text")]
+fn pg_debug_snapshot_members_to_text(
+    xmin: Xid8,
+    xmax: Xid8,
+    xip: default!(Vec<Xid8>, "'{}'"),
+    subxip: default!(Vec<Xid8>, "'{}'"),
+    suboverflowed: default!(bool, false),
+    strict: default!(bool, "true"),
+) -> String {
+    let xids = |values: Vec<Xid8>, name: &str| -> Vec<u32> {
+        values
+            .into_iter()
+            .map(|value| xid_from_u64(value.0, name))
+            .collect()
+    };
+
+    SnapshotArguments::new(
+        xid_from_u64(xmin.0, "xmin"),
+        xid_from_u64(xmax.0, "xmax"),
+        xids(xip, "xip"),
+        strict,
+    )
+    .with_subxip(xids(subxip, "subxip"))
+    .with_suboverflowed(suboverflowed)
+    .to_text()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    use pgrx::pg_sys::panic::CaughtError;
+
+    #[pgrx::pg_test]
+    fn test_parse_snapshot() {
+        let xmin = pgrx::Spi::get_one::<i64>(
+            "SELECT xmin::text::bigint FROM pg_debug_parse_snapshot('10:20:12,15');",
+        )
+        .unwrap();
+        assert_eq!(xmin, Some(10));
+
+        let xmax = pgrx::Spi::get_one::<i64>(
+            "SELECT xmax::text::bigint FROM pg_debug_parse_snapshot('10:20:12,15');",
+        )
+        .unwrap();
+        assert_eq!(xmax, Some(20));
+
+        let xip = pgrx::Spi::get_one::<Vec<i64>>(
+            "SELECT xip::text[]::bigint[] FROM pg_debug_parse_snapshot('10:20:12,15');",
+        )
+        .unwrap();
+        assert_eq!(xip, Some(vec![12, 15]));
+    }
+
+    #[pgrx::pg_test]
+    fn test_parse_snapshot_relaxed() {
+        let xip = pgrx::Spi::get_one::<Vec<i64>>(
+            "SELECT xip::text[]::bigint[] FROM pg_debug_parse_snapshot('10:20:5,12,25', strict => false);",
+        )
+        .unwrap();
+        assert_eq!(xip, Some(vec![12]));
+    }
+
+    #[pgrx::pg_test]
+    fn test_parse_snapshot_subxip() {
+        let subxip = pgrx::Spi::get_one::<Vec<i64>>(
+            "SELECT subxip::text[]::bigint[] FROM pg_debug_parse_snapshot('10:20:12:13,14');",
+        )
+        .unwrap();
+        assert_eq!(subxip, Some(vec![13, 14]));
+
+        let suboverflowed = pgrx::Spi::get_one::<bool>(
+            "SELECT suboverflowed FROM pg_debug_parse_snapshot('10:20:12:overflowed');",
+        )
+        .unwrap();
+        assert_eq!(suboverflowed, Some(true));
+    }
+
+    #[pgrx::pg_test]
+    fn test_snapshot_to_text() {
+        let text = pgrx::Spi::get_one::<String>(
+            "SELECT pg_debug_snapshot_to_text(xmin, xmax, xip, subxip, suboverflowed) \
+             FROM pg_debug_parse_snapshot('10:20:15,12:13,14');",
+        )
+        .unwrap();
+        assert_eq!(text, Some("10:20:12,15:13,14".to_string()));
+
+        let matches = pgrx::Spi::get_one::<bool>(
+            "SELECT xmin::text = txid_snapshot_xmin(txid_current_snapshot())::text \
+             FROM pg_debug_parse_snapshot(pg_debug_snapshot_to_text());",
+        )
+        .unwrap();
+        assert_eq!(matches, Some(true));
+    }
+
+    #[pgrx::pg_test]
+    fn test_recovery_snapshot_layout() {
+        let snapshot_argument =
+            super::SnapshotArguments::new(10, 20, vec![12, 15], true).with_subxip(vec![13]);
+        assert_eq!(snapshot_argument.recovery_subxip(), vec![12, 15, 13]);
+
+        /* Outside of recovery, the xip values are kept in xip */
+        unsafe {
+            let snapshot_data = super::get_snapshot(snapshot_argument);
+            assert!(!(*snapshot_data).takenDuringRecovery);
+            assert_eq!((*snapshot_data).xcnt, 2);
+            assert_eq!((*snapshot_data).subxcnt, 1);
+        }
+    }
+
+    /*
+     * Run the query and return the message of the error it raises
+     */
+    fn query_error(query: &str) -> Option<String> {
+        PgTryBuilder::new(|| {
+            pgrx::Spi::run(query).unwrap();
+            None
+        })
+        .catch_others(|cause| match cause {
+            CaughtError::PostgresError(report)
+            | CaughtError::ErrorReport(report)
+            | CaughtError::RustPanic {
+                ereport: report, ..
+            } => Some(report.message().to_string()),
+        })
+        .execute()
+    }
+
+    #[pgrx::pg_test]
+    fn test_import_snapshot() {
+        let matches = pgrx::Spi::get_one::<bool>(
+            "SELECT xmin::text = txid_snapshot_xmin(txid_current_snapshot())::text \
+             FROM pg_debug_parse_snapshot(pg_export_snapshot());",
+        )
+        .unwrap();
+        assert_eq!(matches, Some(true));
+
+        let error = query_error("SELECT * FROM pg_debug_parse_snapshot('00000003-0000001B-9');");
+
+        assert_eq!(
+            error,
+            Some("Snapshot \"00000003-0000001B-9\" does not exist".to_string())
+        );
+    }
+
+    #[pgrx::pg_test]
+    fn test_import_snapshot_finished() {
+        let name = pgrx::Spi::get_one::<String>("SELECT pg_export_snapshot();")
+            .unwrap()
+            .expect("unable to export a snapshot");
+
+        /* Copy the snapshot as if it was exported by a finished transaction */
+        let contents = std::fs::read_to_string(format!("pg_snapshots/{name}")).unwrap();
+        let contents: String = contents
+            .lines()
+            .map(|line| match line.split_once(':') {
+                Some(("vxid", _)) => "vxid:999/1\n".to_string(),
+                _ => format!("{line}\n"),
+            })
+            .collect();
+
+        let path = "pg_snapshots/000003E7-00000001-1";
+        std::fs::write(path, contents).unwrap();
+
+        let error = query_error("SELECT * FROM pg_debug_parse_snapshot('000003E7-00000001-1');");
+
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            error,
+            Some(
+                "The transaction that exported snapshot \"000003E7-00000001-1\" is not running anymore"
+                    .to_string()
+            )
+        );
+    }
+
+    #[pgrx::pg_test]
+    fn test_strict_frozen_horizon() {
+        pgrx::Spi::run("CREATE TABLE horizon_test (id int);").unwrap();
+        pgrx::Spi::run("INSERT INTO horizon_test VALUES (1);").unwrap();
+
+        /* A warning is emitted by default */
+        let rows = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('horizon_test', '3:3:');",
+        )
+        .unwrap();
+        assert_eq!(rows, Some(1));
+
+        pgrx::Spi::run("SET pg_debug_scan.strict_frozen_horizon = on;").unwrap();
+
+        let error = query_error("SELECT * FROM pg_debug_scan('horizon_test', '3:3:');");
+
+        assert!(error
+            .unwrap()
+            .starts_with("Snapshot xmin 3 precedes the relfrozenxid"));
+    }
+
+    #[pgrx::pg_test]
+    fn test_full_xid_in_future() {
+        pgrx::Spi::run("CREATE TABLE full_xid_test (id int);").unwrap();
+
+        let error =
+            query_error("SELECT * FROM pg_debug_scan('full_xid_test', '4294967296:4294967296:');");
+
+        assert!(error
+            .unwrap()
+            .starts_with("xmin value 4294967296 is in the future"));
+    }
+
+    #[pgrx::pg_test(error = "Invalid xmin value \"a\" in snapshot a:b:c")]
+    fn test_parse_snapshot_invalid_xid() {
+        pgrx::Spi::run("SELECT * FROM pg_debug_parse_snapshot('a:b:c');").unwrap();
+    }
+
+    #[pgrx::pg_test]
+    fn test_parse_snapshot_errors() {
+        /* The error is caught in a subtransaction to check the SQLSTATE */
+        pgrx::Spi::run(
+            "CREATE FUNCTION snapshot_error(snapshot text) RETURNS text AS $$
+            BEGIN
+                PERFORM pg_debug_parse_snapshot(snapshot);
+                RETURN NULL;
+            EXCEPTION WHEN OTHERS THEN
+                RETURN SQLSTATE || ': ' || SQLERRM;
+            END $$ LANGUAGE plpgsql;",
+        )
+        .unwrap();
+
+        for (snapshot, error) in [
+            ("10:20", "22P02: Unable to parse snapshot data 10:20"),
+            (
+                "10:20:12,x",
+                "22P02: Invalid xip value \"x\" in snapshot 10:20:12,x",
+            ),
+            ("10:20:5", "22023: Xip value 5 is outside of 10..20"),
+            (
+                "10:20:12:5",
+                "22023: Subxip value 5 is smaller than xmin 10",
+            ),
+        ] {
+            let result = pgrx::Spi::get_one_with_args::<String>(
+                "SELECT snapshot_error($1);",
+                vec![(PgBuiltInOids::TEXTOID.oid(), snapshot.into_datum())],
+            )
+            .unwrap();
+            assert_eq!(result, Some(error.to_string()));
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+
+use pgrx::{
+    pg_sys::{AccessShareLock, GetTransactionSnapshot, SnapshotData, TransactionId},
+    prelude::*,
+};
+
+use crate::get_relid_from_name;
+use crate::heap::{
+    open_heap_relation, page_get_heap_tuple, prefetch_ahead, with_locked_page, xmin_aborted,
+    HeapTupleHeaderGetUpdateXid, HeapTupleHeaderGetXmax, PageGetItemId, PageGetMaxOffsetNumber,
+    HEAP_XMAX_IS_LOCKED_ONLY,
+};
+use crate::snapshot::get_snapshot_from_str;
+use crate::visibility::{xid_in_snapshot, xid_status};
+use crate::xid8::Xid8;
+
+/* The visibility statistics of a table */
+#[derive(Default)]
+struct VisibilityStats {
+    tuples: i64,
+    visible: i64,
+    xmin_in_progress: i64,
+    deleted: i64,
+    aborted_inserts: i64,
+    frozen: i64,
+    dead_line_pointers: i64,
+}
+
+impl VisibilityStats {
+    /*
+     * Count the given tuple. Invisible tuples are counted by the reason why they
+     * are invisible under the snapshot.
+     */
+    unsafe fn add_tuple(
+        &mut self,
+        tup: pg_sys::HeapTupleHeader,
+        snapshot_data: *mut SnapshotData,
+        visible: bool,
+    ) {
+        self.tuples += 1;
+
+        if pg_sys::HeapTupleHeaderFrozen(tup) {
+            self.frozen += 1;
+        }
+
+        if visible {
+            self.visible += 1;
+            return;
+        }
+
+        let xmin = pg_sys::HeapTupleHeaderGetXmin(tup);
+        let infomask = (*tup).t_infomask;
+
+        if xmin_aborted(tup) {
+            self.aborted_inserts += 1;
+        } else if !pg_sys::TransactionIdIsCurrentTransactionId(xmin)
+            && xid_in_snapshot(xmin, snapshot_data)
+        {
+            self.xmin_in_progress += 1;
+        } else if infomask as u32 & pg_sys::HEAP_XMAX_INVALID == 0
+            && HeapTupleHeaderGetXmax(tup) != pg_sys::InvalidTransactionId
+            && !HEAP_XMAX_IS_LOCKED_ONLY(infomask)
+        {
+            self.deleted += 1;
+        }
+    }
+}
+
+/* A normal line pointer with its tuple and visibility, or a dead line pointer */
+enum ScannedLinePointer {
+    Tuple(pg_sys::HeapTupleHeader, bool),
+    Dead,
+}
+
+/*
+ * Read all pages of the table and call func for each normal and dead line pointer.
+ * The tuples are checked against the snapshot and are only valid during the call.
+ */
+unsafe fn for_each_line_pointer(
+    table: &str,
+    snapshot_data: *mut SnapshotData,
+    mut func: impl FnMut(ScannedLinePointer),
+) {
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+
+    let nblocks =
+        pg_sys::RelationGetNumberOfBlocksInFork(table_rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+
+    for blkno in 0..nblocks {
+        prefetch_ahead(table_rel, blkno, nblocks);
+
+        with_locked_page(table_rel, blkno, |buffer, page| {
+            for offnum in 1..=PageGetMaxOffsetNumber(page) {
+                match (*PageGetItemId(page, offnum)).lp_flags() {
+                    pg_sys::LP_NORMAL => {
+                        let mut htup = page_get_heap_tuple(relid, page, blkno, offnum);
+                        let visible =
+                            pg_sys::HeapTupleSatisfiesVisibility(&mut htup, snapshot_data, buffer);
+
+                        func(ScannedLinePointer::Tuple(htup.t_data, visible));
+                    }
+                    pg_sys::LP_DEAD => func(ScannedLinePointer::Dead),
+                    _ => {}
+                }
+            }
+        });
+    }
+
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+}
+
+/*
+ * Count the tuples of the table by their visibility under the snapshot instead of
+ * returning their data. Invisible tuples are counted by the reason why they are
+ * invisible (inserting transaction in progress, deleted, or aborted). In addition,
+ * the frozen tuples and the dead line pointers are counted.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_stats(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(tuples, i64),
+        name!(visible_tuples, i64),
+        name!(xmin_in_progress_tuples, i64),
+        name!(deleted_tuples, i64),
+        name!(aborted_insert_tuples, i64),
+        name!(frozen_tuples, i64),
+        name!(dead_line_pointers, i64),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let mut stats = VisibilityStats::default();
+
+    for_each_line_pointer(table, snapshot_data, |line_pointer| match line_pointer {
+        ScannedLinePointer::Tuple(tup, visible) => stats.add_tuple(tup, snapshot_data, visible),
+        ScannedLinePointer::Dead => stats.dead_line_pointers += 1,
+    });
+
+    TableIterator::once((
+        stats.tuples,
+        stats.visible,
+        stats.xmin_in_progress,
+        stats.deleted,
+        stats.aborted_inserts,
+        stats.frozen,
+        stats.dead_line_pointers,
+    ))
+}
+
+/* The tuple versions created and deleted by a transaction */
+#[derive(Default)]
+struct TransactionCounts {
+    inserted: i64,
+    visible_inserted: i64,
+    deleted: i64,
+    visible_deleted: i64,
+}
+
+/*
+ * Group all tuple versions of the table by the transactions that inserted and
+ * deleted them. For each transaction, the number of inserted and deleted versions
+ * and how many of them are visible under the snapshot is returned. The transactions
+ * that touched the most versions come first, which quickly identifies the
+ * transaction that is responsible for bloat.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_xacts(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(xid, Xid8),
+        name!(status, Option<String>),
+        name!(inserted_tuples, i64),
+        name!(visible_inserted_tuples, i64),
+        name!(deleted_tuples, i64),
+        name!(visible_deleted_tuples, i64),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let mut transactions: HashMap<TransactionId, TransactionCounts> = HashMap::new();
+
+    for_each_line_pointer(table, snapshot_data, |line_pointer| {
+        let ScannedLinePointer::Tuple(tup, visible) = line_pointer else {
+            return;
+        };
+
+        let xmin = transactions
+            .entry(pg_sys::HeapTupleHeaderGetXmin(tup))
+            .or_default();
+        xmin.inserted += 1;
+        xmin.visible_inserted += i64::from(visible);
+
+        let infomask = (*tup).t_infomask;
+        if infomask as u32 & pg_sys::HEAP_XMAX_INVALID != 0 || HEAP_XMAX_IS_LOCKED_ONLY(infomask) {
+            return;
+        }
+
+        let xmax = HeapTupleHeaderGetUpdateXid(tup);
+        if xmax != pg_sys::InvalidTransactionId {
+            let xmax = transactions.entry(xmax).or_default();
+            xmax.deleted += 1;
+            xmax.visible_deleted += i64::from(visible);
+        }
+    });
+
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    let mut results: Vec<_> = transactions
+        .into_iter()
+        .map(|(xid, counts)| {
+            (
+                Xid8::from_xid(xid, next_xid),
+                xid_status(xid),
+                counts.inserted,
+                counts.visible_inserted,
+                counts.deleted,
+                counts.visible_deleted,
+            )
+        })
+        .collect();
+
+    results.sort_by_key(|(xid, _, inserted, _, deleted, _)| (-(inserted + deleted), *xid));
+
+    TableIterator::new(results)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_scan_stats() {
+        pgrx::Spi::run("CREATE TABLE scan_stats_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_stats_test VALUES (1), (2), (3);").unwrap();
+        pgrx::Spi::run("DELETE FROM scan_stats_test WHERE value = 3;").unwrap();
+
+        /* Insert a tuple in an aborted subtransaction */
+        pgrx::Spi::run(
+            "DO $$ BEGIN
+                INSERT INTO scan_stats_test VALUES (4);
+                RAISE EXCEPTION 'abort';
+            EXCEPTION WHEN OTHERS THEN NULL;
+            END $$;",
+        )
+        .unwrap();
+
+        let stats = pgrx::Spi::get_three::<i64, i64, i64>(
+            "SELECT tuples, visible_tuples, aborted_insert_tuples FROM pg_debug_scan_stats('scan_stats_test');",
+        )
+        .unwrap();
+        assert_eq!(stats, (Some(4), Some(2), Some(1)));
+
+        let deleted = pgrx::Spi::get_one::<i64>(
+            "SELECT deleted_tuples FROM pg_debug_scan_stats('scan_stats_test');",
+        )
+        .unwrap();
+        assert_eq!(deleted, Some(1));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_xacts() {
+        pgrx::Spi::run("CREATE TABLE scan_xacts_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_xacts_test VALUES (1), (2), (3);").unwrap();
+        pgrx::Spi::run("DELETE FROM scan_xacts_test WHERE value = 3;").unwrap();
+
+        let counts = pgrx::Spi::get_three::<i64, i64, i64>(
+            "SELECT inserted_tuples, visible_inserted_tuples, deleted_tuples FROM pg_debug_scan_xacts('scan_xacts_test') WHERE xid::text = txid_current()::text;",
+        )
+        .unwrap();
+        assert_eq!(counts, (Some(3), Some(2), Some(1)));
+    }
+}
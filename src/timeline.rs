@@ -0,0 +1,375 @@
+use std::collections::BTreeSet;
+
+use pgrx::{
+    itemptr::item_pointer_set_all,
+    pg_sys::{AccessShareLock, GetTransactionSnapshot, TransactionId},
+    prelude::*,
+};
+
+use crate::chain::VersionChain;
+use crate::heap::{open_heap_relation, HeapTupleHeaderGetUpdateXid};
+use crate::output::{format_tuple, OutputFormat};
+use crate::snapshot::{get_snapshot, get_snapshot_from_str, SnapshotArguments};
+use crate::versions::{changed_attributes, key_versions, successors};
+use crate::visibility::xid_status;
+use crate::xid8::Xid8;
+use crate::{
+    decode_tuple, get_relid_from_name, scan_heap, tuples_to_rows, ScannedTuple, TupleStream,
+};
+
+/*
+ * Parse a tid in the format (block,offset)
+ */
+fn parse_tid(tid: &str) -> pg_sys::ItemPointerData {
+    let parts: Vec<&str> = tid
+        .trim()
+        .trim_start_matches('(')
+        .trim_end_matches(')')
+        .split(',')
+        .collect();
+
+    let (blkno, offnum) = match parts.as_slice() {
+        [blkno, offnum] => (blkno.trim().parse().ok(), offnum.trim().parse().ok()),
+        _ => (None, None),
+    };
+
+    match (blkno, offnum) {
+        (Some(blkno), Some(offnum)) => {
+            let mut ctid = pg_sys::ItemPointerData::default();
+            item_pointer_set_all(&mut ctid, blkno, offnum);
+            ctid
+        }
+        _ => error!("Unable to parse tid {tid}"),
+    }
+}
+
+/*
+ * Get the raw commit timestamp of the given transaction. The timestamp is only
+ * available if track_commit_timestamp is enabled.
+ */
+unsafe fn xid_commit_timestamp(xid: TransactionId) -> Option<pg_sys::TimestampTz> {
+    if !pg_sys::track_commit_timestamp || xid < pg_sys::FirstNormalTransactionId {
+        return None;
+    }
+
+    let mut timestamp: pg_sys::TimestampTz = 0;
+    let mut nodeid: pg_sys::RepOriginId = 0;
+
+    pg_sys::TransactionIdGetCommitTsData(xid, &mut timestamp, &mut nodeid).then_some(timestamp)
+}
+
+/*
+ * Get the commit timestamp of the given transaction, see xid_commit_timestamp
+ */
+unsafe fn xid_commit_time(xid: TransactionId) -> Option<TimestampWithTimeZone> {
+    let timestamp = xid_commit_timestamp(xid)?;
+
+    TimestampWithTimeZone::from_datum(pg_sys::Datum::from(timestamp), false)
+}
+
+/*
+ * Split the versions into update chains. Each chain starts with a version that
+ * has no predecessor. The chains are ordered by the xmin of their first version.
+ */
+fn update_chains(versions: &[ScannedTuple]) -> Vec<Vec<usize>> {
+    let successors = successors(versions);
+
+    let mut has_predecessor = vec![false; versions.len()];
+    for successor in successors.iter().flatten() {
+        has_predecessor[*successor] = true;
+    }
+
+    let mut chains = Vec::new();
+
+    for root in (0..versions.len()).filter(|position| !has_predecessor[*position]) {
+        let mut chain = vec![root];
+        let mut position = root;
+
+        while let Some(successor) = successors[position] {
+            if chain.contains(&successor) {
+                break;
+            }
+            chain.push(successor);
+            position = successor;
+        }
+
+        chains.push(chain);
+    }
+
+    chains.sort_by_key(|chain| versions[chain[0]].xmin);
+    chains
+}
+
+/*
+ * Assemble the chronological history of a logical row. The row is identified by
+ * a tid, whose version chain is followed like in pg_debug_chain, or, if key_column
+ * is given, by the value of its key column. For each version
+ * the transaction ids and their commit status and commit timestamp, the visibility
+ * under the snapshot and the changed columns compared to the previous version are
+ * returned.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_timeline(
+    table: &str,
+    tid_or_key: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    key_column: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmin_status, Option<String>),
+        name!(xmin_commit_time, Option<TimestampWithTimeZone>),
+        name!(xmax, Xid8),
+        name!(xmax_status, Option<String>),
+        name!(xmax_commit_time, Option<TimestampWithTimeZone>),
+        name!(visible, bool),
+        name!(changed_columns, Vec<String>),
+        name!(data, String),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let (versions, visible): (Vec<ScannedTuple>, Vec<bool>) = match key_column {
+        Some(key_column) => key_versions(table, key_column, tid_or_key, snapshot_data).unzip(),
+        None => {
+            /* Follow the versions of the row from the given tid */
+            let relid = get_relid_from_name(table);
+            let chain = VersionChain::open(relid, parse_tid(tid_or_key), snapshot_data);
+            let tupdesc = chain.tupdesc();
+
+            chain
+                .filter_map(|(_, line_pointer)| {
+                    let (htup, visible) = line_pointer.tuple?;
+                    let version =
+                        ScannedTuple::from_heap_tuple(htup, decode_tuple(relid, htup, tupdesc));
+                    Some((version, visible))
+                })
+                .unzip()
+        }
+    };
+
+    let chains = match key_column {
+        Some(_) => update_chains(&versions),
+        None => vec![(0..versions.len()).collect()],
+    };
+
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut results = Vec::new();
+
+    for chain in chains {
+        let mut previous: Option<&ScannedTuple> = None;
+
+        for position in chain {
+            let version = &versions[position];
+
+            let changed_columns = match previous {
+                Some(previous) => changed_attributes(&previous.attributes, &version.attributes)
+                    .into_iter()
+                    .map(|(column_name, _, _)| column_name)
+                    .collect(),
+                None => Vec::new(),
+            };
+
+            results.push((
+                version.ctid,
+                Xid8::from_xid(version.xmin, next_xid),
+                xid_status(version.xmin),
+                xid_commit_time(version.xmin),
+                Xid8::from_xid(version.xmax, next_xid),
+                xid_status(version.xmax),
+                xid_commit_time(version.xmax),
+                visible[position],
+                changed_columns,
+                format_tuple(&version.attributes, output_format),
+            ));
+
+            previous = Some(version);
+        }
+    }
+
+    TableIterator::new(results)
+}
+
+/*
+ * Build a snapshot that shows the table as of the given time. All transactions that
+ * modified the table and did not commit until then (i.e., they committed later, are
+ * still running, or aborted) are in progress for the snapshot. Transactions without
+ * a commit timestamp (e.g., committed before track_commit_timestamp was enabled) are
+ * considered as committed before the given time.
+ */
+unsafe fn asof_snapshot(table: &str, timestamp: pg_sys::TimestampTz) -> *mut pg_sys::SnapshotData {
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+
+    let mut xids = BTreeSet::new();
+    scan_heap(
+        table_rel,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        |htup, _tupdesc| {
+            let tup = (*htup).t_data;
+            xids.insert(pg_sys::HeapTupleHeaderGetXmin(tup));
+            xids.insert(HeapTupleHeaderGetUpdateXid(tup));
+        },
+    );
+
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+
+    let mut xip: Vec<TransactionId> = xids
+        .into_iter()
+        .filter(|xid| *xid >= pg_sys::FirstNormalTransactionId)
+        .filter(|xid| {
+            !pg_sys::TransactionIdDidCommit(*xid)
+                || xid_commit_timestamp(*xid).is_some_and(|commit_time| commit_time > timestamp)
+        })
+        .collect();
+
+    /* A snapshot holds at most as many running transactions as there are backends */
+    let max_xcnt = pg_sys::GetMaxSnapshotXidCount() as usize;
+    if xip.len() > max_xcnt {
+        error!(
+            "{} transactions that modified {table} did not commit until the given time, but a snapshot can hold at most {max_xcnt}",
+            xip.len()
+        );
+    }
+
+    /* The oldest transaction, considering the wraparound, becomes the xmin of the snapshot */
+    xip.sort_by(|a, b| {
+        if a == b {
+            std::cmp::Ordering::Equal
+        } else if pg_sys::TransactionIdPrecedes(*a, *b) {
+            std::cmp::Ordering::Less
+        } else {
+            std::cmp::Ordering::Greater
+        }
+    });
+
+    let xmax = pg_sys::ReadNextFullTransactionId().value as TransactionId;
+    let xmin = xip.first().copied().unwrap_or(xmax);
+
+    get_snapshot(SnapshotArguments::new(xmin, xmax, xip, true))
+}
+
+/*
+ * Perform a table scan that shows the table as of the given time. The snapshot
+ * of the scan is built from the commit timestamps of the transactions that modified
+ * the table, so track_commit_timestamp has to be enabled. Changes of the current
+ * transaction are always visible.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_asof(
+    table: &str,
+    timestamp: TimestampWithTimeZone,
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(block_number, i64),
+        name!(offset_number, i32),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(data, String),
+    ),
+> {
+    if !pg_sys::track_commit_timestamp {
+        ErrorReport::new(
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            "pg_debug_scan_asof requires track_commit_timestamp to be enabled",
+            "pg_debug_scan_asof",
+        )
+        .report(PgLogLevel::ERROR);
+    }
+
+    let timestamp = timestamp
+        .into_datum()
+        .expect("a timestamp is not null")
+        .value() as pg_sys::TimestampTz;
+
+    TableIterator::new(tuples_to_rows(TupleStream::open(
+        table,
+        asof_snapshot(table, timestamp),
+        None,
+        None,
+        None,
+    )))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_timeline() {
+        pgrx::Spi::run("CREATE TABLE timeline_test (id int, value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO timeline_test VALUES (1, 10), (2, 20);").unwrap();
+        pgrx::Spi::run("UPDATE timeline_test SET value = 11 WHERE id = 1;").unwrap();
+        pgrx::Spi::run("UPDATE timeline_test SET value = 12 WHERE id = 1;").unwrap();
+
+        /* Follow the row from its first version */
+        let versions = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_timeline('timeline_test', '(0,1)');",
+        )
+        .unwrap();
+        assert_eq!(versions, Some(3));
+
+        let visible = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_timeline('timeline_test', '1', key_column => 'id') WHERE visible;",
+        )
+        .unwrap();
+        assert_eq!(visible, Some(1));
+
+        let changed_columns = pgrx::Spi::get_one::<Vec<String>>(
+            "SELECT changed_columns FROM pg_debug_timeline('timeline_test', '1', key_column => 'id') WHERE visible;",
+        )
+        .unwrap();
+        assert_eq!(changed_columns, Some(vec!["value".to_string()]));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_asof() {
+        pgrx::Spi::run("CREATE TABLE asof_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO asof_test VALUES (1), (2);").unwrap();
+
+        /*
+         * The test runs in a single transaction, so the first tuple is attributed to the
+         * committed transaction that created the extension
+         */
+        let xid = pgrx::Spi::get_one::<i64>(
+            "SELECT xmin::text::bigint FROM pg_extension WHERE extname = 'pg_debug_scan';",
+        )
+        .unwrap()
+        .expect("unable to get the xid of the extension");
+
+        unsafe {
+            crate::heap::modify_page("asof_test", 0, |page| {
+                let tup = crate::heap::PageGetItem(page, crate::heap::PageGetItemId(page, 1));
+                (*tup).t_choice.t_heap.t_xmin = xid as pg_sys::TransactionId;
+            });
+        }
+
+        let commit_time =
+            "pg_xact_commit_timestamp((SELECT xmin FROM pg_extension WHERE extname = 'pg_debug_scan'))";
+
+        /* Before the commit, only the changes of the current transaction are visible */
+        let before = pgrx::Spi::get_one::<Vec<i32>>(&format!(
+            "SELECT array_agg(offset_number) FROM pg_debug_scan_asof('asof_test', {commit_time} - interval '1 second');"
+        ))
+        .unwrap();
+        assert_eq!(before, Some(vec![2]));
+
+        let after = pgrx::Spi::get_one::<Vec<i32>>(&format!(
+            "SELECT array_agg(offset_number ORDER BY offset_number) FROM pg_debug_scan_asof('asof_test', {commit_time});"
+        ))
+        .unwrap();
+        assert_eq!(after, Some(vec![1, 2]));
+    }
+}
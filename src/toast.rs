@@ -0,0 +1,585 @@
+use std::ffi::CStr;
+
+use std::collections::HashSet;
+
+use pgrx::{
+    pg_sys::{AccessShareLock, GetTransactionSnapshot, Oid},
+    prelude::*,
+    varlena::{varatt_is_1b_e, varatt_is_4b_c, varsize_any},
+};
+
+use crate::heap::{open_heap_relation, HeapTupleHeaderGetXmax};
+use crate::regclass::RegClass;
+use crate::snapshot::get_snapshot_from_str;
+use crate::xid8::Xid8;
+use crate::{get_relid_from_name, resources_releasable, scan_heap, HeapScanOptions, TupleStream};
+
+#[pg_guard]
+extern "C" {
+    /* Not exported by pgrx, see access/toast_internals.h */
+    fn toast_get_valid_index(toastoid: Oid, lock: pg_sys::LOCKMODE) -> Oid;
+}
+
+/* See VARLENA_EXTSIZE_BITS and VARLENA_EXTSIZE_MASK in varatt.h */
+#[cfg(any(feature = "pg14", feature = "pg15", feature = "pg16"))]
+const VARLENA_EXTSIZE_BITS: u32 = 30;
+#[cfg(any(feature = "pg14", feature = "pg15", feature = "pg16"))]
+const VARLENA_EXTSIZE_MASK: u32 = (1 << VARLENA_EXTSIZE_BITS) - 1;
+
+/* See VARHDRSZ in c.h */
+const VARHDRSZ: i64 = 4;
+
+/* The content of an on-disk TOAST pointer (varatt_external) */
+pub(crate) struct ToastPointer {
+    pub(crate) ext_size: u32,
+    pub(crate) value_id: Oid,
+    pub(crate) raw_size: i32,
+    pub(crate) toast_relid: Oid,
+    /* The compression method, None if the value is stored uncompressed */
+    pub(crate) compression: Option<&'static str>,
+}
+
+impl ToastPointer {
+    /*
+     * Describe the TOAST pointer as JSON object
+     */
+    pub(crate) fn to_json(&self) -> String {
+        let pointer = serde_json::json!({
+            "toast_relid": self.toast_relid.as_u32(),
+            "chunk_id": self.value_id.as_u32(),
+            "raw_size": self.raw_size,
+            "external_size": self.ext_size,
+            "compression": self.compression,
+        });
+
+        pointer.to_string()
+    }
+}
+
+/* Where a varlena value is stored */
+pub(crate) enum VarlenaStorage {
+    /* Stored uncompressed in the tuple */
+    Inline(usize),
+    /* Stored compressed in the tuple */
+    Compressed(usize),
+    /* Stored in the TOAST relation */
+    External(ToastPointer),
+}
+
+/*
+ * Custom implementation for VARATT_IS_EXTERNAL_ONDISK.
+ */
+#[allow(non_snake_case)]
+unsafe fn VARATT_IS_EXTERNAL_ONDISK(value: *const pg_sys::varlena) -> bool {
+    /* The tag is stored in the byte after the 1 byte header */
+    varatt_is_1b_e(value)
+        && *(value as *const u8).add(1) as u32 == pg_sys::vartag_external_VARTAG_ONDISK
+}
+
+/*
+ * Read the TOAST pointer of an external on-disk value. The pointer is not aligned
+ * within the tuple, so it has to be copied.
+ */
+unsafe fn read_toast_pointer(value: *const pg_sys::varlena) -> ToastPointer {
+    /* See VARDATA_EXTERNAL */
+    let data = (value as *const u8).add(2) as *const pg_sys::varatt_external;
+    let toast_pointer = std::ptr::read_unaligned(data);
+
+    #[cfg(any(feature = "pg12", feature = "pg13"))]
+    let ext_size = toast_pointer.va_extsize as u32;
+
+    /* See VARATT_EXTERNAL_GET_EXTSIZE */
+    #[cfg(any(feature = "pg14", feature = "pg15", feature = "pg16"))]
+    let ext_size = toast_pointer.va_extinfo & VARLENA_EXTSIZE_MASK;
+
+    /* See VARATT_EXTERNAL_IS_COMPRESSED */
+    let compressed = (ext_size as i64) < toast_pointer.va_rawsize as i64 - VARHDRSZ;
+
+    /* Only pglz is supported before PostgreSQL 14 */
+    #[cfg(any(feature = "pg12", feature = "pg13"))]
+    let compression = compressed.then_some("pglz");
+
+    /* See VARATT_EXTERNAL_GET_COMPRESS_METHOD */
+    #[cfg(any(feature = "pg14", feature = "pg15", feature = "pg16"))]
+    let compression =
+        compressed.then_some(match toast_pointer.va_extinfo >> VARLENA_EXTSIZE_BITS {
+            0 => "pglz",
+            1 => "lz4",
+            _ => "unknown",
+        });
+
+    ToastPointer {
+        ext_size,
+        value_id: toast_pointer.va_valueid,
+        raw_size: toast_pointer.va_rawsize,
+        toast_relid: toast_pointer.va_toastrelid,
+        compression,
+    }
+}
+
+/*
+ * Determine where the given (not detoasted) varlena value is stored
+ */
+pub(crate) unsafe fn varlena_storage(value: *const pg_sys::varlena) -> VarlenaStorage {
+    if VARATT_IS_EXTERNAL_ONDISK(value) {
+        VarlenaStorage::External(read_toast_pointer(value))
+    } else if varatt_is_4b_c(value) {
+        VarlenaStorage::Compressed(varsize_any(value))
+    } else {
+        VarlenaStorage::Inline(varsize_any(value))
+    }
+}
+
+/*
+ * Determine whether the given (not detoasted) attribute value is compressed or
+ * stored in the TOAST relation and how many bytes it occupies. For values in the
+ * TOAST relation, the size of the stored (possibly compressed) value is returned.
+ */
+pub(crate) unsafe fn attribute_storage(attr: pg_sys::Datum, attlen: i16) -> (bool, i32) {
+    match attlen {
+        -1 => match varlena_storage(attr.cast_mut_ptr::<pg_sys::varlena>()) {
+            VarlenaStorage::Inline(size) => (false, size as i32),
+            VarlenaStorage::Compressed(size) => (true, size as i32),
+            VarlenaStorage::External(toast_pointer) => (true, toast_pointer.ext_size as i32),
+        },
+        /* A null-terminated cstring */
+        -2 => (
+            false,
+            CStr::from_ptr(attr.cast_mut_ptr())
+                .to_bytes_with_nul()
+                .len() as i32,
+        ),
+        attlen => (false, attlen.into()),
+    }
+}
+
+/* The storage statistics of a column */
+#[derive(Default)]
+struct ColumnStorage {
+    inline_bytes: i64,
+    compressed_bytes: i64,
+    toast_bytes: i64,
+    toast_values: i64,
+}
+
+/*
+ * Report per column how many bytes are stored inline, compressed inline and in the
+ * TOAST relation. All tuple versions (including invisible ones) are considered.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_toast_report(
+    table: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(column_name, String),
+        name!(inline_bytes, i64),
+        name!(compressed_bytes, i64),
+        name!(toast_bytes, i64),
+        name!(toast_values, i64),
+    ),
+> {
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+
+    let tupdesc = (*table_rel).rd_att;
+    let nattrs = (*tupdesc).natts as usize;
+    let mut columns: Vec<ColumnStorage> = (0..nattrs).map(|_| ColumnStorage::default()).collect();
+
+    scan_heap(
+        table_rel,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        |htup, tupdesc| {
+            let attrs = (*tupdesc).attrs.as_slice(nattrs);
+
+            for (attr_form_data, column) in attrs.iter().zip(columns.iter_mut()) {
+                if attr_form_data.attisdropped {
+                    continue;
+                }
+
+                let mut isnull: bool = false;
+                let attr =
+                    pg_sys::heap_getattr(htup, attr_form_data.attnum.into(), tupdesc, &mut isnull);
+
+                if isnull {
+                    continue;
+                }
+
+                match attr_form_data.attlen {
+                    -1 => match varlena_storage(attr.cast_mut_ptr::<pg_sys::varlena>()) {
+                        VarlenaStorage::Inline(size) => column.inline_bytes += size as i64,
+                        VarlenaStorage::Compressed(size) => column.compressed_bytes += size as i64,
+                        VarlenaStorage::External(toast_pointer) => {
+                            column.toast_bytes += toast_pointer.ext_size as i64;
+                            column.toast_values += 1;
+                        }
+                    },
+                    -2 => {
+                        let value = CStr::from_ptr(attr.cast_mut_ptr::<std::os::raw::c_char>());
+                        column.inline_bytes += value.to_bytes_with_nul().len() as i64;
+                    }
+                    attlen => column.inline_bytes += attlen as i64,
+                }
+            }
+        },
+    );
+
+    let mut results = Vec::new();
+    let attrs = (*tupdesc).attrs.as_slice(nattrs);
+
+    for (attr_form_data, column) in attrs.iter().zip(columns) {
+        if attr_form_data.attisdropped {
+            continue;
+        }
+
+        let colname_ptr = pg_sys::get_attname(relid, attr_form_data.attnum, false);
+        let colname = CStr::from_ptr(colname_ptr).to_str().unwrap().to_string();
+
+        results.push((
+            colname,
+            column.inline_bytes,
+            column.compressed_bytes,
+            column.toast_bytes,
+            column.toast_values,
+        ));
+    }
+
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+
+    TableIterator::new(results)
+}
+
+/* The opened TOAST relation of a table and its index */
+struct ToastRelation {
+    toast_rel: pg_sys::Relation,
+    toast_index: Oid,
+}
+
+impl ToastRelation {
+    /*
+     * Open the TOAST relation of the given table. None is returned if the
+     * table has no TOAST relation.
+     */
+    unsafe fn open(table_rel: pg_sys::Relation) -> Option<ToastRelation> {
+        let toast_relid = (*(*table_rel).rd_rel).reltoastrelid;
+
+        if toast_relid == pg_sys::InvalidOid {
+            return None;
+        }
+
+        Some(ToastRelation {
+            toast_rel: pg_sys::table_open(toast_relid, AccessShareLock as i32),
+            toast_index: toast_get_valid_index(toast_relid, AccessShareLock as pg_sys::LOCKMODE),
+        })
+    }
+
+    /*
+     * Sum up the size of all chunk tuples of the given TOAST value
+     */
+    unsafe fn value_size(&self, value_id: Oid) -> i64 {
+        let mut key = pg_sys::ScanKeyData::default();
+        pg_sys::ScanKeyInit(
+            &mut key,
+            1,
+            pg_sys::BTEqualStrategyNumber as pg_sys::StrategyNumber,
+            pg_sys::Oid::from(pg_sys::F_OIDEQ),
+            pg_sys::Datum::from(value_id.as_u32()),
+        );
+
+        let scan = pg_sys::systable_beginscan(
+            self.toast_rel,
+            self.toast_index,
+            true,
+            std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+            1,
+            &mut key,
+        );
+
+        let mut size = 0;
+        loop {
+            let chunk = pg_sys::systable_getnext(scan);
+            if chunk.is_null() {
+                break;
+            }
+            size += (*chunk).t_len as i64;
+        }
+
+        pg_sys::systable_endscan(scan);
+
+        size
+    }
+}
+
+impl Drop for ToastRelation {
+    fn drop(&mut self) {
+        if resources_releasable() {
+            unsafe { pg_sys::table_close(self.toast_rel, AccessShareLock as i32) };
+        }
+    }
+}
+
+/*
+ * Report the on-disk size of each tuple version. The size of the values stored
+ * in the TOAST relation is determined by summing up the sizes of their chunks.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_tuple_sizes(
+    table: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(tuple_bytes, i64),
+        name!(toast_bytes, i64),
+        name!(total_bytes, i64),
+    ),
+> {
+    let mut tuples = TupleStream::open(
+        table,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        None,
+        None,
+        None,
+    );
+    let toast_relation = ToastRelation::open(tuples.table_rel);
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    TableIterator::new(std::iter::from_fn(move || {
+        let (htup, tupdesc) = tuples.next_matching()?;
+        let nattrs = (*tupdesc).natts as usize;
+        let attrs = (*tupdesc).attrs.as_slice(nattrs);
+        let mut toast_bytes = 0;
+
+        for attr_form_data in attrs {
+            if attr_form_data.attisdropped || attr_form_data.attlen != -1 {
+                continue;
+            }
+
+            let mut isnull: bool = false;
+            let attr =
+                pg_sys::heap_getattr(htup, attr_form_data.attnum.into(), tupdesc, &mut isnull);
+
+            if isnull {
+                continue;
+            }
+
+            if let (VarlenaStorage::External(toast_pointer), Some(toast_relation)) = (
+                varlena_storage(attr.cast_mut_ptr::<pg_sys::varlena>()),
+                &toast_relation,
+            ) {
+                toast_bytes += toast_relation.value_size(toast_pointer.value_id);
+            }
+        }
+
+        let tuple_bytes = (*htup).t_len as i64;
+
+        Some((
+            (*htup).t_self,
+            Xid8::from_xid(pg_sys::HeapTupleHeaderGetXmin((*htup).t_data), next_xid),
+            Xid8::from_xid(HeapTupleHeaderGetXmax((*htup).t_data), next_xid),
+            tuple_bytes,
+            toast_bytes,
+            tuple_bytes + toast_bytes,
+        ))
+    }))
+}
+
+/*
+ * Collect the ids of all TOAST values that are referenced by a tuple version of
+ * the table, including invisible ones
+ */
+unsafe fn referenced_toast_values(table_rel: pg_sys::Relation) -> HashSet<Oid> {
+    let mut value_ids = HashSet::new();
+
+    scan_heap(
+        table_rel,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        |htup, tupdesc| {
+            let nattrs = (*tupdesc).natts as usize;
+            let attrs = (*tupdesc).attrs.as_slice(nattrs);
+
+            for attr_form_data in attrs {
+                if attr_form_data.attisdropped || attr_form_data.attlen != -1 {
+                    continue;
+                }
+
+                let mut isnull: bool = false;
+                let attr =
+                    pg_sys::heap_getattr(htup, attr_form_data.attnum.into(), tupdesc, &mut isnull);
+
+                if isnull {
+                    continue;
+                }
+
+                if let VarlenaStorage::External(toast_pointer) =
+                    varlena_storage(attr.cast_mut_ptr::<pg_sys::varlena>())
+                {
+                    value_ids.insert(toast_pointer.value_id);
+                }
+            }
+        },
+    );
+
+    value_ids
+}
+
+/*
+ * Scan the TOAST relation of the table and return all chunks together with their
+ * visibility under the snapshot. Chunks of values that are not referenced by any
+ * tuple version of the table are marked as orphaned. Orphaned or invisible chunks
+ * of visible values are a typical symptom of a corruption.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_toast_scan(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(chunk_id, i64),
+        name!(chunk_seq, i32),
+        name!(chunk_size, i32),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(visible, bool),
+        name!(orphaned, bool),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+    let toast_relid = (*(*table_rel).rd_rel).reltoastrelid;
+
+    if toast_relid == pg_sys::InvalidOid {
+        error!("Table {table} has no TOAST relation");
+    }
+
+    let referenced = referenced_toast_values(table_rel);
+
+    /* The lock on the table is kept until the end of the transaction */
+    pg_sys::table_close(table_rel, pg_sys::NoLock as i32);
+
+    /* Read all chunks in a single pass and check the visibility of each chunk */
+    let mut chunks = TupleStream::open_relation(
+        RegClass(toast_relid),
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        None,
+        None,
+        None,
+        None,
+        HeapScanOptions::default(),
+    );
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    TableIterator::new(std::iter::from_fn(move || {
+        let (htup, tupdesc) = chunks.next_matching()?;
+
+        /* The TOAST relation has the columns chunk_id, chunk_seq and chunk_data */
+        let mut isnull: bool = false;
+        let chunk_id = pg_sys::heap_getattr(htup, 1, tupdesc, &mut isnull);
+        let chunk_id = Oid::from(chunk_id.value() as u32);
+        let chunk_seq = pg_sys::heap_getattr(htup, 2, tupdesc, &mut isnull).value() as i32;
+        let chunk_data = pg_sys::heap_getattr(htup, 3, tupdesc, &mut isnull);
+        let chunk_size = varsize_any(chunk_data.cast_mut_ptr::<pg_sys::varlena>()) as i32;
+
+        Some((
+            (*htup).t_self,
+            i64::from(chunk_id.as_u32()),
+            chunk_seq,
+            chunk_size,
+            Xid8::from_xid(pg_sys::HeapTupleHeaderGetXmin((*htup).t_data), next_xid),
+            Xid8::from_xid(HeapTupleHeaderGetXmax((*htup).t_data), next_xid),
+            chunks.satisfies_snapshot(htup, snapshot_data),
+            !referenced.contains(&chunk_id),
+        ))
+    }))
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_toast_report() {
+        pgrx::Spi::run("CREATE TABLE toast_test (id int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO toast_test VALUES (1, 'small');").unwrap();
+        pgrx::Spi::run(
+            "INSERT INTO toast_test SELECT 2, string_agg(md5(i::text), '') FROM generate_series(1, 2000) i;",
+        )
+        .unwrap();
+
+        let toast_values = pgrx::Spi::get_one::<i64>(
+            "SELECT toast_values FROM pg_debug_toast_report('toast_test') WHERE column_name = 'value';",
+        )
+        .unwrap();
+        assert_eq!(toast_values, Some(1));
+
+        let inline_bytes = pgrx::Spi::get_one::<i64>(
+            "SELECT inline_bytes FROM pg_debug_toast_report('toast_test') WHERE column_name = 'id';",
+        )
+        .unwrap();
+        assert_eq!(inline_bytes, Some(8));
+    }
+
+    #[pgrx::pg_test]
+    fn test_tuple_sizes() {
+        pgrx::Spi::run("CREATE TABLE toast_test (id int, value text);").unwrap();
+        pgrx::Spi::run(
+            "INSERT INTO toast_test SELECT 1, string_agg(md5(i::text), '') FROM generate_series(1, 2000) i;",
+        )
+        .unwrap();
+
+        let toast_bytes = pgrx::Spi::get_one::<i64>(
+            "SELECT toast_bytes FROM pg_debug_tuple_sizes('toast_test');",
+        )
+        .unwrap()
+        .expect("unable to get toast size");
+        assert!(toast_bytes > 2000);
+    }
+
+    #[pgrx::pg_test]
+    fn test_toast_pointer_output() {
+        pgrx::Spi::run("CREATE TABLE toast_test (id int, value text);").unwrap();
+        pgrx::Spi::run(
+            "INSERT INTO toast_test SELECT 1, string_agg(md5(i::text), '') FROM generate_series(1, 2000) i;",
+        )
+        .unwrap();
+        pgrx::Spi::run("SET pg_debug_scan.detoast = off;").unwrap();
+
+        /* The raw size includes the varlena header */
+        let raw_size = pgrx::Spi::get_one::<String>(
+            "SELECT ((data::json->>'value')::json)->>'raw_size' FROM pg_debug_scan('toast_test');",
+        )
+        .unwrap();
+        assert_eq!(raw_size, Some("64004".to_string()));
+    }
+
+    #[pgrx::pg_test]
+    fn test_toast_scan() {
+        pgrx::Spi::run("CREATE TABLE toast_test (id int, value text);").unwrap();
+        pgrx::Spi::run("ALTER TABLE toast_test ALTER COLUMN value SET STORAGE EXTERNAL;").unwrap();
+        pgrx::Spi::run(
+            "INSERT INTO toast_test SELECT 1, string_agg(md5(i::text), '') FROM generate_series(1, 200) i;",
+        )
+        .unwrap();
+
+        /* 6400 bytes are split into chunks of about 2000 bytes */
+        let chunks = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_toast_scan('toast_test') WHERE visible AND NOT orphaned;",
+        )
+        .unwrap();
+        assert_eq!(chunks, Some(4));
+    }
+}
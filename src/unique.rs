@@ -0,0 +1,361 @@
+use std::cmp::Ordering;
+use std::ffi::CStr;
+
+use pgrx::{
+    pg_sys::{AccessShareLock, GetTransactionSnapshot},
+    prelude::*,
+};
+
+use crate::heap::{
+    open_heap_relation, page_get_heap_tuples, prefetch_ahead, with_locked_page,
+    HeapTupleHeaderGetXmax,
+};
+use crate::output::{format_tuple, output_datum, AttributeValue, OutputFormat};
+use crate::prune::get_prune_horizon;
+use crate::snapshot::get_snapshot_from_str;
+use crate::xid8::Xid8;
+use crate::{get_relid_from_name, scan_heap};
+
+/* A tuple version with the values of the key columns of the index */
+struct KeyVersion {
+    values: Vec<pg_sys::Datum>,
+    key: String,
+    ctid: pg_sys::ItemPointerData,
+    xmin: pg_sys::TransactionId,
+    xmax: pg_sys::TransactionId,
+}
+
+/*
+ * Compares keys with the operators of the index, so values that are equal for the
+ * index but have a different text representation (e.g., 1.0 and 1 of a numeric
+ * column) are treated as duplicates
+ */
+struct KeyComparator {
+    order_procs: Vec<*mut pg_sys::FmgrInfo>,
+    equal_procs: Vec<pg_sys::FmgrInfo>,
+    collations: Vec<pg_sys::Oid>,
+}
+
+impl KeyComparator {
+    unsafe fn new(index_rel: pg_sys::Relation, nkeys: usize) -> KeyComparator {
+        if (*(*index_rel).rd_rel).relam != pg_sys::BTREE_AM_OID {
+            error!("Only btree indexes are supported");
+        }
+
+        let mut order_procs = Vec::new();
+        let mut equal_procs = Vec::new();
+        let mut collations = Vec::new();
+
+        for i in 0..nkeys {
+            order_procs.push(pg_sys::index_getprocinfo(
+                index_rel,
+                (i + 1) as pg_sys::AttrNumber,
+                pg_sys::BTORDER_PROC as u16,
+            ));
+
+            let opfamily = *(*index_rel).rd_opfamily.add(i);
+            let opcintype = *(*index_rel).rd_opcintype.add(i);
+            let operator = pg_sys::get_opfamily_member(
+                opfamily,
+                opcintype,
+                opcintype,
+                pg_sys::BTEqualStrategyNumber as i16,
+            );
+
+            if operator == pg_sys::InvalidOid {
+                error!("No equality operator found for the key column {}", i + 1);
+            }
+
+            let mut equal_proc: pg_sys::FmgrInfo = std::mem::zeroed();
+            pg_sys::fmgr_info(pg_sys::get_opcode(operator), &mut equal_proc);
+            equal_procs.push(equal_proc);
+
+            collations.push(*(*index_rel).rd_indcollation.add(i));
+        }
+
+        KeyComparator {
+            order_procs,
+            equal_procs,
+            collations,
+        }
+    }
+
+    /* Compare the keys with the btree comparison support functions */
+    unsafe fn compare(&self, left: &[pg_sys::Datum], right: &[pg_sys::Datum]) -> Ordering {
+        for (i, order_proc) in self.order_procs.iter().enumerate() {
+            let result =
+                pg_sys::FunctionCall2Coll(*order_proc, self.collations[i], left[i], right[i]);
+
+            match (result.value() as i32).cmp(&0) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+
+        Ordering::Equal
+    }
+
+    /* Check the keys for equality with the equality operators of the index */
+    unsafe fn equal(&mut self, left: &[pg_sys::Datum], right: &[pg_sys::Datum]) -> bool {
+        self.equal_procs
+            .iter_mut()
+            .enumerate()
+            .all(|(i, equal_proc)| {
+                pg_sys::FunctionCall2Coll(equal_proc, self.collations[i], left[i], right[i]).value()
+                    != 0
+            })
+    }
+}
+
+/*
+ * Get the attribute numbers of the key columns of the unique index on the table
+ */
+unsafe fn unique_index_key_attnums(
+    index_rel: pg_sys::Relation,
+    relid: pg_sys::Oid,
+) -> Vec<pg_sys::AttrNumber> {
+    let index = (*index_rel).rd_index;
+
+    if index.is_null() || (*index).indrelid != relid {
+        error!("Index is not an index of the table");
+    }
+
+    if !(*index).indisunique {
+        error!("Index is not a unique index");
+    }
+
+    if !pg_sys::RelationGetIndexPredicate(index_rel).is_null() {
+        error!("Partial indexes are not supported");
+    }
+
+    let attnums = (*index)
+        .indkey
+        .values
+        .as_slice((*index).indnkeyatts as usize)
+        .to_vec();
+
+    if attnums.contains(&0) {
+        error!("Expression indexes are not supported");
+    }
+
+    attnums
+}
+
+/*
+ * Get the key of the tuple. The values are copied, since they are compared after
+ * the page of the tuple is released. None is returned if one of the key columns is
+ * NULL, since NULL values are not considered as duplicates.
+ */
+unsafe fn tuple_key_version(
+    htup: *mut pg_sys::HeapTupleData,
+    tupdesc: pg_sys::TupleDesc,
+    relid: pg_sys::Oid,
+    attnums: &[pg_sys::AttrNumber],
+) -> Option<KeyVersion> {
+    let mut values = Vec::new();
+    let mut attributes = Vec::new();
+
+    for attnum in attnums {
+        let attrs = (*tupdesc).attrs.as_slice((*tupdesc).natts as usize);
+        let attr_form_data = &attrs[*attnum as usize - 1];
+
+        let mut isnull: bool = false;
+        let datum = pg_sys::heap_getattr(htup, (*attnum).into(), tupdesc, &mut isnull);
+
+        if isnull {
+            return None;
+        }
+
+        let colname_ptr = pg_sys::get_attname(relid, *attnum, false);
+
+        values.push(pg_sys::datumCopy(
+            datum,
+            attr_form_data.attbyval,
+            attr_form_data.attlen.into(),
+        ));
+        attributes.push(AttributeValue {
+            name: CStr::from_ptr(colname_ptr).to_str().unwrap().to_string(),
+            typid: attr_form_data.atttypid,
+            value: Some(output_datum(attr_form_data.atttypid, datum)),
+        });
+    }
+
+    Some(KeyVersion {
+        values,
+        key: format_tuple(&attributes, OutputFormat::RecordCompatible),
+        ctid: (*htup).t_self,
+        xmin: pg_sys::HeapTupleHeaderGetXmin((*htup).t_data),
+        xmax: HeapTupleHeaderGetXmax((*htup).t_data),
+    })
+}
+
+/*
+ * Sort the versions by their key and return the versions of all keys that occur
+ * more than once
+ */
+unsafe fn duplicate_versions(
+    mut versions: Vec<KeyVersion>,
+    comparator: &mut KeyComparator,
+) -> Vec<KeyVersion> {
+    versions.sort_by(|left, right| comparator.compare(&left.values, &right.values));
+
+    let mut duplicates = Vec::new();
+    let mut group: Vec<KeyVersion> = Vec::new();
+
+    for version in versions {
+        if !group.is_empty() && !comparator.equal(&group[0].values, &version.values) {
+            if group.len() > 1 {
+                duplicates.append(&mut group);
+            }
+            group.clear();
+        }
+
+        group.push(version);
+    }
+
+    if group.len() > 1 {
+        duplicates.append(&mut group);
+    }
+
+    duplicates
+}
+
+/*
+ * Check if multiple tuple versions share the same key of a unique index. Two
+ * checks are performed: in mode 'snapshot' all tuples visible under the snapshot
+ * are checked; in mode 'any' all versions that are live according to the removal
+ * horizon (not deleted, or deleted by a transaction that is still in progress or
+ * aborted) are checked. The keys are compared with the operators of the index.
+ * Each version of a duplicate key is returned.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_unique_audit(
+    table: &str,
+    index: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(mode, String),
+        name!(key, String),
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+    let index_rel = pg_sys::index_open(get_relid_from_name(index), AccessShareLock as i32);
+    let attnums = unique_index_key_attnums(index_rel, relid);
+    let mut comparator = KeyComparator::new(index_rel, attnums.len());
+    let tupdesc = (*table_rel).rd_att;
+
+    /* The versions visible under the snapshot */
+    let mut snapshot_versions = Vec::new();
+    scan_heap(table_rel, snapshot_data, |htup, tupdesc| {
+        snapshot_versions.extend(tuple_key_version(htup, tupdesc, relid, &attnums));
+    });
+
+    /* All live versions */
+    let horizon = get_prune_horizon(table_rel);
+    let nblocks =
+        pg_sys::RelationGetNumberOfBlocksInFork(table_rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+    let mut live_versions = Vec::new();
+
+    for blkno in 0..nblocks {
+        prefetch_ahead(table_rel, blkno, nblocks);
+
+        with_locked_page(table_rel, blkno, |buffer, page| {
+            for mut htup in page_get_heap_tuples(relid, page, blkno) {
+                match pg_sys::HeapTupleSatisfiesVacuum(&mut htup, horizon, buffer) {
+                    pg_sys::HTSV_Result_HEAPTUPLE_LIVE
+                    | pg_sys::HTSV_Result_HEAPTUPLE_INSERT_IN_PROGRESS
+                    | pg_sys::HTSV_Result_HEAPTUPLE_DELETE_IN_PROGRESS => {
+                        live_versions.extend(tuple_key_version(&mut htup, tupdesc, relid, &attnums))
+                    }
+                    _ => {}
+                }
+            }
+        });
+    }
+
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut results = Vec::new();
+
+    for (mode, versions) in [("snapshot", snapshot_versions), ("any", live_versions)] {
+        for version in duplicate_versions(versions, &mut comparator) {
+            results.push((
+                mode.to_string(),
+                version.key,
+                version.ctid,
+                Xid8::from_xid(version.xmin, next_xid),
+                Xid8::from_xid(version.xmax, next_xid),
+            ));
+        }
+    }
+
+    pg_sys::index_close(index_rel, AccessShareLock as i32);
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+
+    TableIterator::new(results)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_unique_audit() {
+        pgrx::Spi::run("CREATE TABLE unique_test (id int, value text);").unwrap();
+        pgrx::Spi::run("CREATE INDEX unique_test_idx ON unique_test (id);").unwrap();
+        pgrx::Spi::run("INSERT INTO unique_test VALUES (1, 'a'), (1, 'b'), (2, 'c');").unwrap();
+        pgrx::Spi::run("UPDATE unique_test SET value = 'd' WHERE id = 2;").unwrap();
+
+        /* Simulate a broken unique index that contains duplicates */
+        pgrx::Spi::run(
+            "UPDATE pg_index SET indisunique = true WHERE indexrelid = 'unique_test_idx'::regclass;",
+        )
+        .unwrap();
+
+        let duplicates = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_unique_audit('unique_test', 'unique_test_idx') WHERE mode = 'snapshot';",
+        )
+        .unwrap();
+        assert_eq!(duplicates, Some(2));
+
+        /* The version deleted by the running update is still live */
+        let live_duplicates = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_unique_audit('unique_test', 'unique_test_idx') WHERE mode = 'any' AND key = '(2)';",
+        )
+        .unwrap();
+        assert_eq!(live_duplicates, Some(2));
+    }
+
+    #[pgrx::pg_test]
+    fn test_unique_audit_equal_values() {
+        pgrx::Spi::run("CREATE TABLE unique_numeric_test (id numeric);").unwrap();
+        pgrx::Spi::run("CREATE INDEX unique_numeric_test_idx ON unique_numeric_test (id);")
+            .unwrap();
+        pgrx::Spi::run("INSERT INTO unique_numeric_test VALUES (1.0), (1), (2);").unwrap();
+
+        pgrx::Spi::run(
+            "UPDATE pg_index SET indisunique = true WHERE indexrelid = 'unique_numeric_test_idx'::regclass;",
+        )
+        .unwrap();
+
+        /* 1.0 and 1 are equal for the index, even if they are printed differently */
+        let keys = pgrx::Spi::get_one::<String>(
+            "SELECT string_agg(key, ',' ORDER BY ctid) FROM pg_debug_unique_audit('unique_numeric_test', 'unique_numeric_test_idx') WHERE mode = 'snapshot';",
+        )
+        .unwrap();
+        assert_eq!(keys, Some("(1.0),(1)".to_string()));
+    }
+}
@@ -0,0 +1,396 @@
+use std::ffi::{CStr, CString};
+
+use pgrx::{itemptr::item_pointer_get_both, pg_sys::GetTransactionSnapshot, prelude::*};
+
+use crate::heap::{HeapTupleHeaderGetRawXmax, PageGetItem, PageGetItemId};
+use crate::output::output_datum;
+use crate::snapshot::get_snapshot_from_str;
+use crate::{try_in_subtransaction, TupleStream};
+
+/*
+ * Convert the text representation of a value back into a datum using the input
+ * function of the type and render it again
+ */
+unsafe fn roundtrip_value(value: &str, typid: pg_sys::Oid, typmod: i32) -> String {
+    let value_str = CString::new(value).expect("Unable to convert to string");
+
+    let mut typinput = pg_sys::Oid::default();
+    let mut typioparam = pg_sys::Oid::default();
+
+    pg_sys::getTypeInputInfo(typid, &mut typinput, &mut typioparam);
+    let datum = pg_sys::OidInputFunctionCall(
+        typinput,
+        value_str.as_ptr() as *mut std::os::raw::c_char,
+        typioparam,
+        typmod,
+    );
+
+    output_datum(typid, datum)
+}
+
+/* A value that does not round-trip (ctid, column_name, value_text, roundtrip_text, error) */
+type RoundtripFailure = (
+    pg_sys::ItemPointerData,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+/*
+ * Feed the values of the tuple through the output and input functions of their
+ * types. Both calls are made in a subtransaction per attribute, so a corrupted value
+ * is reported instead of aborting the verification.
+ */
+unsafe fn roundtrip_failures(
+    relid: pg_sys::Oid,
+    htup: *mut pg_sys::HeapTupleData,
+    tupdesc: pg_sys::TupleDesc,
+) -> Vec<RoundtripFailure> {
+    let nattrs = (*tupdesc).natts as usize;
+    let attrs = (*tupdesc).attrs.as_slice(nattrs);
+    let mut failures = Vec::new();
+
+    for attr_form_data in attrs {
+        if attr_form_data.attisdropped {
+            continue;
+        }
+
+        let mut isnull: bool = false;
+        let attr = pg_sys::heap_getattr(htup, attr_form_data.attnum.into(), tupdesc, &mut isnull);
+
+        if isnull {
+            continue;
+        }
+
+        let (value_text, roundtrip_text, error) =
+            match try_in_subtransaction(|| output_datum(attr_form_data.atttypid, attr)) {
+                Err(error) => (None, None, Some(error)),
+                Ok(value) => match try_in_subtransaction(|| {
+                    roundtrip_value(&value, attr_form_data.atttypid, attr_form_data.atttypmod)
+                }) {
+                    Ok(roundtrip_text) if roundtrip_text == value => continue,
+                    Ok(roundtrip_text) => (Some(value), Some(roundtrip_text), None),
+                    Err(error) => (Some(value), None, Some(error)),
+                },
+            };
+
+        let colname_ptr = pg_sys::get_attname(relid, attr_form_data.attnum, false);
+        let colname = CStr::from_ptr(colname_ptr).to_str().unwrap().to_string();
+
+        failures.push(((*htup).t_self, colname, value_text, roundtrip_text, error));
+    }
+
+    failures
+}
+
+/*
+ * Perform a table scan and feed the text representation of each value back through
+ * the input function of its type. Values that do not round-trip (the output or the
+ * input function fails or the value is rendered differently) are returned. This
+ * reveals corrupted values and bugs in output functions that a plain scan does not
+ * show.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_verify_roundtrip(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(column_name, String),
+        name!(value_text, Option<String>),
+        name!(roundtrip_text, Option<String>),
+        name!(error, Option<String>),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let mut tuples = TupleStream::open(table, snapshot_data, None, None, None);
+    let relid = (*tuples.table_rel).rd_id;
+
+    let rows = std::iter::from_fn(move || {
+        let (htup, tupdesc) = tuples.next_matching()?;
+        Some(tuples.in_tuple_context(|| roundtrip_failures(relid, htup, tupdesc)))
+    })
+    .flatten();
+
+    TableIterator::new(rows)
+}
+
+/* The size of the fixed part of a tuple header, see SizeofHeapTupleHeader in access/htup_details.h */
+const SIZEOF_HEAP_TUPLE_HEADER: usize = 23;
+
+/* The bounds of the transaction ids stored in the tuples of a relation */
+struct XidBounds {
+    relfrozenxid: pg_sys::TransactionId,
+    next_xid: pg_sys::TransactionId,
+}
+
+impl XidBounds {
+    /*
+     * Check that a normal xid does not precede relfrozenxid and does not follow the
+     * next xid. Special xids (e.g., the frozen xid) are always valid.
+     */
+    unsafe fn check(&self, xid: pg_sys::TransactionId) -> Option<String> {
+        if xid < pg_sys::FirstNormalTransactionId {
+            return None;
+        }
+
+        if self.relfrozenxid >= pg_sys::FirstNormalTransactionId
+            && pg_sys::TransactionIdPrecedes(xid, self.relfrozenxid)
+        {
+            return Some(format!(
+                "{xid} precedes the relfrozenxid {}",
+                self.relfrozenxid
+            ));
+        }
+
+        if !pg_sys::TransactionIdPrecedes(xid, self.next_xid) {
+            return Some(format!(
+                "{xid} equals or follows the next xid {}",
+                self.next_xid
+            ));
+        }
+
+        None
+    }
+}
+
+/*
+ * The checks of the tuple headers of a relation, similar to the checks of amcheck's
+ * verify_heapam. They are performed by pg_debug_scan with check => true.
+ */
+pub(crate) struct TupleCheck {
+    natts: usize,
+    nblocks: pg_sys::BlockNumber,
+    xid_bounds: XidBounds,
+}
+
+impl TupleCheck {
+    pub(crate) unsafe fn new(table_rel: pg_sys::Relation) -> TupleCheck {
+        TupleCheck {
+            natts: (*(*table_rel).rd_att).natts as usize,
+            nblocks: pg_sys::RelationGetNumberOfBlocksInFork(
+                table_rel,
+                pg_sys::ForkNumber_MAIN_FORKNUM,
+            ),
+            xid_bounds: XidBounds {
+                relfrozenxid: (*(*table_rel).rd_rel).relfrozenxid,
+                next_xid: pg_sys::ReadNextFullTransactionId().value as pg_sys::TransactionId,
+            },
+        }
+    }
+
+    /*
+     * Check the header of the tuple at the given line pointer. The line pointer is
+     * checked first, since the header can not be read if it points outside of the
+     * page. The name of each failed check is returned with a message.
+     */
+    pub(crate) unsafe fn check(
+        &self,
+        page: pg_sys::Page,
+        offnum: pg_sys::OffsetNumber,
+    ) -> Vec<(&'static str, String)> {
+        let itemid = PageGetItemId(page, offnum);
+        let lp_off = (*itemid).lp_off() as usize;
+        let lp_len = (*itemid).lp_len() as usize;
+
+        if lp_off % pg_sys::MAXIMUM_ALIGNOF as usize != 0
+            || lp_len < SIZEOF_HEAP_TUPLE_HEADER
+            || lp_off + lp_len > pg_sys::BLCKSZ as usize
+        {
+            return vec![(
+                "line_pointer",
+                format!("Line pointer with offset {lp_off} and length {lp_len} is invalid"),
+            )];
+        }
+
+        let tup = PageGetItem(page, itemid);
+        let infomask = (*tup).t_infomask as u32;
+        let mut violations = Vec::new();
+
+        let hoff = (*tup).t_hoff as usize;
+        if hoff < SIZEOF_HEAP_TUPLE_HEADER
+            || hoff > lp_len
+            || hoff % pg_sys::MAXIMUM_ALIGNOF as usize != 0
+        {
+            violations.push((
+                "hoff",
+                format!("t_hoff {hoff} is invalid for a tuple of length {lp_len}"),
+            ));
+        }
+
+        if infomask & pg_sys::HEAP_XMAX_IS_MULTI != 0 && infomask & pg_sys::HEAP_XMAX_COMMITTED != 0
+        {
+            violations.push((
+                "infomask",
+                "xmax is a multixact, but marked as committed".to_string(),
+            ));
+        }
+
+        if infomask & pg_sys::HEAP_XMAX_LOCK_ONLY != 0
+            && (*tup).t_infomask2 as u32 & pg_sys::HEAP_KEYS_UPDATED != 0
+        {
+            violations.push((
+                "infomask",
+                "xmax only locks the tuple, but the key columns are marked as updated".to_string(),
+            ));
+        }
+
+        let tuple_natts = ((*tup).t_infomask2 as u32 & pg_sys::HEAP_NATTS_MASK) as usize;
+        if tuple_natts > self.natts {
+            violations.push((
+                "natts",
+                format!(
+                    "Tuple has {tuple_natts} attributes, but the relation only {}",
+                    self.natts
+                ),
+            ));
+        }
+
+        if !pg_sys::HeapTupleHeaderFrozen(tup) {
+            if let Some(message) = self.xid_bounds.check((*tup).t_choice.t_heap.t_xmin) {
+                violations.push(("xmin", format!("xmin {message}")));
+            }
+        }
+
+        if infomask & pg_sys::HEAP_XMAX_INVALID == 0 && infomask & pg_sys::HEAP_XMAX_IS_MULTI == 0 {
+            if let Some(message) = self.xid_bounds.check(HeapTupleHeaderGetRawXmax(tup)) {
+                violations.push(("xmax", format!("xmax {message}")));
+            }
+        }
+
+        let (ctid_blkno, ctid_offnum) = item_pointer_get_both((*tup).t_ctid);
+        if ctid_blkno >= self.nblocks || ctid_offnum == 0 {
+            violations.push((
+                "ctid",
+                format!("t_ctid ({ctid_blkno},{ctid_offnum}) points outside of the relation"),
+            ));
+        }
+
+        violations
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_verify_roundtrip() {
+        pgrx::Spi::run(
+            "CREATE TABLE roundtrip_test (id int, value text, amount numeric(10, 2), time timestamptz);",
+        )
+        .unwrap();
+        pgrx::Spi::run(
+            "INSERT INTO roundtrip_test VALUES (1, E'a\\nb', 12.5, '2024-04-12 15:59:23+02'), (2, NULL, NULL, NULL);",
+        )
+        .unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_verify_roundtrip('roundtrip_test');",
+        )
+        .unwrap();
+        assert_eq!(count, Some(0));
+    }
+
+    #[pgrx::pg_test]
+    fn test_verify_roundtrip_corrupted() {
+        pgrx::Spi::run("CREATE TABLE roundtrip_corrupted_test (id int, value text);").unwrap();
+        pgrx::Spi::run(
+            "INSERT INTO roundtrip_corrupted_test VALUES (1, repeat('x', 200)), (2, 'b');",
+        )
+        .unwrap();
+
+        /* Mark the inline value of the first tuple as compressed, so it can not be decoded */
+        unsafe {
+            crate::heap::modify_page("roundtrip_corrupted_test", 0, |page| {
+                let tup = crate::heap::PageGetItem(page, crate::heap::PageGetItemId(page, 1));
+                let value = (tup as *mut u8).add((*tup).t_hoff as usize + 4);
+                *value = (*value & !0x03) | 0x02;
+            });
+        }
+
+        let (ctid, value_text, error) = pgrx::Spi::get_three::<String, String, String>(
+            "SELECT ctid::text, value_text, error FROM pg_debug_verify_roundtrip('roundtrip_corrupted_test');",
+        )
+        .unwrap();
+        assert_eq!(ctid, Some("(0,1)".to_string()));
+        assert_eq!(value_text, None);
+        assert!(error.is_some());
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_check() {
+        pgrx::Spi::run("CREATE TABLE scan_check_test (id int, value text);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_check_test VALUES (1, 'a'), (2, NULL);").unwrap();
+        pgrx::Spi::run("UPDATE scan_check_test SET value = 'b' WHERE id = 1;").unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('scan_check_test', snapshot_mode => 'any', check => true) WHERE violations <> '{}';",
+        )
+        .unwrap();
+        assert_eq!(count, Some(0));
+
+        /* Without the option, the headers are not checked */
+        let unchecked = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan('scan_check_test') WHERE violations IS NULL;",
+        )
+        .unwrap();
+        assert_eq!(unchecked, Some(2));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_check_corrupted() {
+        pgrx::Spi::run("CREATE TABLE scan_check_corrupted_test (id int, value text);").unwrap();
+        pgrx::Spi::run(
+            "INSERT INTO scan_check_corrupted_test VALUES (1, 'a'), (2, 'b'), (3, 'c');",
+        )
+        .unwrap();
+
+        /* Write an impossible t_hoff into the first and an impossible infomask into the second tuple */
+        unsafe {
+            crate::heap::modify_page("scan_check_corrupted_test", 0, |page| {
+                let tup = crate::heap::PageGetItem(page, crate::heap::PageGetItemId(page, 1));
+                (*tup).t_hoff = 3;
+
+                let tup = crate::heap::PageGetItem(page, crate::heap::PageGetItemId(page, 2));
+                (*tup).t_infomask |=
+                    (pg_sys::HEAP_XMAX_IS_MULTI | pg_sys::HEAP_XMAX_COMMITTED) as u16;
+            });
+        }
+
+        let hoff = pgrx::Spi::get_one::<bool>(
+            "SELECT violations[1] LIKE 'hoff: t_hoff 3 is invalid for a tuple of length %' AND cardinality(violations) = 1 \
+             FROM pg_debug_scan('scan_check_corrupted_test', snapshot_mode => 'any', check => true) WHERE ctid = '(0,1)';",
+        )
+        .unwrap();
+        assert_eq!(hoff, Some(true));
+
+        let infomask = pgrx::Spi::get_one::<Vec<String>>(
+            "SELECT violations FROM pg_debug_scan('scan_check_corrupted_test', snapshot_mode => 'any', check => true) WHERE ctid = '(0,2)';",
+        )
+        .unwrap();
+        assert_eq!(
+            infomask,
+            Some(vec![
+                "infomask: xmax is a multixact, but marked as committed".to_string()
+            ])
+        );
+
+        /* The intact tuple is still decoded */
+        let data = pgrx::Spi::get_one::<String>(
+            "SELECT data FROM pg_debug_scan('scan_check_corrupted_test', snapshot_mode => 'any', check => true) WHERE violations = '{}';",
+        )
+        .unwrap();
+        assert_eq!(data, Some("{\"id\":\"3\",\"value\":\"c\"}".to_string()));
+    }
+}
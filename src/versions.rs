@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+
+use pgrx::{itemptr::item_pointer_get_both, prelude::*};
+
+use crate::heap::deleted_by_inserting_transaction;
+use crate::output::{format_tuple, AttributeValue, OutputFormat};
+use crate::xid8::Xid8;
+use crate::{decode_attribute_or_error, ScannedTuple, TupleStream};
+
+/*
+ * Compare the attributes of two versions of a tuple and return the name, the old
+ * and the new value of each attribute that has changed
+ */
+pub(crate) fn changed_attributes(
+    old: &[AttributeValue],
+    new: &[AttributeValue],
+) -> Vec<(String, Option<String>, Option<String>)> {
+    let mut changes = Vec::new();
+
+    for new_attribute in new {
+        let old_value = old
+            .iter()
+            .find(|old_attribute| old_attribute.name == new_attribute.name)
+            .and_then(|old_attribute| old_attribute.value.clone());
+
+        if old_value != new_attribute.value {
+            changes.push((
+                new_attribute.name.clone(),
+                old_value,
+                new_attribute.value.clone(),
+            ));
+        }
+    }
+
+    changes
+}
+
+/*
+ * Find all versions of the tuples whose key column has the given value (including
+ * invisible ones) together with their visibility under the given snapshot. Only the
+ * key column is decoded to check a version, the whole version is decoded if it
+ * matches.
+ */
+pub(crate) unsafe fn key_versions(
+    table: &str,
+    key_column: &str,
+    key_value: &str,
+    snapshot_data: *mut pg_sys::SnapshotData,
+) -> impl Iterator<Item = (ScannedTuple, bool)> {
+    let mut tuples = TupleStream::open(
+        table,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        None,
+        None,
+        None,
+    );
+
+    let column_name = CString::new(key_column).expect("Unable to convert to string");
+    let attnum = pg_sys::get_attnum((*tuples.table_rel).rd_id, column_name.as_ptr());
+
+    if attnum <= 0 {
+        error!("Column {key_column} does not exist");
+    }
+
+    let key_value = key_value.to_string();
+
+    std::iter::from_fn(move || loop {
+        let (htup, tupdesc) = tuples.next_matching()?;
+        let attrs = (*tupdesc).attrs.as_slice((*tupdesc).natts as usize);
+
+        let (_, value) = tuples.in_tuple_context(|| {
+            decode_attribute_or_error(htup, tupdesc, &attrs[attnum as usize - 1])
+        });
+
+        if value.as_deref() == Some(key_value.as_str()) {
+            let visible = tuples.satisfies_snapshot(htup, snapshot_data);
+            return Some((tuples.decode(htup, tupdesc), visible));
+        }
+    })
+}
+
+/*
+ * Determine for each version the position of its successor in the update chain.
+ * The successor is None for the newest version or if the successor is not part
+ * of the given versions.
+ */
+pub(crate) fn successors(versions: &[ScannedTuple]) -> Vec<Option<usize>> {
+    let positions: HashMap<_, _> = versions
+        .iter()
+        .enumerate()
+        .map(|(position, version)| (item_pointer_get_both(version.ctid), position))
+        .collect();
+
+    versions
+        .iter()
+        .map(|version| {
+            /* The newest version of a tuple points to itself */
+            let next = item_pointer_get_both(version.next_ctid);
+            if next == item_pointer_get_both(version.ctid) {
+                return None;
+            }
+
+            positions.get(&next).copied()
+        })
+        .collect()
+}
+
+/*
+ * Find all versions of the tuples whose key column has the given value (including
+ * invisible ones) and compare each version with its successor in the update chain.
+ * Only the columns whose values changed are returned.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_key_diff(
+    table: &str,
+    key_column: &str,
+    key_value: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(next_ctid, pg_sys::ItemPointerData),
+        name!(xmax, Xid8),
+        name!(column_name, String),
+        name!(old_value, Option<String>),
+        name!(new_value, Option<String>),
+    ),
+> {
+    let versions: Vec<ScannedTuple> = key_versions(
+        table,
+        key_column,
+        key_value,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+    )
+    .map(|(version, _)| version)
+    .collect();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut results = Vec::new();
+
+    for (version, successor) in versions.iter().zip(successors(&versions)) {
+        let successor = match successor {
+            Some(position) => &versions[position],
+            None => continue,
+        };
+
+        for (column_name, old_value, new_value) in
+            changed_attributes(&version.attributes, &successor.attributes)
+        {
+            results.push((
+                version.ctid,
+                successor.ctid,
+                Xid8::from_xid(version.xmax, next_xid),
+                column_name,
+                old_value,
+                new_value,
+            ));
+        }
+    }
+
+    TableIterator::new(results)
+}
+
+/*
+ * Like pg_debug_key_diff, but return one row per step in the update chain with
+ * the names of the changed columns. This gives a compact change history of a key.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_key_changes(
+    table: &str,
+    key_column: &str,
+    key_value: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(next_ctid, pg_sys::ItemPointerData),
+        name!(xmax, Xid8),
+        name!(changed_columns, Vec<String>),
+    ),
+> {
+    let versions: Vec<ScannedTuple> = key_versions(
+        table,
+        key_column,
+        key_value,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+    )
+    .map(|(version, _)| version)
+    .collect();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut results = Vec::new();
+
+    for (version, successor) in versions.iter().zip(successors(&versions)) {
+        let successor = match successor {
+            Some(position) => &versions[position],
+            None => continue,
+        };
+
+        let changed_columns = changed_attributes(&version.attributes, &successor.attributes)
+            .into_iter()
+            .map(|(column_name, _, _)| column_name)
+            .collect();
+
+        results.push((
+            version.ctid,
+            successor.ctid,
+            Xid8::from_xid(version.xmax, next_xid),
+            changed_columns,
+        ));
+    }
+
+    TableIterator::new(results)
+}
+
+/*
+ * Return all tuple versions of the table (including invisible ones). The column
+ * same_xact_delete marks versions that were deleted or updated by the transaction
+ * that inserted them. These versions never become visible to other transactions,
+ * but still consume space.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_versions(
+    table: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(same_xact_delete, bool),
+        name!(data, String),
+    ),
+> {
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+    let mut tuples = TupleStream::open(
+        table,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        None,
+        None,
+        None,
+    );
+
+    let rows = std::iter::from_fn(move || {
+        let (htup, tupdesc) = tuples.next_matching()?;
+        let same_xact_delete = deleted_by_inserting_transaction((*htup).t_data);
+        let tuple = tuples.decode(htup, tupdesc);
+
+        Some((
+            tuple.ctid,
+            Xid8::from_xid(tuple.xmin, next_xid),
+            Xid8::from_xid(tuple.xmax, next_xid),
+            same_xact_delete,
+            format_tuple(&tuple.attributes, output_format),
+        ))
+    });
+
+    TableIterator::new(rows)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_key_diff() {
+        pgrx::Spi::run("CREATE TABLE key_diff_test (id int, name text, value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO key_diff_test VALUES (1, 'a', 10);").unwrap();
+        pgrx::Spi::run("UPDATE key_diff_test SET name = 'b' WHERE id = 1;").unwrap();
+
+        let changes = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_key_diff('key_diff_test', 'id', '1');",
+        )
+        .unwrap();
+        assert_eq!(changes, Some(1));
+
+        let new_value = pgrx::Spi::get_one::<String>(
+            "SELECT new_value FROM pg_debug_key_diff('key_diff_test', 'id', '1') WHERE column_name = 'name';",
+        )
+        .unwrap();
+        assert_eq!(new_value, Some("b".to_string()));
+    }
+
+    #[pgrx::pg_test]
+    fn test_key_changes() {
+        pgrx::Spi::run("CREATE TABLE key_changes_test (id int, name text, value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO key_changes_test VALUES (1, 'a', 10);").unwrap();
+        pgrx::Spi::run("UPDATE key_changes_test SET name = 'b', value = 20 WHERE id = 1;").unwrap();
+
+        let changed_columns = pgrx::Spi::get_one::<Vec<String>>(
+            "SELECT changed_columns FROM pg_debug_key_changes('key_changes_test', 'id', '1');",
+        )
+        .unwrap();
+        assert_eq!(
+            changed_columns,
+            Some(vec!["name".to_string(), "value".to_string()])
+        );
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_versions_same_xact_delete() {
+        pgrx::Spi::run("CREATE TABLE versions_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO versions_test VALUES (1), (2);").unwrap();
+        pgrx::Spi::run("DELETE FROM versions_test WHERE value = 1;").unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan_versions('versions_test') WHERE same_xact_delete;",
+        )
+        .unwrap();
+        assert_eq!(count, Some(1));
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan_versions('versions_test');",
+        )
+        .unwrap();
+        assert_eq!(count, Some(2));
+    }
+}
@@ -0,0 +1,864 @@
+use pgrx::{
+    itemptr::item_pointer_get_both,
+    pg_sys::{AccessShareLock, GetTransactionSnapshot, SnapshotData, TransactionId},
+    prelude::*,
+};
+
+use crate::header::infomask_flags;
+use crate::heap::{
+    open_heap_relation, page_get_heap_tuple, with_locked_page, HeapTupleHeaderGetUpdateXid,
+    HeapTupleHeaderGetXmax, PageGetItemId, PageGetMaxOffsetNumber, HEAP_XMAX_IS_LOCKED_ONLY,
+};
+use crate::output::{format_tuple, OutputFormat};
+use crate::snapshot::get_snapshot_from_str;
+use crate::xid8::Xid8;
+use crate::{decode_tuple, get_relid_from_name, TupleStream};
+
+#[pg_guard]
+extern "C" {
+    /* Not exported by pgrx, see access/subtrans.h */
+    fn SubTransGetTopmostTransaction(xid: TransactionId) -> TransactionId;
+}
+
+/*
+ * Check if the transaction is considered as running by the snapshot, see
+ * XidInMVCCSnapshot()
+ */
+pub(crate) unsafe fn xid_in_snapshot(xid: TransactionId, snapshot_data: *mut SnapshotData) -> bool {
+    if pg_sys::TransactionIdPrecedes(xid, (*snapshot_data).xmin) {
+        return false;
+    }
+
+    if !pg_sys::TransactionIdPrecedes(xid, (*snapshot_data).xmax) {
+        return true;
+    }
+
+    let xip = std::slice::from_raw_parts((*snapshot_data).xip, (*snapshot_data).xcnt as usize);
+    let subxip =
+        std::slice::from_raw_parts((*snapshot_data).subxip, (*snapshot_data).subxcnt as usize);
+
+    /* The subtransactions are not known, so check the top-level transaction */
+    let xid = if (*snapshot_data).suboverflowed {
+        SubTransGetTopmostTransaction(xid)
+    } else {
+        xid
+    };
+
+    xip.contains(&xid) || subxip.contains(&xid)
+}
+
+/*
+ * Describe the state of a transaction that is not considered as running by the
+ * snapshot
+ */
+unsafe fn finished_xid_state(xid: TransactionId) -> &'static str {
+    if pg_sys::TransactionIdDidCommit(xid) {
+        "committed"
+    } else if pg_sys::TransactionIdIsInProgress(xid) {
+        "still in progress, but not part of the snapshot"
+    } else {
+        "aborted"
+    }
+}
+
+/*
+ * Get the commit status of the given transaction
+ */
+pub(crate) unsafe fn xid_status(xid: TransactionId) -> Option<String> {
+    if xid == pg_sys::InvalidTransactionId {
+        return None;
+    }
+
+    let status = if pg_sys::TransactionIdIsCurrentTransactionId(xid)
+        || pg_sys::TransactionIdIsInProgress(xid)
+    {
+        "in progress"
+    } else if pg_sys::TransactionIdDidCommit(xid) {
+        "committed"
+    } else {
+        "aborted"
+    };
+
+    Some(status.to_string())
+}
+
+/*
+ * Get the commit status of the transaction that deleted or updated the tuple.
+ * Multixacts that only lock the tuple have no status.
+ */
+unsafe fn xmax_status(tup: pg_sys::HeapTupleHeader) -> Option<String> {
+    let infomask = (*tup).t_infomask;
+
+    if infomask as u32 & pg_sys::HEAP_XMAX_INVALID != 0 {
+        return None;
+    }
+
+    if infomask as u32 & pg_sys::HEAP_XMAX_IS_MULTI != 0 && HEAP_XMAX_IS_LOCKED_ONLY(infomask) {
+        return None;
+    }
+
+    xid_status(HeapTupleHeaderGetUpdateXid(tup))
+}
+
+/*
+ * Explain why the tuple is visible or invisible under the snapshot
+ */
+pub(crate) unsafe fn visibility_reason(
+    tup: pg_sys::HeapTupleHeader,
+    snapshot_data: *mut SnapshotData,
+    visible: bool,
+) -> String {
+    let xmin = pg_sys::HeapTupleHeaderGetXmin(tup);
+
+    if pg_sys::TransactionIdIsCurrentTransactionId(xmin) {
+        if !visible {
+            return "Inserted by a later command of the current transaction".to_string();
+        }
+    } else if xid_in_snapshot(xmin, snapshot_data) {
+        return format!("Inserting transaction {xmin} is running for the snapshot");
+    } else if !pg_sys::TransactionIdDidCommit(xmin) {
+        return format!(
+            "Inserting transaction {xmin} is {}",
+            finished_xid_state(xmin)
+        );
+    }
+
+    let infomask = (*tup).t_infomask;
+
+    if infomask as u32 & pg_sys::HEAP_XMAX_INVALID != 0
+        || HeapTupleHeaderGetXmax(tup) == pg_sys::InvalidTransactionId
+    {
+        return format!("Inserted by transaction {xmin} and not deleted");
+    }
+
+    if HEAP_XMAX_IS_LOCKED_ONLY(infomask) {
+        return format!("Inserted by transaction {xmin} and only locked");
+    }
+
+    let xmax = HeapTupleHeaderGetUpdateXid(tup);
+
+    if pg_sys::TransactionIdIsCurrentTransactionId(xmax) {
+        if visible {
+            format!("Deleted by a later command of the current transaction {xmax}")
+        } else {
+            format!("Deleted by the current transaction {xmax}")
+        }
+    } else if xid_in_snapshot(xmax, snapshot_data) {
+        format!("Deleting transaction {xmax} is running for the snapshot")
+    } else {
+        format!(
+            "Deleting transaction {xmax} is {}",
+            finished_xid_state(xmax)
+        )
+    }
+}
+
+/*
+ * Check if a tuple deleted by the current transaction is still visible to the
+ * command of the snapshot, i.e., it was deleted by a later command
+ */
+unsafe fn deleted_by_later_command(
+    tup: pg_sys::HeapTupleHeader,
+    snapshot_data: *mut SnapshotData,
+    xmax: TransactionId,
+    steps: &mut Vec<String>,
+) -> bool {
+    let cmax = pg_sys::HeapTupleHeaderGetCmax(tup);
+
+    if cmax >= (*snapshot_data).curcid {
+        steps.push(format!(
+            "Deleted by the current transaction {xmax} in command {cmax}, which is not before the command {} of the snapshot: visible",
+            (*snapshot_data).curcid
+        ));
+        true
+    } else {
+        steps.push(format!(
+            "Deleted by the current transaction {xmax} in command {cmax}, which is before the command {} of the snapshot: invisible",
+            (*snapshot_data).curcid
+        ));
+        false
+    }
+}
+
+/*
+ * Replay the checks of HeapTupleSatisfiesMVCC for the tuple and record each check
+ * that was made. The hint bits are read, but not set. The tuples moved by
+ * VACUUM FULL before PostgreSQL 9.0 are not handled.
+ */
+pub(crate) unsafe fn visibility_steps(
+    tup: pg_sys::HeapTupleHeader,
+    snapshot_data: *mut SnapshotData,
+) -> Vec<String> {
+    let mut steps = Vec::new();
+    mvcc_visibility(tup, snapshot_data, &mut steps);
+    steps
+}
+
+/*
+ * See visibility_steps, the verdict is returned and the steps are added to steps
+ */
+unsafe fn mvcc_visibility(
+    tup: pg_sys::HeapTupleHeader,
+    snapshot_data: *mut SnapshotData,
+    steps: &mut Vec<String>,
+) -> bool {
+    let infomask = (*tup).t_infomask;
+    let xmin = (*tup).t_choice.t_heap.t_xmin;
+    let xmin_committed = infomask as u32 & pg_sys::HEAP_XMIN_COMMITTED != 0;
+    let xmin_invalid = infomask as u32 & pg_sys::HEAP_XMIN_INVALID != 0;
+    let xmax_invalid = infomask as u32 & pg_sys::HEAP_XMAX_INVALID != 0;
+    let xmax_is_multi = infomask as u32 & pg_sys::HEAP_XMAX_IS_MULTI != 0;
+
+    if !xmin_committed {
+        steps.push(format!(
+            "xmin {xmin} is not marked as committed (HEAP_XMIN_COMMITTED)"
+        ));
+
+        if xmin_invalid {
+            steps.push(format!(
+                "xmin {xmin} is marked as aborted (HEAP_XMIN_INVALID): invisible"
+            ));
+            return false;
+        }
+
+        if pg_sys::TransactionIdIsCurrentTransactionId(xmin) {
+            let cmin = pg_sys::HeapTupleHeaderGetCmin(tup);
+
+            if cmin >= (*snapshot_data).curcid {
+                steps.push(format!(
+                    "Inserted by the current transaction {xmin} in command {cmin}, which is not before the command {} of the snapshot: invisible",
+                    (*snapshot_data).curcid
+                ));
+                return false;
+            }
+
+            steps.push(format!(
+                "Inserted by the current transaction {xmin} in command {cmin}, which is before the command {} of the snapshot",
+                (*snapshot_data).curcid
+            ));
+
+            if xmax_invalid {
+                steps.push("xmax is invalid (HEAP_XMAX_INVALID): visible".to_string());
+                return true;
+            }
+
+            if HEAP_XMAX_IS_LOCKED_ONLY(infomask) {
+                steps.push("xmax only locks the tuple: visible".to_string());
+                return true;
+            }
+
+            let xmax = HeapTupleHeaderGetUpdateXid(tup);
+
+            if !pg_sys::TransactionIdIsCurrentTransactionId(xmax) {
+                steps.push(format!(
+                    "The deleting subtransaction {xmax} of the current transaction has aborted: visible"
+                ));
+                return true;
+            }
+
+            return deleted_by_later_command(tup, snapshot_data, xmax, steps);
+        }
+
+        if xid_in_snapshot(xmin, snapshot_data) {
+            steps.push(format!(
+                "Inserting transaction {xmin} is running for the snapshot: invisible"
+            ));
+            return false;
+        }
+
+        if !pg_sys::TransactionIdDidCommit(xmin) {
+            steps.push(format!(
+                "Inserting transaction {xmin} is not committed according to the commit log, it has aborted or crashed: invisible"
+            ));
+            return false;
+        }
+
+        steps.push(format!(
+            "Inserting transaction {xmin} is committed according to the commit log"
+        ));
+    } else if xmin_invalid {
+        steps.push(format!(
+            "xmin {xmin} is marked as frozen (HEAP_XMIN_FROZEN)"
+        ));
+    } else {
+        steps.push(format!(
+            "xmin {xmin} is marked as committed (HEAP_XMIN_COMMITTED)"
+        ));
+
+        if xid_in_snapshot(xmin, snapshot_data) {
+            steps.push(format!(
+                "Inserting transaction {xmin} is running for the snapshot: invisible"
+            ));
+            return false;
+        }
+
+        steps.push(format!(
+            "Inserting transaction {xmin} is not running for the snapshot"
+        ));
+    }
+
+    if xmax_invalid {
+        steps.push("xmax is invalid (HEAP_XMAX_INVALID): visible".to_string());
+        return true;
+    }
+
+    if HEAP_XMAX_IS_LOCKED_ONLY(infomask) {
+        steps.push("xmax only locks the tuple: visible".to_string());
+        return true;
+    }
+
+    let xmax = HeapTupleHeaderGetUpdateXid(tup);
+
+    if xmax_is_multi {
+        steps.push(format!(
+            "xmax is a multixact, its updating transaction is {xmax}"
+        ));
+    }
+
+    if xmax_is_multi || infomask as u32 & pg_sys::HEAP_XMAX_COMMITTED == 0 {
+        if !xmax_is_multi {
+            steps.push(format!(
+                "xmax {xmax} is not marked as committed (HEAP_XMAX_COMMITTED)"
+            ));
+        }
+
+        if pg_sys::TransactionIdIsCurrentTransactionId(xmax) {
+            return deleted_by_later_command(tup, snapshot_data, xmax, steps);
+        }
+
+        if xid_in_snapshot(xmax, snapshot_data) {
+            steps.push(format!(
+                "Deleting transaction {xmax} is running for the snapshot: visible"
+            ));
+            return true;
+        }
+
+        if !pg_sys::TransactionIdDidCommit(xmax) {
+            steps.push(format!(
+                "Deleting transaction {xmax} is not committed according to the commit log, it has aborted or crashed: visible"
+            ));
+            return true;
+        }
+
+        steps.push(format!(
+            "Deleting transaction {xmax} is committed according to the commit log: invisible"
+        ));
+        return false;
+    }
+
+    steps.push(format!(
+        "xmax {xmax} is marked as committed (HEAP_XMAX_COMMITTED)"
+    ));
+
+    if xid_in_snapshot(xmax, snapshot_data) {
+        steps.push(format!(
+            "Deleting transaction {xmax} is running for the snapshot: visible"
+        ));
+        return true;
+    }
+
+    steps.push(format!(
+        "Deleting transaction {xmax} is not running for the snapshot: invisible"
+    ));
+    false
+}
+
+/*
+ * Read a single tuple by its ctid, check it against the snapshot, and explain the
+ * verdict step by step, following the checks of HeapTupleSatisfiesMVCC. Only the
+ * block of the tuple is read, so this is much cheaper than a scan if the ctid is
+ * known, e.g., from an error message.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_tuple(
+    table: &str,
+    tid: pg_sys::ItemPointerData,
+    snapshot: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(flags, Vec<String>),
+        name!(visible, bool),
+        name!(steps, Vec<String>),
+        name!(data, String),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    let (blkno, offnum) = item_pointer_get_both(tid);
+
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+
+    let nblocks =
+        pg_sys::RelationGetNumberOfBlocksInFork(table_rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+    if blkno >= nblocks {
+        error!("Block {blkno} is out of range, the table has {nblocks} blocks");
+    }
+
+    let (htup, visible, steps) = with_locked_page(table_rel, blkno, |buffer, page| {
+        if offnum < 1 || offnum > PageGetMaxOffsetNumber(page) {
+            error!("Offset {offnum} is out of range in block {blkno}");
+        }
+
+        let itemid = PageGetItemId(page, offnum);
+        if (*itemid).lp_flags() == pg_sys::LP_REDIRECT {
+            error!(
+                "Line pointer ({blkno},{offnum}) redirects to ({blkno},{})",
+                (*itemid).lp_off()
+            );
+        }
+
+        if (*itemid).lp_flags() != pg_sys::LP_NORMAL {
+            error!("Line pointer ({blkno},{offnum}) does not point to a tuple");
+        }
+
+        let mut htup = page_get_heap_tuple(relid, page, blkno, offnum);
+        let steps = visibility_steps(htup.t_data, snapshot_data);
+        let visible = pg_sys::HeapTupleSatisfiesVisibility(&mut htup, snapshot_data, buffer);
+
+        /* Copy the tuple, since the page is released afterwards */
+        (pg_sys::heap_copytuple(&mut htup), visible, steps)
+    });
+
+    let tup = (*htup).t_data;
+    let tupdesc = (*table_rel).rd_att;
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    let row = (
+        (*htup).t_self,
+        Xid8::from_xid(pg_sys::HeapTupleHeaderGetXmin(tup), next_xid),
+        Xid8::from_xid(HeapTupleHeaderGetXmax(tup), next_xid),
+        infomask_flags((*tup).t_infomask, (*tup).t_infomask2),
+        visible,
+        steps,
+        format_tuple(
+            &decode_tuple(relid, htup, tupdesc),
+            OutputFormat::from_guc(),
+        ),
+    );
+
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+
+    TableIterator::once(row)
+}
+
+/*
+ * Return all tuples of the table, including the ones that are invisible under the
+ * snapshot, together with the visibility verdict and its reason. This helps to
+ * answer why a tuple is (not) visible under a snapshot. With explain_visibility, the
+ * decision path of the visibility check is returned as well, see visibility_steps.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_visibility(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+    xids: default!(Option<Vec<i64>>, "NULL"),
+    explain_visibility: default!(bool, false),
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(xmin_status, Option<String>),
+        name!(xmax_status, Option<String>),
+        name!(visible, bool),
+        name!(visibility_reason, String),
+        name!(visibility_trace, Option<String>),
+        name!(data, String),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetTransactionSnapshot(),
+    };
+
+    /* All tuples are read and checked against the snapshot one at a time */
+    let mut tuples = TupleStream::open(
+        table,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        xids,
+        None,
+        None,
+    );
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    let rows = std::iter::from_fn(move || {
+        let (htup, tupdesc) = tuples.next_matching()?;
+        let tup = (*htup).t_data;
+        let xmin = pg_sys::HeapTupleHeaderGetXmin(tup);
+        let xmax = HeapTupleHeaderGetXmax(tup);
+
+        let visible = tuples.satisfies_snapshot(htup, snapshot_data);
+
+        Some((
+            (*htup).t_self,
+            Xid8::from_xid(xmin, next_xid),
+            Xid8::from_xid(xmax, next_xid),
+            xid_status(xmin),
+            xmax_status(tup),
+            visible,
+            visibility_reason(tup, snapshot_data, visible),
+            explain_visibility.then(|| visibility_steps(tup, snapshot_data).join("; ")),
+            format_tuple(&tuples.decode(htup, tupdesc).attributes, output_format),
+        ))
+    });
+
+    TableIterator::new(rows)
+}
+
+/*
+ * Compare the hint bits of the tuple with the status of its transactions in the
+ * commit log and return the mismatching hint bits. Frozen tuples and transactions
+ * older than relfrozenxid are not checked, since their commit log may be truncated.
+ */
+unsafe fn hint_bit_mismatches(
+    tup: pg_sys::HeapTupleHeader,
+    relfrozenxid: TransactionId,
+) -> Vec<(TransactionId, &'static str, String)> {
+    let checkable = |xid: TransactionId| {
+        xid >= pg_sys::FirstNormalTransactionId && !pg_sys::TransactionIdPrecedes(xid, relfrozenxid)
+    };
+
+    let infomask = (*tup).t_infomask as u32;
+    let mut mismatches = Vec::new();
+
+    let xmin = (*tup).t_choice.t_heap.t_xmin;
+
+    if !pg_sys::HeapTupleHeaderFrozen(tup) && checkable(xmin) {
+        let status = xid_status(xmin).unwrap_or_default();
+
+        if infomask & pg_sys::HEAP_XMIN_COMMITTED != 0 && status != "committed" {
+            mismatches.push((xmin, "HEAP_XMIN_COMMITTED", status));
+        } else if infomask & pg_sys::HEAP_XMIN_INVALID != 0 && status != "aborted" {
+            mismatches.push((xmin, "HEAP_XMIN_INVALID", status));
+        }
+    }
+
+    let xmax = (*tup).t_choice.t_heap.t_xmax;
+
+    if infomask & pg_sys::HEAP_XMAX_IS_MULTI != 0 {
+        /* Multixacts are never marked as committed, see HeapTupleSatisfiesMVCC */
+        if infomask & pg_sys::HEAP_XMAX_COMMITTED != 0 {
+            mismatches.push((xmax, "HEAP_XMAX_COMMITTED", "multixact".to_string()));
+        }
+    } else if checkable(xmax) {
+        let status = xid_status(xmax).unwrap_or_default();
+        let locked_only = HEAP_XMAX_IS_LOCKED_ONLY((*tup).t_infomask);
+
+        if infomask & pg_sys::HEAP_XMAX_COMMITTED != 0 && status != "committed" {
+            mismatches.push((xmax, "HEAP_XMAX_COMMITTED", status));
+        } else if infomask & pg_sys::HEAP_XMAX_INVALID != 0
+            && (status == "in progress" || (status == "committed" && !locked_only))
+        {
+            /* Lockers are marked as invalid once they finished, even if they committed */
+            mismatches.push((xmax, "HEAP_XMAX_INVALID", status));
+        }
+    }
+
+    mismatches
+}
+
+/*
+ * Return the hint bits of all tuples that contradict the status of the transaction
+ * in the commit log, e.g., HEAP_XMIN_COMMITTED for an aborted xmin. Such hint bits
+ * are a classic source of visibility bugs after restores or failovers. The tuples are
+ * read without a visibility check, so the scan does not set or correct hint bits.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_check_hint_bits(
+    table: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xid, Xid8),
+        name!(hint_bit, String),
+        name!(clog_status, String),
+    ),
+> {
+    let mut tuples = TupleStream::open(
+        table,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        None,
+        None,
+        None,
+    );
+    let relfrozenxid = (*(*tuples.table_rel).rd_rel).relfrozenxid;
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    let rows = std::iter::from_fn(move || {
+        let (htup, _tupdesc) = tuples.next_matching()?;
+        let ctid = (*htup).t_self;
+
+        Some(
+            hint_bit_mismatches((*htup).t_data, relfrozenxid)
+                .into_iter()
+                .map(move |(xid, hint_bit, status)| {
+                    (
+                        ctid,
+                        Xid8::from_xid(xid, next_xid),
+                        hint_bit.to_string(),
+                        status,
+                    )
+                }),
+        )
+    })
+    .flatten();
+
+    TableIterator::new(rows)
+}
+
+/*
+ * Return the tuples whose visibility differs between the two snapshots. The column
+ * visible_in shows under which snapshot (a or b) the tuple is visible. This helps to
+ * reproduce anomalies between two concurrent transactions.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_scan_diff(
+    table: &str,
+    snapshot_a: &str,
+    snapshot_b: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(ctid, pg_sys::ItemPointerData),
+        name!(xmin, Xid8),
+        name!(xmax, Xid8),
+        name!(visible_in, String),
+        name!(data, String),
+    ),
+> {
+    let snapshot_a = get_snapshot_from_str(snapshot_a);
+    let snapshot_b = get_snapshot_from_str(snapshot_b);
+
+    /* All tuples are read and checked against both snapshots one at a time */
+    let mut tuples = TupleStream::open(
+        table,
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        None,
+        None,
+        None,
+    );
+    let output_format = OutputFormat::from_guc();
+    let next_xid = pg_sys::ReadNextFullTransactionId();
+
+    let rows = std::iter::from_fn(move || loop {
+        let (htup, tupdesc) = tuples.next_matching()?;
+
+        let visible_in = match (
+            tuples.satisfies_snapshot(htup, snapshot_a),
+            tuples.satisfies_snapshot(htup, snapshot_b),
+        ) {
+            (true, false) => "a",
+            (false, true) => "b",
+            _ => continue,
+        };
+
+        let tup = (*htup).t_data;
+
+        return Some((
+            (*htup).t_self,
+            Xid8::from_xid(pg_sys::HeapTupleHeaderGetXmin(tup), next_xid),
+            Xid8::from_xid(HeapTupleHeaderGetXmax(tup), next_xid),
+            visible_in.to_string(),
+            format_tuple(&tuples.decode(htup, tupdesc).attributes, output_format),
+        ));
+    });
+
+    TableIterator::new(rows)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_scan_visibility() {
+        pgrx::Spi::run("CREATE TABLE visibility_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO visibility_test VALUES (1);").unwrap();
+
+        /* Insert a tuple in an aborted subtransaction */
+        pgrx::Spi::run(
+            "DO $$ BEGIN
+                INSERT INTO visibility_test VALUES (2);
+                RAISE EXCEPTION 'abort';
+            EXCEPTION WHEN OTHERS THEN NULL;
+            END $$;",
+        )
+        .unwrap();
+
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan_visibility('visibility_test');",
+        )
+        .unwrap();
+        assert_eq!(count, Some(2));
+
+        let reason = pgrx::Spi::get_one::<String>(
+            "SELECT visibility_reason FROM pg_debug_scan_visibility('visibility_test') WHERE NOT visible;",
+        )
+        .unwrap()
+        .expect("unable to get visibility reason");
+        assert!(reason.ends_with("is aborted"));
+
+        let status = pgrx::Spi::get_one::<String>(
+            "SELECT xmin_status FROM pg_debug_scan_visibility('visibility_test') WHERE NOT visible;",
+        )
+        .unwrap();
+        assert_eq!(status, Some("aborted".to_string()));
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_visibility_trace() {
+        pgrx::Spi::run("CREATE TABLE visibility_trace_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO visibility_trace_test VALUES (1);").unwrap();
+
+        let trace = pgrx::Spi::get_one::<String>(
+            "SELECT visibility_trace FROM pg_debug_scan_visibility('visibility_trace_test');",
+        )
+        .unwrap();
+        assert_eq!(trace, None);
+
+        let trace = pgrx::Spi::get_one::<String>(
+            "SELECT visibility_trace FROM pg_debug_scan_visibility('visibility_trace_test', explain_visibility => true);",
+        )
+        .unwrap()
+        .expect("unable to get visibility trace");
+        assert!(trace.ends_with("xmax is invalid (HEAP_XMAX_INVALID): visible"));
+    }
+
+    #[pgrx::pg_test]
+    fn test_check_hint_bits() {
+        pgrx::Spi::run("CREATE TABLE hint_bits_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO hint_bits_test VALUES (1);").unwrap();
+
+        /* Insert a tuple in an aborted subtransaction and set its hint bits */
+        pgrx::Spi::run(
+            "DO $$ BEGIN
+                INSERT INTO hint_bits_test VALUES (2);
+                RAISE EXCEPTION 'abort';
+            EXCEPTION WHEN OTHERS THEN NULL;
+            END $$;",
+        )
+        .unwrap();
+        pgrx::Spi::run("SELECT * FROM hint_bits_test;").unwrap();
+
+        let mismatches = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_check_hint_bits('hint_bits_test');",
+        )
+        .unwrap();
+        assert_eq!(mismatches, Some(0));
+    }
+
+    /*
+     * Change the hint bits of the tuple at the given position, like a restore from
+     * an inconsistent backup could do
+     */
+    unsafe fn set_hint_bits(
+        table: &str,
+        blkno: pg_sys::BlockNumber,
+        offnum: pg_sys::OffsetNumber,
+        set: u32,
+        clear: u32,
+    ) {
+        crate::heap::modify_page(table, blkno, |page| {
+            let tup = crate::heap::PageGetItem(page, crate::heap::PageGetItemId(page, offnum));
+            (*tup).t_infomask = (((*tup).t_infomask as u32 & !clear) | set) as u16;
+        });
+    }
+
+    #[pgrx::pg_test]
+    fn test_check_hint_bits_mismatch() {
+        pgrx::Spi::run("CREATE TABLE hint_bits_mismatch_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO hint_bits_mismatch_test VALUES (1);").unwrap();
+
+        /* Insert a tuple in an aborted subtransaction and mark it as committed */
+        pgrx::Spi::run(
+            "DO $$ BEGIN
+                INSERT INTO hint_bits_mismatch_test VALUES (2);
+                RAISE EXCEPTION 'abort';
+            EXCEPTION WHEN OTHERS THEN NULL;
+            END $$;",
+        )
+        .unwrap();
+
+        unsafe {
+            set_hint_bits(
+                "hint_bits_mismatch_test",
+                0,
+                2,
+                pg_sys::HEAP_XMIN_COMMITTED,
+                pg_sys::HEAP_XMIN_INVALID,
+            );
+        }
+
+        let (ctid, hint_bit, clog_status) = pgrx::Spi::get_three::<String, String, String>(
+            "SELECT ctid::text, hint_bit, clog_status FROM pg_debug_check_hint_bits('hint_bits_mismatch_test');",
+        )
+        .unwrap();
+        assert_eq!(ctid, Some("(0,2)".to_string()));
+        assert_eq!(hint_bit, Some("HEAP_XMIN_COMMITTED".to_string()));
+        assert_eq!(clog_status, Some("aborted".to_string()));
+
+        let mismatches = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_check_hint_bits('hint_bits_mismatch_test');",
+        )
+        .unwrap();
+        assert_eq!(mismatches, Some(1));
+    }
+
+    #[pgrx::pg_test]
+    fn test_tuple() {
+        pgrx::Spi::run("CREATE TABLE tuple_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO tuple_test VALUES (1);").unwrap();
+
+        let visible = pgrx::Spi::get_one::<bool>(
+            "SELECT visible FROM pg_debug_tuple('tuple_test', '(0,1)');",
+        )
+        .unwrap();
+        assert_eq!(visible, Some(true));
+
+        let step = pgrx::Spi::get_one::<String>(
+            "SELECT steps[array_upper(steps, 1)] FROM pg_debug_tuple('tuple_test', '(0,1)');",
+        )
+        .unwrap();
+        assert_eq!(
+            step,
+            Some("xmax is invalid (HEAP_XMAX_INVALID): visible".to_string())
+        );
+    }
+
+    #[pgrx::pg_test]
+    fn test_scan_diff() {
+        pgrx::Spi::run("CREATE TABLE scan_diff_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO scan_diff_test VALUES (1);").unwrap();
+
+        /* The tuple of the current transaction is visible under both snapshots */
+        let count = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_scan_diff('scan_diff_test', '3:3:', txid_current_snapshot()::text);",
+        )
+        .unwrap();
+        assert_eq!(count, Some(0));
+
+        /* The database of the test was created after transaction 3 */
+        let visible_in = pgrx::Spi::get_one::<String>(
+            "SELECT visible_in FROM pg_debug_scan_diff('pg_database', '3:3:', txid_current_snapshot()::text) LIMIT 1;",
+        )
+        .unwrap();
+        assert_eq!(visible_in, Some("b".to_string()));
+    }
+}
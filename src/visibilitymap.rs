@@ -0,0 +1,199 @@
+use pgrx::{
+    pg_sys::{AccessShareLock, GetLatestSnapshot},
+    prelude::*,
+};
+
+use crate::get_relid_from_name;
+use crate::heap::{
+    open_heap_relation, page_get_heap_tuple, prefetch_ahead, with_locked_page, PageGetItemId,
+    PageGetMaxOffsetNumber,
+};
+use crate::snapshot::get_snapshot_from_str;
+
+/* See access/visibilitymap.h */
+pub(crate) const VISIBILITYMAP_ALL_VISIBLE: u8 = 0x01;
+pub(crate) const VISIBILITYMAP_ALL_FROZEN: u8 = 0x02;
+
+#[pg_guard]
+extern "C" {
+    pub(crate) fn visibilitymap_get_status(
+        rel: pg_sys::Relation,
+        heap_blk: pg_sys::BlockNumber,
+        vmbuf: *mut pg_sys::Buffer,
+    ) -> u8;
+}
+
+/*
+ * Compare the visibility map of the table with the tuples of its pages. For each page
+ * marked as all-visible or all-frozen, the tuples that are invisible under the snapshot
+ * (by default, the latest snapshot), the unfrozen tuples of all-frozen pages, and the
+ * dead line pointers are counted. A page with such tuples is not consistent with its
+ * visibility map bits, which indicates a corruption. The bits are read while the page
+ * is locked, so they can not be cleared concurrently.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_check_visibility_map(
+    table: &str,
+    snapshot: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(block_number, i64),
+        name!(all_visible, bool),
+        name!(all_frozen, bool),
+        name!(tuples, i64),
+        name!(invisible_tuples, i64),
+        name!(unfrozen_tuples, i64),
+        name!(dead_line_pointers, i64),
+        name!(consistent, bool),
+    ),
+> {
+    let snapshot_data = match snapshot {
+        Some(snapshot_data) => get_snapshot_from_str(snapshot_data),
+        None => GetLatestSnapshot(),
+    };
+
+    let relid = get_relid_from_name(table);
+    let table_rel = open_heap_relation(relid);
+
+    let nblocks =
+        pg_sys::RelationGetNumberOfBlocksInFork(table_rel, pg_sys::ForkNumber_MAIN_FORKNUM);
+
+    let mut vmbuffer: pg_sys::Buffer = pg_sys::InvalidBuffer as pg_sys::Buffer;
+    let mut results = Vec::new();
+
+    for blkno in 0..nblocks {
+        prefetch_ahead(table_rel, blkno, nblocks);
+
+        with_locked_page(table_rel, blkno, |buffer, page| {
+            let status = visibilitymap_get_status(table_rel, blkno, &mut vmbuffer);
+            let all_visible = status & VISIBILITYMAP_ALL_VISIBLE != 0;
+            let all_frozen = status & VISIBILITYMAP_ALL_FROZEN != 0;
+
+            if !all_visible && !all_frozen {
+                return;
+            }
+
+            let mut tuples = 0;
+            let mut invisible_tuples = 0;
+            let mut unfrozen_tuples = 0;
+            let mut dead_line_pointers = 0;
+
+            for offnum in 1..=PageGetMaxOffsetNumber(page) {
+                match (*PageGetItemId(page, offnum)).lp_flags() {
+                    pg_sys::LP_NORMAL => {
+                        let mut htup = page_get_heap_tuple(relid, page, blkno, offnum);
+                        tuples += 1;
+
+                        if !pg_sys::HeapTupleSatisfiesVisibility(&mut htup, snapshot_data, buffer) {
+                            invisible_tuples += 1;
+                        }
+
+                        if all_frozen && !pg_sys::HeapTupleHeaderFrozen(htup.t_data) {
+                            unfrozen_tuples += 1;
+                        }
+                    }
+                    pg_sys::LP_DEAD => dead_line_pointers += 1,
+                    _ => {}
+                }
+            }
+
+            results.push((
+                blkno.into(),
+                all_visible,
+                all_frozen,
+                tuples,
+                invisible_tuples,
+                unfrozen_tuples,
+                dead_line_pointers,
+                invisible_tuples == 0 && unfrozen_tuples == 0 && dead_line_pointers == 0,
+            ));
+        });
+    }
+
+    if vmbuffer != pg_sys::InvalidBuffer as pg_sys::Buffer {
+        pg_sys::ReleaseBuffer(vmbuffer);
+    }
+
+    pg_sys::table_close(table_rel, AccessShareLock as i32);
+
+    TableIterator::new(results)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    use super::{VISIBILITYMAP_ALL_FROZEN, VISIBILITYMAP_ALL_VISIBLE};
+
+    #[pg_guard]
+    extern "C" {
+        fn visibilitymap_pin(
+            rel: pg_sys::Relation,
+            heap_blk: pg_sys::BlockNumber,
+            vmbuf: *mut pg_sys::Buffer,
+        );
+    }
+
+    /*
+     * Freeze the tuples of the first page and mark the page as all-visible and
+     * all-frozen, as VACUUM can not run in the transaction of the test
+     */
+    unsafe fn freeze_first_page(table: &str) {
+        crate::heap::modify_page(table, 0, |page| {
+            for offnum in 1..=crate::heap::PageGetMaxOffsetNumber(page) {
+                let tup = crate::heap::PageGetItem(page, crate::heap::PageGetItemId(page, offnum));
+                (*tup).t_infomask |=
+                    (pg_sys::HEAP_XMIN_COMMITTED | pg_sys::HEAP_XMIN_INVALID) as u16;
+            }
+
+            (*(page as pg_sys::PageHeader)).pd_flags |= pg_sys::PD_ALL_VISIBLE as u16;
+        });
+
+        let table_rel = pg_sys::table_open(
+            crate::get_relid_from_name(table),
+            pg_sys::RowExclusiveLock as i32,
+        );
+
+        let mut vmbuffer: pg_sys::Buffer = pg_sys::InvalidBuffer as pg_sys::Buffer;
+        visibilitymap_pin(table_rel, 0, &mut vmbuffer);
+        pg_sys::LockBuffer(vmbuffer, pg_sys::BUFFER_LOCK_EXCLUSIVE as i32);
+
+        /* The bits of the first heap block are the lowest bits of the map, see PageGetContents */
+        let map = (crate::heap::BufferGetPage(vmbuffer) as *mut u8)
+            .add(std::mem::size_of::<pg_sys::PageHeaderData>());
+        *map |= VISIBILITYMAP_ALL_VISIBLE | VISIBILITYMAP_ALL_FROZEN;
+
+        pg_sys::MarkBufferDirty(vmbuffer);
+        pg_sys::UnlockReleaseBuffer(vmbuffer);
+        pg_sys::table_close(table_rel, pg_sys::RowExclusiveLock as i32);
+    }
+
+    #[pgrx::pg_test]
+    fn test_check_visibility_map() {
+        pgrx::Spi::run("CREATE TABLE visibility_map_test (value int);").unwrap();
+        pgrx::Spi::run("INSERT INTO visibility_map_test VALUES (1), (2);").unwrap();
+
+        /* The pages are not vacuumed, so no visibility map bit is set */
+        let pages = pgrx::Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_debug_check_visibility_map('visibility_map_test');",
+        )
+        .unwrap();
+        assert_eq!(pages, Some(0));
+
+        unsafe {
+            freeze_first_page("visibility_map_test");
+        }
+
+        let (all_frozen, tuples, consistent) = pgrx::Spi::get_three::<bool, i64, bool>(
+            "SELECT all_frozen, tuples, consistent FROM pg_debug_check_visibility_map('visibility_map_test') WHERE block_number = 0;",
+        )
+        .unwrap();
+        assert_eq!(all_frozen, Some(true));
+        assert_eq!(tuples, Some(2));
+        assert_eq!(consistent, Some(true));
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+use pgrx::prelude::*;
+
+use crate::snapshot::xid_from_i64;
+
+/* The interval in which the state of the transaction is checked */
+const WAIT_INTERVAL_MS: i64 = 10;
+
+/*
+ * Wait until the given transaction is no longer in progress and return if it has
+ * been committed or aborted. If the transaction is still running after timeout_ms
+ * milliseconds, 'timeout' is returned. This allows to coordinate sessions in MVCC
+ * reproductions without sleep loops.
+ */
+#[cfg(not(feature = "embedded"))]
+#[pg_extern]
+unsafe fn pg_debug_wait_for_xid(xid: i64, timeout_ms: default!(Option<i64>, "NULL")) -> String {
+    let xid = xid_from_i64(xid, "xid");
+
+    if pg_sys::TransactionIdIsCurrentTransactionId(xid) {
+        error!("Transaction {xid} is the current transaction and can not be waited for");
+    }
+
+    let deadline = timeout_ms
+        .map(|timeout_ms| Instant::now() + Duration::from_millis(timeout_ms.max(0) as u64));
+
+    while pg_sys::TransactionIdIsInProgress(xid) {
+        if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            return "timeout".to_string();
+        }
+
+        pg_sys::WaitLatch(
+            pg_sys::MyLatch,
+            (pg_sys::WL_LATCH_SET | pg_sys::WL_TIMEOUT | pg_sys::WL_EXIT_ON_PM_DEATH) as i32,
+            WAIT_INTERVAL_MS,
+            pg_sys::PG_WAIT_EXTENSION,
+        );
+        pg_sys::ResetLatch(pg_sys::MyLatch);
+        check_for_interrupts!();
+    }
+
+    if pg_sys::TransactionIdDidCommit(xid) {
+        "committed".to_string()
+    } else {
+        "aborted".to_string()
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use pgrx::prelude::*;
+
+    #[pgrx::pg_test]
+    fn test_wait_for_aborted_xid() {
+        pgrx::Spi::run("CREATE TABLE wait_test (value int);").unwrap();
+
+        /* Insert a tuple in an aborted subtransaction */
+        pgrx::Spi::run(
+            "DO $$ BEGIN
+                INSERT INTO wait_test VALUES (1);
+                RAISE EXCEPTION 'abort';
+            EXCEPTION WHEN OTHERS THEN NULL;
+            END $$;",
+        )
+        .unwrap();
+
+        let status = pgrx::Spi::get_one::<String>(
+            "SELECT pg_debug_wait_for_xid(min_xmin, 1000) FROM pg_debug_block_xids('wait_test');",
+        )
+        .unwrap();
+        assert_eq!(status, Some("aborted".to_string()));
+    }
+}
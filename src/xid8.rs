@@ -0,0 +1,79 @@
+use pgrx::{
+    pg_sys::{FullTransactionId, TransactionId},
+    pgrx_sql_entity_graph::metadata::{
+        ArgumentError, Returns, ReturnsError, SqlMapping, SqlTranslatable,
+    },
+    prelude::*,
+};
+
+/*
+ * A 64 bit transaction id that includes the epoch. It is returned as the SQL type
+ * xid8, which is not available in PostgreSQL 12. There, bigint is used instead.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) struct Xid8(pub(crate) u64);
+
+impl Xid8 {
+    /*
+     * Add the epoch to the given transaction id. The epoch is derived from the next
+     * transaction id, see FullTransactionIdFromAllowableAt(). Special transaction
+     * ids like the frozen transaction id are returned without an epoch.
+     */
+    pub(crate) fn from_xid(xid: TransactionId, next_xid: FullTransactionId) -> Xid8 {
+        if xid < pg_sys::FirstNormalTransactionId {
+            return Xid8(xid.into());
+        }
+
+        /* Transaction ids in the table are at most 2^31 transactions away */
+        let distance = xid.wrapping_sub(next_xid.value as TransactionId) as i32;
+
+        Xid8(next_xid.value.wrapping_add_signed(distance.into()))
+    }
+
+    /* The SQL type of the value */
+    #[cfg(feature = "pg12")]
+    fn sql_type() -> &'static str {
+        "bigint"
+    }
+
+    #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+    fn sql_type() -> &'static str {
+        "xid8"
+    }
+}
+
+unsafe impl SqlTranslatable for Xid8 {
+    fn argument_sql() -> Result<SqlMapping, ArgumentError> {
+        Ok(SqlMapping::literal(Xid8::sql_type()))
+    }
+
+    fn return_sql() -> Result<Returns, ReturnsError> {
+        Ok(Returns::One(SqlMapping::literal(Xid8::sql_type())))
+    }
+}
+
+impl IntoDatum for Xid8 {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        Some(pg_sys::Datum::from(self.0))
+    }
+
+    #[cfg(feature = "pg12")]
+    fn type_oid() -> pg_sys::Oid {
+        pg_sys::INT8OID
+    }
+
+    #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16"))]
+    fn type_oid() -> pg_sys::Oid {
+        pg_sys::XID8OID
+    }
+}
+
+impl FromDatum for Xid8 {
+    unsafe fn from_polymorphic_datum(
+        datum: pg_sys::Datum,
+        is_null: bool,
+        _typoid: pg_sys::Oid,
+    ) -> Option<Xid8> {
+        (!is_null).then(|| Xid8(datum.value() as u64))
+    }
+}